@@ -0,0 +1,153 @@
+// Throughput benchmarks for the packet paths that dominate large
+// transfers, such as reading or writing a megabyte of memory.
+//
+//     cargo bench
+
+#[macro_use]
+extern crate criterion;
+extern crate gdb_rsp;
+
+use std::io;
+
+use criterion::{black_box, Criterion, Throughput};
+use gdb_rsp::{read_memory, read_qxfer_object, RspConnection};
+
+// The size of a large memory transfer.
+const MIB: usize = 1 << 20;
+
+// Frame |contents| as a complete packet.
+fn frame_packet(contents: &[u8]) -> Vec<u8> {
+    let checksum = contents.iter().fold(0u8, |sum, &c| sum.wrapping_add(c));
+    let mut result = b"$".to_vec();
+    result.extend_from_slice(contents);
+    result.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+    result
+}
+
+// Data that covers every byte value, including the ones that must be
+// escaped.
+fn sample_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7) as u8).collect()
+}
+
+fn hex(data: &[u8]) -> Vec<u8> {
+    data.iter().flat_map(|b| format!("{:02x}", b).into_bytes()).collect()
+}
+
+// Escape |data| as in binary packets.
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for &c in data {
+        if c == b'$' || c == b'#' || c == b'}' || c == b'*' {
+            result.push(b'}');
+            result.push(c ^ 0x20);
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn read_paths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Bytes(MIB as u64));
+
+    // A 1MiB memory read, as one hex reply.
+    let reply = frame_packet(&hex(&sample_data(MIB)));
+    group.bench_function("read_memory_hex", |b| b.iter(|| {
+        let mut input: &[u8] = &reply;
+        let mut output = io::sink();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        black_box(read_memory(&mut rsp, 0x1000, MIB, 2 * MIB).unwrap())
+    }));
+
+    // The same amount of data, escaped, as qXfer sends it.
+    let mut reply = b"l".to_vec();
+    reply.extend(escape(&sample_data(MIB)));
+    let reply = frame_packet(&reply);
+    group.bench_function("qxfer_binary", |b| b.iter(|| {
+        let mut input: &[u8] = &reply;
+        let mut output = io::sink();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        black_box(read_qxfer_object(&mut rsp, b"auxv", b"", 2 * MIB).unwrap())
+    }));
+
+    // A reply that is mostly run-length encoded, as for a zeroed
+    // block of memory.
+    let mut reply = Vec::new();
+    while reply.len() < MIB / 98 * 3 {
+        reply.extend_from_slice(b"0*~");
+    }
+    let reply = frame_packet(&reply);
+    group.bench_function("read_packet_rle", |b| b.iter(|| {
+        let mut input: &[u8] = &reply;
+        let mut output = io::sink();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        black_box(rsp.read_packet().unwrap())
+    }));
+
+    // The same reply in ack mode, which also verifies the checksum.
+    let reply = frame_packet(&hex(&sample_data(MIB / 2)));
+    group.bench_function("read_packet_acking", |b| b.iter(|| {
+        let mut input: &[u8] = &reply;
+        let mut output = io::sink();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        black_box(rsp.read_packet().unwrap())
+    }));
+
+    group.finish();
+}
+
+fn write_paths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Bytes(MIB as u64));
+    let data = sample_data(MIB);
+
+    group.bench_function("write_hex", |b| b.iter(|| {
+        let mut input: &[u8] = &[];
+        let mut output = Vec::with_capacity(2 * MIB + 4);
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            rsp.start_packet().unwrap();
+            rsp.write_hex(&data).unwrap();
+            rsp.finish_packet().unwrap();
+        }
+        black_box(output)
+    }));
+
+    group.bench_function("write_binary", |b| b.iter(|| {
+        let mut input: &[u8] = &[];
+        let mut output = Vec::with_capacity(2 * MIB + 4);
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            rsp.start_packet().unwrap();
+            rsp.write_binary(&data).unwrap();
+            rsp.finish_packet().unwrap();
+        }
+        black_box(output)
+    }));
+
+    // In ack mode the packet is kept for retransmission, and the
+    // stub's ack must be read.
+    group.bench_function("write_binary_acking", |b| b.iter(|| {
+        let mut input: &[u8] = b"+";
+        let mut output = Vec::with_capacity(2 * MIB + 4);
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, false);
+            rsp.start_packet().unwrap();
+            rsp.write_binary(&data).unwrap();
+            rsp.finish_packet().unwrap();
+        }
+        black_box(output)
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, read_paths, write_paths);
+criterion_main!(benches);
@@ -0,0 +1,116 @@
+// A small interactive client, for poking at a stub by hand.
+//
+//     cargo run --example rsp-cli -- localhost:1234
+//
+// With --listen, it instead waits for a stub to connect to it, as in
+// reverse setups where the stub connects out to the debugger.
+//
+//     cargo run --example rsp-cli -- --listen localhost:1234
+//
+// Each line read from standard input is either one of the commands
+// below or, failing that, a raw packet to send.  The reply is
+// printed as-is.
+//
+//     mem ADDR LEN        read memory, in hex
+//     reg N               read a register, by number
+//     break ADDR          insert a software breakpoint
+//     delete ADDR         remove it again
+//     continue            continue and wait for a stop
+//     step                single-step
+//     detach              detach and exit
+
+extern crate gdb_rsp;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::process;
+
+use gdb_rsp::{BreakpointType, DebugSession, RspResult, TcpChannel};
+
+fn parse_number(text: Option<&str>) -> Option<u64> {
+    let text = text?;
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    u64::from_str_radix(text, 16).ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Run the command on |line|, returning false if the session is over.
+fn command(session: &mut DebugSession, line: &str) -> RspResult<bool> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let first = parse_number(words.next());
+    let second = parse_number(words.next());
+    match (name, first, second) {
+        ("mem", Some(address), Some(length)) => {
+            println!("{}", hex(&session.read_memory(address, length as usize)?));
+        }
+        ("reg", Some(regnum), None) => {
+            match session.read_register(regnum)? {
+                Some(value) => println!("{}", hex(value.bytes())),
+                None => println!("<unavailable>"),
+            }
+        }
+        ("break", Some(address), None) => {
+            session.insert_breakpoint(BreakpointType::Software, address, 1)?;
+        }
+        ("delete", Some(address), None) => {
+            session.remove_breakpoint(BreakpointType::Software, address, 1)?;
+        }
+        ("continue", None, None) | ("step", None, None) => {
+            let reply = if name == "step" { session.step()? } else { session.cont()? };
+            io::stdout().write_all(&session.take_output())?;
+            println!("{:?}", reply);
+        }
+        ("detach", None, None) => {
+            session.detach()?;
+            return Ok(false);
+        }
+        _ => {
+            let reply = session.connection().request(line.as_bytes())?;
+            println!("{}", String::from_utf8_lossy(&reply));
+        }
+    }
+    Ok(true)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (listen, address) = match args.as_slice() {
+        [address] => (false, address),
+        [flag, address] if flag == "--listen" => (true, address),
+        _ => {
+            eprintln!("usage: rsp-cli [--listen] HOST:PORT");
+            process::exit(2);
+        }
+    };
+    let channel = if listen {
+        TcpListener::bind(address).and_then(|listener| TcpChannel::accept(&listener))
+            .map(|(channel, _)| channel)
+    } else {
+        TcpChannel::connect(address)
+    };
+    let mut channel = channel.unwrap_or_else(|err| {
+        eprintln!("rsp-cli: {}: {}", address, err);
+        process::exit(1);
+    });
+    let mut conn = channel.connection(true);
+    let mut session = DebugSession::start(&mut conn, b"swbreak+;hwbreak+")
+        .expect("could not start session");
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.expect("could not read standard input");
+        if line.trim().is_empty() {
+            continue;
+        }
+        match command(&mut session, line.trim()) {
+            Ok(true) => { }
+            Ok(false) => break,
+            Err(err) => eprintln!("rsp-cli: {:?}", err),
+        }
+    }
+}
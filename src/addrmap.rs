@@ -0,0 +1,347 @@
+#![deny(missing_docs)]
+
+use std::path::{Path, PathBuf};
+
+use low::{check_reply, RspConnection, RspError, RspResult};
+use util::{decode_hex, library_elements, local_path, xml_attribute};
+
+/// The relocation of the executable, as reported by `qOffsets`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionOffsets {
+    /// A `Text=...;Data=...;Bss=...` reply, giving the offset of each
+    /// section.
+    Sections {
+        /// The offset of the text section.
+        text: u64,
+        /// The offset of the data section.
+        data: u64,
+        /// The offset of the bss section.  Stubs are allowed to omit
+        /// this, in which case it is taken to be the same as `data`.
+        bss: u64,
+    },
+    /// A `TextSeg=...;DataSeg=...` reply, giving the address at which
+    /// each segment was loaded.
+    Segments {
+        /// The address of the text segment.
+        text: u64,
+        /// The address of the data segment, if there is one.
+        data: Option<u64>,
+    },
+}
+
+// Parse the value of |key| in a qOffsets reply.
+fn offset_value(pair: &[u8], key: &[u8]) -> Option<Result<u64, &'static str>> {
+    if pair.len() > key.len() && pair.starts_with(key) && pair[key.len()] == b'=' {
+        Some(decode_hex(&pair[key.len() + 1..]).ok_or("invalid offset"))
+    } else {
+        None
+    }
+}
+
+fn parse_offsets_inner(reply: &[u8]) -> Result<SectionOffsets, &'static str> {
+    let mut values: [Option<u64>; 5] = [None; 5];
+    let keys: [&[u8]; 5] = [b"Text", b"Data", b"Bss", b"TextSeg", b"DataSeg"];
+    for pair in reply.split(|&c| c == b';') {
+        let (index, value) = keys.iter().enumerate()
+            .find_map(|(index, key)| offset_value(pair, key).map(|value| (index, value)))
+            .ok_or("unknown field")?;
+        values[index] = Some(value?);
+    }
+    match values {
+        [Some(text), Some(data), bss, None, None] => {
+            Ok(SectionOffsets::Sections { text, data, bss: bss.unwrap_or(data) })
+        }
+        [None, None, None, Some(text), data] => Ok(SectionOffsets::Segments { text, data }),
+        _ => Err("invalid combination of fields"),
+    }
+}
+
+/// Parse the reply to `qOffsets`, for use by a client.  An error
+/// reply results in `RspError::Remote`.
+pub fn parse_offsets(reply: &[u8]) -> RspResult<SectionOffsets> {
+    let reply = check_reply(reply.to_vec())?;
+    parse_offsets_inner(&reply).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.clone(), reason }
+    })
+}
+
+/// Ask for the relocation of the executable with `qOffsets`.
+/// Returns `None` if the stub does not support the packet, which
+/// usually means the executable was loaded where it was linked.
+pub fn section_offsets(conn: &mut RspConnection) -> RspResult<Option<SectionOffsets>> {
+    let reply = conn.request(b"qOffsets")?;
+    if reply.is_empty() {
+        return Ok(None);
+    }
+    parse_offsets(&reply).map(Some)
+}
+
+/// Where a shared library was loaded, as reported in a library list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadAddress {
+    /// The difference between the runtime and link-time addresses,
+    /// as in the `l_addr` attribute of the `libraries-svr4` format.
+    Bias(u64),
+    /// The runtime address of the library's first loadable segment,
+    /// as in the `segment` elements of the `libraries` format.
+    Segment(u64),
+}
+
+/// A shared library from a library list, with its load address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibraryLoad {
+    /// The remote name of the library.
+    pub name: Vec<u8>,
+    /// Where the library was loaded.  This is `None` if the list did
+    /// not say, or only gave the addresses of individual sections,
+    /// which can't be mapped without reading the library.
+    pub address: Option<LoadAddress>,
+}
+
+// Parse an address attribute, which is written in C syntax.
+fn parse_address(value: &[u8]) -> Option<u64> {
+    if value.starts_with(b"0x") || value.starts_with(b"0X") {
+        decode_hex(&value[2..])
+    } else {
+        ::std::str::from_utf8(value).ok()?.parse().ok()
+    }
+}
+
+/// Parse a library list, in either the `libraries` or the
+/// `libraries-svr4` format, as fetched with `read_qxfer_object`.
+/// Libraries without a name are skipped.
+pub fn parse_library_loads(xml: &[u8]) -> Vec<LibraryLoad> {
+    let mut result = Vec::new();
+    for (tag, body) in library_elements(xml) {
+        let name = match xml_attribute(tag, b"name") {
+            Some(name) => name,
+            None => continue,
+        };
+        let address = match xml_attribute(tag, b"l_addr") {
+            Some(bias) => parse_address(&bias).map(LoadAddress::Bias),
+            None => {
+                body.windows(9).position(|w| w == b"<segment ").and_then(|pos| {
+                    let segment = &body[pos + 9..];
+                    let end = segment.iter().position(|&c| c == b'>').unwrap_or(segment.len());
+                    xml_attribute(&segment[..end], b"address")
+                }).and_then(|address| parse_address(&address)).map(LoadAddress::Segment)
+            }
+        };
+        result.push(LibraryLoad { name, address });
+    }
+    result
+}
+
+/// Look for a local copy of the remote file `remote` in the
+/// directories `search_path`, in the manner of gdb's
+/// `solib-search-path`.  Each directory is tried in turn, first as a
+/// sysroot, with the whole remote path appended, and then with just
+/// the file name.
+pub fn find_local_binary(search_path: &[PathBuf], remote: &[u8]) -> Option<PathBuf> {
+    let base = remote.rsplit(|&c| c == b'/').next().unwrap_or(remote);
+    for dir in search_path {
+        for candidate in &[local_path(dir, remote), local_path(dir, base)] {
+            if let Some(ref candidate) = *candidate {
+                if candidate.is_file() {
+                    return Some(candidate.clone());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// A local copy of an executable or shared library, with the
+/// link-time addresses it occupies.  Reading these from the file is
+/// left to the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalBinary {
+    path: PathBuf,
+    link_address: u64,
+    size: u64,
+}
+
+impl LocalBinary {
+    /// Make a new local binary.  `link_address` is the link-time
+    /// address of its first loadable segment, and `size` the extent
+    /// of its loadable segments.
+    pub fn new(path: PathBuf, link_address: u64, size: u64) -> LocalBinary {
+        LocalBinary { path, link_address, size }
+    }
+
+    /// Return the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// One binary in an AddressMap.
+#[derive(Clone, Debug)]
+struct Mapping {
+    binary: LocalBinary,
+    runtime_address: u64,
+}
+
+/// A translation between the link-time addresses of local binaries
+/// and the runtime addresses at which the inferior loaded them, as a
+/// building block for symbolization.  The map is built from
+/// `qOffsets` and the library list; see `add_executable` and
+/// `add_library`.
+#[derive(Clone, Debug, Default)]
+pub struct AddressMap {
+    mappings: Vec<Mapping>,
+}
+
+impl AddressMap {
+    /// Make a new, empty map.
+    pub fn new() -> AddressMap {
+        AddressMap::default()
+    }
+
+    /// Add `binary`, loaded so that its first segment is at
+    /// `runtime_address`.
+    pub fn add(&mut self, binary: LocalBinary, runtime_address: u64) {
+        self.mappings.push(Mapping { binary, runtime_address });
+    }
+
+    /// Add the executable `binary`, relocated as `offsets` says.  A
+    /// `Sections` reply is taken to move the whole executable by the
+    /// text offset.
+    pub fn add_executable(&mut self, binary: LocalBinary, offsets: &SectionOffsets) {
+        let runtime_address = match *offsets {
+            SectionOffsets::Sections { text, .. } => binary.link_address.wrapping_add(text),
+            SectionOffsets::Segments { text, .. } => text,
+        };
+        self.add(binary, runtime_address);
+    }
+
+    /// Add the shared library `binary`, loaded as `library` says.
+    /// Returns false, leaving the map alone, if the load address is
+    /// not known.
+    pub fn add_library(&mut self, binary: LocalBinary, library: &LibraryLoad) -> bool {
+        let runtime_address = match library.address {
+            Some(LoadAddress::Bias(bias)) => binary.link_address.wrapping_add(bias),
+            Some(LoadAddress::Segment(address)) => address,
+            None => return false,
+        };
+        self.add(binary, runtime_address);
+        true
+    }
+
+    /// Translate the runtime address `address` to the binary that
+    /// contains it and the corresponding link-time address.
+    pub fn to_link(&self, address: u64) -> Option<(&Path, u64)> {
+        self.mappings.iter().find_map(|mapping| {
+            let offset = address.checked_sub(mapping.runtime_address)?;
+            if offset < mapping.binary.size {
+                Some((mapping.binary.path(), mapping.binary.link_address + offset))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Translate the link-time address `address` in the binary at
+    /// `path` to its runtime address.
+    pub fn to_runtime(&self, path: &Path, address: u64) -> Option<u64> {
+        self.mappings.iter().find_map(|mapping| {
+            let offset = address.checked_sub(mapping.binary.link_address)?;
+            if mapping.binary.path() == path && offset < mapping.binary.size {
+                Some(mapping.runtime_address + offset)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use super::{find_local_binary, parse_library_loads, parse_offsets, section_offsets,
+                AddressMap, LibraryLoad, LoadAddress, LocalBinary, SectionOffsets};
+    use low::RspConnection;
+    use util::frame_packet;
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_offsets(b"Text=1000;Data=1000").unwrap(),
+                   SectionOffsets::Sections { text: 0x1000, data: 0x1000, bss: 0x1000 });
+        assert_eq!(parse_offsets(b"TextSeg=8000;DataSeg=A000").unwrap(),
+                   SectionOffsets::Segments { text: 0x8000, data: Some(0xa000) });
+        assert!(parse_offsets(b"Text=1000").is_err());
+        assert!(parse_offsets(b"Text=1000;Data=0;TextSeg=0").is_err());
+        assert!(parse_offsets(b"E01").is_err());
+
+        let mut input = frame_packet(b"");
+        input.extend(frame_packet(b"TextSeg=8000"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        assert_eq!(section_offsets(&mut rsp).unwrap(), None);
+        assert_eq!(section_offsets(&mut rsp).unwrap(),
+                   Some(SectionOffsets::Segments { text: 0x8000, data: None }));
+    }
+
+    #[test]
+    fn library_loads() {
+        let svr4 = b"<library-list-svr4 version=\"1.0\">\
+                     <library name=\"/lib/libc.so.6\" lm=\"0x1\" l_addr=\"0x7f0000\" l_ld=\"0x3\"/>\
+                     </library-list-svr4>";
+        assert_eq!(parse_library_loads(svr4),
+                   vec![LibraryLoad { name: b"/lib/libc.so.6".to_vec(),
+                                      address: Some(LoadAddress::Bias(0x7f0000)) }]);
+
+        let plain = b"<library-list><library name=\"/lib/libm.so\">\
+                      <segment address=\"0x10000\"/></library>\
+                      <library name=\"/lib/libz.so\"><section address=\"0x20000\"/></library>\
+                      </library-list>";
+        assert_eq!(parse_library_loads(plain),
+                   vec![LibraryLoad { name: b"/lib/libm.so".to_vec(),
+                                      address: Some(LoadAddress::Segment(0x10000)) },
+                        LibraryLoad { name: b"/lib/libz.so".to_vec(), address: None }]);
+    }
+
+    #[test]
+    fn address_map() {
+        let mut map = AddressMap::new();
+        let exe = LocalBinary::new(PathBuf::from("a.out"), 0x400000, 0x1000);
+        map.add_executable(exe, &SectionOffsets::Sections { text: 0x10000, data: 0, bss: 0 });
+        let libc = LocalBinary::new(PathBuf::from("libc.so.6"), 0, 0x2000);
+        let load = LibraryLoad { name: b"/lib/libc.so.6".to_vec(),
+                                 address: Some(LoadAddress::Bias(0x7f0000)) };
+        assert!(map.add_library(libc.clone(), &load));
+        let unknown = LibraryLoad { name: b"/lib/libc.so.6".to_vec(), address: None };
+        assert!(!map.add_library(libc, &unknown));
+
+        assert_eq!(map.to_link(0x410010), Some((Path::new("a.out"), 0x400010)));
+        assert_eq!(map.to_link(0x7f1fff), Some((Path::new("libc.so.6"), 0x1fff)));
+        assert_eq!(map.to_link(0x7f2000), None);
+        assert_eq!(map.to_runtime(Path::new("libc.so.6"), 0x100), Some(0x7f0100));
+        assert_eq!(map.to_runtime(Path::new("a.out"), 0x100), None);
+    }
+
+    #[test]
+    fn search_path() {
+        let root = env::temp_dir().join(format!("gdb-rsp-addrmap-{}", process::id()));
+        let sysroot = root.join("sysroot");
+        let flat = root.join("flat");
+        fs::create_dir_all(sysroot.join("lib")).unwrap();
+        fs::create_dir_all(&flat).unwrap();
+        fs::write(sysroot.join("lib").join("libc.so.6"), b"").unwrap();
+        fs::write(flat.join("libm.so"), b"").unwrap();
+
+        let search_path = [sysroot.clone(), flat.clone()];
+        assert_eq!(find_local_binary(&search_path, b"/lib/libc.so.6"),
+                   Some(sysroot.join("lib").join("libc.so.6")));
+        assert_eq!(find_local_binary(&search_path, b"/usr/lib/libm.so"),
+                   Some(flat.join("libm.so")));
+        assert_eq!(find_local_binary(&search_path, b"/lib/libz.so"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+#![deny(missing_docs)]
+
+use low::{RspError, RspResult};
+use util::{decode_hex_bytes, parse_hex_number};
+
+/// The type of breakpoint or watchpoint named by a `Z` or `z` packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakpointType {
+    /// Type 0: a software breakpoint.
+    Software,
+    /// Type 1: a hardware breakpoint.
+    Hardware,
+    /// Type 2: a write watchpoint.
+    WriteWatchpoint,
+    /// Type 3: a read watchpoint.
+    ReadWatchpoint,
+    /// Type 4: an access watchpoint.
+    AccessWatchpoint,
+}
+
+/// A parsed `Z` or `z` packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BreakpointRequest {
+    /// True for `Z` (insert), false for `z` (remove).
+    pub insert: bool,
+    /// The type of breakpoint.
+    pub breakpoint_type: BreakpointType,
+    /// The address of the breakpoint.
+    pub address: u64,
+    /// The target-specific kind.  For a software breakpoint this is
+    /// usually the size of the breakpoint instruction; for a
+    /// watchpoint it is the number of bytes to watch.
+    pub kind: u64,
+    /// Conditions, as agent expression bytecode.  The breakpoint
+    /// should only report a stop when one of these is true.
+    pub conditions: Vec<Vec<u8>>,
+    /// Commands to run when the breakpoint is hit, as agent
+    /// expression bytecode.
+    pub commands: Vec<Vec<u8>>,
+    /// True if the commands should continue to run after the
+    /// debugger disconnects.
+    pub persist: bool,
+}
+
+// Parse an agent expression of the form "X len,bytes" at the start of
+// |buf|, returning the expression and the remaining input.
+fn parse_agent_expr(buf: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    if buf.first() != Some(&b'X') {
+        return None;
+    }
+    let comma = buf.iter().position(|&c| c == b',')?;
+    let len = parse_hex_number(&buf[1..comma])? as usize;
+    let rest = &buf[comma + 1..];
+    let hex_len = len.checked_mul(2)?;
+    if rest.len() < hex_len {
+        return None;
+    }
+    Some((decode_hex_bytes(&rest[..hex_len])?, &rest[hex_len..]))
+}
+
+fn parse_breakpoint_inner(packet: &[u8]) -> Result<BreakpointRequest, &'static str> {
+    let insert = match packet.first() {
+        Some(&b'Z') => true,
+        Some(&b'z') => false,
+        _ => return Err("not a breakpoint packet"),
+    };
+
+    let breakpoint_type = match packet.get(1..3) {
+        Some(b"0,") => BreakpointType::Software,
+        Some(b"1,") => BreakpointType::Hardware,
+        Some(b"2,") => BreakpointType::WriteWatchpoint,
+        Some(b"3,") => BreakpointType::ReadWatchpoint,
+        Some(b"4,") => BreakpointType::AccessWatchpoint,
+        _ => return Err("invalid breakpoint type"),
+    };
+
+    let rest = &packet[3..];
+    let comma = rest.iter().position(|&c| c == b',').ok_or("missing breakpoint kind")?;
+    let address = parse_hex_number(&rest[..comma]).ok_or("invalid address")?;
+    let rest = &rest[comma + 1..];
+    let end = rest.iter().position(|&c| c == b';').unwrap_or(rest.len());
+    let kind = parse_hex_number(&rest[..end]).ok_or("invalid breakpoint kind")?;
+
+    let mut result = BreakpointRequest {
+        insert,
+        breakpoint_type,
+        address,
+        kind,
+        conditions: Vec::new(),
+        commands: Vec::new(),
+        persist: false,
+    };
+
+    // Conditions and commands are sent back-to-back, without
+    // separators between the expressions.
+    let mut rest = &rest[end..];
+    let mut in_commands = false;
+    while let Some(&ch) = rest.first() {
+        if ch == b';' {
+            rest = &rest[1..];
+        } else if ch == b'X' {
+            let (expr, tail) = parse_agent_expr(rest).ok_or("invalid agent expression")?;
+            if in_commands {
+                result.commands.push(expr);
+            } else {
+                result.conditions.push(expr);
+            }
+            rest = tail;
+        } else if rest.starts_with(b"cmds:") {
+            let tail = &rest[5..];
+            let comma = tail.iter().position(|&c| c == b',').ok_or("invalid command list")?;
+            result.persist = parse_hex_number(&tail[..comma]).ok_or("invalid persist flag")? != 0;
+            in_commands = true;
+            rest = &tail[comma + 1..];
+        } else {
+            return Err("unrecognized breakpoint option");
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parse a `Z` or `z` packet, for use by a server.  `packet` is the
+/// entire packet.  Conditions (`;X len,expr...`) and commands
+/// (`;cmds:persist,X len,expr...`) are extracted as raw agent
+/// expression bytecode.  Returns `ProtocolViolation` if the packet is
+/// malformed.
+pub fn parse_breakpoint(packet: &[u8]) -> RspResult<BreakpointRequest> {
+    parse_breakpoint_inner(packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_breakpoint, BreakpointRequest, BreakpointType};
+
+    #[test]
+    fn simple() {
+        assert_eq!(parse_breakpoint(b"Z0,401136,1").unwrap(), BreakpointRequest {
+            insert: true,
+            breakpoint_type: BreakpointType::Software,
+            address: 0x401136,
+            kind: 1,
+            conditions: Vec::new(),
+            commands: Vec::new(),
+            persist: false,
+        });
+        let request = parse_breakpoint(b"z2,7ffe0010,8").unwrap();
+        assert!(!request.insert);
+        assert_eq!(request.breakpoint_type, BreakpointType::WriteWatchpoint);
+        assert_eq!(request.kind, 8);
+    }
+
+    #[test]
+    fn conditions_and_commands() {
+        let request = parse_breakpoint(b"Z0,1000,4;X2,2627X1,27;cmds:1,X3,0a0b0cX1,ff").unwrap();
+        assert_eq!(request.conditions, vec![vec![0x26, 0x27], vec![0x27]]);
+        assert_eq!(request.commands, vec![vec![0x0a, 0x0b, 0x0c], vec![0xff]]);
+        assert!(request.persist);
+
+        let request = parse_breakpoint(b"Z1,1000,2;cmds:0,X1,01").unwrap();
+        assert!(request.conditions.is_empty());
+        assert_eq!(request.commands, vec![vec![1]]);
+        assert!(!request.persist);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_breakpoint(b"Z5,1000,1").is_err());
+        assert!(parse_breakpoint(b"Z0,1000").is_err());
+        assert!(parse_breakpoint(b"Z0,1000,1;X4,00").is_err());
+        assert!(parse_breakpoint(b"Z0,1000,1;Y").is_err());
+        assert!(parse_breakpoint(b"m1000,4").is_err());
+    }
+
+    #[test]
+    fn uppercase_hex() {
+        let request = parse_breakpoint(b"Z1,40113A,A;XA,0A0B0C0D0E0F10111213").unwrap();
+        assert_eq!(request.address, 0x40113a);
+        assert_eq!(request.kind, 10);
+        assert_eq!(request.conditions[0][..2], [0x0a, 0x0b]);
+    }
+}
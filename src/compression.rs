@@ -0,0 +1,336 @@
+#![deny(missing_docs)]
+
+use low::{RspError, RspResult};
+use util::{unescape_binary, write_decimal};
+
+/// A packet compression algorithm, as offered by lldb-server in the
+/// `SupportedCompressions` feature of its `qSupported` reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionType {
+    /// Apple's LZFSE.  This crate cannot decompress it.
+    Lzfse,
+    /// Raw deflate, requiring the `zlib` feature.
+    ZlibDeflate,
+    /// LZ4, in the block format used by Apple's compression library.
+    /// This requires the `lz4` feature.
+    Lz4,
+    /// LZMA.  This crate cannot decompress it.
+    Lzma,
+}
+
+impl CompressionType {
+    /// Return the name used for this algorithm in the protocol.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CompressionType::Lzfse => "lzfse",
+            CompressionType::ZlibDeflate => "zlib-deflate",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Lzma => "lzma",
+        }
+    }
+
+    /// Look up an algorithm by its protocol name.
+    pub fn from_name(name: &[u8]) -> Option<CompressionType> {
+        match name {
+            b"lzfse" => Some(CompressionType::Lzfse),
+            b"zlib-deflate" => Some(CompressionType::ZlibDeflate),
+            b"lz4" => Some(CompressionType::Lz4),
+            b"lzma" => Some(CompressionType::Lzma),
+            _ => None,
+        }
+    }
+
+    /// Return true if this build of the crate can decompress packets
+    /// that use this algorithm.
+    pub fn is_available(&self) -> bool {
+        match *self {
+            CompressionType::ZlibDeflate => cfg!(feature = "zlib"),
+            CompressionType::Lz4 => cfg!(feature = "lz4"),
+            CompressionType::Lzfse | CompressionType::Lzma => false,
+        }
+    }
+}
+
+/// Return the compression algorithms listed in a `qSupported` reply,
+/// in the stub's order of preference.  Unknown algorithms are
+/// skipped.
+pub fn parse_supported_compressions(reply: &[u8]) -> Vec<CompressionType> {
+    let prefix = b"SupportedCompressions=";
+    reply.split(|&c| c == b';')
+        .find(|feature| feature.starts_with(prefix))
+        .map(|feature| {
+            feature[prefix.len()..].split(|&c| c == b',')
+                .filter_map(CompressionType::from_name)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Choose the first algorithm in `supported` that this build can
+/// decompress.
+pub fn choose_compression(supported: &[CompressionType]) -> Option<CompressionType> {
+    supported.iter().cloned().find(CompressionType::is_available)
+}
+
+/// Build the `QEnableCompression` packet that turns on compression
+/// using `compression`.  If `min_size` is given, the stub is asked
+/// not to compress packets smaller than that.  Once the stub replies
+/// `OK`, every packet it sends must be passed through
+/// `decompress_packet`; `RspConnection::enable_compression` sends
+/// this packet and arranges for that.
+pub fn enable_compression_packet(compression: CompressionType, min_size: Option<usize>)
+                                 -> Vec<u8> {
+    let mut result = b"QEnableCompression:type:".to_vec();
+    result.extend_from_slice(compression.name().as_bytes());
+    result.push(b';');
+    if let Some(size) = min_size {
+        result.extend_from_slice(b"minsize:");
+        write_decimal(&mut result, size as u64);
+        result.push(b';');
+    }
+    result
+}
+
+// Inflate |data|, failing if the result would be longer than |limit|.
+#[cfg(feature = "zlib")]
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, &'static str> {
+    ::miniz_oxide::inflate::decompress_to_vec_with_limit(data, limit)
+        .map_err(|_| "invalid deflate data")
+}
+
+#[cfg(not(feature = "zlib"))]
+fn inflate(_: &[u8], _: usize) -> Result<Vec<u8>, &'static str> {
+    Err("zlib-deflate support is not enabled")
+}
+
+// Read a little-endian u32 from the start of |data|.
+#[cfg(feature = "lz4")]
+fn read_u32(data: &[u8]) -> Result<usize, &'static str> {
+    if data.len() < 4 {
+        return Err("truncated lz4 block header");
+    }
+    Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize)
+}
+
+// Decode Apple's LZ4 container, which is a series of blocks, each
+// starting with a magic number: "bv41" for a compressed block,
+// "bv4-" for an uncompressed one, and "bv4$" to end the stream.
+// Fails if the result would be longer than |limit|.
+#[cfg(feature = "lz4")]
+fn lz4_decompress(mut data: &[u8], limit: usize) -> Result<Vec<u8>, &'static str> {
+    let mut result = Vec::new();
+    loop {
+        if data.len() < 4 {
+            return Err("truncated lz4 stream");
+        }
+        let (magic, rest) = data.split_at(4);
+        match magic {
+            b"bv4$" => return Ok(result),
+            b"bv41" => {
+                let decoded = read_u32(rest)?;
+                if decoded > limit - result.len() {
+                    return Err("lz4 block is larger than the stated size");
+                }
+                let encoded = read_u32(&rest[4..])?;
+                let block = rest.get(8..8 + encoded).ok_or("truncated lz4 block")?;
+                let output = ::lz4_flex::block::decompress(block, decoded)
+                    .map_err(|_| "invalid lz4 data")?;
+                result.extend_from_slice(&output);
+                data = &rest[8 + encoded..];
+            }
+            b"bv4-" => {
+                let size = read_u32(rest)?;
+                if size > limit - result.len() {
+                    return Err("lz4 block is larger than the stated size");
+                }
+                let block = rest.get(4..4 + size).ok_or("truncated lz4 block")?;
+                result.extend_from_slice(block);
+                data = &rest[4 + size..];
+            }
+            _ => return Err("invalid lz4 block magic"),
+        }
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4_decompress(_: &[u8], _: usize) -> Result<Vec<u8>, &'static str> {
+    Err("lz4 support is not enabled")
+}
+
+fn decompress_packet_inner(compression: CompressionType, packet: &[u8])
+                           -> Result<Vec<u8>, &'static str> {
+    match packet.first() {
+        Some(&b'N') => return Ok(packet[1..].to_vec()),
+        Some(&b'C') => { }
+        _ => return Err("expected N or C"),
+    }
+    let colon = packet.iter().position(|&c| c == b':').ok_or("missing size")?;
+    let size = ::std::str::from_utf8(&packet[1..colon]).ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .ok_or("invalid size")?;
+    let data = unescape_binary(&packet[colon + 1..]).ok_or("invalid escape")?;
+    let result = match compression {
+        CompressionType::ZlibDeflate => inflate(&data, size)?,
+        CompressionType::Lz4 => lz4_decompress(&data, size)?,
+        CompressionType::Lzfse | CompressionType::Lzma => {
+            return Err("unsupported compression type");
+        }
+    };
+    if result.len() != size {
+        return Err("decompressed size does not match");
+    }
+    Ok(result)
+}
+
+/// Decode a packet received from a stub after compression was
+/// enabled with `compression`.  Such a packet is either `N` followed
+/// by the uncompressed contents, or `C`, the decimal size of the
+/// contents, `:`, and the compressed, binary-escaped contents.
+///
+/// Note that the connection must be in no-ack mode, or the packet
+/// must otherwise not be RLE-expanded, for `C` packets to be read
+/// intact; lldb-server only compresses in no-ack mode.
+pub fn decompress_packet(compression: CompressionType, packet: &[u8]) -> RspResult<Vec<u8>> {
+    decompress_packet_inner(compression, packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{choose_compression, decompress_packet, enable_compression_packet,
+                parse_supported_compressions, CompressionType};
+    use low::{RspConnection, RspError};
+    use util::frame_packets;
+    #[cfg(any(feature = "zlib", feature = "lz4"))]
+    use util::write_decimal;
+
+    // Build the header of a compressed packet whose contents are
+    // |size| bytes long.
+    #[cfg(any(feature = "zlib", feature = "lz4"))]
+    fn header(size: usize) -> Vec<u8> {
+        let mut packet = b"C".to_vec();
+        write_decimal(&mut packet, size as u64);
+        packet.push(b':');
+        packet
+    }
+
+    #[test]
+    fn negotiate() {
+        let reply = b"PacketSize=20000;SupportedCompressions=lzfse,zlib-deflate,foo,lz4;qEcho+";
+        let supported = parse_supported_compressions(reply);
+        assert_eq!(supported, vec![CompressionType::Lzfse, CompressionType::ZlibDeflate,
+                                   CompressionType::Lz4]);
+        assert_eq!(parse_supported_compressions(b"PacketSize=20000"), vec![]);
+
+        let expected = if cfg!(feature = "zlib") {
+            Some(CompressionType::ZlibDeflate)
+        } else if cfg!(feature = "lz4") {
+            Some(CompressionType::Lz4)
+        } else {
+            None
+        };
+        assert_eq!(choose_compression(&supported), expected);
+
+        assert_eq!(enable_compression_packet(CompressionType::Lz4, Some(384)),
+                   b"QEnableCompression:type:lz4;minsize:384;".to_vec());
+    }
+
+    #[test]
+    fn uncompressed() {
+        assert_eq!(decompress_packet(CompressionType::Lzma, b"NOK").unwrap(), b"OK".to_vec());
+        assert!(decompress_packet(CompressionType::Lzma, b"C2:xx").is_err());
+        assert!(decompress_packet(CompressionType::Lzma, b"OK").is_err());
+    }
+
+    #[test]
+    fn connection() {
+        let input = frame_packets(&[b"OK", b"NOK", b"C2:xx", b"N1234"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert!(rsp.enable_compression(CompressionType::Lzma, None).unwrap());
+            assert_eq!(rsp.compression(), Some(CompressionType::Lzma));
+            assert_eq!(rsp.request(b"Hg0").unwrap(), b"OK");
+            match rsp.request(b"m0,2") {
+                Err(RspError::ProtocolViolation { .. }) => { }
+                result => panic!("unexpected result {:?}", result),
+            }
+            // The bad packet was still the reply.
+            assert_eq!(rsp.request(b"m2,2").unwrap(), b"1234");
+        }
+        let sent: &[&[u8]] = &[b"QEnableCompression:type:lzma;", b"Hg0", b"m0,2", b"m2,2"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn zlib() {
+        let contents = b"0000000000000000000000000000000000000000".to_vec();
+        let compressed = ::miniz_oxide::deflate::compress_to_vec(&contents, 6);
+        let mut body = Vec::new();
+        for &byte in &compressed {
+            if let b'#' | b'$' | b'}' | b'*' = byte {
+                body.push(b'}');
+                body.push(byte ^ 0x20);
+            } else {
+                body.push(byte);
+            }
+        }
+        let mut packet = header(contents.len());
+        packet.extend_from_slice(&body);
+        assert_eq!(decompress_packet(CompressionType::ZlibDeflate, &packet).unwrap(),
+                   contents);
+
+        // The same packet, read by a connection.
+        let input = frame_packets(&[&packet]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        rsp.set_compression(Some(CompressionType::ZlibDeflate));
+        assert_eq!(rsp.request(b"g").unwrap(), contents);
+
+        // The stated size bounds the output.
+        let mut packet = b"C10:".to_vec();
+        packet.extend_from_slice(&body);
+        assert!(decompress_packet(CompressionType::ZlibDeflate, &packet).is_err());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4() {
+        let contents = b"0000000000000000000000000000000000000000".to_vec();
+        let block = ::lz4_flex::block::compress(&contents);
+        let mut stream = b"bv41".to_vec();
+        stream.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        stream.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        stream.extend_from_slice(&block);
+        stream.extend_from_slice(b"bv4-\x02\x00\x00\x00ok");
+        stream.extend_from_slice(b"bv4$");
+
+        let mut body = Vec::new();
+        for &byte in &stream {
+            if let b'#' | b'$' | b'}' | b'*' = byte {
+                body.push(b'}');
+                body.push(byte ^ 0x20);
+            } else {
+                body.push(byte);
+            }
+        }
+        let mut packet = header(contents.len() + 2);
+        packet.extend_from_slice(&body);
+        let mut expected = contents.clone();
+        expected.extend_from_slice(b"ok");
+        assert_eq!(decompress_packet(CompressionType::Lz4, &packet).unwrap(), expected);
+
+        // A block may not claim more than the stated size.
+        for size in &[10, contents.len() + 1] {
+            let mut packet = header(*size);
+            packet.extend_from_slice(&body);
+            assert!(decompress_packet(CompressionType::Lz4, &packet).is_err());
+        }
+    }
+}
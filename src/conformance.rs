@@ -0,0 +1,350 @@
+#![deny(missing_docs)]
+
+use std::fmt;
+use std::io::Write;
+
+use low::{PacketType, RemoteError, RspConnection, RspError, RspResult, Strictness};
+use stop::parse_stop_reply_with_strictness;
+
+/// The outcome of a single conformance check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    /// The stub behaved as the protocol requires.
+    Pass,
+    /// The stub did not behave as the protocol requires.  This holds
+    /// a description of the problem.
+    Fail(String),
+    /// The check was not run, for example because the stub does not
+    /// claim to support the feature being checked.  This holds the
+    /// reason.
+    Skipped(&'static str),
+}
+
+/// A single named conformance check and its outcome.
+#[derive(Clone, Debug)]
+pub struct ConformanceCheck {
+    /// A short name for the check.
+    pub name: &'static str,
+    /// The outcome of the check.
+    pub result: CheckResult,
+}
+
+/// The results of `run_conformance`.
+#[derive(Clone, Debug, Default)]
+pub struct ConformanceReport {
+    /// The checks that were performed, in order.
+    pub checks: Vec<ConformanceCheck>,
+}
+
+impl ConformanceReport {
+    /// Return true if no check failed.
+    pub fn passed(&self) -> bool {
+        !self.checks.iter().any(|check| matches!(check.result, CheckResult::Fail(_)))
+    }
+
+    fn add(&mut self, name: &'static str, result: CheckResult) {
+        self.checks.push(ConformanceCheck { name, result });
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            match check.result {
+                CheckResult::Pass => writeln!(f, "PASS: {}", check.name)?,
+                CheckResult::Fail(ref why) => writeln!(f, "FAIL: {}: {}", check.name, why)?,
+                CheckResult::Skipped(why) => writeln!(f, "SKIP: {}: {}", check.name, why)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe(packet: &[u8]) -> String {
+    String::from_utf8_lossy(packet).into_owned()
+}
+
+// Check that every entry of a qSupported reply is well-formed.
+fn check_qsupported(reply: &[u8]) -> CheckResult {
+    for feature in reply.split(|&c| c == b';') {
+        if let Some(eq) = feature.iter().position(|&c| c == b'=') {
+            if eq == 0 {
+                return CheckResult::Fail(format!("malformed feature `{}'", describe(feature)));
+            }
+            if &feature[..eq] == b"PacketSize" {
+                let value = &feature[eq + 1..];
+                if value.is_empty() || !value.iter().all(|c| c.is_ascii_hexdigit()) {
+                    return CheckResult::Fail(format!("invalid PacketSize `{}'",
+                                                     describe(value)));
+                }
+            }
+        } else {
+            match feature.last() {
+                Some(&b'+') | Some(&b'-') | Some(&b'?') if feature.len() > 1 => { }
+                _ => {
+                    return CheckResult::Fail(format!("malformed feature `{}'",
+                                                     describe(feature)));
+                }
+            }
+        }
+    }
+
+    CheckResult::Pass
+}
+
+// Return true if the qSupported reply |reply| has an entry for
+// |name|, either as "name+" or "name=value".
+fn reports_feature(reply: &[u8], name: &[u8]) -> bool {
+    reply.split(|&c| c == b';').any(|feature| {
+        feature.len() > name.len() && feature.starts_with(name)
+            && (feature[name.len()] == b'=' || feature[name.len()..] == b"+"[..])
+    })
+}
+
+// Check a reply to a qXfer read, which must be a data reply starting
+// with "m" or "l".
+fn check_qxfer_read(reply: &[u8]) -> CheckResult {
+    match reply.first() {
+        Some(&b'm') | Some(&b'l') => CheckResult::Pass,
+        _ => CheckResult::Fail(format!("qXfer:features:read+ advertised, but reading \
+                                        target.xml replied `{}'",
+                                       describe(reply))),
+    }
+}
+
+// Check that a reply is an error reply, either "E NN" or "E.message".
+fn check_error_reply(reply: &[u8]) -> CheckResult {
+    if RemoteError::parse(reply).is_some() {
+        CheckResult::Pass
+    } else {
+        CheckResult::Fail(format!("expected an error reply, got `{}'", describe(reply)))
+    }
+}
+
+// Check that a "g" reply is a well-formed register block.  Stubs
+// commonly run-length encode this reply, so this also exercises RLE.
+fn check_registers(reply: &[u8]) -> CheckResult {
+    if reply.first() == Some(&b'E') {
+        return check_error_reply(reply);
+    }
+    if reply.is_empty() || !reply.len().is_multiple_of(2) {
+        return CheckResult::Fail(format!("register block has odd length {}", reply.len()));
+    }
+    if !reply.iter().all(|&c| c.is_ascii_hexdigit() || c == b'x') {
+        return CheckResult::Fail("register block contains non-hex characters".to_string());
+    }
+    CheckResult::Pass
+}
+
+// Expand the run-length encoding in the raw packet contents |raw|,
+// independently of RspConnection.  Returns None if a "*" has no
+// preceding character or no count.
+fn expand_rle(raw: &[u8]) -> Option<Vec<u8>> {
+    let mut result: Vec<u8> = Vec::new();
+    let mut iter = raw.iter();
+    while let Some(&ch) = iter.next() {
+        if ch == b'*' {
+            let prev = *result.last()?;
+            let count = iter.next()?.checked_sub(29)?;
+            result.extend(::std::iter::repeat_n(prev, usize::from(count)));
+        } else {
+            result.push(ch);
+        }
+    }
+    Some(result)
+}
+
+// Send a request with a bad checksum, which the stub must NAK.
+fn check_framing(conn: &mut RspConnection) -> RspResult<CheckResult> {
+    // The checksum of "?" is 3f.
+    conn.write_all(b"$?#00")?;
+    conn.flush()?;
+    conn.set_report_control_characters(true);
+    let reply = conn.read_packet();
+    conn.set_report_control_characters(false);
+    Ok(match reply?.0 {
+        PacketType::Nak => CheckResult::Pass,
+        PacketType::Ack => {
+            // The request was taken, so it will be answered.
+            conn.read_reply()?;
+            CheckResult::Fail("a packet with a bad checksum was acked".to_string())
+        }
+        _ => CheckResult::Fail("a packet with a bad checksum was not NAKed".to_string()),
+    })
+}
+
+// Read the registers both with and without run-length decoding, and
+// check that the stub's encoding decodes to the same block.
+fn check_rle(conn: &mut RspConnection, decoded: &[u8]) -> RspResult<CheckResult> {
+    conn.set_rle_decoding(false);
+    let raw = conn.request(b"g");
+    conn.set_rle_decoding(true);
+    let raw = raw?;
+    if !raw.contains(&b'*') {
+        return Ok(CheckResult::Skipped("the `g' reply was not run-length encoded"));
+    }
+    Ok(match expand_rle(&raw) {
+        Some(ref expanded) if expanded == decoded => CheckResult::Pass,
+        Some(_) => CheckResult::Fail("run-length encoded `g' reply decoded differently"
+                                     .to_string()),
+        None => CheckResult::Fail(format!("invalid run-length encoding in `{}'",
+                                          describe(&raw))),
+    })
+}
+
+/// Run a battery of protocol conformance checks against the stub at
+/// the other end of `conn`, which must be a freshly-opened client
+/// connection in acking mode.
+///
+/// This checks that a packet with a bad checksum is NAKed, the
+/// well-formedness of the `qSupported` reply, that `PacketSize` is
+/// reported (skipped if the stub omits it, as it is optional), that
+/// an advertised `qXfer:features:read+` actually works, that unknown
+/// packets get an empty reply, the stop reply (in strict mode), the
+/// `g` reply, that a run-length encoded `g` reply decodes correctly
+/// (skipped if the stub does not encode it), the error reply form,
+/// and finally the no-ack handshake, if the stub offers it.  Because
+/// of the last check, `conn` may be left with acking disabled.
+///
+/// An I/O error ends the run early; the report will include the
+/// checks done so far plus a failure describing the error.
+pub fn run_conformance(conn: &mut RspConnection) -> ConformanceReport {
+    let mut report = ConformanceReport::default();
+    if let Err(e) = run_checks(conn, &mut report) {
+        report.add("connection", CheckResult::Fail(format!("{:?}", e)));
+    }
+    report
+}
+
+fn run_checks(conn: &mut RspConnection, report: &mut ConformanceReport) -> RspResult<()> {
+    report.add("framing", check_framing(conn)?);
+
+    let supported = conn.request(b"qSupported:multiprocess+;swbreak+;hwbreak+")?;
+    report.add("qSupported", check_qsupported(&supported));
+    report.add("PacketSize", if reports_feature(&supported, b"PacketSize") {
+        CheckResult::Pass
+    } else {
+        CheckResult::Skipped("PacketSize not reported")
+    });
+
+    if reports_feature(&supported, b"qXfer:features:read") {
+        let reply = conn.request(b"qXfer:features:read:target.xml:0,100")?;
+        report.add("qSupported honesty", check_qxfer_read(&reply));
+    } else {
+        report.add("qSupported honesty", CheckResult::Skipped("qXfer:features:read not offered"));
+    }
+
+    let reply = conn.request(b"vGdbRspConformanceUnknownPacket")?;
+    report.add("unknown packet", if reply.is_empty() {
+        CheckResult::Pass
+    } else {
+        CheckResult::Fail(format!("expected an empty reply, got `{}'", describe(&reply)))
+    });
+
+    let reply = conn.request(b"?")?;
+    report.add("stop reply", match parse_stop_reply_with_strictness(&reply, Strictness::Strict) {
+        Ok(_) => CheckResult::Pass,
+        Err(RspError::ProtocolViolation { reason, .. }) => {
+            CheckResult::Fail(format!("`{}': {}", describe(&reply), reason))
+        }
+        Err(e) => return Err(e),
+    });
+
+    let reply = conn.request(b"g")?;
+    report.add("registers", check_registers(&reply));
+    report.add("run-length encoding", check_rle(conn, &reply)?);
+
+    let reply = conn.request(b"Hgp7fffffff.7fffffff")?;
+    report.add("error reply", check_error_reply(&reply));
+
+    let offers_no_ack = supported.split(|&c| c == b';').any(|f| f == b"QStartNoAckMode+");
+    if offers_no_ack {
+        let reply = conn.request(b"QStartNoAckMode")?;
+        if reply == b"OK" {
+            conn.disable_acking();
+            let reply = conn.request(b"?")?;
+            report.add("no-ack mode", if reply.is_empty() {
+                CheckResult::Fail("no reply after disabling acks".to_string())
+            } else {
+                CheckResult::Pass
+            });
+        } else {
+            report.add("no-ack mode",
+                       CheckResult::Fail(format!("QStartNoAckMode replied `{}'",
+                                                 describe(&reply))));
+        }
+    } else {
+        report.add("no-ack mode", CheckResult::Skipped("QStartNoAckMode not offered"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_conformance, CheckResult};
+    use low::RspConnection;
+    use util::frame_packet;
+
+    #[test]
+    fn conformance() {
+        let mut input = b"-".to_vec();
+        for reply in [&b"PacketSize=3fff;QStartNoAckMode+;multiprocess+;qXfer:features:read+"[..],
+                      b"l<target/>",
+                      b"",
+                      b"T05thread:p1.1;",
+                      b"00*'",
+                      b"00*'",
+                      b"E01",
+                      b"OK"].iter() {
+            input.push(b'+');
+            input.extend(frame_packet(reply));
+        }
+        // No acks once QStartNoAckMode has been accepted.
+        input.extend(frame_packet(b"S05"));
+
+        let mut reader: &[u8] = &input;
+        let mut output = Vec::new();
+        let report = {
+            let mut rsp = RspConnection::new(&mut reader, &mut output, true);
+            run_conformance(&mut rsp)
+        };
+
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.checks.len(), 10);
+        assert_eq!(report.checks[0].result, CheckResult::Pass);
+        assert_eq!(report.checks[7].name, "run-length encoding");
+        assert_eq!(report.checks[7].result, CheckResult::Pass);
+        assert_eq!(report.checks[9].result, CheckResult::Pass);
+    }
+
+    #[test]
+    fn failures() {
+        // The bad checksum is acked, and the request answered.
+        let mut input = b"+".to_vec();
+        input.extend(frame_packet(b"S05"));
+        for reply in [&b"multiprocess;qXfer:features:read+"[..], b"E01", b"OK",
+                      b"T05thread:p1.1", b"000", b"0*!", b"OK"].iter() {
+            input.push(b'+');
+            input.extend(frame_packet(reply));
+        }
+
+        let mut reader: &[u8] = &input;
+        let mut output = Vec::new();
+        let report = {
+            let mut rsp = RspConnection::new(&mut reader, &mut output, true);
+            run_conformance(&mut rsp)
+        };
+
+        assert!(!report.passed());
+        let failures: Vec<_> = report.checks.iter()
+            .filter(|check| matches!(check.result, CheckResult::Fail(_)))
+            .map(|check| check.name)
+            .collect();
+        assert_eq!(failures, vec!["framing", "qSupported", "qSupported honesty", "unknown packet",
+                                  "stop reply", "registers", "run-length encoding",
+                                  "error reply"]);
+        // PacketSize is optional, so omitting it is not a failure.
+        assert_eq!(report.checks[2].result, CheckResult::Skipped("PacketSize not reported"));
+    }
+}
@@ -0,0 +1,222 @@
+#![deny(missing_docs)]
+
+use std::io;
+use std::io::Write;
+
+use low::{check_reply, RspConnection, RspError, RspResult};
+use util::{decode_hex, decode_hex_bytes, push_hex, to_io_error};
+
+/// Inferior output, as carried by an `O` packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsoleOutput {
+    /// The output.
+    pub text: Vec<u8>,
+    /// True if the packet was not entirely valid hex.  In this case,
+    /// `text` holds the bytes decoded from the valid prefix, followed
+    /// by the rest of the packet as it was received.
+    pub lossy: bool,
+}
+
+/// Parse an `O` packet sent by a stub.  Some stubs send odd-length or
+/// partly non-hex output, so rather than rejecting such a packet, this
+/// decodes as much as it can and marks the result as lossy.  An error
+/// is only returned if the packet is not an `O` packet at all; note
+/// that `OK` is not.
+pub fn parse_inferior_output(packet: &[u8]) -> RspResult<ConsoleOutput> {
+    if packet.first() != Some(&b'O') || packet == b"OK" {
+        return Err(RspError::ProtocolViolation {
+            packet: packet.to_vec(),
+            reason: "not an O packet",
+        });
+    }
+    let data = &packet[1..];
+    let mut text = Vec::with_capacity(data.len() / 2);
+    let mut pairs = data.chunks(2);
+    let mut lossy = false;
+    for pair in &mut pairs {
+        match decode_hex(pair) {
+            Some(byte) if pair.len() == 2 => text.push(byte as u8),
+            _ => {
+                text.extend_from_slice(pair);
+                lossy = true;
+                break;
+            }
+        }
+    }
+    for pair in pairs {
+        text.extend_from_slice(pair);
+    }
+    Ok(ConsoleOutput { text, lossy })
+}
+
+/// Run `command` on the stub with `qRcmd`, as gdb's `monitor`
+/// command does, for use by a client.  Returns the command's output,
+/// which the stub sends as `O` packets, or `None` if the stub does not
+/// support `qRcmd`.
+pub fn monitor_command(conn: &mut RspConnection, command: &[u8]) -> RspResult<Option<Vec<u8>>> {
+    let mut packet = b"qRcmd,".to_vec();
+    push_hex(&mut packet, command);
+    let mut reply = check_reply(conn.request(&packet)?)?;
+    let mut output = Vec::new();
+    loop {
+        match &reply[..] {
+            b"" => return Ok(None),
+            b"OK" => return Ok(Some(output)),
+            [b'O', ..] => output.extend(parse_inferior_output(&reply)?.text),
+            // Some stubs send the output as a single hex reply.
+            _ => match decode_hex_bytes(&reply) {
+                Some(text) => return Ok(Some(text)),
+                None => {
+                    return Err(RspError::ProtocolViolation {
+                        packet: reply,
+                        reason: "invalid qRcmd reply",
+                    });
+                }
+            },
+        }
+        reply = check_reply(conn.read_reply()?)?;
+    }
+}
+
+/// A writer that sends inferior output to the debugger as `O`
+/// packets.  This is for use by servers, for example to forward
+/// semihosted `printf` output.
+///
+/// Output is buffered; each packet carries as much as fits within
+/// the client's maximum packet size.  Call `flush` to send any
+/// buffered output immediately.  Any remaining output is also sent
+/// when the writer is dropped.
+///
+/// `O` packets may only be sent while the inferior is running, that
+/// is, after a resumption request and before the stop reply.
+pub struct ConsoleWriter<'a, 'conn: 'a> {
+    conn: &'a mut RspConnection<'conn>,
+    buffer: Vec<u8>,
+    // The maximum number of output bytes per packet.
+    chunk_size: usize,
+}
+
+impl<'a, 'conn> ConsoleWriter<'a, 'conn> {
+    /// Create a new `ConsoleWriter` that writes to `conn`.
+    /// `packet_size` is the maximum size of a packet's contents; it
+    /// must be at least 3, enough to hold `O` and one hex-encoded
+    /// byte.
+    pub fn new(conn: &'a mut RspConnection<'conn>, packet_size: usize)
+               -> ConsoleWriter<'a, 'conn> {
+        assert!(packet_size >= 3);
+        ConsoleWriter { conn, buffer: Vec::new(), chunk_size: (packet_size - 1) / 2 }
+    }
+
+    // Send a single O packet.
+    fn send(&mut self, data: &[u8]) -> RspResult<()> {
+        self.conn.start_packet()?;
+        self.conn.write_all(b"O")?;
+        self.conn.write_hex(data)?;
+        self.conn.finish_packet()
+    }
+
+    // Send all buffered output.
+    fn send_buffered(&mut self) -> RspResult<()> {
+        let buffer = ::std::mem::take(&mut self.buffer);
+        for chunk in buffer.chunks(self.chunk_size) {
+            self.send(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'conn> Write for ConsoleWriter<'a, 'conn> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= self.chunk_size {
+            // Send whole packets now, and keep the remainder.
+            let whole = self.buffer.len() - self.buffer.len() % self.chunk_size;
+            let rest = self.buffer.split_off(whole);
+            self.send_buffered().map_err(to_io_error)?;
+            self.buffer = rest;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffered().map_err(to_io_error)
+    }
+}
+
+impl<'a, 'conn> Drop for ConsoleWriter<'a, 'conn> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here; callers who care should
+        // flush explicitly.
+        let _ = self.send_buffered();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::{monitor_command, parse_inferior_output, ConsoleOutput, ConsoleWriter};
+    use low::RspConnection;
+    use util::frame_packet;
+
+    #[test]
+    fn console_writer() {
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            let mut console = ConsoleWriter::new(&mut rsp, 8);
+            console.write_all(b"hello, ").unwrap();
+            console.write_all(b"world").unwrap();
+            console.flush().unwrap();
+            console.write_all(b"!").unwrap();
+        }
+
+        let mut expected = frame_packet(b"O68656c");
+        expected.extend(frame_packet(b"O6c6f2c"));
+        expected.extend(frame_packet(b"O20776f"));
+        expected.extend(frame_packet(b"O726c64"));
+        expected.extend(frame_packet(b"O21"));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn inferior_output() {
+        assert_eq!(parse_inferior_output(b"O68690a").unwrap(),
+                   ConsoleOutput { text: b"hi\n".to_vec(), lossy: false });
+        assert_eq!(parse_inferior_output(b"O4A0A").unwrap(),
+                   ConsoleOutput { text: b"J\n".to_vec(), lossy: false });
+        assert_eq!(parse_inferior_output(b"O6869a").unwrap(),
+                   ConsoleOutput { text: b"hia".to_vec(), lossy: true });
+        assert_eq!(parse_inferior_output(b"O68zz69").unwrap(),
+                   ConsoleOutput { text: b"hzz69".to_vec(), lossy: true });
+        assert_eq!(parse_inferior_output(b"O").unwrap(),
+                   ConsoleOutput { text: Vec::new(), lossy: false });
+        assert!(parse_inferior_output(b"OK").is_err());
+        assert!(parse_inferior_output(b"S05").is_err());
+    }
+
+    #[test]
+    fn monitor() {
+        let mut input = frame_packet(b"O4f70656e204f6e2d");
+        input.extend(frame_packet(b"O43686970"));
+        input.extend(frame_packet(b"OK"));
+        input.extend(frame_packet(b"6869"));
+        input.extend(frame_packet(b""));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(monitor_command(&mut rsp, b"version").unwrap(),
+                       Some(b"Open On-Chip".to_vec()));
+            assert_eq!(monitor_command(&mut rsp, b"hi").unwrap(), Some(b"hi".to_vec()));
+            assert_eq!(monitor_command(&mut rsp, b"hi").unwrap(), None);
+        }
+        let mut expected = frame_packet(b"qRcmd,76657273696f6e");
+        expected.extend(frame_packet(b"qRcmd,6869"));
+        expected.extend(frame_packet(b"qRcmd,6869"));
+        assert_eq!(output, expected);
+    }
+}
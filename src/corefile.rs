@@ -0,0 +1,374 @@
+#![deny(missing_docs)]
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use low::{check_reply, Id, ProcessId, RspConnection, RspError, RspResult};
+use memmap::{read_memory_regions, MemoryRegion, MemoryType};
+use memory::read_memory_stream;
+use register::{read_registers, Endian, RegisterValue};
+use tdesc::{Arch, TargetDescription};
+use thread::{parse_thread_info, with_thread};
+
+// ELF constants.
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+
+// How much memory is read, and written out, at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// The general registers in a Linux elf_gregset_t, which is the
+// register part of an NT_PRSTATUS note, for each architecture that
+// is supported.
+const X86_64_GREGS: [&str; 27] = [
+    "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax", "rcx", "rdx",
+    "rsi", "rdi", "orig_rax", "rip", "cs", "eflags", "rsp", "ss", "fs_base", "gs_base", "ds",
+    "es", "fs", "gs",
+];
+const AARCH64_GREGS: [&str; 34] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30", "sp", "pc", "cpsr",
+];
+const ARM_GREGS: [&str; 18] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc", "cpsr", "orig_r0",
+];
+
+// Return the register layout of an NT_PRSTATUS note for |arch|, and
+// the size of each register in it.
+fn gregset(arch: &Arch) -> Option<(&'static [&'static str], usize)> {
+    match *arch {
+        Arch::X86_64 => Some((&X86_64_GREGS, 8)),
+        Arch::AArch64 => Some((&AARCH64_GREGS, 8)),
+        Arch::Arm => Some((&ARM_GREGS, 4)),
+        _ => None,
+    }
+}
+
+// Return the ELF machine number for |arch|, and whether it uses
+// 64-bit ELF.
+fn machine(arch: &Arch) -> Option<(u16, bool)> {
+    Some(match *arch {
+        Arch::X86 => (3, false),
+        Arch::X86_64 => (62, true),
+        Arch::Arm => (40, false),
+        Arch::AArch64 => (183, true),
+        Arch::RiscV32 => (243, false),
+        Arch::RiscV64 => (243, true),
+        Arch::Mips => (8, false),
+        Arch::Mips64 => (8, true),
+        Arch::PowerPc => (20, false),
+        Arch::PowerPc64 => (21, true),
+        Arch::Other(_) => return None,
+    })
+}
+
+// Append |value| to |buf| as a |size|-byte integer.
+fn put(buf: &mut Vec<u8>, value: u64, size: usize, endian: Endian) {
+    buf.extend_from_slice(RegisterValue::from_u64(value, size, endian).bytes());
+}
+
+// Write |count| zero bytes to |out|.
+fn write_zeros(out: &mut dyn Write, mut count: u64) -> RspResult<()> {
+    let zeros = [0u8; 4096];
+    while count > 0 {
+        let size = ::std::cmp::min(count, zeros.len() as u64) as usize;
+        out.write_all(&zeros[..size])?;
+        count -= size as u64;
+    }
+    Ok(())
+}
+
+// List the threads with qfThreadInfo, or return an empty list if the
+// stub does not support it.
+fn list_threads(conn: &mut RspConnection) -> RspResult<Vec<ProcessId>> {
+    let mut threads = Vec::new();
+    let mut packet: &[u8] = b"qfThreadInfo";
+    loop {
+        let reply = check_reply(conn.request(packet)?)?;
+        if reply.is_empty() {
+            break;
+        }
+        let (list, more) = parse_thread_info(&reply)?;
+        threads.extend(list);
+        if !more {
+            break;
+        }
+        packet = b"qsThreadInfo";
+    }
+    Ok(threads)
+}
+
+/// Writes an ELF core file of a stopped inferior, for use by a client
+/// whose stub cannot write one itself; this is gdb's `gcore` done
+/// over the protocol.  The core file holds a `PT_LOAD` segment for
+/// each memory region, and an `NT_PRSTATUS` note with the registers
+/// of each thread.
+///
+/// Register notes are only written for x86-64, AArch64, and 32-bit
+/// Arm, whose layouts are those of Linux; for other architectures the
+/// core file holds only memory.  Registers are matched by name, and
+/// any that the target description lacks are written as zero.
+pub struct CoreDump<'t> {
+    tdesc: &'t TargetDescription,
+    endian: Endian,
+    packet_size: usize,
+    regions: Option<Vec<MemoryRegion>>,
+    pid: Option<u32>,
+}
+
+impl<'t> CoreDump<'t> {
+    /// Make a new `CoreDump` for a target described by `tdesc`, with
+    /// the byte order `endian`.  `packet_size` is the maximum packet
+    /// size reported by the stub's `qSupported` reply.
+    pub fn new(tdesc: &'t TargetDescription, endian: Endian, packet_size: usize)
+               -> CoreDump<'t> {
+        CoreDump { tdesc, endian, packet_size, regions: None, pid: None }
+    }
+
+    /// Dump `regions` rather than the regions of the stub's memory
+    /// map.  This is needed if the stub has no memory map, or to dump
+    /// less than all of memory.
+    pub fn set_regions(&mut self, regions: Vec<MemoryRegion>) {
+        self.regions = Some(regions);
+    }
+
+    /// Set the id of the process being dumped.  If the stub has no
+    /// memory map, the regions are then read from `/proc/<pid>/maps`,
+    /// as `read_memory_regions` does; this suits gdbserver.
+    pub fn set_pid(&mut self, pid: u32) {
+        self.pid = Some(pid);
+    }
+
+    // Build the NT_PRSTATUS note description for thread |lwp|.
+    fn prstatus(&self, lwp: u32, registers: &[(u32, Option<RegisterValue>)],
+                names: &[&str], size: usize, is_64: bool) -> Vec<u8> {
+        // The offsets of pr_pid and pr_reg in struct elf_prstatus.
+        let (pid_offset, reg_offset) = if is_64 { (32, 112) } else { (24, 72) };
+        let mut desc = vec![0; pid_offset];
+        put(&mut desc, u64::from(lwp), 4, self.endian);
+        desc.resize(reg_offset, 0);
+        let numbered = self.tdesc.registers();
+        for name in names {
+            let regnum = numbered.iter()
+                .find(|&&(_, register)| {
+                    // M-profile cores call the status register xpsr.
+                    register.name() == *name || (*name == "cpsr" && register.name() == "xpsr")
+                })
+                .map(|&(regnum, _)| regnum);
+            let value = registers.iter()
+                .find(|&&(regnum2, _)| Some(regnum2) == regnum)
+                .and_then(|(_, value)| value.as_ref())
+                .and_then(|value| value.to_u64(self.endian));
+            // An unknown syscall number is written as -1, as the
+            // kernel does outside of a system call.
+            let default = if name.starts_with("orig_") { u64::MAX } else { 0 };
+            put(&mut desc, value.unwrap_or(default), size, self.endian);
+        }
+        // pr_fpvalid, and padding in the 64-bit layout.
+        desc.resize(desc.len() + if is_64 { 8 } else { 4 }, 0);
+        desc
+    }
+
+    // Read the registers of each thread, and build the notes section.
+    fn notes(&self, conn: &mut RspConnection, is_64: bool) -> RspResult<Vec<u8>> {
+        let (names, size) = match self.tdesc.arch().as_ref().and_then(gregset) {
+            Some(layout) => layout,
+            None => return Ok(Vec::new()),
+        };
+        let mut threads = list_threads(conn)?.into_iter().map(Some).collect::<Vec<_>>();
+        if threads.is_empty() {
+            // Use whichever thread is selected.
+            threads.push(None);
+        }
+        let mut notes = Vec::new();
+        for thread in threads {
+            let lwp = match thread {
+                Some(thread) => match (thread.pid, thread.tid) {
+                    (_, Id::Id(lwp)) | (Id::Id(lwp), _) => lwp,
+                    _ => 0,
+                },
+                None => 0,
+            };
+            let registers = match thread {
+                Some(thread) => with_thread(conn, thread, |conn| read_registers(conn, self.tdesc))?,
+                None => read_registers(conn, self.tdesc)?,
+            };
+            let desc = self.prstatus(lwp, &registers, names, size, is_64);
+            put(&mut notes, 5, 4, self.endian);
+            put(&mut notes, desc.len() as u64, 4, self.endian);
+            put(&mut notes, u64::from(NT_PRSTATUS), 4, self.endian);
+            notes.extend_from_slice(b"CORE\0\0\0\0");
+            notes.extend_from_slice(&desc);
+            notes.resize(notes.len().next_multiple_of(4), 0);
+        }
+        Ok(notes)
+    }
+
+    /// Write the core file to `out`.  The inferior must be stopped.
+    /// This selects each thread in turn with `Hg`, so the selected
+    /// thread is changed.  Memory that cannot be read, because the
+    /// stub replies with an error, is written as zeros; the ranges
+    /// affected are returned as (address, length).
+    ///
+    /// If no regions were set and none can be found, or if a
+    /// region does not fit in a 32-bit core file, this returns
+    /// `RspError::InvalidArgument`.
+    pub fn write(&self, conn: &mut RspConnection, out: &mut dyn Write)
+                 -> RspResult<Vec<(u64, u64)>> {
+        let regions = match self.regions {
+            Some(ref regions) => regions.clone(),
+            None => read_memory_regions(conn, self.pid, self.packet_size)?
+                .ok_or(RspError::InvalidArgument("no memory map"))?,
+        };
+        let wide = regions.iter().any(|r| r.start.saturating_add(r.length) > 1 << 32);
+        let (machine, is_64) = self.tdesc.arch().as_ref().and_then(machine).unwrap_or((0, wide));
+        if wide && !is_64 {
+            return Err(RspError::InvalidArgument("region does not fit in a 32-bit core file"));
+        }
+        let notes = self.notes(conn, is_64)?;
+
+        let word = if is_64 { 8 } else { 4 };
+        let (header_size, phdr_size) = if is_64 { (64, 56) } else { (52, 32) };
+        let endian = self.endian;
+        let mut header = b"\x7fELF".to_vec();
+        header.push(if is_64 { 2 } else { 1 });
+        header.push(if endian == Endian::Little { 1 } else { 2 });
+        header.push(1);
+        header.resize(16, 0);
+        put(&mut header, u64::from(ET_CORE), 2, endian);
+        put(&mut header, u64::from(machine), 2, endian);
+        put(&mut header, 1, 4, endian);
+        // The entry point, program header offset, and section header
+        // offset.
+        put(&mut header, 0, word, endian);
+        put(&mut header, header_size, word, endian);
+        put(&mut header, 0, word, endian);
+        put(&mut header, 0, 4, endian);
+        put(&mut header, header_size, 2, endian);
+        put(&mut header, phdr_size, 2, endian);
+        put(&mut header, regions.len() as u64 + 1, 2, endian);
+        put(&mut header, if is_64 { 64 } else { 40 }, 2, endian);
+        put(&mut header, 0, 2, endian);
+        put(&mut header, 0, 2, endian);
+
+        // The notes follow the program headers, and the memory follows
+        // the notes.
+        let mut offset = header_size + phdr_size * (regions.len() as u64 + 1);
+        let mut segments = vec![(PT_NOTE, 0, offset, 0, notes.len() as u64)];
+        offset += notes.len() as u64;
+        for region in &regions {
+            let flags = match region.memory_type {
+                MemoryType::Ram => PF_R | PF_W | PF_X,
+                MemoryType::Rom | MemoryType::Flash(_) => PF_R | PF_X,
+            };
+            segments.push((PT_LOAD, flags, offset, region.start, region.length));
+            offset += region.length;
+        }
+        for (kind, flags, file_offset, address, size) in segments {
+            put(&mut header, u64::from(kind), 4, endian);
+            if is_64 {
+                put(&mut header, u64::from(flags), 4, endian);
+            }
+            for value in &[file_offset, address, address, size, size] {
+                put(&mut header, *value, word, endian);
+            }
+            if !is_64 {
+                put(&mut header, u64::from(flags), 4, endian);
+            }
+            put(&mut header, 1, word, endian);
+        }
+        out.write_all(&header)?;
+        out.write_all(&notes)?;
+
+        let mut unreadable = Vec::new();
+        for region in &regions {
+            let end = region.start + region.length;
+            let mut stream = read_memory_stream(conn, region.start, region.length as usize,
+                                                CHUNK_SIZE, self.packet_size)?;
+            loop {
+                let address = stream.address();
+                match stream.next() {
+                    None => break,
+                    Some(Ok(bytes)) => out.write_all(&bytes)?,
+                    Some(Err(RspError::Remote(_))) => {
+                        write_zeros(out, end - address)?;
+                        unreadable.push((address, end - address));
+                        break;
+                    }
+                    Some(Err(err)) => return Err(err),
+                }
+            }
+        }
+        out.flush()?;
+        Ok(unreadable)
+    }
+
+    /// Write the core file to a new file at `path`, as `write` does.
+    pub fn generate_core(&self, conn: &mut RspConnection, path: &Path)
+                         -> RspResult<Vec<(u64, u64)>> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(conn, &mut out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CoreDump;
+    use low::RspConnection;
+    use presets::tdesc_x86_64;
+    use register::Endian;
+    use util::frame_packets;
+
+    // Read a little-endian number of |size| bytes at |offset|.
+    fn get(data: &[u8], offset: usize, size: usize) -> u64 {
+        data[offset..offset + size].iter().rev().fold(0, |value, &b| (value << 8) | u64::from(b))
+    }
+
+    #[test]
+    fn x86_64() {
+        let replies: &[&[u8]] = &[b"l<memory-map><memory type=\"ram\" start=\"0x1000\" length=\"4\"/>\
+                                    <memory type=\"rom\" start=\"0x2000\" length=\"2\"/></memory-map>",
+                                  b"mp1.1", b"l", b"QCp1.1", b"0100000000000000", b"E01", b"abcd"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let tdesc = tdesc_x86_64();
+        let mut core = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let dump = CoreDump::new(&tdesc, Endian::Little, 4096);
+            assert_eq!(dump.write(&mut rsp, &mut core).unwrap(), vec![(0x1000, 4)]);
+        }
+        let sent: &[&[u8]] = &[b"qXfer:memory-map:read::0,fff", b"qfThreadInfo", b"qsThreadInfo",
+                               b"qC", b"g", b"m1000,4", b"m2000,2"];
+        assert_eq!(output, frame_packets(sent));
+
+        assert_eq!(&core[..6], b"\x7fELF\x02\x01");
+        assert_eq!(get(&core, 16, 2), 4);
+        assert_eq!(get(&core, 18, 2), 62);
+        assert_eq!(get(&core, 56, 2), 3);
+        // The note comes right after the three program headers.
+        let note = 64 + 3 * 56;
+        assert_eq!(get(&core, note + 4, 4), 336);
+        assert_eq!(&core[note + 12..note + 17], b"CORE\0");
+        let desc = note + 20;
+        assert_eq!(get(&core, desc + 32, 4), 1);
+        // rax, and orig_rax, which the description lacks.
+        assert_eq!(get(&core, desc + 112 + 10 * 8, 8), 1);
+        assert_eq!(get(&core, desc + 112 + 15 * 8, 8), u64::MAX);
+        // The second load segment starts at the memory's offset.
+        let phdr = 64 + 2 * 56;
+        assert_eq!(get(&core, phdr + 8, 8) as usize, core.len() - 2);
+        assert_eq!(get(&core, phdr + 16, 8), 0x2000);
+        assert_eq!(core[desc + 336..], [0, 0, 0, 0, 0xab, 0xcd]);
+    }
+}
@@ -0,0 +1,268 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use low::{ProcessId, RspConnection, RspError, RspResult};
+use memory::read_memory_stream;
+use qxfer::read_qxfer_object;
+use register::{read_registers, Endian, RegisterValue};
+use stop::{StopEvent, StopReply};
+use tdesc::TargetDescription;
+use thread::with_thread;
+
+// The gdb signal numbers of the signals whose siginfo holds a fault
+// address: SIGILL, SIGFPE, SIGBUS, and SIGSEGV.
+const FAULT_SIGNALS: [u8; 4] = [4, 8, 10, 11];
+
+// The stack is read in pieces of this size, so that an unreadable
+// page at its top does not lose what lies below.
+const STACK_CHUNK: usize = 1024;
+
+// Append |bytes| to |json| as a string of hex digits.
+fn push_hex_string(json: &mut String, bytes: &[u8]) {
+    json.push('"');
+    for byte in bytes {
+        let _ = write!(json, "{:02x}", byte);
+    }
+    json.push('"');
+}
+
+// Append |text| to |json| as a string.
+fn push_string(json: &mut String, text: &str) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+// Append the registers |registers| to |json| as an object mapping
+// names to hex strings.
+fn push_registers(json: &mut String, registers: &BTreeMap<String, Vec<u8>>) {
+    json.push('{');
+    for (i, (name, value)) in registers.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        push_string(json, name);
+        json.push(':');
+        push_hex_string(json, value);
+    }
+    json.push('}');
+}
+
+/// The state of a thread that stopped with a signal, as gathered by
+/// `capture_crash_snapshot`: enough for a tool to triage a crash, or
+/// to file a report, without a debugger attached.  Register values
+/// and memory are raw bytes in target byte order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrashSnapshot {
+    /// The gdb signal number of the stop.
+    pub signal: u8,
+    /// The thread that stopped, if the stop reply said.
+    pub thread: Option<ProcessId>,
+    /// The registers expedited in the stop reply, keyed by name.
+    pub expedited: BTreeMap<String, Vec<u8>>,
+    /// All the registers, as read with `g`, keyed by name.  Registers
+    /// that the stub reported as unavailable are left out.
+    pub registers: BTreeMap<String, Vec<u8>>,
+    /// The raw siginfo, from `qXfer:siginfo:read`, if the stub
+    /// supplies it.
+    pub siginfo: Option<Vec<u8>>,
+    /// The address of `stack`; this is the stack pointer.
+    pub stack_address: Option<u64>,
+    /// The memory starting at the stack pointer.  This is shorter than
+    /// requested if the stub could not read all of it.
+    pub stack: Vec<u8>,
+}
+
+impl CrashSnapshot {
+    /// Return the `si_code` field of the siginfo, which gives the
+    /// cause of the signal, for example `SEGV_MAPERR`.  This assumes
+    /// the Linux layout of `siginfo_t`.
+    pub fn signal_code(&self, endian: Endian) -> Option<i32> {
+        let siginfo = self.siginfo.as_ref()?;
+        let bytes = siginfo.get(8..12)?;
+        RegisterValue::new(bytes.to_vec()).to_u64(endian).map(|code| code as u32 as i32)
+    }
+
+    /// Return the faulting address, the `si_addr` field of the
+    /// siginfo, for a SIGSEGV, SIGBUS, SIGILL, or SIGFPE.
+    /// `pointer_size` is the size of a target pointer in bytes.  This
+    /// assumes the Linux layout of `siginfo_t`.
+    pub fn fault_address(&self, endian: Endian, pointer_size: usize) -> Option<u64> {
+        if !FAULT_SIGNALS.contains(&self.signal) {
+            return None;
+        }
+        let siginfo = self.siginfo.as_ref()?;
+        // The union following the three ints is pointer-aligned.
+        let offset = if pointer_size > 4 { 16 } else { 12 };
+        let bytes = siginfo.get(offset..offset + pointer_size)?;
+        RegisterValue::new(bytes.to_vec()).to_u64(endian)
+    }
+
+    /// Return the snapshot as a JSON object, for storing or sending to
+    /// another program.  Register values, the siginfo, and the stack
+    /// are written as strings of hex digits, in target byte order.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(json, "{{\"signal\":{},\"thread\":", self.signal);
+        match self.thread {
+            Some(thread) => push_string(&mut json, &thread.to_string()),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"expedited\":");
+        push_registers(&mut json, &self.expedited);
+        json.push_str(",\"registers\":");
+        push_registers(&mut json, &self.registers);
+        json.push_str(",\"siginfo\":");
+        match self.siginfo {
+            Some(ref siginfo) => push_hex_string(&mut json, siginfo),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"stack_address\":");
+        match self.stack_address {
+            Some(address) => {
+                let _ = write!(json, "{}", address);
+            }
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"stack\":");
+        push_hex_string(&mut json, &self.stack);
+        json.push('}');
+        json
+    }
+}
+
+// Return the stack pointer of |tdesc|: the register conventionally
+// named for it, or failing that the first data pointer register.
+fn stack_pointer(tdesc: &TargetDescription) -> Option<u32> {
+    let numbered = tdesc.registers();
+    ["sp", "rsp", "esp", "r1"].iter()
+        .find_map(|&name| numbered.iter().find(|&&(_, r)| r.name() == name))
+        .or_else(|| numbered.iter().find(|&&(_, r)| r.reg_type() == "data_ptr"))
+        .map(|&(regnum, _)| regnum)
+}
+
+/// Gather a `CrashSnapshot` after `reply`, for use by a client.  This
+/// selects the thread that stopped with `Hg`, if the reply names one,
+/// then reads its registers, its siginfo, and `stack_size` bytes of
+/// memory at its stack pointer, and finally selects the thread that
+/// was selected before; see `with_thread`.  `tdesc` and `endian` describe the
+/// target, and `packet_size` is the maximum packet size reported by
+/// the stub's `qSupported` reply.
+///
+/// Returns `None` if `reply` is not a signal stop.  Memory that the
+/// stub cannot read ends the stack early rather than failing.
+pub fn capture_crash_snapshot(conn: &mut RspConnection, reply: &StopReply,
+                              tdesc: &TargetDescription, endian: Endian, stack_size: usize,
+                              packet_size: usize) -> RspResult<Option<CrashSnapshot>> {
+    let signal = match *reply {
+        StopReply::Signal { signal, .. } => signal,
+        _ => return Ok(None),
+    };
+    let event = StopEvent::new(reply.clone(), tdesc);
+    let thread = event.thread();
+    let capture = |conn: &mut RspConnection| {
+        let numbered = tdesc.registers();
+        let mut registers = BTreeMap::new();
+        for (regnum, value) in read_registers(conn, tdesc)? {
+            let register = numbered.iter().find(|&&(n, _)| n == regnum);
+            if let (Some(&(_, register)), Some(value)) = (register, value) {
+                registers.insert(register.name().to_string(), value.bytes().to_vec());
+            }
+        }
+        let siginfo = read_qxfer_object(conn, b"siginfo", b"", packet_size)?;
+
+        let stack_address = stack_pointer(tdesc)
+            .and_then(|regnum| numbered.iter().find(|&&(n, _)| n == regnum))
+            .and_then(|&(_, register)| {
+                event.register(register.name()).or_else(|| {
+                    registers.get(register.name()).map(|value| &value[..])
+                })
+            })
+            .and_then(|value| RegisterValue::new(value.to_vec()).to_u64(endian));
+        let mut stack = Vec::new();
+        if let Some(address) = stack_address {
+            let length = ::std::cmp::min(stack_size as u64, 0u64.wrapping_sub(address)) as usize;
+            for chunk in read_memory_stream(conn, address, length, STACK_CHUNK, packet_size)? {
+                match chunk {
+                    Ok(bytes) => stack.extend(bytes),
+                    Err(RspError::Remote(_)) => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(CrashSnapshot {
+            signal,
+            thread,
+            expedited: event.registers,
+            registers,
+            siginfo,
+            stack_address,
+            stack,
+        })
+    };
+    match thread {
+        Some(thread) => with_thread(conn, thread, capture).map(Some),
+        None => capture(conn).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::capture_crash_snapshot;
+    use low::RspConnection;
+    use presets::tdesc_x86_64;
+    use register::Endian;
+    use stop::parse_stop_reply;
+    use util::frame_packets;
+
+    #[test]
+    fn snapshot() {
+        let reply = parse_stop_reply(b"T0b07:0010000000000000;thread:p1.2;").unwrap();
+        let replies: &[&[u8]] = &[b"QCp1.1", b"OK", b"0100000000000000",
+                                  b"l\x0b\0\0\0\0\0\0\0\x01\0\0\0\0\0\0\0\xef\xbe\xad\xde\0\0\0\0",
+                                  b"0102", b"E01", b"OK"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let tdesc = tdesc_x86_64();
+        let snapshot = {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            capture_crash_snapshot(&mut rsp, &reply, &tdesc, Endian::Little, 2048, 4096)
+                .unwrap().unwrap()
+        };
+        let sent: &[&[u8]] = &[b"qC", b"Hgp1.2", b"g", b"qXfer:siginfo:read::0,fff", b"m1000,400",
+                               b"m1002,400", b"Hgp1.1"];
+        assert_eq!(output, frame_packets(sent));
+
+        assert_eq!(snapshot.signal, 11);
+        assert_eq!(snapshot.registers.get("rax"), Some(&vec![1, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(snapshot.stack_address, Some(0x1000));
+        assert_eq!(snapshot.stack, vec![1, 2]);
+        assert_eq!(snapshot.signal_code(Endian::Little), Some(1));
+        assert_eq!(snapshot.fault_address(Endian::Little, 8), Some(0xdeadbeef));
+        assert_eq!(snapshot.to_json(),
+                   "{\"signal\":11,\"thread\":\"p1.2\",\"expedited\":{\"rsp\":\"0010000000000000\"},\
+                    \"registers\":{\"rax\":\"0100000000000000\"},\
+                    \"siginfo\":\"0b000000000000000100000000000000efbeadde00000000\",\
+                    \"stack_address\":4096,\"stack\":\"0102\"}");
+
+        let mut input: &[u8] = &[];
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        let exited = parse_stop_reply(b"W00").unwrap();
+        assert!(capture_crash_snapshot(&mut rsp, &exited, &tdesc, Endian::Little, 16, 4096)
+                .unwrap().is_none());
+    }
+}
@@ -0,0 +1,143 @@
+#![deny(missing_docs)]
+
+use std::time::{Duration, Instant};
+
+use low::{RspConnection, RspError, RspResult};
+
+// The request timed by echo when the stub does not support qEcho.
+const FALLBACK_QUERY: &[u8] = b"qC";
+
+/// Statistics about a connection, kept by each `RspConnection`; see
+/// `RspConnection::stats`.  So far this holds the round-trip times
+/// measured with `echo`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The number of round trips measured.
+    pub round_trips: u32,
+    /// The time of the most recent round trip.
+    pub last_round_trip: Option<Duration>,
+    /// The time of the fastest round trip.
+    pub min_round_trip: Option<Duration>,
+    /// The time of the slowest round trip.
+    pub max_round_trip: Option<Duration>,
+    /// The time of all the round trips added together.
+    pub total_round_trip: Duration,
+}
+
+impl ConnectionStats {
+    /// Record a round trip that took `elapsed`.
+    pub fn record_round_trip(&mut self, elapsed: Duration) {
+        self.round_trips += 1;
+        self.last_round_trip = Some(elapsed);
+        self.min_round_trip = Some(self.min_round_trip.map_or(elapsed, |min| min.min(elapsed)));
+        self.max_round_trip = Some(self.max_round_trip.map_or(elapsed, |max| max.max(elapsed)));
+        self.total_round_trip += elapsed;
+    }
+
+    /// Return the mean round-trip time, or `None` if none has been
+    /// measured.
+    pub fn mean_round_trip(&self) -> Option<Duration> {
+        if self.round_trips == 0 {
+            None
+        } else {
+            Some(self.total_round_trip / self.round_trips)
+        }
+    }
+}
+
+/// Measure the round-trip time to the stub using `qEcho`, which
+/// lldb-server and some other stubs answer by sending back the whole
+/// packet.  This is for use by clients.  The time is returned, and
+/// is also recorded in the connection's `ConnectionStats`.
+///
+/// A stub without `qEcho` sends an empty reply instead, and since
+/// such a stub may answer unknown packets by a quicker path than
+/// real requests, the round trip is then timed again with `qC`, a
+/// query that every stub can answer without side effects.  Whatever
+/// the reply to that, its time is the one returned and recorded.  Any
+/// other reply to `qEcho` results in a `ProtocolViolation`, and is
+/// not recorded.
+///
+/// `payload` is sent as-is, so it should be plain text that does not
+/// need escaping.
+pub fn echo(conn: &mut RspConnection, payload: &[u8]) -> RspResult<Duration> {
+    let mut packet = b"qEcho:".to_vec();
+    packet.extend_from_slice(payload);
+
+    let start = Instant::now();
+    let reply = conn.request(&packet)?;
+    let mut elapsed = start.elapsed();
+
+    if reply.is_empty() {
+        let start = Instant::now();
+        conn.request(FALLBACK_QUERY)?;
+        elapsed = start.elapsed();
+    } else if reply != packet {
+        return Err(RspError::ProtocolViolation {
+            packet: reply,
+            reason: "qEcho reply does not match the request",
+        });
+    }
+    conn.stats_mut().record_round_trip(elapsed);
+    Ok(elapsed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{echo, ConnectionStats};
+    use low::{RspConnection, RspError};
+    use util::{frame_packet, frame_packets};
+
+    fn run(reply: &[u8]) -> Result<u32, RspError> {
+        let input = frame_packet(reply);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let result = {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            echo(&mut rsp, b"ping 1").map(|_| rsp.stats().round_trips)
+        };
+        assert_eq!(output, frame_packet(b"qEcho:ping 1"));
+        result
+    }
+
+    #[test]
+    fn echo_reply() {
+        // lldb-server echoes the whole packet.
+        assert_eq!(run(b"qEcho:ping 1").unwrap(), 1);
+        assert!(run(b"ping 1").is_err());
+        assert!(run(b"qEcho:ping 2").is_err());
+    }
+
+    #[test]
+    fn fallback() {
+        // Without qEcho, qC is timed instead, whatever its reply.
+        for reply in &[&b"QCp1.1"[..], b""] {
+            let input = frame_packets(&[b"", reply]);
+            let mut input: &[u8] = &input;
+            let mut output = Vec::new();
+            {
+                let mut rsp = RspConnection::new(&mut input, &mut output, true);
+                rsp.disable_acking();
+                echo(&mut rsp, b"ping 1").unwrap();
+                assert_eq!(rsp.stats().round_trips, 1);
+            }
+            assert_eq!(output, frame_packets(&[b"qEcho:ping 1", b"qC"]));
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = ConnectionStats::default();
+        assert_eq!(stats.mean_round_trip(), None);
+        stats.record_round_trip(Duration::from_millis(4));
+        stats.record_round_trip(Duration::from_millis(2));
+        assert_eq!(stats.round_trips, 2);
+        assert_eq!(stats.last_round_trip, Some(Duration::from_millis(2)));
+        assert_eq!(stats.min_round_trip, Some(Duration::from_millis(2)));
+        assert_eq!(stats.max_round_trip, Some(Duration::from_millis(4)));
+        assert_eq!(stats.mean_round_trip(), Some(Duration::from_millis(3)));
+    }
+}
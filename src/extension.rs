@@ -0,0 +1,157 @@
+#![deny(missing_docs)]
+
+use low::PacketType;
+use proxy::{Direction, ProxyAction, ProxyHandler};
+
+/// A handler for a custom packet, such as a vendor-specific query or
+/// WebAssembly's `qWasmLocal`.
+pub trait PacketExtension {
+    /// The name of the packet, for example `b"qWasmLocal"`.
+    fn name(&self) -> &[u8];
+
+    /// Handle a request.  `args` is the remainder of the packet after
+    /// the name and its separator.  Returns the contents of the reply.
+    fn handle(&mut self, args: &[u8]) -> Vec<u8>;
+}
+
+/// A registry of `PacketExtension`s.  Incoming requests can be
+/// dispatched to the matching extension using `dispatch`.  The
+/// registry is also a `ProxyHandler`, so it can be used to add
+/// packets to an existing stub by way of a `Proxy`.  Each
+/// `RspConnection` has a registry as well; see
+/// `RspConnection::register_extension`.
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    extensions: Vec<Box<dyn PacketExtension + Send>>,
+}
+
+// If |packet| is a request for the packet named |name|, return the
+// arguments.  The name must be followed by ':', ',', ';', or the end
+// of the packet.
+fn match_name<'a>(packet: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    if !packet.starts_with(name) {
+        return None;
+    }
+    match packet.get(name.len()) {
+        None => Some(&[]),
+        Some(&b':') | Some(&b',') | Some(&b';') => Some(&packet[name.len() + 1..]),
+        Some(_) => None,
+    }
+}
+
+impl ExtensionRegistry {
+    /// Make a new, empty registry.
+    pub fn new() -> ExtensionRegistry {
+        ExtensionRegistry::default()
+    }
+
+    /// Register an extension.  If an extension with the same name was
+    /// already registered, it is replaced.
+    pub fn register(&mut self, extension: Box<dyn PacketExtension + Send>) {
+        self.extensions.retain(|ext| ext.name() != extension.name());
+        self.extensions.push(extension);
+    }
+
+    /// Return true if no extensions are registered.
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Return true if some registered extension handles `packet`.
+    pub fn handles(&self, packet: &[u8]) -> bool {
+        self.extensions.iter().any(|ext| match_name(packet, ext.name()).is_some())
+    }
+
+    /// Dispatch a request to the matching extension, returning the
+    /// reply.  Returns `None` if no extension handles the request.
+    pub fn dispatch(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        for ext in &mut self.extensions {
+            let args = match match_name(packet, ext.name()) {
+                Some(args) => args,
+                None => continue,
+            };
+            return Some(ext.handle(args));
+        }
+        None
+    }
+}
+
+impl ProxyHandler for ExtensionRegistry {
+    fn packet(&mut self, direction: Direction, kind: PacketType, contents: &mut Vec<u8>)
+              -> ProxyAction {
+        if direction != Direction::ToStub || kind != PacketType::Normal {
+            return ProxyAction::Forward;
+        }
+        match self.dispatch(contents) {
+            Some(reply) => ProxyAction::Reply(reply),
+            None => ProxyAction::Forward,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExtensionRegistry, PacketExtension};
+    use low::{PacketType, RspConnection};
+    use proxy::{Direction, ProxyAction, ProxyHandler};
+    use util::frame_packets;
+
+    struct WasmLocal;
+
+    impl PacketExtension for WasmLocal {
+        fn name(&self) -> &[u8] {
+            b"qWasmLocal"
+        }
+
+        fn handle(&mut self, args: &[u8]) -> Vec<u8> {
+            let mut reply = b"local ".to_vec();
+            reply.extend_from_slice(args);
+            reply
+        }
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut registry = ExtensionRegistry::new();
+        registry.register(Box::new(WasmLocal));
+
+        assert!(registry.handles(b"qWasmLocal:0;1"));
+        assert!(!registry.handles(b"qWasmLocals"));
+        assert_eq!(registry.dispatch(b"qWasmLocal:0;1"), Some(b"local 0;1".to_vec()));
+        assert_eq!(registry.dispatch(b"qWasmGlobal:0;1"), None);
+
+        let action = registry.packet(Direction::ToStub, PacketType::Normal,
+                                     &mut b"qWasmLocal".to_vec());
+        assert_eq!(action, ProxyAction::Reply(b"local ".to_vec()));
+        let action = registry.packet(Direction::ToStub, PacketType::Normal, &mut b"g".to_vec());
+        assert_eq!(action, ProxyAction::Forward);
+    }
+
+    #[test]
+    fn connection() {
+        // On a client, the extension answers without sending anything.
+        let input = frame_packets(&[b"OK"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            rsp.register_extension(Box::new(WasmLocal));
+            assert_eq!(rsp.request(b"qWasmLocal:0;1").unwrap(), b"local 0;1".to_vec());
+            assert_eq!(rsp.request(b"QNonStop:0").unwrap(), b"OK".to_vec());
+        }
+        assert_eq!(output, frame_packets(&[b"QNonStop:0"]));
+
+        // On a server, the extension's reply is sent to the client.
+        let input = frame_packets(&[b"qWasmLocal:2;3", b"?"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            rsp.register_extension(Box::new(WasmLocal));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"?".to_vec()));
+        }
+        assert_eq!(output, frame_packets(&[b"local 2;3"]));
+    }
+}
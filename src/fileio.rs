@@ -0,0 +1,619 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use hostio::{file_io_errno, FILE_IO_O_APPEND, FILE_IO_O_CREAT, FILE_IO_O_EXCL, FILE_IO_O_RDWR,
+             FILE_IO_O_TRUNC, FILE_IO_O_WRONLY};
+use low::{RspConnection, RspError, RspResult};
+use memory::{read_memory, write_memory};
+use util::{local_path, parse_hex_number, parse_signed_hex, write_hex_number};
+
+/// A string or buffer in the inferior's memory, as passed to a
+/// File-I/O call: its address and its length.  For strings, the
+/// length includes the trailing NUL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetBuffer {
+    /// The address of the buffer.
+    pub address: u64,
+    /// The length of the buffer.
+    pub length: u64,
+}
+
+/// A File-I/O request, which a stub sends in place of a stop reply to
+/// ask the debugger to perform a system call on the inferior's
+/// behalf.  Pointers refer to the inferior's memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileIoRequest {
+    /// `Fopen,pathptr/len,flags,mode`.
+    Open {
+        /// The file name.
+        path: TargetBuffer,
+        /// The `FILE_IO_O_*` flags.
+        flags: u32,
+        /// The mode to create the file with.
+        mode: u32,
+    },
+    /// `Fclose,fd`.
+    Close {
+        /// The file descriptor.
+        fd: i64,
+    },
+    /// `Fread,fd,bufptr,count`.
+    Read {
+        /// The file descriptor.
+        fd: i64,
+        /// Where to store the data that is read.
+        buffer: u64,
+        /// The maximum number of bytes to read.
+        count: u64,
+    },
+    /// `Fwrite,fd,bufptr,count`.
+    Write {
+        /// The file descriptor.
+        fd: i64,
+        /// The data to write.
+        buffer: u64,
+        /// The number of bytes to write.
+        count: u64,
+    },
+    /// `Flseek,fd,offset,flag`.
+    Lseek {
+        /// The file descriptor.
+        fd: i64,
+        /// The offset, relative to `whence`.
+        offset: i64,
+        /// 0 for `SEEK_SET`, 1 for `SEEK_CUR`, or 2 for `SEEK_END`.
+        whence: u32,
+    },
+    /// `Frename,oldpathptr/len,newpathptr/len`.
+    Rename {
+        /// The existing file name.
+        old: TargetBuffer,
+        /// The new file name.
+        new: TargetBuffer,
+    },
+    /// `Funlink,pathptr/len`.
+    Unlink {
+        /// The file name.
+        path: TargetBuffer,
+    },
+    /// `Fstat,pathptr/len,bufptr`.
+    Stat {
+        /// The file name.
+        path: TargetBuffer,
+        /// Where to store the `struct stat`.
+        buffer: u64,
+    },
+    /// `Ffstat,fd,bufptr`.
+    Fstat {
+        /// The file descriptor.
+        fd: i64,
+        /// Where to store the `struct stat`.
+        buffer: u64,
+    },
+    /// `Fgettimeofday,tvptr,tzptr`.
+    Gettimeofday {
+        /// Where to store the `struct timeval`.
+        tv: u64,
+        /// The time zone pointer, which is unused.
+        tz: u64,
+    },
+    /// `Fisatty,fd`.
+    Isatty {
+        /// The file descriptor.
+        fd: i64,
+    },
+    /// `Fsystem,commandptr/len`.
+    System {
+        /// The command to run.
+        command: TargetBuffer,
+    },
+}
+
+// Parse a "ptr/len" argument.
+fn parse_target_buffer(text: &[u8]) -> Option<TargetBuffer> {
+    let slash = text.iter().position(|&c| c == b'/')?;
+    Some(TargetBuffer {
+        address: parse_hex_number(&text[..slash])?,
+        length: parse_hex_number(&text[slash + 1..])?,
+    })
+}
+
+fn parse_file_io_request_inner(packet: &[u8]) -> Result<FileIoRequest, &'static str> {
+    if packet.first() != Some(&b'F') {
+        return Err("not a File-I/O request");
+    }
+    let mut fields = packet[1..].split(|&c| c == b',');
+    let name = fields.next().unwrap_or(b"");
+    let args: Vec<&[u8]> = fields.collect();
+
+    let buffer = |i: usize| args.get(i).and_then(|a| parse_target_buffer(a))
+        .ok_or("invalid pointer argument");
+    let number = |i: usize| args.get(i).and_then(|a| parse_hex_number(a))
+        .ok_or("invalid numeric argument");
+    let signed = |i: usize| args.get(i).and_then(|a| parse_signed_hex(a))
+        .ok_or("invalid numeric argument");
+
+    let (request, count) = match name {
+        b"open" => (FileIoRequest::Open {
+            path: buffer(0)?,
+            flags: number(1)? as u32,
+            mode: number(2)? as u32,
+        }, 3),
+        b"close" => (FileIoRequest::Close { fd: signed(0)? }, 1),
+        b"read" => (FileIoRequest::Read { fd: signed(0)?, buffer: number(1)?, count: number(2)? }, 3),
+        b"write" => (FileIoRequest::Write { fd: signed(0)?, buffer: number(1)?, count: number(2)? }, 3),
+        b"lseek" => (FileIoRequest::Lseek {
+            fd: signed(0)?,
+            offset: signed(1)?,
+            whence: number(2)? as u32,
+        }, 3),
+        b"rename" => (FileIoRequest::Rename { old: buffer(0)?, new: buffer(1)? }, 2),
+        b"unlink" => (FileIoRequest::Unlink { path: buffer(0)? }, 1),
+        b"stat" => (FileIoRequest::Stat { path: buffer(0)?, buffer: number(1)? }, 2),
+        b"fstat" => (FileIoRequest::Fstat { fd: signed(0)?, buffer: number(1)? }, 2),
+        b"gettimeofday" => (FileIoRequest::Gettimeofday { tv: number(0)?, tz: number(1)? }, 2),
+        b"isatty" => (FileIoRequest::Isatty { fd: signed(0)? }, 1),
+        b"system" => (FileIoRequest::System { command: buffer(0)? }, 1),
+        _ => return Err("unknown File-I/O call"),
+    };
+    if args.len() != count {
+        return Err("wrong number of arguments");
+    }
+    Ok(request)
+}
+
+/// Parse a File-I/O request, for use by a client.  Returns
+/// `ProtocolViolation` if the packet is malformed or names an
+/// unknown call.
+pub fn parse_file_io_request(packet: &[u8]) -> RspResult<FileIoRequest> {
+    parse_file_io_request_inner(packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+// The default limit on the size of a single transfer; see
+// FileIoHandler::max_transfer.
+const DEFAULT_MAX_TRANSFER: usize = 0x10000;
+
+fn unsupported<T>() -> io::Result<T> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The host side of File-I/O: the system calls a client performs for
+/// the inferior, as dispatched by `handle_file_io_request`.  Errors
+/// are reported to the stub as File-I/O errno values, using
+/// `file_io_errno`.  The less common calls default to failing with
+/// `ENOSYS`.  `stat`, `fstat`, and `gettimeofday` are always refused
+/// this way.
+pub trait FileIoHandler {
+    /// Open `path`, returning the new file descriptor.  `flags` are
+    /// the `FILE_IO_O_*` flags.
+    fn open(&mut self, path: &[u8], flags: u32, mode: u32) -> io::Result<i64>;
+
+    /// Close `fd`.
+    fn close(&mut self, fd: i64) -> io::Result<()>;
+
+    /// Read at most `count` bytes from `fd`.
+    fn read(&mut self, fd: i64, count: usize) -> io::Result<Vec<u8>>;
+
+    /// Write `data` to `fd`, returning the number of bytes written.
+    fn write(&mut self, fd: i64, data: &[u8]) -> io::Result<usize>;
+
+    /// Seek on `fd`, returning the new offset.
+    fn lseek(&mut self, fd: i64, offset: i64, whence: u32) -> io::Result<u64>;
+
+    /// Rename `old` to `new`.
+    fn rename(&mut self, _old: &[u8], _new: &[u8]) -> io::Result<()> {
+        unsupported()
+    }
+
+    /// Remove `path`.
+    fn unlink(&mut self, _path: &[u8]) -> io::Result<()> {
+        unsupported()
+    }
+
+    /// Return true if `fd` refers to the debugger's console.
+    fn isatty(&mut self, _fd: i64) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Run `command`, returning its exit status.  An empty command
+    /// asks whether commands can be run at all.
+    fn system(&mut self, _command: &[u8]) -> io::Result<i64> {
+        unsupported()
+    }
+
+    /// Return the largest number of bytes that a single read or
+    /// write transfers, which also limits the length of a file name
+    /// or command read from the inferior.  Longer reads and writes
+    /// are shortened before the inferior's memory is touched, which
+    /// the inferior sees as a short read or write; longer strings are
+    /// refused with `ENAMETOOLONG`.  The default is 64KiB.
+    fn max_transfer(&self) -> usize {
+        DEFAULT_MAX_TRANSFER
+    }
+}
+
+// Read the NUL-terminated string |buffer| from the inferior.  A
+// string longer than |limit| is refused without reading it.
+fn read_string(conn: &mut RspConnection, buffer: TargetBuffer, packet_size: usize, limit: usize)
+               -> RspResult<io::Result<Vec<u8>>> {
+    if buffer.length > limit as u64 {
+        return Ok(Err(io::ErrorKind::InvalidFilename.into()));
+    }
+    let mut result = read_memory(conn, buffer.address, buffer.length as usize, packet_size)?;
+    if result.last() == Some(&0) {
+        result.pop();
+    }
+    Ok(Ok(result))
+}
+
+// Perform |request|, returning the result to send to the stub.
+// Errors from the connection itself are returned in the outer result.
+fn perform(conn: &mut RspConnection, handler: &mut dyn FileIoHandler, request: &FileIoRequest,
+           packet_size: usize) -> RspResult<io::Result<i64>> {
+    let limit = handler.max_transfer();
+    Ok(match *request {
+        FileIoRequest::Open { path, flags, mode } => {
+            read_string(conn, path, packet_size, limit)?
+                .and_then(|path| handler.open(&path, flags, mode))
+        }
+        FileIoRequest::Close { fd } => handler.close(fd).map(|()| 0),
+        FileIoRequest::Read { fd, buffer, count } => {
+            let count = ::std::cmp::min(count, limit as u64) as usize;
+            match handler.read(fd, count) {
+                Ok(data) => {
+                    let data = &data[..::std::cmp::min(data.len(), count)];
+                    write_memory(conn, buffer, data, packet_size)?;
+                    Ok(data.len() as i64)
+                }
+                Err(err) => Err(err),
+            }
+        }
+        FileIoRequest::Write { fd, buffer, count } => {
+            let count = ::std::cmp::min(count, limit as u64) as usize;
+            let data = read_memory(conn, buffer, count, packet_size)?;
+            handler.write(fd, &data).map(|n| n as i64)
+        }
+        FileIoRequest::Lseek { fd, offset, whence } => {
+            handler.lseek(fd, offset, whence).map(|offset| offset as i64)
+        }
+        FileIoRequest::Rename { old, new } => {
+            let old = read_string(conn, old, packet_size, limit)?;
+            let new = read_string(conn, new, packet_size, limit)?;
+            old.and_then(|old| new.and_then(|new| handler.rename(&old, &new)))
+                .map(|()| 0)
+        }
+        FileIoRequest::Unlink { path } => {
+            read_string(conn, path, packet_size, limit)?
+                .and_then(|path| handler.unlink(&path))
+                .map(|()| 0)
+        }
+        FileIoRequest::Isatty { fd } => handler.isatty(fd).map(i64::from),
+        FileIoRequest::System { command } => {
+            read_string(conn, command, packet_size, limit)?
+                .and_then(|command| handler.system(&command))
+        }
+        FileIoRequest::Stat { .. } | FileIoRequest::Fstat { .. }
+            | FileIoRequest::Gettimeofday { .. } => unsupported(),
+    })
+}
+
+// The File-I/O errno value for an interrupted call.
+const EINTR: u64 = 4;
+
+/// Build the reply to a File-I/O request: `F` followed by the result
+/// or, on error, `F-1,errno`.  If `interrupted` is true, the Ctrl-C
+/// flag is added, telling the stub to stop with `SIGINT` once the
+/// call is finished; an error is then reported as `EINTR`, meaning
+/// that the call was not performed.
+pub fn file_io_reply(result: &io::Result<i64>, interrupted: bool) -> Vec<u8> {
+    let mut reply = b"F".to_vec();
+    match *result {
+        Ok(value) => {
+            if value < 0 {
+                reply.push(b'-');
+            }
+            write_hex_number(&mut reply, value.unsigned_abs());
+        }
+        Err(ref err) => {
+            reply.extend_from_slice(b"-1,");
+            let errno = if interrupted { EINTR } else { file_io_errno(err.kind()) };
+            write_hex_number(&mut reply, errno);
+        }
+    }
+    if interrupted {
+        if result.is_ok() {
+            reply.extend_from_slice(b",0");
+        }
+        reply.extend_from_slice(b",C");
+    }
+    reply
+}
+
+/// Service the File-I/O request `packet` with `handler`, for use by a
+/// client.  The inferior's memory is read and written as needed with
+/// `m` and `M` packets, whose size is limited by `packet_size`, and
+/// then the result is sent to the stub.  The stub replies with
+/// another File-I/O request or with a stop reply, which the caller
+/// should read.
+///
+/// While a File-I/O request is pending, the stub is waiting for the
+/// reply rather than running, so a user's interrupt must not be sent
+/// with `RspConnection::interrupt`.  Instead, the interrupt handler
+/// should set `interrupt`.  If it is already set, the call is not
+/// performed and the stub is told it was interrupted; if it is set
+/// while the call runs, the call's result is sent along with the
+/// Ctrl-C flag.  Either way, `interrupt` is cleared and the stub
+/// then reports a stop.
+pub fn handle_file_io_request(conn: &mut RspConnection, handler: &mut dyn FileIoHandler,
+                              packet: &[u8], packet_size: usize, interrupt: &AtomicBool)
+                              -> RspResult<()> {
+    let request = parse_file_io_request(packet)?;
+    if interrupt.swap(false, Ordering::SeqCst) {
+        return conn.full_packet(&file_io_reply(&Err(io::ErrorKind::Interrupted.into()), true));
+    }
+    let result = perform(conn, handler, &request, packet_size)?;
+    let interrupted = interrupt.swap(false, Ordering::SeqCst);
+    conn.full_packet(&file_io_reply(&result, interrupted))
+}
+
+// The first descriptor handed out by LocalFileIo; 0, 1, and 2 are
+// the console.
+const FIRST_FD: i64 = 3;
+
+/// A `FileIoHandler` that uses the local filesystem, for
+/// semihosting-style programs that just want to open, read, and write
+/// files.  Paths are confined to a root directory: absolute paths are
+/// taken to be relative to it, and paths that would leave it are
+/// refused.  Descriptors 0, 1, and 2 are this process's standard
+/// input, output, and error.  `system` is not supported.
+pub struct LocalFileIo {
+    root: PathBuf,
+    max_transfer: usize,
+    max_file_size: Option<u64>,
+    files: BTreeMap<i64, fs::File>,
+}
+
+impl LocalFileIo {
+    /// Make a new `LocalFileIo` whose files are under `root`.
+    pub fn new(root: PathBuf) -> LocalFileIo {
+        LocalFileIo {
+            root,
+            max_transfer: DEFAULT_MAX_TRANSFER,
+            max_file_size: None,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Set the largest number of bytes a single read or write will
+    /// transfer, and the longest file name that will be read.  Longer
+    /// requests are shortened, which the inferior sees as a short read
+    /// or write.  The default is 64KiB.
+    pub fn set_max_transfer(&mut self, max: usize) {
+        self.max_transfer = ::std::cmp::max(max, 1);
+    }
+
+    /// Set the largest size a file may grow to through writes, or
+    /// `None`, the default, for no limit.  A write that would exceed
+    /// the limit fails with `EFBIG`.
+    pub fn set_max_file_size(&mut self, max: Option<u64>) {
+        self.max_file_size = max;
+    }
+
+    // Return the local path for the inferior's |path|.
+    fn path(&self, path: &[u8]) -> io::Result<PathBuf> {
+        local_path(&self.root, path).ok_or_else(|| io::ErrorKind::PermissionDenied.into())
+    }
+
+    // Return the open file |fd|.
+    fn file(&mut self, fd: i64) -> io::Result<&mut fs::File> {
+        self.files.get_mut(&fd).ok_or_else(|| io::ErrorKind::InvalidInput.into())
+    }
+}
+
+impl FileIoHandler for LocalFileIo {
+    fn open(&mut self, path: &[u8], flags: u32, mode: u32) -> io::Result<i64> {
+        let path = self.path(path)?;
+        let access = flags & (FILE_IO_O_WRONLY | FILE_IO_O_RDWR);
+        let create = flags & FILE_IO_O_CREAT != 0;
+        let exclusive = flags & FILE_IO_O_EXCL != 0;
+        let mut options = fs::OpenOptions::new();
+        options.read(access != FILE_IO_O_WRONLY)
+            .write(access != 0)
+            .append(flags & FILE_IO_O_APPEND != 0)
+            .truncate(flags & FILE_IO_O_TRUNC != 0)
+            .create(create && !exclusive)
+            .create_new(create && exclusive);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode & 0o777);
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        let file = options.open(path)?;
+
+        let fd = (FIRST_FD..).find(|fd| !self.files.contains_key(fd)).unwrap();
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    fn close(&mut self, fd: i64) -> io::Result<()> {
+        self.files.remove(&fd).map(|_| ()).ok_or_else(|| io::ErrorKind::InvalidInput.into())
+    }
+
+    fn read(&mut self, fd: i64, count: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0; ::std::cmp::min(count, self.max_transfer)];
+        let n = if fd == 0 {
+            io::stdin().read(&mut buffer)?
+        } else {
+            self.file(fd)?.read(&mut buffer)?
+        };
+        buffer.truncate(n);
+        Ok(buffer)
+    }
+
+    fn write(&mut self, fd: i64, data: &[u8]) -> io::Result<usize> {
+        let data = &data[..::std::cmp::min(data.len(), self.max_transfer)];
+        match fd {
+            1 => return io::stdout().write(data),
+            2 => return io::stderr().write(data),
+            _ => { }
+        }
+        let max_file_size = self.max_file_size;
+        let file = self.file(fd)?;
+        if let Some(max) = max_file_size {
+            let position = file.stream_position()?;
+            if position + data.len() as u64 > max {
+                return Err(io::ErrorKind::FileTooLarge.into());
+            }
+        }
+        file.write(data)
+    }
+
+    fn lseek(&mut self, fd: i64, offset: i64, whence: u32) -> io::Result<u64> {
+        let position = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        };
+        if fd < FIRST_FD {
+            return Err(io::ErrorKind::NotSeekable.into());
+        }
+        self.file(fd)?.seek(position)
+    }
+
+    fn rename(&mut self, old: &[u8], new: &[u8]) -> io::Result<()> {
+        fs::rename(self.path(old)?, self.path(new)?)
+    }
+
+    fn unlink(&mut self, path: &[u8]) -> io::Result<()> {
+        fs::remove_file(self.path(path)?)
+    }
+
+    fn isatty(&mut self, fd: i64) -> io::Result<bool> {
+        if fd < FIRST_FD {
+            Ok(true)
+        } else {
+            self.file(fd).map(|_| false)
+        }
+    }
+
+    fn max_transfer(&self) -> usize {
+        self.max_transfer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::{file_io_reply, handle_file_io_request, parse_file_io_request, FileIoRequest,
+                LocalFileIo, TargetBuffer};
+    use low::RspConnection;
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_file_io_request(b"Fopen,1000/6,201,1a4").unwrap(),
+                   FileIoRequest::Open {
+                       path: TargetBuffer { address: 0x1000, length: 6 },
+                       flags: 0x201,
+                       mode: 0x1a4,
+                   });
+        assert_eq!(parse_file_io_request(b"Flseek,3,-10,2").unwrap(),
+                   FileIoRequest::Lseek { fd: 3, offset: -0x10, whence: 2 });
+        assert_eq!(parse_file_io_request(b"Fwrite,A,1F00,FF").unwrap(),
+                   FileIoRequest::Write { fd: 10, buffer: 0x1f00, count: 0xff });
+        assert!(parse_file_io_request(b"Fopen,1000,201,1a4").is_err());
+        assert!(parse_file_io_request(b"Fclose,3,4").is_err());
+        assert!(parse_file_io_request(b"Fioctl,3,4").is_err());
+
+        assert_eq!(file_io_reply(&Ok(0x10), false), b"F10".to_vec());
+        let not_found = Err(io::ErrorKind::NotFound.into());
+        assert_eq!(file_io_reply(&not_found, false), b"F-1,2".to_vec());
+        assert_eq!(file_io_reply(&Ok(0x10), true), b"F10,0,C".to_vec());
+        assert_eq!(file_io_reply(&not_found, true), b"F-1,4,C".to_vec());
+    }
+
+    #[test]
+    fn local_file_io() {
+        let root = env::temp_dir().join(format!("gdb-rsp-fileio-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut handler = LocalFileIo::new(root.clone());
+        handler.set_max_file_size(Some(4));
+
+        let mut input = frame_packet(b"612e74787400");
+        input.extend(frame_packet(b"68656c6c6f"));
+        input.extend(frame_packet(b"2e2e2f7800"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let requests: &[&[u8]] = &[b"Fopen,1000/6,201,1a4", b"Fwrite,3,2000,5",
+                                       b"Fwrite,3,2000,0", b"Fclose,3", b"Funlink,1000/5",
+                                       b"Fstat,1000/6,3000"];
+            let interrupt = AtomicBool::new(false);
+            for request in requests {
+                handle_file_io_request(&mut rsp, &mut handler, request, 0x100, &interrupt)
+                    .unwrap();
+            }
+
+            // An interrupt before the call means it is not performed.
+            interrupt.store(true, Ordering::SeqCst);
+            handle_file_io_request(&mut rsp, &mut handler, b"Fclose,5", 0x100, &interrupt)
+                .unwrap();
+            assert!(!interrupt.load(Ordering::SeqCst));
+        }
+        assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"".to_vec());
+        fs::remove_dir_all(&root).unwrap();
+
+        let sent: &[&[u8]] = &[b"m1000,6", b"F3", b"m2000,5", b"F-1,1b", b"F0", b"F0",
+                               b"m1000,5", b"F-1,d", b"F-1,58", b"F-1,4,C"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn transfer_limit() {
+        let root = env::temp_dir().join(format!("gdb-rsp-fileio-limit-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut handler = LocalFileIo::new(root.clone());
+        handler.set_max_transfer(2);
+
+        let mut input = frame_packet(b"6100");
+        input.extend(frame_packet(b"6869"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let interrupt = AtomicBool::new(false);
+            // The second file name is too long to be read at all.
+            let requests: &[&[u8]] = &[b"Fopen,1000/2,201,1a4", b"Fopen,1000/6,201,1a4",
+                                       b"Fwrite,3,2000,ffffffff"];
+            for request in requests {
+                handle_file_io_request(&mut rsp, &mut handler, request, 0x100, &interrupt)
+                    .unwrap();
+            }
+        }
+        // Only as much of the buffer as will be written is read.
+        assert_eq!(fs::read(root.join("a")).unwrap(), b"hi".to_vec());
+        fs::remove_dir_all(&root).unwrap();
+
+        let sent: &[&[u8]] = &[b"m1000,2", b"F3", b"F-1,5b", b"m2000,2", b"F2"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
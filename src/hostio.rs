@@ -0,0 +1,386 @@
+#![deny(missing_docs)]
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use low::{RspConnection, RspError, RspResult};
+use util::{parse_hex_number, parse_signed_hex, push_hex, to_io_error, unescape_binary,
+           write_hex_number};
+
+/// Open for reading only; a flag for `RemoteFile::open`.
+pub const FILE_IO_O_RDONLY: u32 = 0x0;
+/// Open for writing only.
+pub const FILE_IO_O_WRONLY: u32 = 0x1;
+/// Open for reading and writing.
+pub const FILE_IO_O_RDWR: u32 = 0x2;
+/// Append to the file.
+pub const FILE_IO_O_APPEND: u32 = 0x8;
+/// Create the file if it does not exist.
+pub const FILE_IO_O_CREAT: u32 = 0x200;
+/// Truncate the file.
+pub const FILE_IO_O_TRUNC: u32 = 0x400;
+/// With `FILE_IO_O_CREAT`, fail if the file exists.
+pub const FILE_IO_O_EXCL: u32 = 0x800;
+
+/// A reply to a host I/O (`vFile`) request, of the form
+/// `F result[,errno][;attachment]`.  This is also the form of a
+/// debugger's reply to a File-I/O request, which may add a `,C` flag
+/// after the errno.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileReply {
+    /// The result of the operation; -1 indicates an error.
+    pub result: i64,
+    /// The File-I/O errno value, which is only sent on error.
+    pub errno: Option<u64>,
+    /// The unescaped binary attachment, sent by requests such as
+    /// `vFile:pread` and `vFile:readlink`.
+    pub attachment: Option<Vec<u8>>,
+    /// True if the reply to a File-I/O request carried the Ctrl-C
+    /// flag.  The stub must then report a stop for `SIGINT` once the
+    /// call is finished.  If `errno` is `EINTR`, the call was not
+    /// performed at all.
+    pub interrupted: bool,
+}
+
+// The errno values defined by the File-I/O protocol, paired with the
+// closest io::ErrorKind.  Where several values share a kind, the
+// first one is used when mapping back.
+const ERRNO_KINDS: &[(u64, io::ErrorKind)] = &[
+    (2, io::ErrorKind::NotFound),            // ENOENT
+    (13, io::ErrorKind::PermissionDenied),   // EACCES
+    (1, io::ErrorKind::PermissionDenied),    // EPERM
+    (4, io::ErrorKind::Interrupted),         // EINTR
+    (22, io::ErrorKind::InvalidInput),       // EINVAL
+    (9, io::ErrorKind::InvalidInput),        // EBADF
+    (14, io::ErrorKind::InvalidInput),       // EFAULT
+    (16, io::ErrorKind::ResourceBusy),       // EBUSY
+    (17, io::ErrorKind::AlreadyExists),      // EEXIST
+    (19, io::ErrorKind::NotFound),           // ENODEV
+    (20, io::ErrorKind::NotADirectory),      // ENOTDIR
+    (21, io::ErrorKind::IsADirectory),       // EISDIR
+    (27, io::ErrorKind::FileTooLarge),       // EFBIG
+    (28, io::ErrorKind::StorageFull),        // ENOSPC
+    (29, io::ErrorKind::NotSeekable),        // ESPIPE
+    (30, io::ErrorKind::ReadOnlyFilesystem), // EROFS
+    (91, io::ErrorKind::InvalidFilename),    // ENAMETOOLONG
+    (88, io::ErrorKind::Unsupported),        // ENOSYS
+];
+
+/// The File-I/O errno value used for errors that have no better
+/// value.
+pub const FILE_IO_EUNKNOWN: u64 = 9999;
+
+/// Map a File-I/O errno value, as sent in a host I/O or `F` reply, to
+/// the closest `io::ErrorKind`.  Unknown values map to `Other`.
+pub fn file_io_error_kind(errno: u64) -> io::ErrorKind {
+    ERRNO_KINDS.iter().find(|&&(value, _)| value == errno)
+        .map_or(io::ErrorKind::Other, |&(_, kind)| kind)
+}
+
+/// Map an `io::ErrorKind` to a File-I/O errno value, for servers
+/// replying to host I/O requests.  Kinds with no counterpart map to
+/// `FILE_IO_EUNKNOWN`.
+pub fn file_io_errno(kind: io::ErrorKind) -> u64 {
+    ERRNO_KINDS.iter().find(|&&(_, k)| k == kind)
+        .map_or(FILE_IO_EUNKNOWN, |&(value, _)| value)
+}
+
+impl FileReply {
+    /// Convert this reply to an `io::Result`, mapping a failed
+    /// result to an `io::Error` using `file_io_error_kind`.
+    pub fn io_result(&self) -> io::Result<i64> {
+        if self.result != -1 {
+            return Ok(self.result);
+        }
+        let errno = self.errno.unwrap_or(FILE_IO_EUNKNOWN);
+        Err(io::Error::new(file_io_error_kind(errno), format!("remote errno {}", errno)))
+    }
+}
+
+fn parse_file_reply_inner(packet: &[u8]) -> Result<FileReply, &'static str> {
+    if packet.first() != Some(&b'F') {
+        return Err("not an F reply");
+    }
+    // The attachment may contain anything, so only look for the
+    // first ';'.
+    let (head, attachment) = match packet.iter().position(|&c| c == b';') {
+        Some(semi) => {
+            let data = unescape_binary(&packet[semi + 1..]).ok_or("invalid escape")?;
+            (&packet[1..semi], Some(data))
+        }
+        None => (&packet[1..], None),
+    };
+    let mut fields = head.splitn(3, |&c| c == b',');
+    let result = fields.next().and_then(parse_signed_hex).ok_or("invalid result")?;
+    let errno = match fields.next() {
+        Some(text) => Some(parse_hex_number(text).ok_or("invalid errno")?),
+        None => None,
+    };
+    let interrupted = match fields.next() {
+        Some(b"C") => true,
+        Some(_) => return Err("invalid Ctrl-C flag"),
+        None => false,
+    };
+    Ok(FileReply { result, errno, attachment, interrupted })
+}
+
+/// Parse a reply to a host I/O request.
+pub fn parse_file_reply(packet: &[u8]) -> RspResult<FileReply> {
+    parse_file_reply_inner(packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+/// A reply to an `x` binary memory read request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BinaryReadReply {
+    /// The unescaped memory contents.
+    Data(Vec<u8>),
+    /// An error reply, `E NN`.
+    Error(u8),
+}
+
+/// Parse a reply to an `x` request, as sent by gdbserver: either `b`
+/// followed by the escaped data, or an error.
+pub fn parse_binary_read_reply(packet: &[u8]) -> RspResult<BinaryReadReply> {
+    let violation = |reason| RspError::ProtocolViolation { packet: packet.to_vec(), reason };
+    match packet.split_first() {
+        Some((&b'b', data)) => {
+            unescape_binary(data).map(BinaryReadReply::Data)
+                .ok_or_else(|| violation("invalid escape"))
+        }
+        Some((&b'E', code)) if code.len() == 2 => {
+            parse_hex_number(code).map(|v| BinaryReadReply::Error(v as u8))
+                .ok_or_else(|| violation("invalid error code"))
+        }
+        _ => Err(violation("expected b or E")),
+    }
+}
+
+// The smallest packet size a RemoteFile will use; anything smaller
+// would leave no room for data after the packet's own fields.
+const MINIMUM_PACKET_SIZE: usize = 64;
+
+/// A file on the remote target, accessed using host I/O (`vFile`)
+/// requests.  This is for use by clients.
+///
+/// `RemoteFile` implements `Read`, `Write`, and `Seek` using
+/// `vFile:pread` and `vFile:pwrite` with an offset that it tracks
+/// itself, so a remote file can be handed to code that expects a
+/// local one.  Each read or write transfers at most one packet's
+/// worth of data.  The file is closed when the `RemoteFile` is
+/// dropped.
+pub struct RemoteFile<'a, 'conn: 'a> {
+    conn: &'a mut RspConnection<'conn>,
+    fd: i64,
+    offset: u64,
+    packet_size: usize,
+}
+
+impl<'a, 'conn> RemoteFile<'a, 'conn> {
+    /// Open the remote file `path`.  `flags` is a combination of the
+    /// `FILE_IO_O_*` constants, and `mode` gives the permissions for
+    /// a newly-created file.
+    pub fn open(conn: &'a mut RspConnection<'conn>, path: &[u8], flags: u32, mode: u32)
+                -> io::Result<RemoteFile<'a, 'conn>> {
+        let mut packet = b"vFile:open:".to_vec();
+        push_hex(&mut packet, path);
+        packet.push(b',');
+        write_hex_number(&mut packet, u64::from(flags));
+        packet.push(b',');
+        write_hex_number(&mut packet, u64::from(mode));
+        let fd = RemoteFile::send(conn, &packet)?;
+        Ok(RemoteFile { conn, fd, offset: 0, packet_size: 1024 })
+    }
+
+    /// Set the maximum packet size, as reported by the stub's
+    /// `qSupported` reply.  This limits how much is transferred by a
+    /// single read or write.  A size below 64, which would leave no
+    /// room for data, is taken to be 64.  The default is 1024.
+    pub fn set_packet_size(&mut self, packet_size: usize) {
+        self.packet_size = ::std::cmp::max(packet_size, MINIMUM_PACKET_SIZE);
+    }
+
+    // Send a request and return the successful result.
+    fn send(conn: &mut RspConnection, packet: &[u8]) -> io::Result<i64> {
+        let reply = conn.request(packet).map_err(to_io_error)?;
+        parse_file_reply(&reply).map_err(to_io_error)?.io_result()
+    }
+
+    /// Return the size of the file, using `vFile:fstat`.
+    pub fn size(&mut self) -> io::Result<u64> {
+        let mut packet = b"vFile:fstat:".to_vec();
+        write_hex_number(&mut packet, self.fd as u64);
+        let reply = self.conn.request(&packet).map_err(to_io_error)?;
+        let reply = parse_file_reply(&reply).map_err(to_io_error)?;
+        reply.io_result()?;
+        // st_size follows seven 4-byte fields, and is big-endian.
+        match reply.attachment {
+            Some(ref stat) if stat.len() >= 36 => {
+                Ok(stat[28..36].iter().fold(0, |size, &b| (size << 8) | u64::from(b)))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "short fstat reply")),
+        }
+    }
+}
+
+impl<'a, 'conn> Read for RemoteFile<'a, 'conn> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Leave room for the reply's "F" and count, and for escaping.
+        let count = ::std::cmp::min(buf.len(), (self.packet_size - 32) / 2);
+        let mut packet = b"vFile:pread:".to_vec();
+        write_hex_number(&mut packet, self.fd as u64);
+        packet.push(b',');
+        write_hex_number(&mut packet, count as u64);
+        packet.push(b',');
+        write_hex_number(&mut packet, self.offset);
+        let reply = self.conn.request(&packet).map_err(to_io_error)?;
+        let reply = parse_file_reply(&reply).map_err(to_io_error)?;
+        reply.io_result()?;
+        let data = reply.attachment.unwrap_or_default();
+        if data.len() > count {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "pread returned too much"));
+        }
+        buf[..data.len()].copy_from_slice(&data);
+        self.offset += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl<'a, 'conn> Write for RemoteFile<'a, 'conn> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Leave room for the header, and assume the worst case, that
+        // every byte must be escaped.
+        let count = ::std::cmp::min(buf.len(), (self.packet_size - 48) / 2);
+        self.conn.start_packet().map_err(to_io_error)?;
+        self.conn.write_all(b"vFile:pwrite:")?;
+        self.conn.write_hex_number(self.fd as u64).map_err(to_io_error)?;
+        self.conn.write_all(b",")?;
+        self.conn.write_hex_number(self.offset).map_err(to_io_error)?;
+        self.conn.write_all(b",")?;
+        self.conn.write_binary(&buf[..count]).map_err(to_io_error)?;
+        self.conn.finish_packet().map_err(to_io_error)?;
+        let reply = self.conn.read_reply().map_err(to_io_error)?;
+        let written = parse_file_reply(&reply).map_err(to_io_error)?.io_result()?;
+        if written < 0 || written as usize > count {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid pwrite result"));
+        }
+        self.offset += written as u64;
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'conn> Seek for RemoteFile<'a, 'conn> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::Current(delta) => (self.offset, delta),
+            SeekFrom::End(delta) => (self.size()?, delta),
+        };
+        match base.checked_add_signed(delta) {
+            Some(offset) => {
+                self.offset = offset;
+                Ok(offset)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek offset")),
+        }
+    }
+}
+
+impl<'a, 'conn> Drop for RemoteFile<'a, 'conn> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here.
+        let mut packet = b"vFile:close:".to_vec();
+        write_hex_number(&mut packet, self.fd as u64);
+        let _ = self.conn.request(&packet);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::{file_io_errno, file_io_error_kind, parse_binary_read_reply, parse_file_reply,
+                BinaryReadReply, FileReply, RemoteFile, FILE_IO_EUNKNOWN, FILE_IO_O_RDWR};
+    use low::RspConnection;
+    use util::frame_packets;
+
+    #[test]
+    fn file_reply() {
+        assert_eq!(parse_file_reply(b"F1a").unwrap(),
+                   FileReply { result: 0x1a, errno: None, attachment: None, interrupted: false });
+        assert_eq!(parse_file_reply(b"F-1,2").unwrap(),
+                   FileReply { result: -1, errno: Some(2), attachment: None, interrupted: false });
+        assert_eq!(parse_file_reply(b"F4;a};,}]").unwrap(),
+                   FileReply {
+                       result: 4,
+                       errno: None,
+                       attachment: Some(b"a\x1b,}".to_vec()),
+                       interrupted: false,
+                   });
+        assert!(parse_file_reply(b"F-1,4,C").unwrap().interrupted);
+        assert_eq!(parse_file_reply(b"F-1,1B").unwrap().errno, Some(0x1b));
+        assert_eq!(parse_file_reply(b"F1A").unwrap().result, 0x1a);
+        assert!(parse_file_reply(b"F-1,4,X").is_err());
+        assert!(parse_file_reply(b"F").is_err());
+        assert!(parse_file_reply(b"F1;}").is_err());
+        assert!(parse_file_reply(b"OK").is_err());
+    }
+
+    #[test]
+    fn binary_read_reply() {
+        assert_eq!(parse_binary_read_reply(b"b}\x03\x00").unwrap(),
+                   BinaryReadReply::Data(b"#\0".to_vec()));
+        assert_eq!(parse_binary_read_reply(b"b").unwrap(), BinaryReadReply::Data(Vec::new()));
+        assert_eq!(parse_binary_read_reply(b"E0e").unwrap(), BinaryReadReply::Error(14));
+        assert!(parse_binary_read_reply(b"").is_err());
+    }
+
+    #[test]
+    fn errno() {
+        assert_eq!(file_io_error_kind(2), io::ErrorKind::NotFound);
+        assert_eq!(file_io_error_kind(1), io::ErrorKind::PermissionDenied);
+        assert_eq!(file_io_error_kind(12345), io::ErrorKind::Other);
+        assert_eq!(file_io_errno(io::ErrorKind::PermissionDenied), 13);
+        assert_eq!(file_io_errno(io::ErrorKind::NotSeekable), 29);
+        assert_eq!(file_io_errno(io::ErrorKind::TimedOut), FILE_IO_EUNKNOWN);
+
+        assert_eq!(parse_file_reply(b"F10").unwrap().io_result().unwrap(), 16);
+        let err = parse_file_reply(b"F-1,11").unwrap().io_result().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn remote_file() {
+        let mut stat = vec![0u8; 64];
+        stat[35] = 10;
+        let mut fstat = b"F40;".to_vec();
+        fstat.extend_from_slice(&stat);
+
+        let input = frame_packets(&[b"F5", b"F3;abc", b"F2", &fstat, b"F1;z", b"F0"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut file = RemoteFile::open(&mut rsp, b"/x", FILE_IO_O_RDWR, 0o644).unwrap();
+            // A bogus packet size from the stub is raised to the minimum.
+            file.set_packet_size(16);
+            let mut buf = [0; 8];
+            assert_eq!(file.read(&mut buf).unwrap(), 3);
+            assert_eq!(&buf[..3], b"abc");
+            assert_eq!(file.write(b"#!").unwrap(), 2);
+            assert_eq!(file.seek(SeekFrom::End(-1)).unwrap(), 9);
+            assert_eq!(file.read(&mut buf).unwrap(), 1);
+        }
+
+        assert_eq!(output, frame_packets(&[b"vFile:open:2f78,2,1a4", b"vFile:pread:5,8,0",
+                                           b"vFile:pwrite:5,3,}\x03!", b"vFile:fstat:5",
+                                           b"vFile:pread:5,8,9", b"vFile:close:5"]));
+    }
+}
@@ -0,0 +1,303 @@
+#![deny(missing_docs)]
+
+use low::{check_reply, RspConnection, RspError, RspResult};
+use startup::NegotiatedCapabilities;
+use util::{push_hex, write_decimal};
+
+// Return true if |byte| cannot appear literally in a packet that
+// carries text, such as QEnvironment.
+fn needs_escape(byte: u8) -> bool {
+    match byte {
+        b'$' | b'#' | b'}' | b'*' => true,
+        _ => !(0x20..0x7f).contains(&byte),
+    }
+}
+
+// Build the QEnvironmentHexEncoded packet that sets |name| to |value|.
+fn hex_environment_packet(name: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut result = b"QEnvironmentHexEncoded:".to_vec();
+    push_hex(&mut result, name);
+    push_hex(&mut result, b"=");
+    push_hex(&mut result, value);
+    result
+}
+
+// Build the plain QEnvironment packet that sets |name| to |value|, or
+// return None if either contains a character that would need escaping.
+fn plain_environment_packet(name: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+    if name.iter().chain(value.iter()).any(|&c| needs_escape(c)) {
+        return None;
+    }
+    let mut result = b"QEnvironment:".to_vec();
+    result.extend_from_slice(name);
+    result.push(b'=');
+    result.extend_from_slice(value);
+    Some(result)
+}
+
+/// Sets environment variables in the inferior, for use by a client,
+/// using whichever packet the stub understands.
+///
+/// gdbserver and lldb-server both accept `QEnvironmentHexEncoded`,
+/// which can carry any bytes, but some stubs derived from
+/// lldb-server only accept the plain `QEnvironment:NAME=VALUE` form.
+/// The first variable is sent hex-encoded; if the stub answers with
+/// an empty reply, it is resent in the plain form, and the plain form
+/// is used from then on.  A stub that reported
+/// `QEnvironmentHexEncoded+` in `qSupported` is not probed at all.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvironmentSetter {
+    // Whether the stub accepts QEnvironmentHexEncoded, where known.
+    hex_encoded: Option<bool>,
+}
+
+impl EnvironmentSetter {
+    /// Make a new `EnvironmentSetter` that does not yet know which
+    /// packet the stub accepts.
+    pub fn new() -> EnvironmentSetter {
+        EnvironmentSetter::default()
+    }
+
+    /// Make an `EnvironmentSetter` holding what `startup` already
+    /// learned from `qSupported`.
+    pub fn from_capabilities(caps: &NegotiatedCapabilities) -> EnvironmentSetter {
+        let mut setter = EnvironmentSetter::new();
+        if caps.supports(b"QEnvironmentHexEncoded") {
+            setter.hex_encoded = Some(true);
+        }
+        setter
+    }
+
+    /// Return whether the stub accepts `QEnvironmentHexEncoded`, or
+    /// `None` if that is not known yet.
+    pub fn hex_encoded(&self) -> Option<bool> {
+        self.hex_encoded
+    }
+
+    /// Set the environment variable `name` to `value` in the
+    /// inferior, sending the request over `conn`.  If the stub only
+    /// accepts the plain form and the variable holds a character
+    /// that form cannot carry, `RspError::InvalidArgument` is
+    /// returned and nothing is sent.
+    pub fn set(&mut self, conn: &mut RspConnection, name: &[u8], value: &[u8])
+               -> RspResult<()> {
+        if self.hex_encoded != Some(false) {
+            let reply = check_reply(conn.request(&hex_environment_packet(name, value))?)?;
+            if !reply.is_empty() || self.hex_encoded.is_some() {
+                self.hex_encoded = Some(true);
+                return EnvironmentSetter::check_ok(reply);
+            }
+            self.hex_encoded = Some(false);
+        }
+        match plain_environment_packet(name, value) {
+            Some(packet) => EnvironmentSetter::check_ok(check_reply(conn.request(&packet)?)?),
+            None => Err(RspError::InvalidArgument("variable cannot be sent with QEnvironment")),
+        }
+    }
+
+    // Check that |reply| is "OK".
+    fn check_ok(reply: Vec<u8>) -> RspResult<()> {
+        if reply != b"OK" {
+            return Err(RspError::ProtocolViolation {
+                packet: reply,
+                reason: "setting environment variable failed",
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The program and arguments used to start a new inferior, either
+/// with `vRun` (in extended mode) or with the older `A` packet.
+///
+/// Each argument is sent hex-encoded, so arguments may contain any
+/// bytes at all: spaces, semicolons, NULs, or text that is not
+/// UTF-8.
+///
+/// The inferior's standard streams can also be redirected to files
+/// or ptys on the remote system.  This uses lldb-server's
+/// `QSetSTDIN`, `QSetSTDOUT`, and `QSetSTDERR` packets, which
+/// gdbserver does not support; with gdbserver, redirections are
+/// instead written into the arguments, which its startup shell
+/// interprets.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchArgs {
+    program: Vec<u8>,
+    args: Vec<Vec<u8>>,
+    stdin: Option<Vec<u8>>,
+    stdout: Option<Vec<u8>>,
+    stderr: Option<Vec<u8>>,
+}
+
+impl LaunchArgs {
+    /// Make a new `LaunchArgs` that runs `program`.  An empty
+    /// `program` asks the stub to reuse the program it was last
+    /// given.
+    pub fn new(program: &[u8]) -> LaunchArgs {
+        LaunchArgs { program: program.to_vec(), ..Default::default() }
+    }
+
+    /// Add an argument.
+    pub fn push_arg(&mut self, arg: &[u8]) {
+        self.args.push(arg.to_vec());
+    }
+
+    /// Return the program name.
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    /// Return the arguments, not including the program name.
+    pub fn args(&self) -> &[Vec<u8>] {
+        &self.args
+    }
+
+    /// Redirect the inferior's standard input from the remote file
+    /// `path`.
+    pub fn set_stdin(&mut self, path: &[u8]) {
+        self.stdin = Some(path.to_vec());
+    }
+
+    /// Redirect the inferior's standard output to the remote file
+    /// `path`.
+    pub fn set_stdout(&mut self, path: &[u8]) {
+        self.stdout = Some(path.to_vec());
+    }
+
+    /// Redirect the inferior's standard error to the remote file
+    /// `path`.
+    pub fn set_stderr(&mut self, path: &[u8]) {
+        self.stderr = Some(path.to_vec());
+    }
+
+    /// Build the `QSetSTDIN`, `QSetSTDOUT`, and `QSetSTDERR` packets
+    /// for any redirections that were set.  These must be sent, and
+    /// answered with `OK`, before the launch packet.
+    pub fn stdio_packets(&self) -> Vec<Vec<u8>> {
+        let streams = [(&b"QSetSTDIN:"[..], &self.stdin), (b"QSetSTDOUT:", &self.stdout),
+                       (b"QSetSTDERR:", &self.stderr)];
+        streams.iter().filter_map(|&(name, path)| {
+            path.as_ref().map(|path| {
+                let mut packet = name.to_vec();
+                push_hex(&mut packet, path);
+                packet
+            })
+        }).collect()
+    }
+
+    // Check the size of a finished packet.
+    fn check_size(packet: Vec<u8>, packet_size: Option<usize>) -> RspResult<Vec<u8>> {
+        match packet_size {
+            Some(max) if packet.len() > max => Err(RspError::PacketTooLarge),
+            _ => Ok(packet),
+        }
+    }
+
+    /// Build a `vRun` packet.  `packet_size` is the maximum packet
+    /// size reported by the stub's `qSupported` reply, if known.
+    /// Neither `vRun` nor `A` has a continuation form, so if the
+    /// packet would be too large, `PacketTooLarge` is returned.
+    pub fn vrun_packet(&self, packet_size: Option<usize>) -> RspResult<Vec<u8>> {
+        let mut result = b"vRun;".to_vec();
+        push_hex(&mut result, &self.program);
+        for arg in &self.args {
+            result.push(b';');
+            push_hex(&mut result, arg);
+        }
+        LaunchArgs::check_size(result, packet_size)
+    }
+
+    /// Build an `A` packet.  The program name is sent as argument 0.
+    /// The lengths and argument numbers are written in decimal, as
+    /// lldb-server expects.  `packet_size` is treated as in
+    /// `vrun_packet`.
+    pub fn a_packet(&self, packet_size: Option<usize>) -> RspResult<Vec<u8>> {
+        let mut result = b"A".to_vec();
+        let all = Some(&self.program).into_iter().chain(self.args.iter());
+        for (i, arg) in all.enumerate() {
+            if i > 0 {
+                result.push(b',');
+            }
+            write_decimal(&mut result, (arg.len() * 2) as u64);
+            result.push(b',');
+            write_decimal(&mut result, i as u64);
+            result.push(b',');
+            push_hex(&mut result, arg);
+        }
+        LaunchArgs::check_size(result, packet_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EnvironmentSetter, LaunchArgs};
+    use low::{RspConnection, RspError};
+    use util::frame_packets;
+
+    #[test]
+    fn launch_args() {
+        let mut args = LaunchArgs::new(b"/bin/ls");
+        args.push_arg(b"a b;c");
+        args.push_arg(b"\0\xff");
+        assert_eq!(args.vrun_packet(None).unwrap(),
+                   b"vRun;2f62696e2f6c73;6120623b63;00ff".to_vec());
+        assert_eq!(args.a_packet(None).unwrap(),
+                   b"A14,0,2f62696e2f6c73,10,1,6120623b63,4,2,00ff".to_vec());
+
+        match args.vrun_packet(Some(16)) {
+            Err(RspError::PacketTooLarge) => { }
+            _ => panic!("expected PacketTooLarge"),
+        }
+        assert_eq!(LaunchArgs::new(b"").vrun_packet(Some(16)).unwrap(), b"vRun;".to_vec());
+    }
+
+    #[test]
+    fn stdio() {
+        let mut args = LaunchArgs::new(b"/bin/cat");
+        assert!(args.stdio_packets().is_empty());
+        args.set_stdin(b"/dev/null");
+        args.set_stderr(b"/tmp/e");
+        assert_eq!(args.stdio_packets(),
+                   vec![b"QSetSTDIN:2f6465762f6e756c6c".to_vec(),
+                        b"QSetSTDERR:2f746d702f65".to_vec()]);
+    }
+
+    #[test]
+    fn environment() {
+        let input = frame_packets(&[b"OK", b"OK"]);
+        let mut output = Vec::new();
+        let mut setter = EnvironmentSetter::new();
+        {
+            let mut reader: &[u8] = &input;
+            let mut rsp = RspConnection::new(&mut reader, &mut output, true);
+            rsp.disable_acking();
+            setter.set(&mut rsp, b"HOME", b"/home/me").expect("set");
+            setter.set(&mut rsp, b"PS1", b"$ ").expect("set");
+        }
+        assert_eq!(setter.hex_encoded(), Some(true));
+        assert_eq!(output, frame_packets(&[b"QEnvironmentHexEncoded:484f4d453d2f686f6d652f6d65",
+                                           b"QEnvironmentHexEncoded:5053313d2420"]));
+    }
+
+    #[test]
+    fn environment_fallback() {
+        let input = frame_packets(&[b"", b"OK", b"OK"]);
+        let mut output = Vec::new();
+        let mut setter = EnvironmentSetter::new();
+        {
+            let mut reader: &[u8] = &input;
+            let mut rsp = RspConnection::new(&mut reader, &mut output, true);
+            rsp.disable_acking();
+            setter.set(&mut rsp, b"HOME", b"/home/me").expect("set");
+            setter.set(&mut rsp, b"A", b"b").expect("set");
+            match setter.set(&mut rsp, b"PS1", b"$ ") {
+                Err(RspError::InvalidArgument(_)) => { }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(setter.hex_encoded(), Some(false));
+        assert_eq!(output, frame_packets(&[b"QEnvironmentHexEncoded:484f4d453d2f686f6d652f6d65",
+                                           b"QEnvironment:HOME=/home/me",
+                                           b"QEnvironment:A=b"]));
+    }
+}
@@ -0,0 +1,218 @@
+#![deny(missing_docs)]
+
+use low::{check_reply, Id, ProcessId, RspConnection, RspError, RspResult};
+use thread::ThreadOperation;
+use util::{hex_byte, parse_hex_number, push_hex, write_thread_id};
+
+// The number of threads asked for in each qL request; this is what
+// GDB used.
+const THREAD_LIST_BATCH: u8 = 32;
+
+// The qP tags, which are bits in the mode mask.
+const TAG_THREADID: u32 = 1;
+const TAG_EXISTS: u32 = 2;
+const TAG_DISPLAY: u32 = 4;
+const TAG_THREADNAME: u32 = 8;
+const TAG_MOREDISPLAY: u32 = 16;
+const ALL_TAGS: u32 = TAG_THREADID | TAG_EXISTS | TAG_DISPLAY | TAG_THREADNAME | TAG_MOREDISPLAY;
+
+// Split |count| characters off the front of |*input|.
+fn take<'a>(input: &mut &'a [u8], count: usize) -> Result<&'a [u8], &'static str> {
+    if input.len() < count {
+        return Err("reply too short");
+    }
+    let (head, tail) = input.split_at(count);
+    *input = tail;
+    Ok(head)
+}
+
+// Read a fixed-width hex number of |count| digits from |*input|.
+fn take_hex(input: &mut &[u8], count: usize) -> Result<u64, &'static str> {
+    parse_hex_number(take(input, count)?).ok_or("invalid hex number")
+}
+
+// Parse a qM reply to qL.  Returns whether the list is done, the
+// thread echoed from the request, and the threads.
+fn parse_thread_list(reply: &[u8]) -> Result<(bool, u64, Vec<u64>), &'static str> {
+    let mut rest = reply.strip_prefix(b"qM").ok_or("expected qM")?;
+    let count = take_hex(&mut rest, 2)?;
+    let done = take_hex(&mut rest, 1)? != 0;
+    let echo = take_hex(&mut rest, 16)?;
+    let threads = (0..count).map(|_| take_hex(&mut rest, 16)).collect::<Result<Vec<_>, _>>()?;
+    Ok((done, echo, threads))
+}
+
+/// List the threads with the obsolete `qL` packet, for use by a
+/// client talking to a stub that predates `qfThreadInfo`.  Thread ids
+/// in this form are 64-bit values.  This is only available when the
+/// `legacy` feature is enabled.
+pub fn legacy_thread_list(conn: &mut RspConnection) -> RspResult<Vec<u64>> {
+    let mut result = Vec::new();
+    let mut next = 0u64;
+    let mut start = true;
+    loop {
+        // The fields have fixed widths.
+        let mut request = vec![b'q', b'L', if start { b'1' } else { b'0' }];
+        request.extend_from_slice(&hex_byte(THREAD_LIST_BATCH));
+        push_hex(&mut request, &next.to_be_bytes());
+        let reply = check_reply(conn.request(&request)?)?;
+        let (done, echo, threads) = parse_thread_list(&reply).map_err(|reason| {
+            RspError::ProtocolViolation { packet: reply.clone(), reason }
+        })?;
+        if !start && echo != next {
+            return Err(RspError::ProtocolViolation {
+                packet: reply,
+                reason: "qM reply is for a different request",
+            });
+        }
+        let last = threads.last().cloned();
+        result.extend(threads);
+        match last {
+            Some(thread) if !done => next = thread,
+            _ => return Ok(result),
+        }
+        start = false;
+    }
+}
+
+/// Information about a thread, as returned by the obsolete `qP`
+/// packet.  Fields the stub did not supply are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LegacyThreadInfo {
+    /// Whether the thread is still alive.
+    pub exists: Option<bool>,
+    /// The thread's name.
+    pub name: Option<Vec<u8>>,
+    /// A description of the thread, for display.
+    pub display: Option<Vec<u8>>,
+    /// Further information about the thread, for display.
+    pub more_display: Option<Vec<u8>>,
+}
+
+// Parse a QP reply to qP.  Returns the thread echoed from the request,
+// and the information.
+fn parse_thread_info(reply: &[u8]) -> Result<(u64, LegacyThreadInfo), &'static str> {
+    let mut rest = reply.strip_prefix(b"QP").ok_or("expected QP")?;
+    let mut mask = take_hex(&mut rest, 8)? as u32;
+    let thread = take_hex(&mut rest, 16)?;
+    let mut info = LegacyThreadInfo::default();
+    while !rest.is_empty() && mask != 0 {
+        let tag = take_hex(&mut rest, 8)? as u32;
+        let length = take_hex(&mut rest, 2)? as usize;
+        if tag & mask == 0 {
+            return Err("unexpected qP tag");
+        }
+        let data = take(&mut rest, length)?;
+        match tag {
+            TAG_THREADID => {
+                if parse_hex_number(data) != Some(thread) {
+                    return Err("qP reply is for a different thread");
+                }
+            }
+            TAG_EXISTS => {
+                info.exists = Some(parse_hex_number(data).ok_or("invalid hex number")? != 0);
+            }
+            TAG_DISPLAY => info.display = Some(data.to_vec()),
+            TAG_THREADNAME => info.name = Some(data.to_vec()),
+            TAG_MOREDISPLAY => info.more_display = Some(data.to_vec()),
+            _ => return Err("unexpected qP tag"),
+        }
+        mask &= !tag;
+    }
+    Ok((thread, info))
+}
+
+/// Ask for information about `thread` with the obsolete `qP` packet,
+/// for use by a client.  Returns `None` if the stub does not support
+/// `qP`.  This is only available when the `legacy` feature is
+/// enabled.
+pub fn legacy_thread_info(conn: &mut RspConnection, thread: u64)
+                          -> RspResult<Option<LegacyThreadInfo>> {
+    // The fields have fixed widths.
+    let mut request = b"qP".to_vec();
+    push_hex(&mut request, &ALL_TAGS.to_be_bytes());
+    push_hex(&mut request, &thread.to_be_bytes());
+    let reply = check_reply(conn.request(&request)?)?;
+    if reply.is_empty() {
+        return Ok(None);
+    }
+    let violation = |reason| RspError::ProtocolViolation { packet: reply.clone(), reason };
+    let (echo, info) = parse_thread_info(&reply).map_err(violation)?;
+    if echo != thread {
+        return Err(violation("qP reply is for a different thread"));
+    }
+    Ok(Some(info))
+}
+
+/// Select `thread` for `operation` with an `H` packet that uses the
+/// plain, non-multiprocess form of the thread id, for use by a client.
+/// Old stubs understand only this form: for example `Hc-1` to resume
+/// all threads, or `Hg0` for any thread.  This is only available when
+/// the `legacy` feature is enabled.
+pub fn legacy_set_thread(conn: &mut RspConnection, operation: ThreadOperation, thread: Id)
+                         -> RspResult<()> {
+    let op = match operation {
+        ThreadOperation::General => b'g',
+        ThreadOperation::Continue => b'c',
+    };
+    let mut packet = vec![b'H', op];
+    write_thread_id(&mut packet, ProcessId { pid: Id::Any, tid: thread });
+    let reply = check_reply(conn.request(&packet)?)?;
+    if reply != b"OK" {
+        return Err(RspError::ProtocolViolation { packet: reply, reason: "expected OK" });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{legacy_set_thread, legacy_thread_info, legacy_thread_list, LegacyThreadInfo};
+    use low::{Id, RspConnection};
+    use thread::ThreadOperation;
+    use util::frame_packet;
+
+    #[test]
+    fn thread_list() {
+        let mut input = frame_packet(b"qM020000000000000000000000000000000010000000000000002");
+        input.extend(frame_packet(b"qM0110000000000000002000000000000002a"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(legacy_thread_list(&mut rsp).unwrap(), vec![1, 2, 0x2a]);
+        }
+        let mut expected = frame_packet(b"qL1200000000000000000");
+        expected.extend(frame_packet(b"qL0200000000000000002"));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn thread_info() {
+        let mut input = frame_packet(b"QP0000001f000000000000002a\
+                                       0000000110000000000000002a\
+                                       00000002011\
+                                       0000000804main\
+                                       0000000408Runnable");
+        input.extend(frame_packet(b""));
+        input.extend(frame_packet(b"OK"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(legacy_thread_info(&mut rsp, 0x2a).unwrap(), Some(LegacyThreadInfo {
+                exists: Some(true),
+                name: Some(b"main".to_vec()),
+                display: Some(b"Runnable".to_vec()),
+                more_display: None,
+            }));
+            assert_eq!(legacy_thread_info(&mut rsp, 0x2a).unwrap(), None);
+            legacy_set_thread(&mut rsp, ThreadOperation::Continue, Id::All).unwrap();
+        }
+        let mut expected = frame_packet(b"qP0000001f000000000000002a");
+        expected.extend(frame_packet(b"qP0000001f000000000000002a"));
+        expected.extend(frame_packet(b"Hc-1"));
+        assert_eq!(output, expected);
+    }
+}
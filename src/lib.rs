@@ -1,4 +1,142 @@
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "zlib")]
+extern crate miniz_oxide;
+#[cfg(feature = "lz4")]
+extern crate lz4_flex;
+
 mod low;
 pub use low::*;
 
+mod stop;
+pub use stop::*;
+
+mod conformance;
+pub use conformance::*;
+
+mod proxy;
+pub use proxy::*;
+
+mod extension;
+pub use extension::*;
+
+mod launch;
+pub use launch::*;
+
+mod console;
+pub use console::*;
+
+mod resume;
+pub use resume::*;
+
+mod breakpoint;
+pub use breakpoint::*;
+
+mod qxfer;
+pub use qxfer::*;
+
+mod tdesc;
+pub use tdesc::*;
+
+mod presets;
+pub use presets::*;
+
+mod memmap;
+pub use memmap::*;
+
+mod signals;
+pub use signals::*;
+
+mod thread;
+pub use thread::*;
+
+mod echo;
+pub use echo::*;
+
+mod compression;
+pub use compression::*;
+
+mod hostio;
+pub use hostio::*;
+
+mod sysroot;
+pub use sysroot::*;
+
+mod lldb;
+pub use lldb::*;
+
+mod startup;
+pub use startup::*;
+
+mod packet;
+pub use packet::*;
+
+mod register;
+pub use register::*;
+
+mod session;
+pub use session::*;
+
+mod memory;
+pub use memory::*;
+
+mod fileio;
+pub use fileio::*;
+
+mod tcp;
+pub use tcp::*;
+
+mod pipe;
+pub use pipe::*;
+
+mod libraries;
+pub use libraries::*;
+
+mod addrmap;
+pub use addrmap::*;
+
+mod trace;
+pub use trace::*;
+
+mod transport;
+pub use transport::*;
+
+mod quirks;
+pub use quirks::*;
+
+mod openocd;
+pub use openocd::*;
+
+mod probe;
+pub use probe::*;
+
+mod osdata;
+pub use osdata::*;
+
+mod corefile;
+pub use corefile::*;
+
+mod crash;
+pub use crash::*;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::*;
+
+#[cfg(feature = "legacy")]
+mod legacy;
+#[cfg(feature = "legacy")]
+pub use legacy::*;
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::*;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use windows::*;
+
 mod util;
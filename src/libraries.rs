@@ -0,0 +1,111 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeSet;
+
+use low::{RspConnection, RspResult};
+use qxfer::read_qxfer_object;
+use stop::{StopReply, StopReplyValue};
+use util::library_names;
+
+/// A change to the set of loaded shared libraries, as reported by
+/// `LibraryList`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LibraryEvent {
+    /// The library with this name was loaded.
+    Loaded(Vec<u8>),
+    /// The library with this name was unloaded.
+    Unloaded(Vec<u8>),
+}
+
+/// The shared libraries loaded in the inferior, for use by a client
+/// that loads and unloads symbol files as they change.  The list is
+/// fetched with `qXfer:libraries-svr4` or, failing that,
+/// `qXfer:libraries`, and refetched whenever a stop reply has a
+/// `library` value.  Libraries are identified by name.
+pub struct LibraryList {
+    libraries: BTreeSet<Vec<u8>>,
+    packet_size: usize,
+}
+
+impl LibraryList {
+    /// Make a new, empty list.  `packet_size` is the maximum packet
+    /// size reported by the stub's `qSupported` reply.
+    pub fn new(packet_size: usize) -> LibraryList {
+        LibraryList { libraries: BTreeSet::new(), packet_size }
+    }
+
+    /// Return the names of the loaded libraries, in sorted order.
+    pub fn libraries(&self) -> impl Iterator<Item = &[u8]> {
+        self.libraries.iter().map(|name| &name[..])
+    }
+
+    /// Fetch the library list, returning the libraries that were
+    /// loaded or unloaded since the last fetch.  Unloads are reported
+    /// first.  If the stub does not supply a library list, the list
+    /// is left empty.
+    pub fn refresh(&mut self, conn: &mut RspConnection) -> RspResult<Vec<LibraryEvent>> {
+        let mut current = BTreeSet::new();
+        for object in &[&b"libraries-svr4"[..], b"libraries"] {
+            if let Some(xml) = read_qxfer_object(conn, object, b"", self.packet_size)? {
+                current.extend(library_names(&xml));
+                break;
+            }
+        }
+        let mut events: Vec<LibraryEvent> = self.libraries.difference(&current).cloned()
+            .map(LibraryEvent::Unloaded).collect();
+        events.extend(current.difference(&self.libraries).cloned().map(LibraryEvent::Loaded));
+        self.libraries = current;
+        Ok(events)
+    }
+
+    /// Update the list after the stop reported by `reply`, refetching
+    /// it if the stop was for a library event.  Returns the changes,
+    /// as `refresh` does.
+    pub fn update(&mut self, conn: &mut RspConnection, reply: &StopReply)
+                  -> RspResult<Vec<LibraryEvent>> {
+        match *reply {
+            StopReply::Signal { ref values, .. }
+                if values.contains(&StopReplyValue::Library) => {
+                self.refresh(conn)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LibraryEvent, LibraryList};
+    use low::RspConnection;
+    use stop::{StopReply, StopReplyValue};
+    use util::frame_packet;
+
+    #[test]
+    fn update() {
+        let mut input = frame_packet(b"l<library-list-svr4><library name=\"/lib/a.so\"/>\
+                                       <library name=\"/lib/b.so\"/></library-list-svr4>");
+        input.extend(frame_packet(b""));
+        input.extend(frame_packet(b"l<library-list><library name=\"/lib/b.so\">\
+                                   </library><library name=\"/lib/c.so\"></library>\
+                                   </library-list>"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+
+        let mut list = LibraryList::new(4096);
+        assert_eq!(list.refresh(&mut rsp).unwrap(),
+                   vec![LibraryEvent::Loaded(b"/lib/a.so".to_vec()),
+                        LibraryEvent::Loaded(b"/lib/b.so".to_vec())]);
+
+        // Only a library event causes a refetch.
+        let plain = StopReply::Signal { signal: 5, values: Vec::new() };
+        assert_eq!(list.update(&mut rsp, &plain).unwrap(), vec![]);
+        let library = StopReply::Signal { signal: 5, values: vec![StopReplyValue::Library] };
+        assert_eq!(list.update(&mut rsp, &library).unwrap(),
+                   vec![LibraryEvent::Unloaded(b"/lib/a.so".to_vec()),
+                        LibraryEvent::Loaded(b"/lib/c.so".to_vec())]);
+        assert_eq!(list.libraries().collect::<Vec<_>>(),
+                   vec![&b"/lib/b.so"[..], &b"/lib/c.so"[..]]);
+    }
+}
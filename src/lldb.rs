@@ -0,0 +1,309 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+
+use launch::LaunchArgs;
+use low::{check_reply, RemoteError, RspConnection, RspError, RspResult};
+use util::{decode_hex_bytes, parse_hex_number, push_hex, write_decimal, write_hex_number};
+
+/// Build the `qModuleInfo` request that lldb-server uses to identify
+/// the module at `path`, built for the target `triple`.
+pub fn module_info_packet(path: &[u8], triple: &[u8]) -> Vec<u8> {
+    let mut result = b"qModuleInfo:".to_vec();
+    push_hex(&mut result, path);
+    result.push(b';');
+    push_hex(&mut result, triple);
+    result
+}
+
+/// The identity of a module, as sent in reply to `qModuleInfo`.
+/// lldb uses this to match a remote module with local symbols.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleInfo {
+    /// The module's build ID or UUID, if it has one.
+    pub uuid: Option<Vec<u8>>,
+    /// The MD5 checksum of the file, sent when there is no UUID.
+    pub md5: Option<Vec<u8>>,
+    /// The target triple, which includes the OS type.
+    pub triple: Vec<u8>,
+    /// The offset of the module within the file.  This is non-zero
+    /// when the module is stored inside another file, as for a
+    /// library loaded directly from an Android APK.
+    pub file_offset: u64,
+    /// The size of the module.
+    pub file_size: u64,
+    /// The path of the file holding the module.
+    pub file_path: Vec<u8>,
+}
+
+impl ModuleInfo {
+    /// Build the reply to a `qModuleInfo` request, for use by a
+    /// server.
+    pub fn to_reply(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        if let Some(ref uuid) = self.uuid {
+            result.extend_from_slice(b"uuid:");
+            push_hex(&mut result, uuid);
+            result.push(b';');
+        }
+        if let Some(ref md5) = self.md5 {
+            result.extend_from_slice(b"md5:");
+            push_hex(&mut result, md5);
+            result.push(b';');
+        }
+        result.extend_from_slice(b"triple:");
+        push_hex(&mut result, &self.triple);
+        result.extend_from_slice(b";file_offset:");
+        write_hex_number(&mut result, self.file_offset);
+        result.extend_from_slice(b";file_size:");
+        write_hex_number(&mut result, self.file_size);
+        result.extend_from_slice(b";file_path:");
+        push_hex(&mut result, &self.file_path);
+        result.push(b';');
+        result
+    }
+}
+
+fn parse_module_info_inner(reply: &[u8]) -> Result<ModuleInfo, &'static str> {
+    let mut info = ModuleInfo::default();
+    let mut saw_size = false;
+    for pair in reply.split(|&c| c == b';').filter(|pair| !pair.is_empty()) {
+        let colon = pair.iter().position(|&c| c == b':').ok_or("missing ':'")?;
+        let (key, value) = (&pair[..colon], &pair[colon + 1..]);
+        match key {
+            b"uuid" => info.uuid = Some(decode_hex_bytes(value).ok_or("invalid uuid")?),
+            b"md5" => info.md5 = Some(decode_hex_bytes(value).ok_or("invalid md5")?),
+            b"triple" => info.triple = decode_hex_bytes(value).ok_or("invalid triple")?,
+            b"file_offset" => {
+                info.file_offset = parse_hex_number(value).ok_or("invalid file_offset")?;
+            }
+            b"file_size" => {
+                info.file_size = parse_hex_number(value).ok_or("invalid file_size")?;
+                saw_size = true;
+            }
+            b"file_path" => info.file_path = decode_hex_bytes(value).ok_or("invalid file_path")?,
+            // Ignore unknown keys, for compatibility with later
+            // versions of lldb-server.
+            _ => { }
+        }
+    }
+    if !saw_size {
+        return Err("missing file_size");
+    }
+    Ok(info)
+}
+
+/// Parse the reply to a `qModuleInfo` request, for use by a client.
+/// Returns `None` if the stub does not know the module, which it
+/// indicates with an empty or error reply.
+pub fn parse_module_info(reply: &[u8]) -> RspResult<Option<ModuleInfo>> {
+    if reply.is_empty() || reply.first() == Some(&b'E') {
+        return Ok(None);
+    }
+    parse_module_info_inner(reply).map(Some).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.to_vec(), reason }
+    })
+}
+
+/// Information about a process, as sent by lldb-server in reply to
+/// `qProcessInfo` or `qProcessInfoPID`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// The process ID.
+    pub pid: u64,
+    /// The parent's process ID, if known.
+    pub parent_pid: Option<u64>,
+    /// The process name, if sent.
+    pub name: Option<Vec<u8>>,
+    /// The target triple, if sent.
+    pub triple: Option<Vec<u8>>,
+    /// Any other keys, such as `ostype` and `ptrsize`, with their raw
+    /// values.
+    pub other: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+fn parse_process_info_inner(reply: &[u8]) -> Result<ProcessInfo, &'static str> {
+    let mut info = ProcessInfo::default();
+    let mut saw_pid = false;
+    for pair in reply.split(|&c| c == b';').filter(|pair| !pair.is_empty()) {
+        let colon = pair.iter().position(|&c| c == b':').ok_or("missing ':'")?;
+        let (key, value) = (&pair[..colon], &pair[colon + 1..]);
+        match key {
+            b"pid" => {
+                info.pid = parse_hex_number(value).ok_or("invalid pid")?;
+                saw_pid = true;
+            }
+            b"parent-pid" => {
+                info.parent_pid = Some(parse_hex_number(value).ok_or("invalid parent-pid")?);
+            }
+            b"name" => info.name = Some(decode_hex_bytes(value).ok_or("invalid name")?),
+            b"triple" => info.triple = Some(decode_hex_bytes(value).ok_or("invalid triple")?),
+            _ => {
+                info.other.insert(key.to_vec(), value.to_vec());
+            }
+        }
+    }
+    if !saw_pid {
+        return Err("missing pid");
+    }
+    Ok(info)
+}
+
+/// Parse the reply to `qProcessInfo` or `qProcessInfoPID`, for use by
+/// a client.  An error reply results in `RspError::Remote`.
+pub fn parse_process_info(reply: &[u8]) -> RspResult<ProcessInfo> {
+    let reply = check_reply(reply.to_vec())?;
+    parse_process_info_inner(&reply).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.clone(), reason }
+    })
+}
+
+/// Ask for information about the process `pid` with
+/// `qProcessInfoPID`, or about the current process with
+/// `qProcessInfo` if `pid` is `None`.
+pub fn process_info(conn: &mut RspConnection, pid: Option<u64>) -> RspResult<ProcessInfo> {
+    let reply = match pid {
+        // Unlike most numbers in the protocol, this one is decimal.
+        Some(pid) => {
+            let mut packet = b"qProcessInfoPID:".to_vec();
+            write_decimal(&mut packet, pid);
+            conn.request(&packet)?
+        }
+        None => conn.request(b"qProcessInfo")?,
+    };
+    parse_process_info(&reply)
+}
+
+/// Ask whether the last launch succeeded, with `qLaunchSuccess`.
+/// lldb-server replies to the `A` packet before the program has
+/// really started, so failures such as a missing executable are only
+/// reported here.  A failure results in `RspError::Remote`, carrying
+/// lldb-server's description of the problem.
+pub fn check_launch_success(conn: &mut RspConnection) -> RspResult<()> {
+    let reply = conn.request(b"qLaunchSuccess")?;
+    if reply == b"OK" {
+        return Ok(());
+    }
+    let error = match RemoteError::parse(&reply) {
+        Some(error) => error,
+        // lldb-server sends "E" followed by plain text.
+        None if reply.first() == Some(&b'E') => {
+            RemoteError::Message(String::from_utf8_lossy(&reply[1..]).into_owned())
+        }
+        None => {
+            return Err(RspError::ProtocolViolation {
+                packet: reply,
+                reason: "invalid qLaunchSuccess reply",
+            });
+        }
+    };
+    Err(RspError::Remote(error))
+}
+
+/// Launch a program on lldb-server, for use by a client: send any
+/// standard stream redirections, then the `A` packet, then check
+/// that the launch worked with `qLaunchSuccess` and return the new
+/// process's information from `qProcessInfo`.  `packet_size` is as
+/// for `LaunchArgs::a_packet`.
+pub fn launch(conn: &mut RspConnection, args: &LaunchArgs, packet_size: Option<usize>)
+              -> RspResult<ProcessInfo> {
+    for packet in args.stdio_packets() {
+        let reply = check_reply(conn.request(&packet)?)?;
+        if reply != b"OK" {
+            return Err(RspError::ProtocolViolation { packet: reply, reason: "redirection failed" });
+        }
+    }
+    let reply = check_reply(conn.request(&args.a_packet(packet_size)?)?)?;
+    if reply != b"OK" {
+        return Err(RspError::ProtocolViolation { packet: reply, reason: "launch failed" });
+    }
+    check_launch_success(conn)?;
+    process_info(conn, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{launch, module_info_packet, parse_module_info, parse_process_info, process_info,
+                ModuleInfo};
+    use launch::LaunchArgs;
+    use low::{RemoteError, RspConnection, RspError};
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn module_info() {
+        assert_eq!(module_info_packet(b"/a", b"x86_64-pc-linux"),
+                   b"qModuleInfo:2f61;7838365f36342d70632d6c696e7578".to_vec());
+
+        let info = ModuleInfo {
+            uuid: Some(vec![0xde, 0xad, 0xbe, 0xef]),
+            md5: None,
+            triple: b"aarch64-unknown-linux-android".to_vec(),
+            file_offset: 0x1000,
+            file_size: 0x2345,
+            file_path: b"/data/app/base.apk".to_vec(),
+        };
+        let reply = info.to_reply();
+        assert!(reply.starts_with(b"uuid:deadbeef;triple:"));
+        assert_eq!(parse_module_info(&reply).unwrap(), Some(info));
+
+        assert_eq!(parse_module_info(b"").unwrap(), None);
+        assert_eq!(parse_module_info(b"E44").unwrap(), None);
+        let info = parse_module_info(b"uuid:DEADBEEF;file_offset:1A;file_size:FF;").unwrap().unwrap();
+        assert_eq!((info.uuid, info.file_offset, info.file_size),
+                   (Some(vec![0xde, 0xad, 0xbe, 0xef]), 0x1a, 0xff));
+        assert!(parse_module_info(b"uuid:zz;file_size:1;").is_err());
+        assert!(parse_module_info(b"uuid:00;").is_err());
+    }
+
+    #[test]
+    fn process_info_reply() {
+        let info = parse_process_info(b"pid:1f;parent-pid:1;name:6c73;ostype:linux;").unwrap();
+        assert_eq!(info.pid, 0x1f);
+        assert_eq!(info.parent_pid, Some(1));
+        assert_eq!(info.name, Some(b"ls".to_vec()));
+        assert_eq!(info.other.get(&b"ostype"[..]), Some(&b"linux".to_vec()));
+        assert!(parse_process_info(b"name:6c73;").is_err());
+        assert!(parse_process_info(b"E01").is_err());
+    }
+
+    fn run(replies: &[&[u8]], args: &LaunchArgs) -> (Result<u64, RspError>, Vec<u8>) {
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let result = {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            launch(&mut rsp, args, None).map(|info| info.pid)
+        };
+        (result, output)
+    }
+
+    #[test]
+    fn launch_checks() {
+        let mut args = LaunchArgs::new(b"a");
+        let (result, output) = run(&[b"OK", b"OK", b"pid:10;"], &args);
+        assert_eq!(result.unwrap(), 0x10);
+        let mut expected = frame_packet(b"A2,0,61");
+        expected.extend(frame_packet(b"qLaunchSuccess"));
+        expected.extend(frame_packet(b"qProcessInfo"));
+        assert_eq!(output, expected);
+
+        args.set_stdout(b"o");
+        let (result, output) = run(&[b"OK", b"OK", b"Eno such file"], &args);
+        match result {
+            Err(RspError::Remote(RemoteError::Message(ref text))) if text == "no such file" => { }
+            other => panic!("expected a launch failure, got {:?}", other),
+        }
+        assert!(output.starts_with(&frame_packet(b"QSetSTDOUT:6f")));
+
+        let input = frame_packet(b"pid:2a;");
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(process_info(&mut rsp, Some(42)).unwrap().pid, 42);
+        }
+        assert_eq!(output, frame_packet(b"qProcessInfoPID:42"));
+    }
+}
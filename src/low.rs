@@ -1,10 +1,25 @@
 #![deny(missing_docs)]
 
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
 use std::io;
+use std::io::IoSlice;
 use std::io::Read;
 use std::io::Write;
+use std::thread;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
-use util::decode_hex;
+use compression::{decompress_packet, enable_compression_packet, CompressionType};
+use echo::ConnectionStats;
+use extension::{ExtensionRegistry, PacketExtension};
+use packet::ParsedPacket;
+use proxy::{Direction, ProxyAction, ProxyHandler};
+use qxfer::QXferError;
+use util;
+use util::{decode_hex, hex_byte, request_gets_reply};
 
 /// A low-level error that occurred when communicating over the RSP
 /// connection.
@@ -16,23 +31,150 @@ pub enum RspError {
     /// checksums are only checked in "ack" mode; if `QStartNoAckMode`
     /// is used, then acking and checksum checking are disabled.
     InvalidChecksum,
-    /// The maximum number of ack retries was exceeded.
+    /// The maximum number of ack retries, or of consecutive NAKs, was
+    /// exceeded.
     TooManyRetries,
+    /// A packet would not fit within the maximum packet size, or a
+    /// packet was received that exceeded the limit set by
+    /// `RspConnection::set_maximum_packet_size`.
+    PacketTooLarge,
+    /// A client function was called with an invalid argument, for
+    /// example a memory range that wraps around the address space.
+    /// Nothing was sent.
+    InvalidArgument(&'static str),
+    /// An operation was attempted that is not valid in the current
+    /// mode, for example waiting for a stop reply after resuming in
+    /// non-stop mode, or sending a packet with a `PacketWriter` in
+    /// acking mode.
+    WrongMode,
+    /// A packet was received that did not conform to the protocol.
+    /// This is returned by the packet parsers in this crate.
+    ProtocolViolation {
+        /// The contents of the offending packet.
+        packet: Vec<u8>,
+        /// A description of the problem.
+        reason: &'static str,
+    },
+    /// No input arrived within the read timeout, or before the
+    /// deadline; see `RspConnection::set_read_timeout`.
+    Timeout,
+    /// The remote replied to a request with an error.
+    Remote(RemoteError),
+    /// A `qXfer` read failed; see `read_qxfer`.
+    QXfer(QXferError),
+}
+
+/// An error reply, such as a stub sends when a request fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemoteError {
+    /// `E NN`: an error number.  Its meaning is up to the stub, though
+    /// it is often an errno value.
+    Code(u8),
+    /// `E.message`: a textual error.  A stub only sends this if the
+    /// client included `error-message+` in its `qSupported` request.
+    Message(String),
+}
+
+impl RemoteError {
+    /// Parse `reply` as an error reply.  Returns `None` if it is not
+    /// one.
+    pub fn parse(reply: &[u8]) -> Option<RemoteError> {
+        match reply {
+            [b'E', b'.', message @ ..] => {
+                Some(RemoteError::Message(String::from_utf8_lossy(message).into_owned()))
+            }
+            [b'E', a, b] => decode_hex(&[*a, *b]).map(|code| RemoteError::Code(code as u8)),
+            _ => None,
+        }
+    }
+
+    /// Build the reply for this error, for use by a server.  A
+    /// `Message` should only be sent if the client advertised the
+    /// `error-message` feature.
+    pub fn to_reply(&self) -> Vec<u8> {
+        match *self {
+            RemoteError::Code(code) => format!("E{:02x}", code).into_bytes(),
+            RemoteError::Message(ref message) => format!("E.{}", message).into_bytes(),
+        }
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RemoteError::Code(code) => write!(f, "remote error {:02x}", code),
+            RemoteError::Message(ref message) => f.write_str(message),
+        }
+    }
+}
+
+/// Check a reply for an error, for use by a client.  An error reply is
+/// returned as `RspError::Remote`; any other reply is passed through.
+pub fn check_reply(reply: Vec<u8>) -> RspResult<Vec<u8>> {
+    match RemoteError::parse(&reply) {
+        Some(error) => Err(RspError::Remote(error)),
+        None => Ok(reply),
+    }
 }
 
 /// The result of a RSP request.
 pub type RspResult<T> = Result<T, RspError>;
 
-/// The type of a packet.
+/// How closely a stop reply must follow the documented grammar; see
+/// `parse_stop_reply_with_strictness`.  Only the stop reply parser
+/// takes this.  The other parsers in this crate, such as those for
+/// thread-ids, `qXfer` replies, and File-I/O requests, are always
+/// lenient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strictness {
+    /// Reject anything outside the grammar documented in the GDB
+    /// manual.  This is useful when validating a stub under
+    /// development.
+    Strict,
+    /// Accept common deviations seen in real stubs, such as extra
+    /// semicolons, uppercase hex digits, a missing trailing
+    /// separator, or an exit status wider than two digits.
+    Lenient,
+}
+
+/// The type of a packet.  Besides packets proper, this covers the
+/// single-character control messages that can arrive between
+/// packets.  These are only returned by `read_packet` when
+/// `set_report_control_characters` is enabled, and always have empty
+/// contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PacketType {
     /// A normal packet.
     Normal,
     /// A notification packet.
     Notification,
+    /// An ack, `+`.
+    Ack,
+    /// A NAK, `-`, asking for the last packet to be resent.
+    Nak,
+    /// The interrupt character, 0x03.
+    Interrupt,
+}
+
+/// How a connection treats the sequence ids of the obsolete form of
+/// the protocol, in which a packet could start with a two-digit hex
+/// id and a colon, as in `$1f:OK#..`.  Some very old stubs still send
+/// these.  See `RspConnection::set_sequence_ids`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceIdMode {
+    /// Sequence ids are not recognized, and are left in the packet
+    /// contents.  This is the default.
+    Off,
+    /// A sequence id is stripped from the contents of a received
+    /// packet; see `RspConnection::last_sequence_id`.
+    Strip,
+    /// As for `Strip`, and in acking mode the ack echoes the sequence
+    /// id, as in `+1f`, as the old protocol required.
+    Echo,
 }
 
 /// Part of a process id.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Id {
     /// A process or thread id.  This value may not be 0 or -1.
     Id(u32),
@@ -48,7 +190,10 @@ pub enum Id {
 /// that is passed across the wire.  It needn't correspond to any real
 /// process id (though obviously it may be more convenient when it
 /// does).
-#[derive(Clone, Copy)]
+///
+/// The derived equality is exact: `All` is only equal to `All`.  Use
+/// `matches` to treat `All` and `Any` as wildcards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ProcessId {
     /// The process id.
     pub pid: Id,
@@ -56,22 +201,156 @@ pub struct ProcessId {
     pub tid: Id,
 }
 
+impl Id {
+    /// Return true if this id matches `other`, treating `All` and
+    /// `Any` on either side as wildcards that match any id.
+    pub fn matches(&self, other: &Id) -> bool {
+        match (*self, *other) {
+            (Id::Id(a), Id::Id(b)) => a == b,
+            _ => true,
+        }
+    }
+}
+
 impl ProcessId {
+    /// Return true if this thread-id matches `other`, treating `All`
+    /// and `Any` as wildcards in both the process and thread parts.
+    /// For example, `p1.-1` from a `vCont` action matches the
+    /// concrete thread `p1.2` from a thread list.
+    pub fn matches(&self, other: &ProcessId) -> bool {
+        self.pid.matches(&other.pid) && self.tid.matches(&other.tid)
+    }
+
     /// Make a new process/thread id.  |pid| is the process id; it
     /// must be greater than or equal to zero.  |tid|, if given, is
     /// the thread id.
+    #[deprecated(note = "use try_new, which does not panic")]
     pub fn new(pid: i32, tid: Option<i32>) -> ProcessId {
         assert!(pid > 0);
         let mut result = ProcessId { pid: Id::Id(pid as u32), tid: Id::Any };
-        match tid {
-            Some(value) => {
-                assert!(value > 0);
-                result.tid = Id::Id(value as u32);
-            }
-            None => { }
+        if let Some(value) = tid {
+            assert!(value > 0);
+            result.tid = Id::Id(value as u32);
         }
         result
     }
+
+    /// Make a new process/thread id, using the protocol's conventions:
+    /// a `pid` or `tid` of 0 means `Any`, and -1 means `All`.  If
+    /// `tid` is not given, it is `Any`.  Returns `None` if either
+    /// value is less than -1.
+    pub fn try_new(pid: i32, tid: Option<i32>) -> Option<ProcessId> {
+        fn to_id(value: i32) -> Option<Id> {
+            match value {
+                -1 => Some(Id::All),
+                0 => Some(Id::Any),
+                v if v > 0 => Some(Id::Id(v as u32)),
+                _ => None,
+            }
+        }
+        Some(ProcessId { pid: to_id(pid)?, tid: to_id(tid.unwrap_or(0))? })
+    }
+
+    /// Return the thread-id meaning any thread of any process.
+    pub fn any() -> ProcessId {
+        ProcessId { pid: Id::Any, tid: Id::Any }
+    }
+
+    /// Return the thread-id meaning all threads of the process `pid`.
+    /// A `pid` of 0 means any process.
+    pub fn all_threads_of(pid: u32) -> ProcessId {
+        let pid = if pid == 0 { Id::Any } else { Id::Id(pid) };
+        ProcessId { pid, tid: Id::All }
+    }
+
+    /// Parse a "thread-id" as it appears in a packet.  Both the
+    /// multiprocess forms, `pPID.TID` and `pPID`, and the plain `TID`
+    /// form are accepted.  A bare `pPID` means all threads of that
+    /// process; a plain `TID` leaves the process as `Any`.  Returns
+    /// `None` if `buf` is not a valid thread-id.
+    pub fn parse(buf: &[u8]) -> Option<ProcessId> {
+        if buf.first() != Some(&b'p') {
+            return Some(ProcessId { pid: Id::Any, tid: parse_id(buf)? });
+        }
+
+        let rest = &buf[1..];
+        match rest.iter().position(|&c| c == b'.') {
+            Some(dot) => Some(ProcessId {
+                pid: parse_id(&rest[..dot])?,
+                tid: parse_id(&rest[dot + 1..])?,
+            }),
+            None => Some(ProcessId { pid: parse_id(rest)?, tid: Id::All }),
+        }
+    }
+}
+
+/// `Id` is displayed as it appears in a thread-id: in hex, or as
+/// `-1` for `All` and `0` for `Any`.
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Id::Id(val) => write!(f, "{:x}", val),
+            Id::All => f.write_str("-1"),
+            Id::Any => f.write_str("0"),
+        }
+    }
+}
+
+/// `ProcessId` is displayed in GDB's `pPID.TID` notation, or as a
+/// plain `TID` when the process is `Any`, so that the result parses
+/// back to the same value.
+impl fmt::Display for ProcessId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pid {
+            Id::Any => write!(f, "{}", self.tid),
+            pid => write!(f, "p{}.{}", pid, self.tid),
+        }
+    }
+}
+
+/// The error returned when parsing an `Id` or `ProcessId` from a
+/// string fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseThreadIdError;
+
+impl fmt::Display for ParseThreadIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid thread-id")
+    }
+}
+
+impl error::Error for ParseThreadIdError {}
+
+impl FromStr for Id {
+    type Err = ParseThreadIdError;
+
+    fn from_str(s: &str) -> Result<Id, ParseThreadIdError> {
+        parse_id(s.as_bytes()).ok_or(ParseThreadIdError)
+    }
+}
+
+/// This accepts the same forms as `ProcessId::parse`.
+impl FromStr for ProcessId {
+    type Err = ParseThreadIdError;
+
+    fn from_str(s: &str) -> Result<ProcessId, ParseThreadIdError> {
+        ProcessId::parse(s.as_bytes()).ok_or(ParseThreadIdError)
+    }
+}
+
+// Parse a single component of a thread-id.
+fn parse_id(buf: &[u8]) -> Option<Id> {
+    if buf == b"-1" {
+        return Some(Id::All);
+    }
+    if buf.is_empty() {
+        return None;
+    }
+    match decode_hex(buf)? {
+        0 => Some(Id::Any),
+        n if n <= u64::from(u32::MAX) => Some(Id::Id(n as u32)),
+        _ => None,
+    }
 }
 
 // fixme -
@@ -83,8 +362,8 @@ impl ProcessId {
 /// supplies a number of convenience methods for constructing and
 /// parsing RSP packets.
 pub struct RspConnection<'conn> {
-    wchan: &'conn mut Write,
-    rchan: &'conn mut Read,
+    wchan: Box<dyn Write + Send + 'conn>,
+    rchan: Box<dyn Read + Send + 'conn>,
 
     // True if we must ack packets.
     acking: bool,
@@ -93,39 +372,185 @@ pub struct RspConnection<'conn> {
     // some protocol details.
     is_client: bool,
 
+    // On the client side, whether the stub accepted "!"; see
+    // set_extended_mode.
+    extended_mode: bool,
+
     // If 0, not in a packet; otherwise holds the packet type.
     in_packet: u8,
 
     // Checksum of the packet currently being constructed.
     checksum: u8,
 
-    // When acking we must keep the last packet around.
-    last_packet: Vec<u8>,
+    // The packet being written, from its '$' or '%' on.  Writes to an
+    // open packet are collected here, so that the whole packet can be
+    // sent with a single write.  When acking, it is kept until the
+    // ack arrives in case it must be resent, unless |retain| is false;
+    // see send_packet_with.
+    packet: Vec<u8>,
+    retain: bool,
 
     // The maximum number of times to retry an ack.
     max_retries: Option<u16>,
+
+    // The maximum number of consecutive NAKs, regardless of
+    // max_retries.
+    max_naks: Option<u32>,
+
+    // The largest packet contents that will be accepted when reading.
+    max_packet_size: Option<usize>,
+
+    // The delay before the first retransmission, and the limit that
+    // the delay doubles up to on each later one.
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+
+    // On the server side in acking mode, the last request received
+    // since a reply was started.  See read_packet.
+    last_received: Option<Vec<u8>>,
+
+    // Notifications that arrived while waiting for a reply.
+    notifications: VecDeque<Vec<u8>>,
+
+    // If true, finished packets are not flushed when acking is off;
+    // see set_batching.  |unflushed| is true if something has been
+    // written since the last flush.
+    batching: bool,
+    unflushed: bool,
+
+    // If true, read_packet returns control characters seen between
+    // packets; see set_report_control_characters.
+    report_control: bool,
+
+    // If true, '*' in a received packet starts a run-length encoding;
+    // see set_rle_decoding.
+    rle: bool,
+
+    // On the client side, how the stub compresses the packets it
+    // sends, if it does; see enable_compression.
+    compression: Option<CompressionType>,
+
+    // What to do with sequence ids in received packets, and the id of
+    // the last packet read; see set_sequence_ids.
+    sequence_ids: SequenceIdMode,
+    sequence_id: Option<u8>,
+
+    // The limit on each wait for input, and the time by which reading
+    // must be done; see set_read_timeout and set_deadline.
+    // |timeout_hook| passes the resulting limit on to the reader, and
+    // |applied_timeout| is the limit it was last given.
+    read_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    timeout_hook: Option<Box<TimeoutHook<'conn>>>,
+    applied_timeout: Option<Duration>,
+
+    // A packet whose reading was cut short, for example by a timeout,
+    // so that the next read can carry on with it.
+    partial: Option<PartialPacket>,
+
+    // On the client side, the number of requests sent whose replies
+    // have not been read; see drain_reply.
+    outstanding: usize,
+
+    // See stats.
+    stats: ConnectionStats,
+
+    // The hook called for each packet; see set_packet_handler.  On
+    // the client side, |synthesized| holds the replies it gave to
+    // requests that were not sent, to be read before anything else.
+    handler: Option<Box<PacketHandler<'conn>>>,
+    synthesized: VecDeque<Vec<u8>>,
+
+    // See register_extension.
+    extensions: ExtensionRegistry,
+}
+
+// A hook called for each packet sent or received on a connection.
+type PacketHandler<'conn> = dyn ProxyHandler + Send + 'conn;
+
+// A function that sets the read timeout of a connection's reader.
+type TimeoutHook<'conn> = dyn FnMut(Option<Duration>) -> io::Result<()> + Send + 'conn;
+
+// The state of a packet that is being read.
+struct PartialPacket {
+    packet_type: PacketType,
+    contents: Vec<u8>,
+    checksum: u8,
+    // The last character, which a run-length encoding repeats.
+    prev_ch: u8,
+    // True if a '*' was read, and its repeat count is next.
+    repeat: bool,
+    // The checksum digits, once the '#' has been read.
+    trailer: Option<Vec<u8>>,
+    // True if the contents grew past the maximum packet size, and so
+    // were dropped.  The rest of the packet is read and discarded.
+    oversized: bool,
+    // True if a run-length count was invalid.  The rest of the packet
+    // is read, so that the next one can be found.
+    bad_repeat: bool,
+}
+
+impl PartialPacket {
+    // Append |count| copies of |ch| to the contents, unless that would
+    // make them longer than |limit|.
+    fn push(&mut self, ch: u8, count: usize, limit: Option<usize>) {
+        if self.oversized {
+            return;
+        }
+        if limit.is_some_and(|limit| self.contents.len() + count > limit) {
+            self.oversized = true;
+            self.contents = Vec::new();
+        } else {
+            self.contents.extend(::std::iter::repeat_n(ch, count));
+        }
+    }
+}
+
+/// A snapshot of an `RspConnection`'s state, for logging.  This is
+/// returned by `RspConnection::debug_state`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RspConnectionState {
+    /// True if the connection is in acking mode.
+    pub acking: bool,
+    /// True if this is the client side of the connection.
+    pub is_client: bool,
+    /// The type of the packet currently being written, or `None` if
+    /// no packet is open.
+    pub open_packet: Option<PacketType>,
+    /// The value set by `set_maximum_retries`.
+    pub maximum_retries: Option<u16>,
+    /// The value set by `set_maximum_naks`.
+    pub maximum_naks: Option<u32>,
+    /// The value set by `set_maximum_packet_size`.
+    pub maximum_packet_size: Option<usize>,
 }
 
+/// The default limit on consecutive NAKs; see `set_maximum_naks`.
+pub const DEFAULT_MAXIMUM_NAKS: u32 = 1000;
+
+/// The default limit on the size of a received packet; see
+/// `set_maximum_packet_size`.
+pub const DEFAULT_MAXIMUM_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
 impl<'conn> Write for RspConnection<'conn> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let result = self.wchan.write(buf);
-        if let Ok(nbytes) = result {
-            for i in 0..nbytes {
-                self.checksum = self.checksum.wrapping_add(buf[i]);
-            }
-            if self.acking {
-                self.last_packet.extend_from_slice(&buf[0..nbytes]);
-            }
+        if self.in_packet == 0 {
+            return self.wchan.write(buf);
         }
-        result
+        for ch in buf {
+            self.checksum = self.checksum.wrapping_add(*ch);
+        }
+        self.packet.extend_from_slice(buf);
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = false;
         self.wchan.flush()
     }
 }
 
-// Ensure we can use try! to turn an io::Error into an RspError.
+// Ensure we can use ? to turn an io::Error into an RspError.
 impl From<io::Error> for RspError {
     fn from(t: io::Error) -> Self {
         RspError::IOError(t)
@@ -137,17 +562,50 @@ impl<'conn> RspConnection<'conn> {
     /// object should be an RSP client, or `False` if this object
     /// should be an RSP server.  (The two halves differ in some
     /// protocol details.)  The reader and writer should already be
-    /// connected to the other side.
-    pub fn new(reader: &'conn mut Read, writer: &'conn mut Write, is_client: bool) -> RspConnection<'conn> {
+    /// connected to the other side.  They must be `Send`, so that the
+    /// connection can be split between threads; see `split`.
+    pub fn new(reader: &'conn mut (dyn Read + Send), writer: &'conn mut (dyn Write + Send),
+               is_client: bool) -> RspConnection<'conn> {
+        RspConnection::from_boxes(Box::new(reader), Box::new(writer), is_client)
+    }
+
+    // Create a new RspConnection that owns its channels.
+    fn from_boxes(reader: Box<dyn Read + Send + 'conn>, writer: Box<dyn Write + Send + 'conn>,
+                  is_client: bool) -> RspConnection<'conn> {
         RspConnection {
             wchan: writer,
             rchan: reader,
             acking: true,
-            is_client: is_client,
+            is_client,
+            extended_mode: false,
             in_packet: 0,
             checksum: 0,
-            last_packet: Vec::new(),
+            packet: Vec::new(),
+            retain: true,
             max_retries: None,
+            max_naks: Some(DEFAULT_MAXIMUM_NAKS),
+            max_packet_size: Some(DEFAULT_MAXIMUM_PACKET_SIZE),
+            retry_delay: Duration::from_secs(0),
+            max_retry_delay: Duration::from_secs(0),
+            last_received: None,
+            notifications: VecDeque::new(),
+            batching: false,
+            unflushed: false,
+            report_control: false,
+            rle: true,
+            compression: None,
+            sequence_ids: SequenceIdMode::Off,
+            sequence_id: None,
+            read_timeout: None,
+            deadline: None,
+            timeout_hook: None,
+            applied_timeout: None,
+            partial: None,
+            outstanding: 0,
+            stats: ConnectionStats::default(),
+            handler: None,
+            synthesized: VecDeque::new(),
+            extensions: ExtensionRegistry::new(),
         }
     }
 
@@ -158,44 +616,270 @@ impl<'conn> RspConnection<'conn> {
         self.max_retries = max;
     }
 
+    /// Record whether the stub is in extended mode, on the client
+    /// side.  `enter_extended_mode` sets this when the stub accepts
+    /// `!`, so this only needs calling when `!` was sent some other
+    /// way.  Operations that send packets which are only valid in
+    /// extended mode, such as `R` and `vRun`, should check this.  The
+    /// default is `false`.
+    pub fn set_extended_mode(&mut self, extended: bool) {
+        self.extended_mode = extended;
+    }
+
+    /// Return true if the stub is in extended mode; see
+    /// `set_extended_mode`.
+    pub fn extended_mode(&self) -> bool {
+        self.extended_mode
+    }
+
+    /// Ask the stub to enter extended mode with `!`, for use by a
+    /// client.  In extended mode, the stub keeps running after the
+    /// inferior exits or is killed, and accepts packets such as `R`
+    /// and `vRun`.  Returns true if the stub accepted, in which case
+    /// this is recorded; see `set_extended_mode`.
+    pub fn enter_extended_mode(&mut self) -> RspResult<bool> {
+        assert!(self.is_client);
+        let accepted = self.request(b"!")? == b"OK";
+        if accepted {
+            self.extended_mode = true;
+        }
+        Ok(accepted)
+    }
+
+    /// Set the maximum number of consecutive NAKs (or other non-ack
+    /// characters) that will be accepted while waiting for an ack.
+    /// Unlike `set_maximum_retries`, this applies even when the retry
+    /// limit is `None`, so that a broken stub cannot keep this side
+    /// retransmitting forever.  The default is
+    /// `Some(DEFAULT_MAXIMUM_NAKS)`; `None` removes the limit.
+    pub fn set_maximum_naks(&mut self, max: Option<u32>) {
+        self.max_naks = max;
+    }
+
+    /// Set the largest packet that will be accepted when reading, as
+    /// the length of its contents after run-length decoding.  A larger
+    /// packet is read to its end and discarded, so that its contents
+    /// are never held in memory, and reading it fails with
+    /// `RspError::PacketTooLarge`; in acking mode it is still acked,
+    /// so that the peer does not resend it.  This protects against a
+    /// peer that sends an unbounded packet.  The default is
+    /// `Some(DEFAULT_MAXIMUM_PACKET_SIZE)`; `None` removes the limit.
+    ///
+    /// A server can pass the `PacketSize` that it reports in its
+    /// `qSupported` reply, since a client must not send more.  On a
+    /// client, `startup` raises the limit to the stub's `PacketSize`
+    /// if that is larger; it never lowers it, since some stubs send
+    /// replies larger than the size they advertise.
+    pub fn set_maximum_packet_size(&mut self, max: Option<usize>) {
+        self.max_packet_size = max;
+    }
+
+    /// Return the limit set by `set_maximum_packet_size`.
+    pub fn maximum_packet_size(&self) -> Option<usize> {
+        self.max_packet_size
+    }
+
+    /// Set the delay between retransmissions.  The first resend waits
+    /// for `initial`, and each later one waits twice as long as the
+    /// one before, up to `max`.  By default there is no delay.
+    pub fn set_retry_backoff(&mut self, initial: Duration, max: Duration) {
+        self.retry_delay = initial;
+        self.max_retry_delay = max;
+    }
+
+    /// Set the longest time that reading a packet or an ack will wait
+    /// for input.  When it runs out, the read fails with
+    /// `RspError::Timeout`.  The default is `None`, meaning to wait
+    /// forever.
+    ///
+    /// Only the reader can stop a read that is blocked, so this needs
+    /// a hook that sets the reader's own timeout; see
+    /// `set_read_timeout_hook`.  The channel types in this crate
+    /// install one where the underlying stream supports it.  If there
+    /// is no hook, the timeout is not enforced.
+    ///
+    /// A timeout leaves the connection usable.  If it happens part way
+    /// through a packet, the next read carries on with that packet.
+    /// On the client side, the reply to the request that timed out is
+    /// still to come, and should be read with `drain_reply` before
+    /// anything else is sent.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Set a time by which all reading must be done.  A read that
+    /// would wait past `deadline` fails with `RspError::Timeout`, as
+    /// does any read once the deadline has passed.  This combines
+    /// with `set_read_timeout`, and is enforced in the same way; but
+    /// even without a hook, the deadline is checked before each
+    /// character is read.  The default is `None`.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Set the function that applies the read timeout to this
+    /// connection's reader.  It is called before a read whenever the
+    /// limit changes, with `None` meaning no limit; for a socket, it
+    /// would call `set_read_timeout`.  The reader must then report a
+    /// timeout as an error of kind `WouldBlock` or `TimedOut`.
+    pub fn set_read_timeout_hook<F>(&mut self, hook: F)
+        where F: FnMut(Option<Duration>) -> io::Result<()> + Send + 'conn
+    {
+        self.timeout_hook = Some(Box::new(hook));
+        self.applied_timeout = None;
+    }
+
+    /// Set a handler that is called for every packet sent or received
+    /// on this connection, replacing any that was set before.  The
+    /// handler is used as in a `Proxy`: it can log, rewrite, drop, or
+    /// answer packets.  On a client, the packets sent travel
+    /// `Direction::ToStub` and those received `Direction::ToDebugger`;
+    /// on a server, it is the other way around.
+    ///
+    /// A handler that answers a request with `ProxyAction::Reply` on
+    /// a client keeps the request from being sent, and the reply is
+    /// what the next read returns.  On a server, the reply is sent to
+    /// the client, and reading carries on with the next packet.
+    /// Control characters are not passed to the handler.  After
+    /// `split`, only the reader calls the handler.
+    pub fn set_packet_handler<H>(&mut self, handler: H)
+        where H: ProxyHandler + Send + 'conn
+    {
+        self.handler = Some(Box::new(handler));
+    }
+
+    /// Remove the handler set with `set_packet_handler`, and return
+    /// it.
+    pub fn take_packet_handler(&mut self) -> Option<Box<dyn ProxyHandler + Send + 'conn>> {
+        self.handler.take()
+    }
+
+    /// Register a handler for a custom packet, replacing any with the
+    /// same name.  On a client, a request that the extension handles
+    /// is not sent; the extension's reply is what the next read
+    /// returns, as for a packet handler's `ProxyAction::Reply`.  On a
+    /// server, a request that the extension handles is answered with
+    /// its reply, and is not returned by `read_packet`.  Extensions
+    /// are consulted before the packet handler.
+    pub fn register_extension(&mut self, extension: Box<dyn PacketExtension + Send>) {
+        self.extensions.register(extension);
+    }
+
+    // Return true if packets must be passed to handle_sent and
+    // handle_received.
+    fn has_hooks(&self) -> bool {
+        self.handler.is_some() || !self.extensions.is_empty()
+    }
+
+    // Pass a packet of type |kind| that is about to be sent to the
+    // extensions and the packet handler, which may change |contents|.
+    // Returns false if the packet should not be sent.
+    fn handle_sent(&mut self, kind: PacketType, contents: &mut Vec<u8>) -> RspResult<bool> {
+        if self.is_client && kind == PacketType::Normal {
+            if let Some(reply) = self.extensions.dispatch(contents) {
+                self.synthesized.push_back(reply);
+                return Ok(false);
+            }
+        }
+        let direction = if self.is_client { Direction::ToStub } else { Direction::ToDebugger };
+        let action = match self.handler {
+            Some(ref mut handler) => handler.packet(direction, kind, contents),
+            None => return Ok(true),
+        };
+        match action {
+            ProxyAction::Forward => Ok(true),
+            ProxyAction::Drop => Ok(false),
+            ProxyAction::Reply(reply) => {
+                if !self.is_client || kind != PacketType::Normal {
+                    return Err(RspError::InvalidArgument("only a request can be replied to"));
+                }
+                self.synthesized.push_back(reply);
+                Ok(false)
+            }
+        }
+    }
+
+    // Pass a packet of type |kind| that was just read to the
+    // extensions and the packet handler, which may change |contents|.
+    // Returns false if the packet should not be returned to the
+    // caller.
+    fn handle_received(&mut self, kind: PacketType, contents: &mut Vec<u8>) -> RspResult<bool> {
+        if !self.is_client && kind == PacketType::Normal {
+            if let Some(reply) = self.extensions.dispatch(contents) {
+                self.send_full_packet(&reply)?;
+                return Ok(false);
+            }
+        }
+        let direction = if self.is_client { Direction::ToDebugger } else { Direction::ToStub };
+        let action = match self.handler {
+            Some(ref mut handler) => handler.packet(direction, kind, contents),
+            None => return Ok(true),
+        };
+        match action {
+            ProxyAction::Forward => Ok(true),
+            ProxyAction::Drop => Ok(false),
+            ProxyAction::Reply(reply) => {
+                if self.is_client || kind != PacketType::Normal {
+                    return Err(RspError::InvalidArgument("only a request can be replied to"));
+                }
+                self.send_full_packet(&reply)?;
+                Ok(false)
+            }
+        }
+    }
+
     /// Start a new packet.  The caller is responsible for the entire
     /// contents of the packet, but the framing is handled by this
     /// object.  Call `finish_packet` when the packet contents are
     /// fully written; it's an error to start a new packet before the
     /// current packet is finished.
+    ///
+    /// Nothing is sent until the packet is finished; writes are
+    /// buffered, and `finish_packet` sends the whole packet with a
+    /// single write.
     pub fn start_packet(&mut self) -> RspResult<()> {
+        // Once a reply is underway, a repeat of the request is a new
+        // request rather than a retransmission.
+        self.last_received = None;
+        self.open_packet(b'$');
+        Ok(())
+    }
+
+    // Start buffering a packet of type |kind|.
+    fn open_packet(&mut self, kind: u8) {
         assert!(self.in_packet == 0);
         self.checksum = 0;
-        self.in_packet = b'$';
-        // Bypass the checksumming.
-        try!(self.wchan.write_all(b"$"));
-        Ok(())
+        self.in_packet = kind;
+        self.retain = true;
+        self.packet.clear();
+        self.packet.push(kind);
     }
 
     /// Start a new notification packet.  The caller is responsible
     /// for the entire contents of the packet, but the framing is
     /// handled by this object.  Call `finish_packet` when the packet
     /// contents are fully written; it's an error to start a new
-    /// packet before the current packet is finished.
+    /// packet before the current packet is finished.  As with
+    /// `start_packet`, the packet is buffered until it is finished.
     ///
     /// Note that while notifications are defined for both sides of
     /// the protocol, in practice they are only emitted by servers.
     pub fn start_notification_packet(&mut self) -> RspResult<()> {
-        assert!(self.in_packet == 0);
-        self.checksum = 0;
-        self.in_packet = b'%';
-        // Bypass the checksumming.
-        try!(self.wchan.write_all(b"%"));
+        self.open_packet(b'%');
         Ok(())
     }
 
     /// Finish a packet.  Either `start_packet` or
     /// `start_notification_packet` must have been called previously.
+    /// The packet is sent with a single write.
     ///
     /// When the `RspConnection` is in acking mode, this method will
     /// read an ack, and will resend the current packet until acked.
-    /// This will respect any value set using `set_maximum_retries`,
-    /// returning `TooManyRetries` if this is exceeded.
+    /// This will respect any values set using `set_maximum_retries`
+    /// and `set_maximum_naks`, returning `TooManyRetries` if either is
+    /// exceeded, and will wait between resends as configured by
+    /// `set_retry_backoff`.  Notifications are never acked, so for a
+    /// notification this does not wait.
     ///
     /// Note that this method does not read any other reply from the
     /// remote.  That is, on the client side, `read_packet` must be
@@ -203,151 +887,633 @@ impl<'conn> RspConnection<'conn> {
     /// as-yet-unwritten (FIXME) method must be called to read the
     /// response to a notification.
     pub fn finish_packet(&mut self) -> RspResult<()> {
+        self.finish_packet_with(|conn| Ok(conn.wchan.write_all(&conn.packet)?))
+    }
+
+    // Finish a packet as for finish_packet, calling |resend| to write
+    // the whole packet again whenever it must be resent.
+    fn finish_packet_with<F>(&mut self, resend: F) -> RspResult<()>
+        where F: FnMut(&mut RspConnection<'conn>) -> RspResult<()>
+    {
         assert!(self.in_packet != 0);
         let kind = self.in_packet;
+        if self.has_hooks() {
+            let packet_type = if kind == b'$' { PacketType::Normal } else { PacketType::Notification };
+            let mut contents = self.packet.split_off(1);
+            match self.handle_sent(packet_type, &mut contents) {
+                Ok(true) => {
+                    self.checksum = contents.iter().fold(0, |sum: u8, &c| sum.wrapping_add(c));
+                    self.packet.extend_from_slice(&contents);
+                }
+                result => {
+                    self.in_packet = 0;
+                    self.packet.clear();
+                    return result.map(|_| ());
+                }
+            }
+        }
+        self.close_packet();
+        self.wchan.write_all(&self.packet)?;
+        let contents = &self.packet[1..self.packet.len() - 3];
+        let gets_reply = kind == b'$' && request_gets_reply(contents);
+        self.sent(gets_reply);
+        // Notifications are not acked, and so are never resent.
+        let notification = kind == b'%';
+        if !self.retain {
+            self.packet = Vec::new();
+        } else if !self.acking || notification {
+            self.packet.clear();
+        }
+        self.end_packet()?;
+        if notification {
+            return Ok(());
+        }
+        self.await_ack(resend)
+    }
+
+    // Note that a packet was sent; |gets_reply| is true if it is a
+    // request that will be answered.
+    fn sent(&mut self, gets_reply: bool) {
+        if self.is_client && gets_reply {
+            self.outstanding += 1;
+        }
+    }
+
+    // Add the trailer to the buffered packet.
+    fn close_packet(&mut self) {
         self.in_packet = 0;
-        // Bypass the checksumming.
-        try!(write!(self.wchan, "#{:02x}", self.checksum));
-        try!(self.wchan.flush());
+        let trailer = self.trailer();
+        self.packet.extend_from_slice(&trailer);
+    }
+
+    // Return the "#xx" trailer for the current packet.
+    fn trailer(&self) -> [u8; 3] {
+        let digits = hex_byte(self.checksum);
+        [b'#', digits[0], digits[1]]
+    }
+
+    // Flush the just-written packet, unless batching.
+    fn end_packet(&mut self) -> RspResult<()> {
+        if self.batching && !self.acking {
+            self.unflushed = true;
+        } else {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write all of |bufs| to the channel, as few writes as the channel
+    // allows.
+    fn write_all_vectored(&mut self, mut bufs: &mut [IoSlice]) -> io::Result<()> {
+        IoSlice::advance_slices(&mut bufs, 0);
+        while !bufs.is_empty() {
+            match self.wchan.write_vectored(bufs) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => { }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
 
+    // In acking mode, wait for the ack of the packet just sent, calling
+    // |resend| to write the whole packet again whenever it must be
+    // resent.
+    fn await_ack<F>(&mut self, mut resend: F) -> RspResult<()>
+        where F: FnMut(&mut RspConnection<'conn>) -> RspResult<()>
+    {
         if self.acking {
-            let mut count = 0;
+            let mut count: u32 = 0;
+            let mut delay = self.retry_delay;
             loop {
-                let ch = try!(self.read_char());
+                let ch = self.read_char()?;
                 if ch == b'+' {
                     break;
                 }
 
-                if let Some(max) = self.max_retries {
-                    count = count + 1;
-                    if count > max {
-                        return Err(RspError::TooManyRetries);
-                    }
+                count += 1;
+                let too_many_retries = match self.max_retries {
+                    Some(max) => count > u32::from(max),
+                    None => false,
+                };
+                let too_many_naks = match self.max_naks {
+                    Some(max) => count > max,
+                    None => false,
+                };
+                if too_many_retries || too_many_naks {
+                    self.packet.clear();
+                    return Err(RspError::TooManyRetries);
+                }
+
+                if delay > Duration::from_secs(0) {
+                    thread::sleep(delay);
+                    delay = ::std::cmp::min(delay * 2, self.max_retry_delay);
                 }
 
-                let buf = [kind];
-                try!(self.wchan.write_all(&buf));
-                try!(self.wchan.write_all(&self.last_packet));
-                try!(write!(self.wchan, "#{:02x}", self.checksum));
-                try!(self.wchan.flush());
+                resend(self)?;
+                self.flush()?;
             }
 
-            self.last_packet.clear();
+            self.packet.clear();
         }
 
         Ok(())
     }
 
+    /// Send a packet whose contents are written by `build`, which is
+    /// called with the connection after the packet is started.  This
+    /// is like calling `start_packet`, writing, and `finish_packet`;
+    /// but in acking mode, rather than keeping the packet in case it
+    /// must be resent, `build` is simply called again.  This avoids
+    /// holding on to a large packet, such as an `X` packet during a
+    /// flash load, while waiting for the ack.  `build` must write the
+    /// same contents each time.
+    pub fn send_packet_with<F>(&mut self, mut build: F) -> RspResult<()>
+        where F: FnMut(&mut RspConnection<'conn>) -> RspResult<()>
+    {
+        self.start_packet()?;
+        // A packet handler may rewrite the packet, so then it must be
+        // kept after all.
+        if self.has_hooks() {
+            build(self)?;
+            return self.finish_packet();
+        }
+        self.retain = false;
+        build(self)?;
+        self.finish_packet_with(|conn| {
+            conn.open_packet(b'$');
+            conn.retain = false;
+            let result = build(conn);
+            conn.close_packet();
+            result?;
+            let result = conn.wchan.write_all(&conn.packet);
+            conn.packet = Vec::new();
+            Ok(result?)
+        })
+    }
+
     /// A convenience function that sends an entire packet at once.
-    /// This simply calls `start_packet`, `write_all` with the
-    /// contents, and then `finish_packet`.
+    /// This is like calling `start_packet`, `write_all` with the
+    /// contents, and then `finish_packet`, except that the packet is
+    /// sent with a single vectored write, and no copy of the contents
+    /// is kept for resending.
     pub fn full_packet(&mut self, contents: &[u8]) -> RspResult<()> {
-        try!(self.start_packet());
-        try!(self.write_all(contents));
-        self.finish_packet()
+        if self.has_hooks() {
+            let mut contents = contents.to_vec();
+            if !self.handle_sent(PacketType::Normal, &mut contents)? {
+                return Ok(());
+            }
+            return self.send_full_packet(&contents);
+        }
+        self.send_full_packet(contents)
     }
 
-    /// Disable acking mode on this object.
-    ///
-    /// Note that this can only be done by coordination with the other
-    /// end of the connection, in particular using `QStartNoAckMode`,
-    /// which is not done here.  Calling this method without this
-    /// handshake will cause communication failures.
-    ///
-    /// There is no way to re-enable acking mode.
-    pub fn disable_acking(&mut self) {
-        self.acking = false;
-        // Free any memory taken by the previous vec.
-        self.last_packet = Vec::new();
+    // Send a packet as for full_packet, bypassing the packet handler.
+    fn send_full_packet(&mut self, contents: &[u8]) -> RspResult<()> {
+        assert!(self.in_packet == 0);
+        // As in start_packet.
+        self.last_received = None;
+        self.checksum = contents.iter().fold(0, |sum: u8, &c| sum.wrapping_add(c));
+        let trailer = self.trailer();
+        self.write_all_vectored(&mut [IoSlice::new(b"$"), IoSlice::new(contents),
+                                      IoSlice::new(&trailer)])?;
+        self.sent(request_gets_reply(contents));
+        self.end_packet()?;
+        self.await_ack(|conn| {
+            let trailer = conn.trailer();
+            Ok(conn.write_all_vectored(&mut [IoSlice::new(b"$"), IoSlice::new(contents),
+                                             IoSlice::new(&trailer)])?)
+        })
     }
 
-    /// Write some binary data into an open packet, using the "new"
-    /// 8-bit-clean binary interface.  Check the details of the
-    /// protocol message you are sending to ensure this makes sense;
-    /// and see `write_hex` as well.  `buf` is the raw data to write;
-    /// this method takes care of any quoting that may be necessary.
-    pub fn write_binary(&mut self, buf: &[u8]) -> RspResult<()> {
-        assert!(self.in_packet != 0);
+    /// Set whether finished packets are batched.  Normally each packet
+    /// is flushed as soon as it is finished.  In batching mode, when
+    /// acking is disabled, packets are left in the writer, so that a
+    /// burst of pipelined requests can go out together; the writer is
+    /// flushed when `flush` is called, or before waiting for any
+    /// packet.  In acking mode every packet must be flushed to get
+    /// its ack, so this has no effect.  The default is `false`.
+    pub fn set_batching(&mut self, batching: bool) {
+        self.batching = batching;
+    }
 
-        let mut last_index = 0;
+    /// Set whether `read_packet` reports the control characters that
+    /// arrive between packets: acks, NAKs, and the interrupt
+    /// character.  Normally these are silently discarded.  When this
+    /// is enabled, each one is returned as a packet of type
+    /// `PacketType::Ack`, `PacketType::Nak`, or
+    /// `PacketType::Interrupt`, so that a server can notice an
+    /// interrupt, and a proxy can see everything on the wire.  Acks
+    /// that are consumed while sending a packet are not reported.
+    /// The default is `false`.
+    pub fn set_report_control_characters(&mut self, report: bool) {
+        self.report_control = report;
+    }
 
-        for i in 0..buf.len() {
-            match buf[i] {
-                // It doesn't hurt to always escape "*", and this lets
-                // the code work for both the client and the server.
-                b'$' | b'#' | b'}' | b'*' => {
-                    if i > last_index {
-                        try!(self.write_all(&buf[last_index..i]));
-                        last_index = i + 1;
-                    }
-                    let bytes = [b'}', buf[i] ^ 0x20];
-                    try!(self.write_all(&bytes));
-                }
-                _ => {
-                    // Ignore, it will be caught next time we have to
-                    // escape, or at the end.
-                }
-            }
-        }
+    /// Set whether a client decodes run-length encoding in the packets
+    /// it receives.  Some stubs never use run-length encoding, and send
+    /// `*` unescaped in binary data; for these, turning this off makes
+    /// `*` an ordinary character.  The default is `true`.
+    pub fn set_rle_decoding(&mut self, rle: bool) {
+        self.rle = rle;
+    }
 
-        if buf.len() >= last_index {
-            try!(self.write_all(&buf[last_index..]));
+    /// Ask the stub to compress the packets it sends with
+    /// `compression`, for use by a client; see
+    /// `enable_compression_packet`.  Returns true if the stub
+    /// accepted, in which case this is recorded; see
+    /// `set_compression`.  lldb-server only compresses in no-ack mode,
+    /// so acking should be disabled first.
+    pub fn enable_compression(&mut self, compression: CompressionType, min_size: Option<usize>)
+                              -> RspResult<bool> {
+        assert!(self.is_client);
+        let accepted = self.request(&enable_compression_packet(compression, min_size))? == b"OK";
+        if accepted {
+            self.compression = Some(compression);
         }
+        Ok(accepted)
+    }
 
-        Ok(())
+    /// Record how the stub compresses the packets it sends, on the
+    /// client side.  While this is set, every packet read is passed
+    /// through `decompress_packet` before it is returned, and one that
+    /// cannot be decompressed results in `RspError::ProtocolViolation`.
+    /// `enable_compression` sets this, so this only needs calling when
+    /// `QEnableCompression` was sent some other way.  The default is
+    /// `None`.
+    pub fn set_compression(&mut self, compression: Option<CompressionType>) {
+        self.compression = compression;
     }
 
-    /// Write some binary data into an open packet, using the old hex
-    /// interface -- each byte is written as two hex digits.  Check
-    /// the details of the protocol message you are sending to ensure
-    /// this makes sense; and see `write_binary` as well.
-    pub fn write_hex(&mut self, data: &[u8]) -> RspResult<()> {
-        assert!(self.in_packet != 0);
+    /// Return how the stub compresses its packets; see
+    /// `set_compression`.
+    pub fn compression(&self) -> Option<CompressionType> {
+        self.compression
+    }
 
-        for elt in data.iter() {
-            try!(write!(self, "{:02x}", elt));
-        }
-        Ok(())
+    /// Set how sequence ids in received packets are handled; see
+    /// `SequenceIdMode`.  Only normal packets are affected.  Note that
+    /// a packet whose contents happen to start with two hex digits
+    /// and a colon will be taken to have a sequence id, so this should
+    /// only be turned on for stubs known to send them.  The default is
+    /// `SequenceIdMode::Off`.
+    pub fn set_sequence_ids(&mut self, mode: SequenceIdMode) {
+        self.sequence_ids = mode;
     }
 
-    /// Write a "thread-id" into an open packet.
-    pub fn write_thread_id(&mut self, pid: ProcessId) -> RspResult<()> {
-        // FIXME when not in multiprocess mode...
-        // but maybe this library should be opinionated.
-
-        try!(self.write_all(b"p"));
-        match pid.pid {
-            Id::Id(val) => {
-                try!(write!(self, "{:x}.", val.to_be()));
-                match pid.tid {
-                    Id::Id(val) => try!(write!(self, "{:x}", val.to_be())),
-                    Id::All => try!(self.write_all(b"-1")),
-                    Id::Any => try!(self.write_all(b"0")),
-                };
-            },
-            Id::All => try!(self.write_all(b"-1")),
-            Id::Any => try!(self.write_all(b"0")),
-        };
+    /// Return the sequence id that was stripped from the last packet
+    /// read, or `None` if it had none, or if sequence ids are not
+    /// being recognized.
+    pub fn last_sequence_id(&self) -> Option<u8> {
+        self.sequence_id
+    }
 
-        Ok(())
+    /// Return the statistics gathered about this connection, such as
+    /// the round-trip times measured by `echo`.
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
     }
 
-    /// Send the low-level interrupt, 0x03, to the server.  This is
-    /// not valid when a packet has been opened (e.g., between
-    /// `start_packet` and `finish_packet`).  It is also only valid to
-    /// call this on the client.
-    pub fn interrupt(&mut self) -> RspResult<()> {
+    /// Return the statistics gathered about this connection, so that
+    /// they can be reset, or so that measurements made some other way
+    /// can be recorded.
+    pub fn stats_mut(&mut self) -> &mut ConnectionStats {
+        &mut self.stats
+    }
+
+    /// Write a single control character: `+` for `PacketType::Ack`,
+    /// `-` for `PacketType::Nak`, or 0x03 for
+    /// `PacketType::Interrupt`.  This is not valid when a packet has
+    /// been opened.  Any other type results in
+    /// `RspError::InvalidArgument`.
+    pub fn write_control(&mut self, kind: PacketType) -> RspResult<()> {
+        assert!(self.in_packet == 0);
+        let ch = match kind {
+            PacketType::Ack => b'+',
+            PacketType::Nak => b'-',
+            PacketType::Interrupt => 3,
+            PacketType::Normal | PacketType::Notification => {
+                return Err(RspError::InvalidArgument("not a control character"));
+            }
+        };
+        self.wchan.write_all(&[ch])?;
+        self.flush()?;
+        Ok(())
+    }
+
+    /// A convenience function for clients that sends an entire packet
+    /// and waits for the reply, returning its contents.  Any
+    /// notifications that arrive in the meantime are queued, to be
+    /// retrieved with `pop_notification`, and a reply with a bad
+    /// checksum is waited for again.
+    pub fn request(&mut self, contents: &[u8]) -> RspResult<Vec<u8>> {
+        self.full_packet(contents)?;
+        self.read_reply()
+    }
+
+    /// Wait for the reply to a request that has already been sent,
+    /// as for `request`.  This is useful when the request was built
+    /// up using `start_packet` and `finish_packet`.
+    pub fn read_reply(&mut self) -> RspResult<Vec<u8>> {
+        loop {
+            match self.read_packet() {
+                Ok((PacketType::Normal, reply)) => return Ok(reply),
+                Ok((PacketType::Notification, contents)) => self.notifications.push_back(contents),
+                // Stray control characters don't matter here, and a
+                // bad checksum will be resent.
+                Ok(_) | Err(RspError::InvalidChecksum) => { }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Return the number of requests sent on this connection, on the
+    /// client side, whose replies have not yet been read.  This is
+    /// normally zero between requests; it is more after pipelining,
+    /// or after a read was abandoned, for example because it timed
+    /// out.  The requests that get no reply, `k` and `R`, are not
+    /// counted.
+    pub fn outstanding_replies(&self) -> usize {
+        self.outstanding
+    }
+
+    /// Read a packet toward the replies to requests whose replies have
+    /// not been read, or return `None` if there are none.  Calling this
+    /// until it returns `None` brings the connection back in step, so
+    /// that the next reply read is for the next request sent.  This is
+    /// how a client recovers after abandoning a request, for example
+    /// because a read timed out: otherwise the late reply would be
+    /// taken for the reply to a later request.
+    ///
+    /// The packets are returned so that the caller can look at them;
+    /// for example, after an abandoned wait for a stop, one will be
+    /// the stop reply.  Console output packets (`O`) are returned too,
+    /// but are not counted as replies.  To stop waiting for a running
+    /// inferior, send an interrupt first.  Only requests sent through
+    /// this connection are counted.  Since this reads one packet at a
+    /// time, a timeout here loses nothing.
+    pub fn drain_reply(&mut self) -> RspResult<Option<Vec<u8>>> {
+        if self.outstanding == 0 {
+            return Ok(None);
+        }
+        self.read_reply().map(Some)
+    }
+
+    /// Return the oldest notification queued by `request` or
+    /// `read_reply`, if any.  Notifications are returned in the order
+    /// in which they arrived.
+    pub fn pop_notification(&mut self) -> Option<Vec<u8>> {
+        self.notifications.pop_front()
+    }
+
+    /// Split this connection into a reader and a writer that can be
+    /// used from different threads; for example, so that one thread
+    /// can send an interrupt while another is blocked in
+    /// `read_packet`.  Any batched packets are flushed first.  This
+    /// must not be called while a packet is open.
+    ///
+    /// In acking mode, the reader still acks each packet that it
+    /// reads.  However, the acks for anything the writer sends would
+    /// arrive at the reader, so in this mode the writer can only send
+    /// interrupts.
+    pub fn split(mut self) -> RspResult<(PacketReader<'conn>, PacketWriter<'conn>)> {
+        assert!(self.in_packet == 0);
+        if self.unflushed {
+            self.flush()?;
+        }
+        let shared = Arc::new(Mutex::new(self.wchan));
+        let mut conn = RspConnection::from_boxes(self.rchan, Box::new(SharedWriter(shared.clone())),
+                                                 self.is_client);
+        conn.acking = self.acking;
+        conn.extended_mode = self.extended_mode;
+        conn.last_received = self.last_received;
+        conn.notifications = self.notifications;
+        conn.report_control = self.report_control;
+        conn.rle = self.rle;
+        conn.compression = self.compression;
+        conn.sequence_ids = self.sequence_ids;
+        conn.sequence_id = self.sequence_id;
+        conn.max_packet_size = self.max_packet_size;
+        conn.read_timeout = self.read_timeout;
+        conn.deadline = self.deadline;
+        conn.timeout_hook = self.timeout_hook;
+        conn.applied_timeout = self.applied_timeout;
+        conn.partial = self.partial;
+        conn.outstanding = self.outstanding;
+        conn.stats = self.stats;
+        conn.handler = self.handler;
+        conn.synthesized = self.synthesized;
+        conn.extensions = self.extensions;
+        let writer = PacketWriter {
+            writer: SharedWriter(shared),
+            acking: self.acking,
+            is_client: self.is_client,
+        };
+        Ok((PacketReader { conn }, writer))
+    }
+
+    /// Disable acking mode on this object.
+    ///
+    /// Note that this can only be done by coordination with the other
+    /// end of the connection, in particular using `QStartNoAckMode`,
+    /// which is not done here.  Calling this method without this
+    /// handshake will cause communication failures.
+    ///
+    /// Acking mode can be turned back on with `enable_acking`.
+    pub fn disable_acking(&mut self) {
+        self.acking = false;
+        // Free any memory taken by a packet kept for resending.
+        if self.in_packet == 0 {
+            self.packet = Vec::new();
+        }
+    }
+
+    /// Enable acking mode again after `disable_acking`.  Every new
+    /// connection starts in acking mode, so a server that outlives its
+    /// client, and keeps this object for the next client, must call
+    /// this when that client connects.  As with `disable_acking`, the
+    /// other end must agree.
+    pub fn enable_acking(&mut self) {
+        self.acking = true;
+        // A request from an earlier client is not a retransmission.
+        self.last_received = None;
+    }
+
+    /// Send an ack, `+`, at once.  Packets are acked automatically
+    /// when they are read in acking mode, so this is only needed by
+    /// code that handles the protocol itself, for example a server
+    /// that must ack the first packet of a new client before reading
+    /// it with this object.
+    pub fn send_ack(&mut self) -> RspResult<()> {
+        self.wchan.write_all(b"+")?;
+        self.wchan.flush()?;
+        Ok(())
+    }
+
+    /// Send a NAK, `-`, at once, asking the other end to resend its
+    /// last packet.  See `send_ack`.
+    pub fn send_nack(&mut self) -> RspResult<()> {
+        self.wchan.write_all(b"-")?;
+        self.wchan.flush()?;
+        Ok(())
+    }
+
+    /// Return true if this connection is in acking mode.
+    pub fn is_acking(&self) -> bool {
+        self.acking
+    }
+
+    /// Return true if this is the client side of the connection.
+    pub fn is_client(&self) -> bool {
+        self.is_client
+    }
+
+    /// Return a snapshot of this connection's state, for logging.
+    pub fn debug_state(&self) -> RspConnectionState {
+        RspConnectionState {
+            acking: self.acking,
+            is_client: self.is_client,
+            open_packet: match self.in_packet {
+                b'$' => Some(PacketType::Normal),
+                b'%' => Some(PacketType::Notification),
+                _ => None,
+            },
+            maximum_retries: self.max_retries,
+            maximum_naks: self.max_naks,
+            maximum_packet_size: self.max_packet_size,
+        }
+    }
+
+    /// Write some binary data into an open packet, using the "new"
+    /// 8-bit-clean binary interface.  Check the details of the
+    /// protocol message you are sending to ensure this makes sense;
+    /// and see `write_hex` as well.  `buf` is the raw data to write;
+    /// this method takes care of any quoting that may be necessary.
+    pub fn write_binary(&mut self, buf: &[u8]) -> RspResult<()> {
+        assert!(self.in_packet != 0);
+
+        let mut last_index = 0;
+
+        for i in 0..buf.len() {
+            match buf[i] {
+                // It doesn't hurt to always escape "*", and this lets
+                // the code work for both the client and the server.
+                b'$' | b'#' | b'}' | b'*' => {
+                    if i > last_index {
+                        self.write_all(&buf[last_index..i])?;
+                    }
+                    last_index = i + 1;
+                    let bytes = [b'}', buf[i] ^ 0x20];
+                    self.write_all(&bytes)?;
+                }
+                _ => {
+                    // Ignore, it will be caught next time we have to
+                    // escape, or at the end.
+                }
+            }
+        }
+
+        if buf.len() > last_index {
+            self.write_all(&buf[last_index..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write some binary data into an open packet, using the old hex
+    /// interface -- each byte is written as two hex digits.  Check
+    /// the details of the protocol message you are sending to ensure
+    /// this makes sense; and see `write_binary` as well.
+    pub fn write_hex(&mut self, data: &[u8]) -> RspResult<()> {
+        assert!(self.in_packet != 0);
+
+        // Encode through a buffer, rather than writing each byte.
+        let mut buffer = [0; 512];
+        for chunk in data.chunks(buffer.len() / 2) {
+            for (i, &byte) in chunk.iter().enumerate() {
+                buffer[2 * i..2 * i + 2].copy_from_slice(&hex_byte(byte));
+            }
+            self.write_all(&buffer[..2 * chunk.len()])?;
+        }
+        Ok(())
+    }
+
+    /// Write a number into an open packet in hex, the usual form for
+    /// numbers in the protocol: lowercase, with no leading zeros.
+    pub fn write_hex_number(&mut self, value: u64) -> RspResult<()> {
+        assert!(self.in_packet != 0);
+        let mut text = Vec::new();
+        util::write_hex_number(&mut text, value);
+        self.write_all(&text)?;
+        Ok(())
+    }
+
+    /// Write a number into an open packet in decimal, as used by a few
+    /// packets such as `A`.
+    pub fn write_decimal(&mut self, value: u64) -> RspResult<()> {
+        assert!(self.in_packet != 0);
+        let mut text = Vec::new();
+        util::write_decimal(&mut text, value);
+        self.write_all(&text)?;
+        Ok(())
+    }
+
+    /// Write a "thread-id" into an open packet.  A thread whose
+    /// process is `Id::Any` is written as a bare thread-id, as
+    /// without the multiprocess extensions; any other is written as
+    /// `pPID.TID`.
+    pub fn write_thread_id(&mut self, pid: ProcessId) -> RspResult<()> {
+        // As in GDB, all processes are written as "p-1.", followed by
+        // the thread.
+        let mut text = Vec::new();
+        util::write_thread_id(&mut text, pid);
+        self.write_all(&text)?;
+        Ok(())
+    }
+
+    /// Send the low-level interrupt, 0x03, to the server.  This is
+    /// not valid when a packet has been opened (e.g., between
+    /// `start_packet` and `finish_packet`).  It is also only valid to
+    /// call this on the client.
+    pub fn interrupt(&mut self) -> RspResult<()> {
         assert!(self.in_packet == 0);
         assert!(self.is_client);
-        try!(self.wchan.write_all(b"\x03"));
-        try!(self.wchan.flush());
+        self.wchan.write_all(b"\x03")?;
+        self.flush()?;
+        Ok(())
+    }
+
+    // Work out how long the next read may wait, given the read timeout
+    // and the deadline, and pass it to the timeout hook if it changed.
+    fn apply_timeout(&mut self) -> RspResult<()> {
+        let mut timeout = self.read_timeout;
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RspError::Timeout);
+            }
+            let remaining = deadline - now;
+            timeout = Some(timeout.map_or(remaining, |limit| ::std::cmp::min(limit, remaining)));
+        }
+        if timeout != self.applied_timeout {
+            if let Some(ref mut hook) = self.timeout_hook {
+                hook(timeout)?;
+            }
+            self.applied_timeout = timeout;
+        }
         Ok(())
     }
 
     // Get a single character from the read channel.
     fn read_char(&mut self) -> RspResult<u8> {
+        self.apply_timeout()?;
         let mut buf = [0u8];
         match self.rchan.read_exact(&mut buf) {
+            // Sockets report a timeout as WouldBlock on Unix, and as
+            // TimedOut on Windows.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                || e.kind() == io::ErrorKind::TimedOut => Err(RspError::Timeout),
             Err(e) => Err(RspError::IOError(e)),
             Ok(_) => Ok(buf[0]),
         }
@@ -367,60 +1533,177 @@ impl<'conn> RspConnection<'conn> {
     /// again.  This approach was taken to better handle the (possibly
     /// impossible) case where a notification is delivered while
     /// waiting for a packet to be resent.
+    ///
+    /// On the server side in acking mode, a request that is identical
+    /// to the previous one, and that arrives before any reply was
+    /// started, is assumed to be a retransmission caused by a lost
+    /// ack.  As in gdbserver, it is acked again but not returned.
     pub fn read_packet(&mut self) -> RspResult<(PacketType, Vec<u8>)> {
-        // Ignore anything until we see a packet start.
-        let packet_type = {
-            let mut kind;
-            loop {
-                kind = try!(self.read_char());
-                if kind == b'$' && kind == b'%' {
-                    break;
+        let mut contents = Vec::new();
+        let packet_type = self.read_packet_into(&mut contents)?;
+        Ok((packet_type, contents))
+    }
+
+    /// Read a packet as for `read_packet`, but put its contents in
+    /// `buf` rather than in a new vector, and return its type.  `buf`
+    /// is cleared first.  Reusing one buffer saves an allocation per
+    /// packet in loops that read many packets, such as when stepping.
+    /// If this fails, the contents of `buf` are unspecified.
+    pub fn read_packet_into(&mut self, buf: &mut Vec<u8>) -> RspResult<PacketType> {
+        loop {
+            if let Some(reply) = self.synthesized.pop_front() {
+                *buf = reply;
+                return Ok(PacketType::Normal);
+            }
+            let packet_type = self.read_one_packet(buf)?;
+            let is_packet = packet_type == PacketType::Normal
+                || packet_type == PacketType::Notification;
+            if let (Some(compression), true) = (self.compression, self.is_client && is_packet) {
+                match decompress_packet(compression, buf) {
+                    Ok(contents) => *buf = contents,
+                    Err(err) => {
+                        // The packet still counts as a reply.
+                        if packet_type == PacketType::Normal {
+                            self.outstanding = self.outstanding.saturating_sub(1);
+                        }
+                        return Err(err);
+                    }
                 }
             }
-            if kind == b'$' {
-                PacketType::Normal
-            } else {
-                PacketType::Notification
+            if self.acking && !self.is_client && packet_type == PacketType::Normal {
+                if self.last_received.as_ref() == Some(buf) {
+                    continue;
+                }
+                self.last_received = Some(buf.clone());
             }
-        };
+            // Console output comes before a reply, not instead of one.
+            let output = buf.first() == Some(&b'O') && buf != b"OK";
+            if self.is_client && packet_type == PacketType::Normal && !output {
+                self.outstanding = self.outstanding.saturating_sub(1);
+            }
+            if is_packet && !self.handle_received(packet_type, buf)? {
+                continue;
+            }
+            return Ok(packet_type);
+        }
+    }
 
-        let mut contents = Vec::new();
-        let mut checksum: u8 = 0;
-        let mut prev_ch = b'$';
+    /// Read a packet as for `read_packet`, and wrap it in a
+    /// `ParsedPacket`.
+    pub fn read_parsed_packet(&mut self) -> RspResult<ParsedPacket> {
+        let (kind, contents) = self.read_packet()?;
+        Ok(ParsedPacket::new(kind, contents))
+    }
 
+    // Skip to the start of the next packet, returning its type.
+    // Control characters are skipped too, unless they are wanted.
+    fn read_packet_start(&mut self) -> RspResult<PacketType> {
         loop {
-            let ch = try!(self.read_char());
-            match ch {
-                b'#' => {
-                    break;
-                }
+            let kind = match self.read_char()? {
+                b'$' => PacketType::Normal,
+                b'%' => PacketType::Notification,
+                b'+' => PacketType::Ack,
+                b'-' => PacketType::Nak,
+                3 => PacketType::Interrupt,
+                _ => continue,
+            };
+            if self.report_control || kind == PacketType::Normal
+                || kind == PacketType::Notification {
+                return Ok(kind);
+            }
+        }
+    }
 
-                b'*' if self.is_client => {
-                    // RLE decoding.
-                    let repeat_ch = try!(self.read_char());
-                    let repeat = repeat_ch - 29;
+    // Read the rest of the packet in |partial|, up to and including
+    // the checksum.  If this fails, |partial| holds what was read, so
+    // that reading can carry on later.
+    fn read_packet_rest(&mut self, partial: &mut PartialPacket) -> RspResult<()> {
+        loop {
+            if let Some(ref mut trailer) = partial.trailer {
+                if trailer.len() == 2 {
+                    return Ok(());
+                }
+                let ch = self.read_char()?;
+                trailer.push(ch);
+                continue;
+            }
 
-                    for _ in 0..repeat {
-                        contents.push(prev_ch);
+            let ch = self.read_char()?;
+            if partial.repeat {
+                // RLE decoding.
+                partial.repeat = false;
+                match ch.checked_sub(29) {
+                    Some(repeat) => {
+                        partial.push(partial.prev_ch, usize::from(repeat), self.max_packet_size)
                     }
-                    // FIXME should report an error if we see "*"
-                    // without a preceding character.
-                    prev_ch = b'$';
-
-                    checksum = checksum.wrapping_add(b'*');
-                    checksum = checksum.wrapping_add(repeat_ch);
+                    None => partial.bad_repeat = true,
                 }
+                // FIXME should report an error if we see "*"
+                // without a preceding character.
+                partial.prev_ch = b'$';
+                partial.checksum = partial.checksum.wrapping_add(ch);
+                continue;
+            }
 
+            match ch {
+                b'#' => partial.trailer = Some(Vec::with_capacity(2)),
+                b'*' if self.is_client && self.rle => {
+                    partial.repeat = true;
+                    partial.checksum = partial.checksum.wrapping_add(b'*');
+                }
                 _ => {
-                    contents.push(ch);
-                    checksum = checksum.wrapping_add(ch);
-                    prev_ch = ch;
+                    partial.push(ch, 1, self.max_packet_size);
+                    partial.checksum = partial.checksum.wrapping_add(ch);
+                    partial.prev_ch = ch;
+                }
+            }
+        }
+    }
+
+    // Read a single packet into |buf|; see read_packet_into.
+    fn read_one_packet(&mut self, buf: &mut Vec<u8>) -> RspResult<PacketType> {
+        buf.clear();
+        // Don't wait for a reply to a request that was never sent.
+        if self.unflushed {
+            self.flush()?;
+        }
+
+        let mut partial = match self.partial.take() {
+            Some(partial) => partial,
+            None => {
+                let packet_type = self.read_packet_start()?;
+                if packet_type != PacketType::Normal && packet_type != PacketType::Notification {
+                    return Ok(packet_type);
+                }
+                PartialPacket {
+                    packet_type,
+                    contents: ::std::mem::take(buf),
+                    checksum: 0,
+                    prev_ch: b'$',
+                    repeat: false,
+                    trailer: None,
+                    oversized: false,
+                    bad_repeat: false,
                 }
             }
+        };
+        if let Err(e) = self.read_packet_rest(&mut partial) {
+            self.partial = Some(partial);
+            return Err(e);
         }
+        let PartialPacket { packet_type, contents, checksum, trailer, oversized, bad_repeat, .. } =
+            partial;
+        *buf = contents;
 
-        let n1 = try!(self.read_char());
-        let n2 = try!(self.read_char());
+        self.sequence_id = None;
+        if self.sequence_ids != SequenceIdMode::Off && packet_type == PacketType::Normal {
+            if let [a, b, b':', ..] = buf[..] {
+                if let Some(id) = decode_hex(&[a, b]) {
+                    self.sequence_id = Some(id as u8);
+                    buf.drain(..3);
+                }
+            }
+        }
 
         // Only bother with checksum verification in acking mode.
         // This is a little sad maybe, but the manual says this is ok,
@@ -428,7 +1711,7 @@ impl<'conn> RspConnection<'conn> {
         // computing the checksum properly in this case (though
         // there's no evidence any actually does so).
         if self.acking  {
-            let n = match decode_hex(&[n1, n2]) {
+            let n = match trailer.as_ref().and_then(|digits| decode_hex(digits)) {
                 Some(v) => v as u8,
                 // Pick an invalid value if we can't decode the checksum.
                 _ => !checksum,
@@ -437,15 +1720,120 @@ impl<'conn> RspConnection<'conn> {
             // No acks for notification packets.
             if let PacketType::Normal = packet_type {
                 if n == checksum {
-                    try!(self.wchan.write_all(b"+"))
+                    self.wchan.write_all(b"+")?;
+                    if self.sequence_ids == SequenceIdMode::Echo {
+                        if let Some(id) = self.sequence_id {
+                            self.wchan.write_all(&hex_byte(id))?;
+                        }
+                    }
                 } else {
-                    try!(self.wchan.write_all(b"-"));
+                    self.wchan.write_all(b"-")?;
                     return Err(RspError::InvalidChecksum);
                 }
             }
         }
 
-        Ok((packet_type, contents))
+        if oversized || bad_repeat {
+            // The packet still counts as a reply.
+            if self.is_client && packet_type == PacketType::Normal {
+                self.outstanding = self.outstanding.saturating_sub(1);
+            }
+            if oversized {
+                return Err(RspError::PacketTooLarge);
+            }
+            return Err(RspError::ProtocolViolation {
+                packet: ::std::mem::take(buf),
+                reason: "invalid run-length count",
+            });
+        }
+        Ok(packet_type)
+    }
+}
+
+// The writer of a split connection, shared by the PacketWriter and
+// the PacketReader, which must send acks.
+struct SharedWriter<'conn>(Arc<Mutex<Box<dyn Write + Send + 'conn>>>);
+
+impl<'conn> SharedWriter<'conn> {
+    fn lock(&self) -> MutexGuard<'_, Box<dyn Write + Send + 'conn>> {
+        // A panic while holding the lock can't leave the writer in a
+        // state that is worse than any other I/O error.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Each write is flushed at once, so that the reader's acks are not
+// held back.
+impl<'conn> Write for SharedWriter<'conn> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut writer = self.lock();
+        let nbytes = writer.write(buf)?;
+        writer.flush()?;
+        Ok(nbytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+/// The reading half of a connection split by `RspConnection::split`.
+pub struct PacketReader<'conn> {
+    conn: RspConnection<'conn>,
+}
+
+impl<'conn> PacketReader<'conn> {
+    /// Read a packet, as for `RspConnection::read_packet`.
+    pub fn read_packet(&mut self) -> RspResult<(PacketType, Vec<u8>)> {
+        self.conn.read_packet()
+    }
+
+    /// Wait for a reply, as for `RspConnection::read_reply`.
+    pub fn read_reply(&mut self) -> RspResult<Vec<u8>> {
+        self.conn.read_reply()
+    }
+
+    /// Return the oldest notification queued by `read_reply`, as for
+    /// `RspConnection::pop_notification`.
+    pub fn pop_notification(&mut self) -> Option<Vec<u8>> {
+        self.conn.pop_notification()
+    }
+}
+
+/// The writing half of a connection split by `RspConnection::split`.
+pub struct PacketWriter<'conn> {
+    writer: SharedWriter<'conn>,
+    acking: bool,
+    is_client: bool,
+}
+
+impl<'conn> PacketWriter<'conn> {
+    /// Send the low-level interrupt, as for
+    /// `RspConnection::interrupt`.  It is only valid to call this on
+    /// the client.
+    pub fn interrupt(&mut self) -> RspResult<()> {
+        assert!(self.is_client);
+        self.writer.write_all(b"\x03")?;
+        Ok(())
+    }
+
+    /// Send an entire packet, as for `RspConnection::full_packet`.
+    /// This is only possible when acking is disabled; otherwise this
+    /// returns `RspError::WrongMode`.
+    pub fn full_packet(&mut self, contents: &[u8]) -> RspResult<()> {
+        if self.acking {
+            return Err(RspError::WrongMode);
+        }
+        let checksum = contents.iter().fold(0, |sum: u8, &c| sum.wrapping_add(c));
+        let digits = hex_byte(checksum);
+        // Hold the lock for the whole packet, so that an ack can't
+        // land in the middle of it.
+        let mut writer = self.writer.lock();
+        writer.write_all(b"$")?;
+        writer.write_all(contents)?;
+        writer.write_all(&[b'#', digits[0], digits[1]])?;
+        writer.flush()?;
+        Ok(())
     }
 }
 
@@ -469,4 +1857,727 @@ mod test {
         }
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn extended_mode() {
+        let mut input: &[u8] = b"$OK#9a$#00";
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert!(!rsp.extended_mode());
+            assert!(rsp.enter_extended_mode().unwrap());
+            assert!(rsp.extended_mode());
+
+            // A stub that refuses leaves the mode unchanged.
+            rsp.set_extended_mode(false);
+            assert!(!rsp.enter_extended_mode().unwrap());
+            assert!(!rsp.extended_mode());
+        }
+        assert_eq!(output, b"$!#21$!#21".to_vec());
+    }
+
+    #[test]
+    fn interleaved_notifications() {
+        use ::PacketType;
+        use util::Script;
+
+        let input = Script::new()
+            .ack()
+            .notification(b"Stop:T05thread:1;")
+            .packet(b"OK")
+            .packet(b"S05")
+            .notification(b"Stop:T05thread:2;")
+            .notification(b"Stop:T05thread:3;")
+            .packet(b"E01")
+            .into_bytes();
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+
+        assert_eq!(rsp.request(b"vCont;c").unwrap(), b"OK".to_vec());
+        assert_eq!(rsp.pop_notification(), Some(b"Stop:T05thread:1;".to_vec()));
+        assert_eq!(rsp.pop_notification(), None);
+
+        // read_packet reports notifications itself, in order.
+        assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"S05".to_vec()));
+        assert_eq!(rsp.read_packet().unwrap(),
+                   (PacketType::Notification, b"Stop:T05thread:2;".to_vec()));
+
+        assert_eq!(rsp.read_reply().unwrap(), b"E01".to_vec());
+        assert_eq!(rsp.pop_notification(), Some(b"Stop:T05thread:3;".to_vec()));
+    }
+
+    #[test]
+    fn control_characters() {
+        use ::PacketType;
+        use util::frame_packet;
+
+        let mut input = b"+\x03".to_vec();
+        input.extend(frame_packet(b"?"));
+        input.extend(b"-\x03");
+        input.extend(frame_packet(b"g"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            // By default these are skipped.
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"?".to_vec()));
+            rsp.set_report_control_characters(true);
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Nak, Vec::new()));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Interrupt, Vec::new()));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"g".to_vec()));
+
+            rsp.write_control(PacketType::Ack).unwrap();
+            rsp.write_control(PacketType::Interrupt).unwrap();
+            assert!(rsp.write_control(PacketType::Normal).is_err());
+        }
+        assert_eq!(output, b"+\x03".to_vec());
+    }
+
+    #[test]
+    fn split() {
+        use std::thread;
+        use ::RspError;
+        use util::frame_packet;
+
+        let input = frame_packet(b"T02");
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let rsp = ::RspConnection::new(&mut input, &mut output, true);
+            let (mut reader, mut writer) = rsp.split().unwrap();
+            thread::scope(|scope| {
+                scope.spawn(move || {
+                    writer.interrupt().unwrap();
+                    match writer.full_packet(b"?") {
+                        Err(RspError::WrongMode) => { }
+                        other => panic!("expected WrongMode, got {:?}", other),
+                    }
+                }).join().unwrap();
+            });
+            assert_eq!(reader.read_reply().unwrap(), b"T02".to_vec());
+        }
+        assert_eq!(output, b"\x03+".to_vec());
+
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let (_, mut writer) = rsp.split().unwrap();
+            writer.full_packet(b"vCtrlC").unwrap();
+        }
+        assert_eq!(output, frame_packet(b"vCtrlC"));
+    }
+
+    #[test]
+    fn debug_state() {
+        use ::{PacketType, RspConnectionState, DEFAULT_MAXIMUM_NAKS, DEFAULT_MAXIMUM_PACKET_SIZE};
+
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+        assert!(rsp.is_acking());
+        rsp.disable_acking();
+        rsp.set_maximum_retries(Some(3));
+        rsp.start_packet().expect("start_packet");
+        assert_eq!(rsp.debug_state(), RspConnectionState {
+            acking: false,
+            is_client: true,
+            open_packet: Some(PacketType::Normal),
+            maximum_retries: Some(3),
+            maximum_naks: Some(DEFAULT_MAXIMUM_NAKS),
+            maximum_packet_size: Some(DEFAULT_MAXIMUM_PACKET_SIZE),
+        });
+    }
+
+    #[test]
+    fn nak_flood() {
+        use std::time::Duration;
+        use ::RspError;
+
+        let mut input: &[u8] = &[b'-'; 10];
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.set_maximum_naks(Some(3));
+            rsp.set_retry_backoff(Duration::from_millis(1), Duration::from_millis(2));
+            match rsp.full_packet(b"g") {
+                Err(RspError::TooManyRetries) => { }
+                other => panic!("expected TooManyRetries, got {:?}", other),
+            }
+        }
+        assert_eq!(output, b"$g#67".repeat(4));
+    }
+
+    #[test]
+    fn resend_without_copy() {
+        let mut input: &[u8] = b"-+-+-+";
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.full_packet(b"g").unwrap();
+            assert_eq!(rsp.packet.capacity(), 0);
+
+            let mut calls = 0;
+            rsp.send_packet_with(|conn| {
+                calls += 1;
+                conn.write_binary(b"X0,1:#")
+            }).unwrap();
+            assert_eq!(calls, 2);
+            assert_eq!(rsp.packet.capacity(), 0);
+
+            rsp.start_packet().unwrap();
+            rsp.write_all(b"g").unwrap();
+            rsp.finish_packet().unwrap();
+        }
+        let mut expected = b"$g#67".repeat(2);
+        expected.extend(b"$X0,1:}\x03#9f".repeat(2));
+        expected.extend(b"$g#67".repeat(2));
+        assert_eq!(output, expected);
+    }
+
+    // A writer that counts flushes and vectored writes.
+    #[derive(Default)]
+    struct CountingWriter {
+        data: Vec<u8>,
+        writes: usize,
+        flushes: usize,
+    }
+
+    impl ::std::io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.writes += 1;
+            self.data.write(buf)
+        }
+
+        fn write_vectored(&mut self, bufs: &[::std::io::IoSlice]) -> ::std::io::Result<usize> {
+            self.writes += 1;
+            self.data.write_vectored(bufs)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn batching() {
+        use util::frame_packet;
+
+        let mut input = frame_packet(b"OK");
+        input.extend(frame_packet(b"OK"));
+        let mut input: &[u8] = &input;
+        let mut output = CountingWriter::default();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            rsp.full_packet(b"g").unwrap();
+            rsp.set_batching(true);
+            rsp.full_packet(b"m0,4").unwrap();
+            rsp.full_packet(b"m4,4").unwrap();
+            rsp.read_reply().unwrap();
+            rsp.read_reply().unwrap();
+        }
+        let mut expected = frame_packet(b"g");
+        expected.extend(frame_packet(b"m0,4"));
+        expected.extend(frame_packet(b"m4,4"));
+        assert_eq!(output.data, expected);
+        // One vectored write per packet, and one flush for the first
+        // packet and one for the batch.
+        assert_eq!(output.writes, 3);
+        assert_eq!(output.flushes, 2);
+    }
+
+    #[test]
+    fn buffered_packet() {
+        use util::frame_packet;
+
+        let mut input: &[u8] = b"+-+";
+        let mut output = CountingWriter::default();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.start_packet().unwrap();
+            rsp.write_all(b"M0,2:").unwrap();
+            rsp.write_hex(&[1, 2]).unwrap();
+            rsp.finish_packet().unwrap();
+            rsp.start_packet().unwrap();
+            rsp.write_hex_number(0x1000).unwrap();
+            rsp.finish_packet().unwrap();
+        }
+        let mut expected = frame_packet(b"M0,2:0102");
+        expected.extend(frame_packet(b"1000").repeat(2));
+        assert_eq!(output.data, expected);
+        // One write per packet, including the resend.
+        assert_eq!(output.writes, 3);
+    }
+
+    // A reader that hands out |chunks| in turn, where `None` stands
+    // for a read that times out.
+    struct ScriptedReader {
+        chunks: ::std::collections::VecDeque<Option<Vec<u8>>>,
+    }
+
+    impl ::std::io::Read for ScriptedReader {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            match self.chunks.pop_front() {
+                Some(Some(mut chunk)) => {
+                    let count = ::std::cmp::min(buf.len(), chunk.len());
+                    buf[..count].copy_from_slice(&chunk[..count]);
+                    let rest = chunk.split_off(count);
+                    if !rest.is_empty() {
+                        self.chunks.push_front(Some(rest));
+                    }
+                    Ok(count)
+                }
+                Some(None) => Err(::std::io::ErrorKind::WouldBlock.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn drain_reply() {
+        use ::RspError;
+        use util::frame_packet;
+
+        let first = frame_packet(b"1234");
+        let (head, tail) = first.split_at(3);
+        let mut reply = frame_packet(b"O6869");
+        reply.extend(frame_packet(b"OK"));
+        let chunks = vec![Some(head.to_vec()), None, Some(tail.to_vec()), None, Some(reply),
+                          Some(frame_packet(b"aa"))];
+        let mut input = ScriptedReader { chunks: chunks.into_iter().collect() };
+        let mut output = Vec::new();
+        let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+
+        // The reply times out part way through.
+        assert!(matches!(rsp.request(b"m0,2"), Err(RspError::Timeout)));
+        assert_eq!(rsp.outstanding_replies(), 1);
+        // A second request is pipelined after it.
+        rsp.full_packet(b"Z0,0,1").unwrap();
+        assert_eq!(rsp.outstanding_replies(), 2);
+        assert_eq!(rsp.drain_reply().unwrap(), Some(b"1234".to_vec()));
+        assert!(matches!(rsp.drain_reply(), Err(RspError::Timeout)));
+        assert_eq!(rsp.outstanding_replies(), 1);
+        // The output packet is not taken for the second reply.
+        assert_eq!(rsp.drain_reply().unwrap(), Some(b"O6869".to_vec()));
+        assert_eq!(rsp.drain_reply().unwrap(), Some(b"OK".to_vec()));
+        assert_eq!(rsp.drain_reply().unwrap(), None);
+        // Neither "k" nor "R" gets a reply.
+        rsp.full_packet(b"R00").unwrap();
+        rsp.start_packet().unwrap();
+        rsp.write_all(b"k").unwrap();
+        rsp.finish_packet().unwrap();
+        assert_eq!(rsp.outstanding_replies(), 0);
+        assert_eq!(rsp.drain_reply().unwrap(), None);
+        assert_eq!(rsp.request(b"g").unwrap(), b"aa".to_vec());
+    }
+
+    #[test]
+    fn read_packet_into() {
+        use ::PacketType;
+        use util::frame_packet;
+
+        let mut input = frame_packet(b"S05");
+        input.extend(b"%Stop:T05#ea");
+        input.extend(frame_packet(b"0* "));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+
+        let mut buf = Vec::with_capacity(64);
+        let start = buf.as_ptr();
+        assert_eq!(rsp.read_packet_into(&mut buf).unwrap(), PacketType::Normal);
+        assert_eq!(buf, b"S05".to_vec());
+        assert_eq!(rsp.read_packet_into(&mut buf).unwrap(), PacketType::Notification);
+        assert_eq!(buf, b"Stop:T05".to_vec());
+        assert_eq!(rsp.read_packet_into(&mut buf).unwrap(), PacketType::Normal);
+        assert_eq!(buf, b"0000".to_vec());
+        // The same buffer was used throughout.
+        assert_eq!(buf.as_ptr(), start);
+    }
+
+    #[test]
+    fn maximum_packet_size() {
+        use ::{PacketType, RspError};
+        use util::frame_packet;
+
+        let mut input = frame_packet(b"abcdef");
+        input.extend(frame_packet(b"0* "));
+        input.extend(frame_packet(b"1* !"));
+        input.extend(frame_packet(b"OK"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.set_maximum_packet_size(Some(4));
+            assert!(matches!(rsp.read_packet(), Err(RspError::PacketTooLarge)));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"0000".to_vec()));
+            // The limit applies after run-length decoding.
+            assert!(matches!(rsp.read_packet(), Err(RspError::PacketTooLarge)));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"OK".to_vec()));
+        }
+        // Oversized packets are acked all the same.
+        assert_eq!(output, b"++++".to_vec());
+    }
+
+    #[test]
+    fn bad_repeat_count() {
+        use ::{PacketType, RspError};
+        use util::frame_packet;
+
+        let mut input = frame_packet(b"a*\x01");
+        input.extend(frame_packet(b"OK"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            match rsp.read_packet() {
+                Err(RspError::ProtocolViolation { reason, .. }) => {
+                    assert_eq!(reason, "invalid run-length count")
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"OK".to_vec()));
+        }
+        assert_eq!(output, b"++".to_vec());
+    }
+
+    // A packet handler that emulates qEcho, hides a feature, and
+    // drops notifications.
+    struct Middleware;
+
+    impl ::ProxyHandler for Middleware {
+        fn packet(&mut self, direction: ::Direction, kind: ::PacketType,
+                  contents: &mut Vec<u8>) -> ::ProxyAction {
+            if direction == ::Direction::ToStub && contents.starts_with(b"qEcho:") {
+                ::ProxyAction::Reply(contents[6..].to_vec())
+            } else if kind == ::PacketType::Notification {
+                ::ProxyAction::Drop
+            } else {
+                if direction == ::Direction::ToDebugger && contents == b"swbreak+" {
+                    *contents = b"swbreak-".to_vec();
+                }
+                ::ProxyAction::Forward
+            }
+        }
+    }
+
+    #[test]
+    fn packet_handler_client() {
+        use util::{frame_packets, Script};
+
+        let input = Script::new().notification(b"Stop:S05").packet(b"swbreak+").into_bytes();
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            rsp.set_packet_handler(Middleware);
+            assert_eq!(rsp.request(b"qEcho:hi").unwrap(), b"hi".to_vec());
+            assert_eq!(rsp.outstanding_replies(), 0);
+            assert_eq!(rsp.request(b"qSupported").unwrap(), b"swbreak-".to_vec());
+            assert_eq!(rsp.pop_notification(), None);
+            assert!(rsp.take_packet_handler().is_some());
+        }
+        assert_eq!(output, frame_packets(&[b"qSupported"]));
+    }
+
+    #[test]
+    fn packet_handler_server() {
+        use ::PacketType;
+        use util::frame_packets;
+
+        let input = frame_packets(&[b"qEcho:hi", b"?"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            rsp.set_packet_handler(Middleware);
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"?".to_vec()));
+            rsp.start_notification_packet().unwrap();
+            rsp.write_all(b"Stop:S05").unwrap();
+            rsp.finish_packet().unwrap();
+            rsp.full_packet(b"S05").unwrap();
+        }
+        assert_eq!(output, frame_packets(&[b"hi", b"S05"]));
+    }
+
+    #[test]
+    fn sequence_ids() {
+        use ::{PacketType, SequenceIdMode};
+        use util::frame_packet;
+
+        let mut input = Vec::new();
+        for _ in 0..3 {
+            input.extend(frame_packet(b"1f:OK"));
+        }
+        input.extend(frame_packet(b"S05"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"1f:OK".to_vec()));
+            assert_eq!(rsp.last_sequence_id(), None);
+            rsp.set_sequence_ids(SequenceIdMode::Strip);
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"OK".to_vec()));
+            assert_eq!(rsp.last_sequence_id(), Some(0x1f));
+            rsp.set_sequence_ids(SequenceIdMode::Echo);
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"OK".to_vec()));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"S05".to_vec()));
+            assert_eq!(rsp.last_sequence_id(), None);
+        }
+        assert_eq!(output, b"+++1f+".to_vec());
+    }
+
+    #[test]
+    fn reenable_acking() {
+        use ::PacketType;
+        use util::frame_packet;
+
+        let mut input = frame_packet(b"g");
+        input.extend(frame_packet(b"g"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"g".to_vec()));
+            // A new client connects; its identical request is not
+            // taken for a retransmission.
+            rsp.enable_acking();
+            assert!(rsp.is_acking());
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"g".to_vec()));
+            rsp.send_nack().unwrap();
+            rsp.send_ack().unwrap();
+        }
+        assert_eq!(output, b"+-+".to_vec());
+    }
+
+    #[test]
+    fn duplicate_request() {
+        use ::PacketType;
+        use util::frame_packet;
+
+        let mut input = frame_packet(b"g");
+        input.extend(frame_packet(b"g"));
+        input.extend(frame_packet(b"m0,4"));
+        input.push(b'+');
+        input.extend(frame_packet(b"m0,4"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, false);
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"g".to_vec()));
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"m0,4".to_vec()));
+            rsp.full_packet(b"E01").unwrap();
+            // After a reply, the same request is a new request.
+            assert_eq!(rsp.read_packet().unwrap(), (PacketType::Normal, b"m0,4".to_vec()));
+        }
+        let mut expected = b"+++".to_vec();
+        expected.extend(frame_packet(b"E01"));
+        expected.push(b'+');
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_thread_id() {
+        use ::{Id, ProcessId};
+
+        assert_eq!(ProcessId::parse(b"p1f.-1").unwrap(),
+                   ProcessId { pid: Id::Id(0x1f), tid: Id::All });
+        assert_eq!(ProcessId::parse(b"p1f").unwrap(),
+                   ProcessId { pid: Id::Id(0x1f), tid: Id::All });
+        assert_eq!(ProcessId::parse(b"p1F.2A").unwrap(),
+                   ProcessId { pid: Id::Id(0x1f), tid: Id::Id(0x2a) });
+        assert_eq!(ProcessId::parse(b"0").unwrap(),
+                   ProcessId { pid: Id::Any, tid: Id::Any });
+        assert_eq!(ProcessId::parse(b"p"), None);
+        assert_eq!(ProcessId::parse(b"p1.123456789"), None);
+    }
+
+    #[test]
+    fn thread_id_matches() {
+        use std::collections::HashSet;
+        use ::{Id, ProcessId};
+
+        let thread = ProcessId { pid: Id::Id(1), tid: Id::Id(2) };
+        assert!(ProcessId { pid: Id::Id(1), tid: Id::All }.matches(&thread));
+        assert!(ProcessId { pid: Id::Any, tid: Id::Any }.matches(&thread));
+        assert!(thread.matches(&thread));
+        assert!(!ProcessId { pid: Id::Id(3), tid: Id::All }.matches(&thread));
+        assert!(!ProcessId { pid: Id::Id(1), tid: Id::Id(3) }.matches(&thread));
+        assert!(thread != ProcessId { pid: Id::Id(1), tid: Id::All });
+
+        let mut threads = vec![ProcessId { pid: Id::Id(2), tid: Id::Id(1) }, thread];
+        threads.sort();
+        assert_eq!(threads[0], thread);
+        let set: HashSet<ProcessId> = threads.into_iter().collect();
+        assert!(set.contains(&thread));
+    }
+
+    #[test]
+    fn thread_id_constructors() {
+        use ::{Id, ProcessId};
+
+        assert_eq!(ProcessId::try_new(5, Some(6)),
+                   Some(ProcessId { pid: Id::Id(5), tid: Id::Id(6) }));
+        assert_eq!(ProcessId::try_new(0, None), Some(ProcessId::any()));
+        assert_eq!(ProcessId::try_new(5, Some(-1)), Some(ProcessId::all_threads_of(5)));
+        assert_eq!(ProcessId::try_new(-2, None), None);
+        assert_eq!(ProcessId::all_threads_of(0), ProcessId { pid: Id::Any, tid: Id::All });
+    }
+
+    #[test]
+    fn thread_id_strings() {
+        use ::{Id, ParseThreadIdError, ProcessId};
+
+        let thread = ProcessId { pid: Id::Id(0x1f), tid: Id::Id(0x2a) };
+        assert_eq!(thread.to_string(), "p1f.2a");
+        assert_eq!("p1f.2a".parse(), Ok(thread));
+        let all = ProcessId { pid: Id::Id(0x1f), tid: Id::All };
+        assert_eq!(all.to_string(), "p1f.-1");
+        assert_eq!("p1f".parse(), Ok(all));
+        let plain = ProcessId { pid: Id::Any, tid: Id::Id(7) };
+        assert_eq!(plain.to_string(), "7");
+        assert_eq!(plain.to_string().parse(), Ok(plain));
+        let any = ProcessId { pid: Id::All, tid: Id::Any };
+        assert_eq!(any.to_string().parse(), Ok(any));
+
+        assert_eq!("-1".parse(), Ok(Id::All));
+        assert_eq!("p".parse::<ProcessId>(), Err(ParseThreadIdError));
+        assert_eq!("".parse::<Id>(), Err(ParseThreadIdError));
+    }
+
+    #[test]
+    fn write_numbers() {
+        use ::{Id, ProcessId};
+
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            rsp.start_packet().expect("start_packet");
+            rsp.write_hex_number(0).expect("write_hex_number");
+            rsp.write_all(b",").expect("write_all");
+            rsp.write_hex_number(0xABC).expect("write_hex_number");
+            rsp.write_all(b",").expect("write_all");
+            rsp.write_decimal(1234).expect("write_decimal");
+            rsp.write_all(b";").expect("write_all");
+            rsp.write_thread_id(ProcessId { pid: Id::Id(0x1f), tid: Id::Id(0x100) })
+                .expect("write_thread_id");
+            rsp.finish_packet().expect("finish_packet");
+        }
+        assert_eq!(output, ::util::frame_packet(b"0,abc,1234;p1f.100"));
+    }
+
+    // The examples given in the "Remote Protocol" appendix of the GDB
+    // manual, checked byte for byte.
+    #[test]
+    fn manual_examples() {
+        use ::{Id, ProcessId};
+
+        // From "Overview": reading two bytes of memory in ack mode.
+        let mut input: &[u8] = b"+$2f86#06";
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            assert_eq!(rsp.request(b"m4015bc,2").unwrap(), b"2f86".to_vec());
+        }
+        assert_eq!(output, b"$m4015bc,2#5a+".to_vec());
+
+        // Also from "Overview": "0* " means the same as "0000".
+        let mut input: &[u8] = b"$0* #7a";
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            assert_eq!(rsp.read_reply().unwrap(), b"0000".to_vec());
+        }
+        assert_eq!(output, b"+".to_vec());
+
+        // "the byte 0x7d would be transmitted as the two bytes 0x7d
+        // 0x5d"; '#', '$', and '*' are escaped the same way.
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            rsp.start_packet().expect("start_packet");
+            rsp.write_binary(&[0x7d, 0x23, 0x24, 0x2a]).expect("write_binary");
+            rsp.finish_packet().expect("finish_packet");
+        }
+        assert_eq!(output, ::util::frame_packet(&[0x7d, 0x5d, 0x7d, 0x03, 0x7d, 0x04, 0x7d, 0x0a]));
+
+        // From "Packets": the multiprocess thread-id forms.
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            rsp.start_packet().expect("start_packet");
+            for &(pid, tid) in &[(Id::Id(0x7b), Id::Id(0x7c)), (Id::Id(0x7b), Id::All),
+                                 (Id::All, Id::All), (Id::Any, Id::Any), (Id::Any, Id::Id(7)),
+                                 (Id::Any, Id::All)] {
+                rsp.write_all(b";").expect("write_all");
+                rsp.write_thread_id(ProcessId { pid, tid }).expect("write_thread_id");
+            }
+            rsp.finish_packet().expect("finish_packet");
+        }
+        assert_eq!(output, ::util::frame_packet(b";p7b.7c;p7b.-1;p-1.-1;0;7;-1"));
+        assert_eq!(ProcessId::parse(b"p7b"), Some(ProcessId { pid: Id::Id(0x7b), tid: Id::All }));
+        assert_eq!(ProcessId::parse(b"p-1.-1"), Some(ProcessId { pid: Id::All, tid: Id::All }));
+    }
+
+    #[test]
+    fn write_binary() {
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            rsp.start_packet().expect("start_packet");
+            rsp.write_binary(b"a$#}*b").expect("write_binary");
+            rsp.finish_packet().expect("finish_packet");
+        }
+        assert_eq!(output, ::util::frame_packet(b"a}\x04}\x03}]}\x0ab"));
+    }
+
+    #[test]
+    fn remote_error() {
+        use ::{check_reply, RemoteError, RspError};
+
+        assert_eq!(RemoteError::parse(b"E0e"), Some(RemoteError::Code(14)));
+        let message = "Cannot access memory at address 0x0";
+        assert_eq!(RemoteError::parse(b"E.Cannot access memory at address 0x0"),
+                   Some(RemoteError::Message(message.to_string())));
+        assert_eq!(RemoteError::parse(b"E1"), None);
+        assert_eq!(RemoteError::parse(b"OK"), None);
+        assert_eq!(RemoteError::Code(1).to_reply(), b"E01".to_vec());
+        assert_eq!(RemoteError::Message(message.to_string()).to_string(), message);
+
+        assert_eq!(check_reply(b"OK".to_vec()).unwrap(), b"OK".to_vec());
+        match check_reply(b"E.no".to_vec()) {
+            Err(RspError::Remote(RemoteError::Message(ref text))) if text == "no" => { }
+            other => panic!("expected a remote error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn uppercase_checksum() {
+        let mut input: &[u8] = b"$m0,4#FD";
+        let mut output = Vec::new();
+        {
+            let mut rsp = ::RspConnection::new(&mut input, &mut output, false);
+            assert_eq!(rsp.read_packet().unwrap().1, b"m0,4".to_vec());
+        }
+        assert_eq!(output, b"+");
+    }
 }
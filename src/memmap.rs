@@ -0,0 +1,280 @@
+#![deny(missing_docs)]
+
+use std::fmt::Write;
+use std::io;
+use std::io::Read;
+
+use hostio::{RemoteFile, FILE_IO_O_RDONLY};
+use low::{RspConnection, RspResult};
+use qxfer::read_qxfer_object;
+use util::{parse_hex_number, xml_attribute};
+
+/// The kind of a memory region in a memory map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Ordinary read-write memory.
+    Ram,
+    /// Read-only memory.
+    Rom,
+    /// Flash memory, which must be written using the `vFlash`
+    /// packets.  This holds the size of an erase block.
+    Flash(u64),
+}
+
+/// A region of target memory, as described by a memory map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// The kind of memory.
+    pub memory_type: MemoryType,
+    /// The start address.
+    pub start: u64,
+    /// The length in bytes.
+    pub length: u64,
+}
+
+impl MemoryRegion {
+    /// Make a new memory region.
+    pub fn new(memory_type: MemoryType, start: u64, length: u64) -> MemoryRegion {
+        MemoryRegion { memory_type, start, length }
+    }
+}
+
+/// Return a memory map XML document describing `regions`, for use by
+/// a server.  This is served as `qXfer:memory-map:read`; see
+/// `send_qxfer_read_reply`.
+pub fn memory_map_xml(regions: &[MemoryRegion]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\"?>\n");
+    xml.push_str("<!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB Memory Map V1.0//EN\" \
+                  \"http://sourceware.org/gdb/gdb-memory-map.dtd\">\n");
+    xml.push_str("<memory-map>\n");
+    for region in regions {
+        let name = match region.memory_type {
+            MemoryType::Ram => "ram",
+            MemoryType::Rom => "rom",
+            MemoryType::Flash(_) => "flash",
+        };
+        let _ = write!(xml, "  <memory type=\"{}\" start=\"0x{:x}\" length=\"0x{:x}\"",
+                       name, region.start, region.length);
+        match region.memory_type {
+            MemoryType::Flash(blocksize) => {
+                let _ = writeln!(xml, ">\n    <property name=\"blocksize\">0x{:x}</property>",
+                                 blocksize);
+                xml.push_str("  </memory>\n");
+            }
+            _ => xml.push_str("/>\n"),
+        }
+    }
+    xml.push_str("</memory-map>\n");
+    xml
+}
+
+// Parse a number in a memory map, which is hex with a 0x prefix, or
+// decimal.
+fn parse_number(text: &[u8]) -> Option<u64> {
+    match text.strip_prefix(b"0x") {
+        Some(hex) => parse_hex_number(hex),
+        None => ::std::str::from_utf8(text).ok()?.parse().ok(),
+    }
+}
+
+/// Parse a memory map XML document, as fetched by a client.  Regions
+/// of an unknown type, or that lack a start or length, are skipped,
+/// as is a flash region without a valid block size.
+pub fn parse_memory_map(xml: &[u8]) -> Vec<MemoryRegion> {
+    let mut result = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.windows(8).position(|w| w == b"<memory ") {
+        rest = &rest[start + 8..];
+        let end = rest.iter().position(|&c| c == b'>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        let attribute = |name: &[u8]| xml_attribute(tag, name).and_then(|v| parse_number(&v));
+        let (start, length) = match (attribute(b"start"), attribute(b"length")) {
+            (Some(start), Some(length)) => (start, length),
+            _ => continue,
+        };
+        let memory_type = match xml_attribute(tag, b"type").as_ref().map(|t| &t[..]) {
+            Some(b"ram") => MemoryType::Ram,
+            Some(b"rom") => MemoryType::Rom,
+            Some(b"flash") => {
+                // The block size is the text of a property element
+                // within this one.
+                let close = rest.windows(9).position(|w| w == b"</memory>" || w == b"<memory ");
+                let body = &rest[..close.unwrap_or(0)];
+                let key = b"<property name=\"blocksize\">";
+                let blocksize = body.windows(key.len()).position(|w| w == key).and_then(|at| {
+                    let value = &body[at + key.len()..];
+                    let end = value.iter().position(|&c| c == b'<').unwrap_or(value.len());
+                    parse_number(value[..end].trim_ascii())
+                });
+                match blocksize {
+                    Some(blocksize) => MemoryType::Flash(blocksize),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        result.push(MemoryRegion::new(memory_type, start, length));
+    }
+    result
+}
+
+/// Fetch the target's memory map with `qXfer:memory-map:read`, for
+/// use by a client.  `packet_size` is the maximum packet size
+/// reported by the stub's `qSupported` reply.  Returns `None` if the
+/// stub does not supply a memory map.
+pub fn read_memory_map(conn: &mut RspConnection, packet_size: usize)
+                       -> RspResult<Option<Vec<MemoryRegion>>> {
+    Ok(read_qxfer_object(conn, b"memory-map", b"", packet_size)?.map(|xml| parse_memory_map(&xml)))
+}
+
+/// Parse the contents of a Linux `/proc/<pid>/maps` file into memory
+/// regions.  Mappings that are not readable, such as guard pages, are
+/// skipped, as are malformed lines.  Writable mappings are `Ram` and
+/// the others are `Rom`.
+pub fn parse_proc_maps(text: &[u8]) -> Vec<MemoryRegion> {
+    let mut result = Vec::new();
+    for line in text.split(|&c| c == b'\n') {
+        let mut fields = line.split(|&c| c == b' ').filter(|field| !field.is_empty());
+        let (range, perms) = match (fields.next(), fields.next()) {
+            (Some(range), Some(perms)) => (range, perms),
+            _ => continue,
+        };
+        let dash = match range.iter().position(|&c| c == b'-') {
+            Some(dash) => dash,
+            None => continue,
+        };
+        let (start, end) = match (parse_hex_number(&range[..dash]),
+                                  parse_hex_number(&range[dash + 1..])) {
+            (Some(start), Some(end)) if start < end => (start, end),
+            _ => continue,
+        };
+        let memory_type = match perms {
+            [b'r', b'w', ..] => MemoryType::Ram,
+            [b'r', ..] => MemoryType::Rom,
+            _ => continue,
+        };
+        result.push(MemoryRegion::new(memory_type, start, end - start));
+    }
+    result
+}
+
+/// Read `/proc/<pid>/maps` from a Linux target with host I/O, and
+/// parse it with `parse_proc_maps`, for use by a client.  This is a
+/// fallback for stubs, such as gdbserver, that have no memory map.
+/// `packet_size` is the maximum packet size reported by the stub's
+/// `qSupported` reply.  Returns `None` if the file cannot be opened,
+/// as on a target that is not Linux.
+pub fn read_proc_maps(conn: &mut RspConnection, pid: u32, packet_size: usize)
+                      -> RspResult<Option<Vec<MemoryRegion>>> {
+    let path = format!("/proc/{}/maps", pid);
+    let mut file = match RemoteFile::open(conn, path.as_bytes(), FILE_IO_O_RDONLY, 0) {
+        Ok(file) => file,
+        Err(ref e) if matches!(e.kind(), io::ErrorKind::NotFound
+                               | io::ErrorKind::PermissionDenied
+                               | io::ErrorKind::Unsupported) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    file.set_packet_size(packet_size);
+    // The file's size is reported as zero, so read until end of file.
+    let mut text = Vec::new();
+    let mut buf = vec![0; packet_size];
+    loop {
+        let count = file.read(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+        text.extend_from_slice(&buf[..count]);
+    }
+    Ok(Some(parse_proc_maps(&text)))
+}
+
+/// Find the target's memory regions, for use by a client: from its
+/// memory map if it has one, and otherwise, if `pid` is given, from
+/// `/proc/<pid>/maps` as `read_proc_maps` does.  This lets code that
+/// walks memory, such as `CoreDump`, work the same way with embedded
+/// stubs and with gdbserver.  Returns `None` if neither is available.
+pub fn read_memory_regions(conn: &mut RspConnection, pid: Option<u32>, packet_size: usize)
+                           -> RspResult<Option<Vec<MemoryRegion>>> {
+    if let Some(regions) = read_memory_map(conn, packet_size)? {
+        return Ok(Some(regions));
+    }
+    match pid {
+        Some(pid) => read_proc_maps(conn, pid, packet_size),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{memory_map_xml, parse_memory_map, parse_proc_maps, read_memory_regions,
+                MemoryRegion, MemoryType};
+    use low::RspConnection;
+    use util::frame_packets;
+
+    #[test]
+    fn memory_map() {
+        let regions = [MemoryRegion::new(MemoryType::Flash(0x800), 0, 0x80000),
+                       MemoryRegion::new(MemoryType::Ram, 0x2000_0000, 0x10000)];
+        assert_eq!(memory_map_xml(&regions), "<?xml version=\"1.0\"?>
+<!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB Memory Map V1.0//EN\" \
+\"http://sourceware.org/gdb/gdb-memory-map.dtd\">
+<memory-map>
+  <memory type=\"flash\" start=\"0x0\" length=\"0x80000\">
+    <property name=\"blocksize\">0x800</property>
+  </memory>
+  <memory type=\"ram\" start=\"0x20000000\" length=\"0x10000\"/>
+</memory-map>
+");
+    }
+
+    #[test]
+    fn parse() {
+        let regions = [MemoryRegion::new(MemoryType::Flash(0x800), 0, 0x80000),
+                       MemoryRegion::new(MemoryType::Ram, 0x2000_0000, 0x10000)];
+        assert_eq!(parse_memory_map(memory_map_xml(&regions).as_bytes()), regions.to_vec());
+        let xml = b"<memory-map><memory type=\"rom\" start=\"16\" length=\"0x10\"/>\
+                    <memory type=\"flash\" start=\"0x0\" length=\"0x10\"></memory>\
+                    <memory type=\"nvram\" start=\"0x0\" length=\"0x10\"/></memory-map>";
+        assert_eq!(parse_memory_map(xml), vec![MemoryRegion::new(MemoryType::Rom, 16, 16)]);
+    }
+
+    const MAPS: &[u8] = b"\
+55d0c0a00000-55d0c0a02000 r-xp 00000000 08:01 1234    /bin/true
+55d0c0c01000-55d0c0c02000 rw-p 00001000 08:01 1234    /bin/true
+7ffd1000-7ffd2000 ---p 00000000 00:00 0
+7ffe2000-7ffe3000 rw-p 00000000 00:00 0               [stack]
+bad line
+";
+
+    #[test]
+    fn proc_maps() {
+        assert_eq!(parse_proc_maps(MAPS), vec![
+            MemoryRegion::new(MemoryType::Rom, 0x55d0_c0a0_0000, 0x2000),
+            MemoryRegion::new(MemoryType::Ram, 0x55d0_c0c0_1000, 0x1000),
+            MemoryRegion::new(MemoryType::Ram, 0x7ffe_2000, 0x1000),
+        ]);
+
+        let mut reply = b"F".to_vec();
+        reply.extend(format!("{:x};", MAPS.len()).bytes());
+        reply.extend_from_slice(MAPS);
+        let replies: &[&[u8]] = &[b"", b"F5", &reply, b"F0;", b"F0"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let regions = read_memory_regions(&mut rsp, Some(42), 4096).unwrap().unwrap();
+            assert_eq!(regions, parse_proc_maps(MAPS));
+        }
+        let second_read = format!("vFile:pread:5,7f0,{:x}", MAPS.len());
+        let sent: &[&[u8]] = &[b"qXfer:memory-map:read::0,fff",
+                               b"vFile:open:2f70726f632f34322f6d617073,0,0",
+                               b"vFile:pread:5,7f0,0",
+                               second_read.as_bytes(),
+                               b"vFile:close:5"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
@@ -0,0 +1,253 @@
+#![deny(missing_docs)]
+
+use low::{check_reply, RspConnection, RspError, RspResult};
+use register::{Endian, RegisterValue};
+use util::{check_range, decode_hex_bytes, push_hex, write_hex_number};
+
+// The largest number of bytes whose hex encoding, after a request
+// header, fits in |packet_size|.
+fn hex_chunk_size(packet_size: usize) -> usize {
+    // Allow for the longest header, "M" plus two 64-bit numbers and
+    // separators.
+    ::std::cmp::max(packet_size.saturating_sub(35) / 2, 1)
+}
+
+// Read up to |size| bytes at |address| with a single m packet.
+fn read_chunk(conn: &mut RspConnection, address: u64, size: usize) -> RspResult<Vec<u8>> {
+    let mut packet = b"m".to_vec();
+    write_hex_number(&mut packet, address);
+    packet.push(b',');
+    write_hex_number(&mut packet, size as u64);
+    let reply = check_reply(conn.request(&packet)?)?;
+    match decode_hex_bytes(&reply) {
+        Some(bytes) if !bytes.is_empty() && bytes.len() <= size => Ok(bytes),
+        _ => Err(RspError::ProtocolViolation { packet: reply, reason: "memory read failed" }),
+    }
+}
+
+/// Read `length` bytes of memory at `address` with `m` packets, for
+/// use by a client.  The read is split so that each reply fits in
+/// `packet_size`, the maximum packet size reported by the stub's
+/// `qSupported` reply.  An error reply, as for an inaccessible
+/// address, results in `RspError::Remote`.  A range that wraps around
+/// the address space, or a `packet_size` too small for any data,
+/// results in `RspError::InvalidArgument`.
+pub fn read_memory(conn: &mut RspConnection, address: u64, length: usize, packet_size: usize)
+                   -> RspResult<Vec<u8>> {
+    check_range(address, length as u64)?;
+    if packet_size < 2 {
+        return Err(RspError::InvalidArgument("packet size too small"));
+    }
+    let chunk = packet_size / 2;
+    // Don't trust |length| for the allocation; the stub may fail
+    // long before it is reached.
+    let mut result = Vec::with_capacity(::std::cmp::min(length, chunk));
+    while result.len() < length {
+        let size = ::std::cmp::min(chunk, length - result.len());
+        result.extend(read_chunk(conn, address + result.len() as u64, size)?);
+    }
+    Ok(result)
+}
+
+/// An iterator over the chunks of a memory read, returned by
+/// `read_memory_stream`.  Each call to `next` sends one `m` packet
+/// and waits for its reply, so nothing is read before the consumer
+/// asks for it.  The iterator ends after the last chunk, or after
+/// the first error.
+pub struct MemoryStream<'a, 'conn: 'a> {
+    conn: &'a mut RspConnection<'conn>,
+    address: u64,
+    remaining: usize,
+    chunk: usize,
+}
+
+impl<'a, 'conn> MemoryStream<'a, 'conn> {
+    /// Return the address of the next chunk.
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Return the number of bytes still to be read.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl<'a, 'conn> Iterator for MemoryStream<'a, 'conn> {
+    type Item = RspResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<RspResult<Vec<u8>>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let size = ::std::cmp::min(self.chunk, self.remaining);
+        let result = read_chunk(self.conn, self.address, size);
+        match result {
+            Ok(ref bytes) => {
+                self.address += bytes.len() as u64;
+                self.remaining -= bytes.len();
+            }
+            Err(_) => self.remaining = 0,
+        }
+        Some(result)
+    }
+}
+
+/// Read `length` bytes of memory at `address` as `read_memory` does,
+/// but a chunk at a time, for use by a client that processes or saves
+/// the data as it arrives, such as when writing a core file.  Each
+/// chunk holds at most `chunk` bytes, and less if `packet_size` does
+/// not allow that many, or if the stub returns less.  Since a chunk
+/// is only requested when the iterator is advanced, only one chunk is
+/// held in memory at a time.  Errors are as for `read_memory`; an
+/// invalid argument is reported before anything is sent.
+pub fn read_memory_stream<'a, 'conn>(conn: &'a mut RspConnection<'conn>, address: u64,
+                                     length: usize, chunk: usize, packet_size: usize)
+                                     -> RspResult<MemoryStream<'a, 'conn>> {
+    check_range(address, length as u64)?;
+    if packet_size < 2 {
+        return Err(RspError::InvalidArgument("packet size too small"));
+    }
+    if chunk == 0 {
+        return Err(RspError::InvalidArgument("chunk size is zero"));
+    }
+    let chunk = ::std::cmp::min(chunk, packet_size / 2);
+    Ok(MemoryStream { conn, address, remaining: length, chunk })
+}
+
+/// Read a `size`-byte integer at `address`, in the byte order
+/// `endian`, for use by a client.  This is as `read_memory`, and in
+/// addition a `size` greater than 8 results in
+/// `RspError::InvalidArgument`.
+pub fn read_integer(conn: &mut RspConnection, address: u64, size: usize, endian: Endian,
+                    packet_size: usize) -> RspResult<u64> {
+    if size > 8 {
+        return Err(RspError::InvalidArgument("integer too wide"));
+    }
+    let bytes = read_memory(conn, address, size, packet_size)?;
+    if bytes.len() != size {
+        return Err(RspError::ProtocolViolation { packet: bytes, reason: "short memory read" });
+    }
+    Ok(RegisterValue::new(bytes).to_u64(endian).unwrap_or(0))
+}
+
+/// Write `data` to memory at `address` with `M` packets, for use by a
+/// client.  The write is split to fit `packet_size`, as for
+/// `read_memory`.  An error reply results in `RspError::Remote`, and
+/// any other reply but `OK` in a `ProtocolViolation`.  A range that
+/// wraps around the address space results in
+/// `RspError::InvalidArgument`, and a `packet_size` too small for a
+/// request in `RspError::PacketTooLarge`.
+pub fn write_memory(conn: &mut RspConnection, address: u64, data: &[u8], packet_size: usize)
+                    -> RspResult<()> {
+    check_range(address, data.len() as u64)?;
+    let mut offset = 0;
+    for chunk in data.chunks(hex_chunk_size(packet_size)) {
+        let mut packet = b"M".to_vec();
+        write_hex_number(&mut packet, address + offset as u64);
+        packet.push(b',');
+        write_hex_number(&mut packet, chunk.len() as u64);
+        packet.push(b':');
+        if packet.len() + 2 * chunk.len() > packet_size {
+            return Err(RspError::PacketTooLarge);
+        }
+        push_hex(&mut packet, chunk);
+        let reply = check_reply(conn.request(&packet)?)?;
+        if reply != b"OK" {
+            return Err(RspError::ProtocolViolation { packet: reply, reason: "memory write failed" });
+        }
+        offset += chunk.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_integer, read_memory, read_memory_stream, write_memory};
+    use low::{RspConnection, RspError};
+    use register::Endian;
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn read_write() {
+        let mut input = frame_packet(b"12345678");
+        input.extend(frame_packet(b"9a"));
+        input.extend(frame_packet(b"E01"));
+        input.extend(frame_packet(b"OK"));
+        input.extend(frame_packet(b"OK"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(read_memory(&mut rsp, 0x1000, 5, 8).unwrap(),
+                       vec![0x12, 0x34, 0x56, 0x78, 0x9a]);
+            assert!(read_memory(&mut rsp, 0, 1, 8).is_err());
+            write_memory(&mut rsp, 0x2000, &[0xab; 3], 39).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"m1000,4", b"m1004,1", b"m0,1", b"M2000,2:abab", b"M2002,1:ab"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert!(matches!(read_memory(&mut rsp, u64::MAX, 2, 400),
+                             Err(RspError::InvalidArgument(_))));
+            assert!(matches!(write_memory(&mut rsp, u64::MAX - 1, &[0; 3], 400),
+                             Err(RspError::InvalidArgument(_))));
+            assert!(matches!(read_memory(&mut rsp, 0, 1, 1), Err(RspError::InvalidArgument(_))));
+            assert!(matches!(write_memory(&mut rsp, 0, &[0], 4), Err(RspError::PacketTooLarge)));
+        }
+        // Nothing was sent.
+        assert_eq!(output, Vec::new());
+    }
+
+    #[test]
+    fn big_endian() {
+        // A pointer and a short from a big-endian target.
+        let mut input = frame_packet(b"0ffffe30");
+        input.extend(frame_packet(b"beef"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        assert_eq!(read_integer(&mut rsp, 0x1000, 4, Endian::Big, 400).unwrap(), 0x0fff_fe30);
+        assert_eq!(read_integer(&mut rsp, 0x1004, 2, Endian::Big, 400).unwrap(), 0xbeef);
+        assert!(matches!(read_integer(&mut rsp, 0, 16, Endian::Big, 400),
+                         Err(RspError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn stream() {
+        let mut input = frame_packet(b"010203");
+        // A short read.
+        input.extend(frame_packet(b"04"));
+        input.extend(frame_packet(b"0506"));
+        input.extend(frame_packet(b"E14"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut stream = read_memory_stream(&mut rsp, 0x100, 6, 3, 400).unwrap();
+            assert_eq!(stream.next().unwrap().unwrap(), vec![1, 2, 3]);
+            assert_eq!((stream.address(), stream.remaining()), (0x103, 3));
+            let rest: Vec<_> = stream.map(Result::unwrap).collect();
+            assert_eq!(rest, vec![vec![4], vec![5, 6]]);
+
+            // The stream ends at the first error.
+            let mut stream = read_memory_stream(&mut rsp, 0, 100, 100, 8).unwrap();
+            assert!(matches!(stream.next(), Some(Err(RspError::Remote(_)))));
+            assert!(stream.next().is_none());
+            assert!(matches!(read_memory_stream(&mut rsp, 0, 1, 0, 400),
+                             Err(RspError::InvalidArgument(_))));
+        }
+        let sent: &[&[u8]] = &[b"m100,3", b"m103,3", b"m104,2", b"m0,4"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
@@ -0,0 +1,161 @@
+#![deny(missing_docs)]
+
+use console::monitor_command;
+use low::{RemoteError, RspConnection, RspError, RspResult};
+use util::parse_hex_number;
+
+/// What OpenOCD reported after a reset left the target halted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HaltInfo {
+    /// Why the target halted, for example `debug-request`.
+    pub reason: Option<String>,
+    /// The program counter, if OpenOCD printed it.
+    pub pc: Option<u64>,
+    /// The full output of the command.
+    pub output: String,
+}
+
+/// A flash bank, as found by `flash probe`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlashBank {
+    /// The name of the flash driver, for example `stm32f1x`.
+    pub driver: String,
+    /// The base address of the bank.
+    pub base: u64,
+}
+
+// Return the text following |prefix| in |text|, up to the end of the
+// word.
+fn word_after<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let start = text.find(prefix)? + prefix.len();
+    let rest = &text[start..];
+    let end = rest.find(|c: char| c.is_whitespace() || c == ',').unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+// Parse a number written as 0x followed by hex digits.
+fn parse_address(text: &str) -> Option<u64> {
+    parse_hex_number(text.strip_prefix("0x")?.as_bytes())
+}
+
+// Parse the output of a reset command, which OpenOCD prints when the
+// target halts.
+fn parse_halt(output: String) -> HaltInfo {
+    HaltInfo {
+        reason: word_after(&output, "halted due to ").map(str::to_string),
+        pc: word_after(&output, "pc: ").and_then(parse_address),
+        output,
+    }
+}
+
+// Parse the output of flash probe, for example
+// "flash 'stm32f1x' found at 0x08000000".
+fn parse_flash_probe(output: &str) -> Option<FlashBank> {
+    let start = output.find("flash '")? + 7;
+    let rest = &output[start..];
+    let end = rest.find('\'')?;
+    Some(FlashBank {
+        driver: rest[..end].to_string(),
+        base: word_after(&rest[end..], "found at ").and_then(parse_address)?,
+    })
+}
+
+/// A thin layer over `monitor_command` for OpenOCD, for use by a
+/// client.  Each method runs one OpenOCD command and parses its
+/// output.  A method returns `None` if the stub does not support
+/// `qRcmd`.  If OpenOCD prints an `Error:` line, that line is
+/// returned as `RspError::Remote`.
+pub struct OpenOcd<'a, 'conn: 'a> {
+    conn: &'a mut RspConnection<'conn>,
+}
+
+impl<'a, 'conn> OpenOcd<'a, 'conn> {
+    /// Make a new `OpenOcd` that sends commands over `conn`.
+    pub fn new(conn: &'a mut RspConnection<'conn>) -> OpenOcd<'a, 'conn> {
+        OpenOcd { conn }
+    }
+
+    /// Run `command`, and return its output as text.
+    pub fn command(&mut self, command: &str) -> RspResult<Option<String>> {
+        let output = match monitor_command(self.conn, command.as_bytes())? {
+            Some(output) => String::from_utf8_lossy(&output).into_owned(),
+            None => return Ok(None),
+        };
+        if let Some(line) = output.lines().find(|line| line.starts_with("Error:")) {
+            return Err(RspError::Remote(RemoteError::Message(line.to_string())));
+        }
+        Ok(Some(output))
+    }
+
+    /// Reset the target and halt it at the reset vector, with
+    /// `reset halt`.
+    pub fn reset_halt(&mut self) -> RspResult<Option<HaltInfo>> {
+        Ok(self.command("reset halt")?.map(parse_halt))
+    }
+
+    /// Reset the target, halt it, and run the board's `reset-init`
+    /// script, with `reset init`.  This usually sets up clocks and
+    /// memory so that the target can be flashed.
+    pub fn reset_init(&mut self) -> RspResult<Option<HaltInfo>> {
+        Ok(self.command("reset init")?.map(parse_halt))
+    }
+
+    /// Probe flash bank `bank`, with `flash probe`.
+    pub fn flash_probe(&mut self, bank: u32) -> RspResult<Option<FlashBank>> {
+        let output = match self.command(&format!("flash probe {}", bank))? {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+        match parse_flash_probe(&output) {
+            Some(flash_bank) => Ok(Some(flash_bank)),
+            None => Err(RspError::ProtocolViolation {
+                packet: output.into_bytes(),
+                reason: "unrecognized flash probe output",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashBank, OpenOcd};
+    use low::{RemoteError, RspConnection, RspError};
+    use util::{frame_packet, push_hex};
+
+    // Frame |text| as the O packet that carries it.
+    fn output(text: &str) -> Vec<u8> {
+        let mut packet = b"O".to_vec();
+        push_hex(&mut packet, text.as_bytes());
+        frame_packet(&packet)
+    }
+
+    #[test]
+    fn commands() {
+        let mut input = output("target halted due to debug-request, current mode: Thread \n\
+                                xPSR: 0x01000000 pc: 0x08000188 msp: 0x20005000\n");
+        input.extend(frame_packet(b"OK"));
+        input.extend(output("flash 'stm32f1x' found at 0x08000000\n"));
+        input.extend(frame_packet(b"OK"));
+        input.extend(output("Error: flash bank 3 does not exist\n"));
+        input.extend(frame_packet(b"OK"));
+        input.extend(frame_packet(b""));
+        let mut input: &[u8] = &input;
+        let mut out = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut out, true);
+        rsp.disable_acking();
+        let mut openocd = OpenOcd::new(&mut rsp);
+
+        let halt = openocd.reset_halt().unwrap().unwrap();
+        assert_eq!(halt.reason, Some("debug-request".to_string()));
+        assert_eq!(halt.pc, Some(0x0800_0188));
+        assert_eq!(openocd.flash_probe(0).unwrap(),
+                   Some(FlashBank { driver: "stm32f1x".to_string(), base: 0x0800_0000 }));
+        match openocd.flash_probe(3) {
+            Err(RspError::Remote(RemoteError::Message(message))) => {
+                assert_eq!(message, "Error: flash bank 3 does not exist");
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert_eq!(openocd.reset_init().unwrap(), None);
+    }
+}
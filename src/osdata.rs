@@ -0,0 +1,220 @@
+#![deny(missing_docs)]
+
+use low::{RspConnection, RspResult};
+use qxfer::read_qxfer_object;
+use util::{unescape_xml, xml_attribute};
+
+/// One row of an osdata table: the columns, as pairs of name and
+/// value, in the order the stub sent them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OsDataItem {
+    /// The columns of this row.
+    pub columns: Vec<(String, String)>,
+}
+
+impl OsDataItem {
+    /// Return the value of the column called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.columns.iter().find(|(column, _)| column == name).map(|(_, value)| &value[..])
+    }
+}
+
+/// An osdata table, as returned by `qXfer:osdata:read`.  gdb shows
+/// these with `info os`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OsData {
+    /// The table's type, for example `processes`.
+    pub table_type: String,
+    /// The rows of the table.
+    pub items: Vec<OsDataItem>,
+}
+
+// Return the position of |needle| in |haystack|.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// Unescape |text| and turn it into a string.
+fn xml_text(text: &[u8]) -> String {
+    String::from_utf8_lossy(&unescape_xml(text)).into_owned()
+}
+
+// Parse the <column> elements of a single <item>.
+fn parse_item(mut rest: &[u8]) -> OsDataItem {
+    let mut item = OsDataItem::default();
+    while let Some(start) = find(rest, b"<column ") {
+        rest = &rest[start + 8..];
+        let end = match rest.iter().position(|&c| c == b'>') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = xml_attribute(&rest[..end], b"name");
+        rest = &rest[end + 1..];
+        let close = find(rest, b"</column>").unwrap_or(rest.len());
+        if let Some(name) = name {
+            item.columns.push((xml_text(&name), xml_text(&rest[..close]).trim().to_string()));
+        }
+        rest = &rest[close..];
+    }
+    item
+}
+
+/// Parse an osdata document, as fetched by a client.  Columns without
+/// a name are skipped.
+pub fn parse_osdata(xml: &[u8]) -> OsData {
+    let mut result = OsData::default();
+    if let Some(start) = find(xml, b"<osdata ") {
+        let rest = &xml[start + 8..];
+        let end = rest.iter().position(|&c| c == b'>').unwrap_or(rest.len());
+        if let Some(table_type) = xml_attribute(&rest[..end], b"type") {
+            result.table_type = xml_text(&table_type);
+        }
+    }
+    let mut rest = xml;
+    while let Some(start) = find(rest, b"<item>") {
+        rest = &rest[start + 6..];
+        let end = find(rest, b"</item>").unwrap_or(rest.len());
+        result.items.push(parse_item(&rest[..end]));
+        rest = &rest[end..];
+    }
+    result
+}
+
+/// Fetch the osdata table named by `annex` with
+/// `qXfer:osdata:read`, for use by a client.  An empty annex asks for
+/// the list of tables.  `packet_size` is the maximum packet size
+/// reported by the stub's `qSupported` reply.  Returns `None` if the
+/// stub does not supply the table.
+pub fn read_osdata(conn: &mut RspConnection, annex: &[u8], packet_size: usize)
+                   -> RspResult<Option<OsData>> {
+    Ok(read_qxfer_object(conn, b"osdata", annex, packet_size)?.map(|xml| parse_osdata(&xml)))
+}
+
+/// A process, from a row of the osdata `processes` table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OsProcess {
+    /// The process id.
+    pub pid: u32,
+    /// The name of the user that owns the process.
+    pub user: String,
+    /// The command line of the process.
+    pub command: String,
+    /// The cores that the process's threads are running on.
+    pub cores: Vec<u32>,
+}
+
+impl OsProcess {
+    /// Make an `OsProcess` from a row of the `processes` table.
+    /// Returns `None` if the row has no valid `pid` column; the other
+    /// columns are empty if they are missing.
+    pub fn from_item(item: &OsDataItem) -> Option<OsProcess> {
+        let cores = item.get("cores").unwrap_or("").split(',')
+            .filter_map(|core| core.trim().parse().ok())
+            .collect();
+        Some(OsProcess {
+            pid: item.get("pid")?.parse().ok()?,
+            user: item.get("user").unwrap_or("").to_string(),
+            command: item.get("command").unwrap_or("").to_string(),
+            cores,
+        })
+    }
+
+    /// Return the name of the program: the last component of the
+    /// first word of the command line.
+    pub fn name(&self) -> &str {
+        let program = self.command.split_whitespace().next().unwrap_or("");
+        program.rsplit('/').next().unwrap_or(program)
+    }
+}
+
+/// Fetch the processes running on the target with
+/// `qXfer:osdata:read:processes`, for use by a client; for example
+/// to find a process to attach to.  `packet_size` is as for
+/// `read_osdata`.  Rows without a valid pid are skipped.  Returns
+/// `None` if the stub does not supply the table.
+pub fn read_processes(conn: &mut RspConnection, packet_size: usize)
+                      -> RspResult<Option<Vec<OsProcess>>> {
+    Ok(read_osdata(conn, b"processes", packet_size)?.map(|table| {
+        table.items.iter().filter_map(OsProcess::from_item).collect()
+    }))
+}
+
+/// A filter on processes, for example to find the process to attach
+/// to by name.  Each field that is set must match; an empty filter
+/// matches every process.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessFilter {
+    /// The program name, compared with `OsProcess::name`.
+    pub name: Option<String>,
+    /// The owning user.
+    pub user: Option<String>,
+    /// A core that the process must be running on.
+    pub core: Option<u32>,
+}
+
+impl ProcessFilter {
+    /// Return true if `process` passes this filter.
+    pub fn matches(&self, process: &OsProcess) -> bool {
+        self.name.as_ref().is_none_or(|name| process.name() == name)
+            && self.user.as_ref().is_none_or(|user| &process.user == user)
+            && self.core.is_none_or(|core| process.cores.contains(&core))
+    }
+
+    /// Return the processes in `processes` that pass this filter.
+    pub fn filter<'a>(&self, processes: &'a [OsProcess]) -> Vec<&'a OsProcess> {
+        processes.iter().filter(|process| self.matches(process)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_processes, OsProcess, ProcessFilter};
+    use low::RspConnection;
+    use util::frame_packet;
+
+    #[test]
+    fn processes() {
+        let input = frame_packet(b"l<?xml version=\"1.0\"?>\n\
+                                   <osdata type=\"processes\">\n\
+                                   <item><column name=\"pid\">1</column>\
+                                   <column name=\"user\">root</column>\
+                                   <column name=\"command\">/sbin/init splash</column>\
+                                   <column name=\"cores\">0</column></item>\n\
+                                   <item><column name=\"pid\">42</column>\
+                                   <column name=\"user\">tom</column>\
+                                   <column name=\"command\">./a&amp;b --x</column>\
+                                   <column name=\"cores\">1,3</column></item>\n\
+                                   <item><column name=\"user\">nobody</column></item>\n\
+                                   </osdata>");
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let processes = {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            read_processes(&mut rsp, 4096).unwrap().unwrap()
+        };
+        assert_eq!(output, frame_packet(b"qXfer:osdata:read:processes:0,fff"));
+        assert_eq!(processes, vec![
+            OsProcess {
+                pid: 1,
+                user: "root".to_string(),
+                command: "/sbin/init splash".to_string(),
+                cores: vec![0],
+            },
+            OsProcess {
+                pid: 42,
+                user: "tom".to_string(),
+                command: "./a&b --x".to_string(),
+                cores: vec![1, 3],
+            },
+        ]);
+        assert_eq!(processes[0].name(), "init");
+
+        let by_name = ProcessFilter { name: Some("a&b".to_string()), ..Default::default() };
+        assert_eq!(by_name.filter(&processes), vec![&processes[1]]);
+        let by_core = ProcessFilter { user: Some("root".to_string()), core: Some(3),
+                                      ..Default::default() };
+        assert!(by_core.filter(&processes).is_empty());
+        assert_eq!(ProcessFilter::default().filter(&processes).len(), 2);
+    }
+}
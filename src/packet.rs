@@ -0,0 +1,115 @@
+#![deny(missing_docs)]
+
+use low::{PacketType, RspResult};
+
+/// A packet as returned by `read_packet`, split into its name and
+/// arguments.  `ParsedPacket` owns the packet's buffer and records
+/// only where the name ends, so the views it hands out borrow from it
+/// without copying.
+///
+/// For `q`, `Q`, and `v` packets, the name runs up to the first `:`,
+/// `,`, or `;`, and the arguments follow that separator; for example
+/// `qXfer:features:read:...` has the name `qXfer`.  A notification's
+/// name runs up to the first `:`, so `Stop:T05...` has the name `Stop`
+/// and the arguments `T05...`.  Any other packet has a one-character
+/// name, so `m1000,4` has the name `m` and the arguments `1000,4`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedPacket {
+    kind: PacketType,
+    buffer: Vec<u8>,
+    // The end of the name, and the start of the arguments.
+    name_end: usize,
+    args_start: usize,
+}
+
+impl ParsedPacket {
+    /// Wrap a packet, such as one returned by `read_packet`.
+    pub fn new(kind: PacketType, buffer: Vec<u8>) -> ParsedPacket {
+        let (name_end, args_start) = match buffer.first() {
+            None => (0, 0),
+            Some(_) if kind == PacketType::Notification => {
+                match buffer.iter().position(|&c| c == b':') {
+                    Some(sep) => (sep, sep + 1),
+                    None => (buffer.len(), buffer.len()),
+                }
+            }
+            Some(&b'q') | Some(&b'Q') | Some(&b'v') => {
+                match buffer.iter().position(|&c| c == b':' || c == b',' || c == b';') {
+                    Some(sep) => (sep, sep + 1),
+                    None => (buffer.len(), buffer.len()),
+                }
+            }
+            Some(_) => (1, 1),
+        };
+        ParsedPacket { kind, buffer, name_end, args_start }
+    }
+
+    /// Return the type of the packet.
+    pub fn kind(&self) -> PacketType {
+        self.kind
+    }
+
+    /// Return the name of the packet.  This is empty for an empty
+    /// packet.
+    pub fn name(&self) -> &[u8] {
+        &self.buffer[..self.name_end]
+    }
+
+    /// Return the arguments of the packet, not including the
+    /// separator that follows the name.
+    pub fn args(&self) -> &[u8] {
+        &self.buffer[self.args_start..]
+    }
+
+    /// Return the entire packet.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Return the packet's buffer, giving up ownership.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Run one of this crate's packet parsers, such as
+    /// `parse_stop_reply`, over the entire packet.
+    pub fn parse<T, F>(&self, parser: F) -> RspResult<T>
+        where F: FnOnce(&[u8]) -> RspResult<T>
+    {
+        parser(&self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParsedPacket;
+    use low::PacketType;
+    use stop::{parse_stop_reply, StopReply, StopReplyValue};
+
+    #[test]
+    fn split() {
+        let packet = ParsedPacket::new(PacketType::Normal,
+                                       b"qXfer:features:read:target.xml:0,fff".to_vec());
+        assert_eq!(packet.name(), b"qXfer");
+        assert_eq!(packet.args(), b"features:read:target.xml:0,fff");
+
+        let packet = ParsedPacket::new(PacketType::Normal, b"vCont;c".to_vec());
+        assert_eq!((packet.name(), packet.args()), (&b"vCont"[..], &b"c"[..]));
+        let packet = ParsedPacket::new(PacketType::Normal, b"qC".to_vec());
+        assert_eq!((packet.name(), packet.args()), (&b"qC"[..], &b""[..]));
+        let packet = ParsedPacket::new(PacketType::Normal, b"m1000,4".to_vec());
+        assert_eq!((packet.name(), packet.args()), (&b"m"[..], &b"1000,4"[..]));
+        let packet = ParsedPacket::new(PacketType::Normal, Vec::new());
+        assert_eq!((packet.name(), packet.args()), (&b""[..], &b""[..]));
+
+        let packet = ParsedPacket::new(PacketType::Notification, b"Stop:T05core:1;".to_vec());
+        assert_eq!((packet.name(), packet.args()), (&b"Stop"[..], &b"T05core:1;"[..]));
+        assert_eq!(parse_stop_reply(packet.args()).unwrap(),
+                   StopReply::Signal { signal: 5, values: vec![StopReplyValue::Core(1)] });
+        assert_eq!(packet.into_bytes(), b"Stop:T05core:1;".to_vec());
+
+        let packet = ParsedPacket::new(PacketType::Normal, b"S05".to_vec());
+        assert_eq!(packet.parse(parse_stop_reply).unwrap(),
+                   StopReply::Signal { signal: 5, values: Vec::new() });
+    }
+}
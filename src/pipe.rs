@@ -0,0 +1,97 @@
+#![deny(missing_docs)]
+
+use std::io;
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+
+use low::RspConnection;
+use transport::Transport;
+
+/// An RSP channel over the standard input and output of a stub
+/// subprocess, such as `gdbserver - ./prog` or `qemu -gdb stdio`.
+/// This is the equivalent of gdb's `target remote | command`.  The
+/// child's standard error is left alone, so that its diagnostics are
+/// seen.
+///
+/// `RspConnection` borrows its reader and writer, so this type owns
+/// the child's pipes while connections are made from it using
+/// `connection`.  Use `shutdown` to let the child exit cleanly;
+/// otherwise it is killed when the channel is dropped.
+pub struct PipeChannel {
+    child: Child,
+    reader: ChildStdout,
+    // This is only None once the channel has been shut down.
+    writer: Option<ChildStdin>,
+}
+
+impl PipeChannel {
+    /// Spawn `command` with its standard input and output redirected
+    /// to pipes, and make a channel that talks to it.
+    pub fn spawn(command: &mut Command) -> io::Result<PipeChannel> {
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let reader = child.stdout.take().expect("stdout was piped");
+        let writer = child.stdin.take();
+        Ok(PipeChannel { child, reader, writer })
+    }
+
+    /// Return the child process, for example to find its id.
+    pub fn child(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Make an `RspConnection` that uses this channel.  `is_client` is
+    /// as for `RspConnection::new`.
+    pub fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        RspConnection::new(&mut self.reader, self.writer.as_mut().expect("channel is open"),
+                           is_client)
+    }
+
+    /// Close the child's standard input and wait for it to exit.
+    /// Stubs exit when the debugger goes away, but it is usually best
+    /// to detach or kill the inferior first, with `D` or `k`.
+    pub fn shutdown(mut self) -> io::Result<ExitStatus> {
+        self.writer = None;
+        self.child.wait()
+    }
+}
+
+impl Transport for PipeChannel {
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        PipeChannel::connection(self, is_client)
+    }
+}
+
+impl Drop for PipeChannel {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::process::Command;
+
+    use super::PipeChannel;
+
+    #[test]
+    fn spawn() {
+        // cat echoes each packet back, which is enough to see that
+        // both pipes are connected.
+        let mut channel = PipeChannel::spawn(&mut Command::new("cat")).unwrap();
+        {
+            let mut conn = channel.connection(true);
+            conn.disable_acking();
+            assert_eq!(conn.request(b"qC").unwrap(), b"qC".to_vec());
+        }
+        assert!(channel.shutdown().unwrap().success());
+
+        // Dropping the channel kills a child that is still running.
+        let mut channel = PipeChannel::spawn(&mut Command::new("cat")).unwrap();
+        let mut probe = Command::new("kill");
+        probe.arg("-0").arg(channel.child().id().to_string());
+        drop(channel);
+        assert!(!probe.status().unwrap().success());
+    }
+}
@@ -0,0 +1,195 @@
+#![deny(missing_docs)]
+
+use tdesc::{Feature, Field, FlagsType, Register, TargetDescription};
+
+// Add registers that all have the same size and type.
+fn add_registers(feature: &mut Feature, names: &[&str], bitsize: u32, reg_type: &str) {
+    for name in names {
+        feature.add_register(Register::new(name, bitsize, reg_type));
+    }
+}
+
+// Add |count| registers named with a common prefix and a number
+// counting from zero; for example "x0".."x30".
+fn add_numbered(feature: &mut Feature, prefix: &str, count: u32, bitsize: u32, reg_type: &str) {
+    for i in 0..count {
+        feature.add_register(Register::new(&format!("{}{}", prefix, i), bitsize, reg_type));
+    }
+}
+
+// Add a single register that belongs to a register group.
+fn add_grouped(feature: &mut Feature, name: &str, bitsize: u32, reg_type: &str, group: &str) {
+    let mut reg = Register::new(name, bitsize, reg_type);
+    reg.set_group(group);
+    feature.add_register(reg);
+}
+
+/// A target description for x86-64, matching the register layout
+/// that GDB expects from gdbserver: the general registers, the x87
+/// registers, and the SSE registers.  `eflags` has GDB's
+/// `i386_eflags` type, and vector registers use a plain integer type.
+pub fn tdesc_x86_64() -> TargetDescription {
+    let mut eflags = FlagsType::new("i386_eflags", 4);
+    let bits = [("CF", 0), ("PF", 2), ("AF", 4), ("ZF", 6), ("SF", 7), ("TF", 8), ("IF", 9),
+                ("DF", 10), ("OF", 11), ("NT", 14), ("RF", 16), ("VM", 17), ("AC", 18),
+                ("VIF", 19), ("VIP", 20), ("ID", 21)];
+    for &(name, bit) in &bits {
+        eflags.add_field(Field::new(name, bit, bit));
+    }
+
+    let mut core = Feature::new("org.gnu.gdb.i386.core");
+    core.add_flags(eflags);
+    add_registers(&mut core, &["rax", "rbx", "rcx", "rdx", "rsi", "rdi"], 64, "int64");
+    add_registers(&mut core, &["rbp", "rsp"], 64, "data_ptr");
+    for i in 8..16 {
+        core.add_register(Register::new(&format!("r{}", i), 64, "int64"));
+    }
+    core.add_register(Register::new("rip", 64, "code_ptr"));
+    core.add_register(Register::new("eflags", 32, "i386_eflags"));
+    add_registers(&mut core, &["cs", "ss", "ds", "es", "fs", "gs"], 32, "int32");
+    add_numbered(&mut core, "st", 8, 80, "i387_ext");
+    for name in &["fctrl", "fstat", "ftag", "fiseg", "fioff", "foseg", "fooff", "fop"] {
+        add_grouped(&mut core, name, 32, "int", "float");
+    }
+
+    let mut sse = Feature::new("org.gnu.gdb.i386.sse");
+    add_numbered(&mut sse, "xmm", 16, 128, "uint128");
+    add_grouped(&mut sse, "mxcsr", 32, "int", "vector");
+
+    let mut result = TargetDescription::new();
+    result.set_architecture("i386:x86-64");
+    result.add_feature(core);
+    result.add_feature(sse);
+    result
+}
+
+/// A target description for AArch64: the core registers and the
+/// FP/SIMD registers.  Vector registers use a plain integer type.
+pub fn tdesc_aarch64() -> TargetDescription {
+    let mut core = Feature::new("org.gnu.gdb.aarch64.core");
+    add_numbered(&mut core, "x", 31, 64, "int");
+    core.add_register(Register::new("sp", 64, "data_ptr"));
+    core.add_register(Register::new("pc", 64, "code_ptr"));
+    core.add_register(Register::new("cpsr", 32, "int"));
+
+    let mut fpu = Feature::new("org.gnu.gdb.aarch64.fpu");
+    add_numbered(&mut fpu, "v", 32, 128, "uint128");
+    add_registers(&mut fpu, &["fpsr", "fpcr"], 32, "int");
+
+    let mut result = TargetDescription::new();
+    result.set_architecture("aarch64");
+    result.add_feature(core);
+    result.add_feature(fpu);
+    result
+}
+
+/// A target description for ARMv7-M (M-profile) cores, without
+/// floating point.
+pub fn tdesc_armv7m() -> TargetDescription {
+    let mut core = Feature::new("org.gnu.gdb.arm.m-profile");
+    add_numbered(&mut core, "r", 13, 32, "uint32");
+    core.add_register(Register::new("sp", 32, "data_ptr"));
+    core.add_register(Register::new("lr", 32, "int"));
+    core.add_register(Register::new("pc", 32, "code_ptr"));
+    let mut xpsr = Register::new("xpsr", 32, "int");
+    xpsr.set_regnum(25);
+    core.add_register(xpsr);
+
+    let mut result = TargetDescription::new();
+    result.set_architecture("arm");
+    result.add_feature(core);
+    result
+}
+
+// The RISC-V integer registers, which are the same for both widths.
+fn riscv(bitsize: u32, architecture: &str) -> TargetDescription {
+    let mut cpu = Feature::new("org.gnu.gdb.riscv.cpu");
+    cpu.add_register(Register::new("zero", bitsize, "int"));
+    cpu.add_register(Register::new("ra", bitsize, "code_ptr"));
+    add_registers(&mut cpu, &["sp", "gp", "tp"], bitsize, "data_ptr");
+    add_registers(&mut cpu, &["t0", "t1", "t2"], bitsize, "int");
+    cpu.add_register(Register::new("fp", bitsize, "data_ptr"));
+    cpu.add_register(Register::new("s1", bitsize, "int"));
+    add_numbered(&mut cpu, "a", 8, bitsize, "int");
+    for i in 2..12 {
+        cpu.add_register(Register::new(&format!("s{}", i), bitsize, "int"));
+    }
+    add_registers(&mut cpu, &["t3", "t4", "t5", "t6"], bitsize, "int");
+    cpu.add_register(Register::new("pc", bitsize, "code_ptr"));
+
+    let mut result = TargetDescription::new();
+    result.set_architecture(architecture);
+    result.add_feature(cpu);
+    result
+}
+
+/// A target description for 32-bit RISC-V: the integer registers
+/// and the PC.
+pub fn tdesc_riscv32() -> TargetDescription {
+    riscv(32, "riscv:rv32")
+}
+
+/// A target description for 64-bit RISC-V: the integer registers
+/// and the PC.
+pub fn tdesc_riscv64() -> TargetDescription {
+    riscv(64, "riscv:rv64")
+}
+
+/// A target description for 32-bit PowerPC, such as QEMU's default
+/// `qemu-system-ppc` CPU: the core registers only.  The target is
+/// big-endian.  As in GDB, the PC is register 64, leaving room for the
+/// floating-point registers.
+pub fn tdesc_powerpc32() -> TargetDescription {
+    let mut core = Feature::new("org.gnu.gdb.power.core");
+    add_numbered(&mut core, "r", 32, 32, "uint32");
+    let mut pc = Register::new("pc", 32, "code_ptr");
+    pc.set_regnum(64);
+    core.add_register(pc);
+    core.add_register(Register::new("msr", 32, "uint32"));
+    core.add_register(Register::new("cr", 32, "uint32"));
+    core.add_register(Register::new("lr", 32, "code_ptr"));
+    core.add_register(Register::new("ctr", 32, "uint32"));
+    core.add_register(Register::new("xer", 32, "uint32"));
+
+    let mut result = TargetDescription::new();
+    result.set_architecture("powerpc:common");
+    result.add_feature(core);
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tdesc_aarch64, tdesc_armv7m, tdesc_powerpc32, tdesc_riscv32, tdesc_riscv64,
+                tdesc_x86_64};
+    use tdesc::TargetDescription;
+
+    fn find(tdesc: &TargetDescription, name: &str) -> (u32, u32) {
+        let regs = tdesc.registers();
+        let &(regnum, reg) = regs.iter().find(|&&(_, reg)| reg.name() == name).unwrap();
+        (regnum, reg.bitsize())
+    }
+
+    #[test]
+    fn presets() {
+        let x86_64 = tdesc_x86_64();
+        assert_eq!(find(&x86_64, "rip"), (16, 64));
+        assert_eq!(find(&x86_64, "st0"), (24, 80));
+        assert_eq!(find(&x86_64, "xmm0"), (40, 128));
+        assert_eq!(find(&x86_64, "mxcsr"), (56, 32));
+
+        let aarch64 = tdesc_aarch64();
+        assert_eq!(find(&aarch64, "pc"), (32, 64));
+        assert_eq!(find(&aarch64, "fpcr"), (67, 32));
+
+        let armv7m = tdesc_armv7m();
+        assert_eq!(find(&armv7m, "pc"), (15, 32));
+        assert_eq!(find(&armv7m, "xpsr"), (25, 32));
+
+        assert_eq!(find(&tdesc_riscv32(), "pc"), (32, 32));
+        assert_eq!(find(&tdesc_riscv64(), "a0"), (10, 64));
+
+        let powerpc32 = tdesc_powerpc32();
+        assert_eq!(find(&powerpc32, "pc"), (64, 32));
+        assert_eq!(find(&powerpc32, "xer"), (69, 32));
+    }
+}
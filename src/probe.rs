@@ -0,0 +1,149 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+
+use low::{RspConnection, RspResult};
+use startup::NegotiatedCapabilities;
+
+/// A question about a stub's capabilities that is answered by sending
+/// a packet, rather than by `qSupported`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Probe {
+    /// Whether `vCont` is supported, asked with `vCont?`.  The actions
+    /// themselves are available from `ProbeCache::vcont_actions`.
+    VCont,
+    /// Whether `vAttachOrWait` is supported, asked with
+    /// `qVAttachOrWaitSupported`.
+    AttachOrWait,
+    /// Whether the `qXfer` object with this name can be read, asked by
+    /// reading a single byte of it.
+    QXferRead(String),
+}
+
+/// The answers to `Probe`s, for use by a client.  Each question is
+/// only sent the first time it is asked; after that the cached answer
+/// is used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProbeCache {
+    answers: BTreeMap<Probe, bool>,
+    vcont_actions: Vec<Vec<u8>>,
+}
+
+impl ProbeCache {
+    /// Make an empty `ProbeCache`.
+    pub fn new() -> ProbeCache {
+        ProbeCache::default()
+    }
+
+    /// Make a `ProbeCache` holding what `startup` already learned:
+    /// the `vCont` actions, and the `qXfer` objects that the stub
+    /// reported as readable in `qSupported`.
+    pub fn from_capabilities(caps: &NegotiatedCapabilities) -> ProbeCache {
+        let mut cache = ProbeCache::new();
+        cache.answers.insert(Probe::VCont, !caps.vcont_actions.is_empty());
+        cache.vcont_actions = caps.vcont_actions.clone();
+        for feature in &caps.supported {
+            let name = feature.strip_prefix(b"qXfer:").and_then(|f| f.strip_suffix(b":read"));
+            if let Some(name) = name {
+                let name = String::from_utf8_lossy(name).into_owned();
+                cache.answers.insert(Probe::QXferRead(name), true);
+            }
+        }
+        cache
+    }
+
+    // Send the packet for |probe| and interpret the reply.
+    fn ask(&mut self, conn: &mut RspConnection, probe: &Probe) -> RspResult<bool> {
+        Ok(match *probe {
+            Probe::VCont => {
+                let reply = conn.request(b"vCont?")?;
+                match reply.strip_prefix(b"vCont;") {
+                    Some(actions) => {
+                        self.vcont_actions = actions.split(|&c| c == b';')
+                            .filter(|a| !a.is_empty())
+                            .map(|a| a.to_vec())
+                            .collect();
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Probe::AttachOrWait => conn.request(b"qVAttachOrWaitSupported")? == b"OK",
+            Probe::QXferRead(ref name) => {
+                // An error means that the object is known, but that
+                // there was a problem reading it.
+                let mut packet = b"qXfer:".to_vec();
+                packet.extend_from_slice(name.as_bytes());
+                packet.extend_from_slice(b":read::0,1");
+                !conn.request(&packet)?.is_empty()
+            }
+        })
+    }
+
+    /// Return the answer to `probe`, asking the stub over `conn` if it
+    /// is not already known.
+    pub fn probe(&mut self, conn: &mut RspConnection, probe: &Probe) -> RspResult<bool> {
+        if let Some(&answer) = self.answers.get(probe) {
+            return Ok(answer);
+        }
+        let answer = self.ask(conn, probe)?;
+        self.answers.insert(probe.clone(), answer);
+        Ok(answer)
+    }
+
+    /// Return the `vCont` actions that the stub supports, such as `c`
+    /// and `s`, asking over `conn` if they are not already known.
+    /// This is empty if the stub does not support `vCont`.
+    pub fn vcont_actions(&mut self, conn: &mut RspConnection) -> RspResult<&[Vec<u8>]> {
+        self.probe(conn, &Probe::VCont)?;
+        Ok(&self.vcont_actions)
+    }
+
+    /// Forget all the answers, for example after reconnecting, when
+    /// the stub may be a different one.
+    pub fn clear(&mut self) {
+        self.answers.clear();
+        self.vcont_actions.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Probe, ProbeCache};
+    use low::RspConnection;
+    use startup::NegotiatedCapabilities;
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn probes() {
+        let mut input = frame_packet(b"vCont;c;C;s;S");
+        input.extend(frame_packet(b""));
+        input.extend(frame_packet(b"E00"));
+        input.extend(frame_packet(b""));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut cache = ProbeCache::new();
+            assert_eq!(cache.vcont_actions(&mut rsp).unwrap().len(), 4);
+            assert!(cache.probe(&mut rsp, &Probe::VCont).unwrap());
+            assert!(!cache.probe(&mut rsp, &Probe::AttachOrWait).unwrap());
+            assert!(!cache.probe(&mut rsp, &Probe::AttachOrWait).unwrap());
+            let auxv = Probe::QXferRead("auxv".to_string());
+            assert!(cache.probe(&mut rsp, &auxv).unwrap());
+
+            // Startup's answers are used without asking again.
+            let mut caps = NegotiatedCapabilities::default();
+            caps.supported.insert(b"qXfer:features:read".to_vec());
+            let mut cache = ProbeCache::from_capabilities(&caps);
+            assert!(cache.probe(&mut rsp, &Probe::QXferRead("features".to_string())).unwrap());
+            assert!(cache.vcont_actions(&mut rsp).unwrap().is_empty());
+            cache.clear();
+            assert!(!cache.probe(&mut rsp, &Probe::VCont).unwrap());
+        }
+        let sent: &[&[u8]] = &[b"vCont?", b"qVAttachOrWaitSupported", b"qXfer:auxv:read::0,1",
+                               b"vCont?"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
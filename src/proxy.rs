@@ -0,0 +1,552 @@
+#![deny(missing_docs)]
+
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use low::{PacketType, RspConnection, RspError, RspResult};
+use util::request_gets_reply;
+
+/// The direction in which a packet is travelling through a `Proxy`,
+/// or through a connection with a packet handler; see
+/// `RspConnection::set_packet_handler`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the debugger to the stub.
+    ToStub,
+    /// From the stub to the debugger.
+    ToDebugger,
+}
+
+/// What a `Proxy` should do with a packet, as decided by a
+/// `ProxyHandler`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProxyAction {
+    /// Send the packet on to its destination.
+    Forward,
+    /// Discard the packet.  Note that discarding a request or a reply
+    /// that the debugger is waiting for will leave it waiting.
+    Drop,
+    /// Only valid for requests going to the stub: do not forward the
+    /// request, but instead send this reply to the debugger.  This
+    /// can be used to emulate packets that the stub lacks.  Returning
+    /// this for any other packet makes the proxy fail with
+    /// `RspError::InvalidArgument`.
+    Reply(Vec<u8>),
+}
+
+/// A hook that a `Proxy` calls for every packet it relays.  This can
+/// be used to log traffic, to rewrite packets on the way through, or
+/// to filter or synthesize packets.  A handler can also be installed
+/// on a single connection, client or server, with
+/// `RspConnection::set_packet_handler`.
+pub trait ProxyHandler {
+    /// Called with each packet before it is forwarded.  `contents`
+    /// may be modified in place; if the packet is forwarded, whatever
+    /// it holds on return is what is sent.
+    fn packet(&mut self, direction: Direction, kind: PacketType, contents: &mut Vec<u8>)
+              -> ProxyAction;
+}
+
+/// A `ProxyHandler` that forwards everything unchanged.
+impl ProxyHandler for () {
+    fn packet(&mut self, _: Direction, _: PacketType, _: &mut Vec<u8>) -> ProxyAction {
+        ProxyAction::Forward
+    }
+}
+
+/// A `ProxyHandler` that writes a line describing each packet to a
+/// writer, and otherwise forwards everything unchanged.
+pub struct LogHandler<W: Write> {
+    out: W,
+}
+
+impl<W: Write> LogHandler<W> {
+    /// Make a new `LogHandler` that logs to `out`.
+    pub fn new(out: W) -> LogHandler<W> {
+        LogHandler { out }
+    }
+}
+
+impl<W: Write> ProxyHandler for LogHandler<W> {
+    fn packet(&mut self, direction: Direction, kind: PacketType, contents: &mut Vec<u8>)
+              -> ProxyAction {
+        let arrow = match direction {
+            Direction::ToStub => "-->",
+            Direction::ToDebugger => "<--",
+        };
+        let prefix = match kind {
+            PacketType::Normal => "$",
+            PacketType::Notification => "%",
+            PacketType::Ack => "+",
+            PacketType::Nak => "-",
+            PacketType::Interrupt => "^C",
+        };
+        // Logging is best-effort; a failure here shouldn't break the
+        // session being proxied.
+        let _ = writeln!(self.out, "{} {}{}", arrow, prefix, String::from_utf8_lossy(contents));
+        ProxyAction::Forward
+    }
+}
+
+// While waiting for a reply, how long the proxy reads from each side
+// before looking at the other.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A proxy that sits between a debugger and a stub, relaying packets
+/// between them.  Each packet is passed to a `ProxyHandler` on the
+/// way through.
+///
+/// The proxy works in lock-step: it reads a request from the
+/// debugger, forwards it, and then waits for the stub's reply.  Any
+/// notifications that arrive in the meantime are passed along as
+/// well.  A `QStartNoAckMode` handshake is mirrored on both sides.
+///
+/// While waiting for a reply, the proxy polls both connections in
+/// turn, so that an interrupt sent by the debugger while the stub is
+/// running is relayed to the stub.  This relies on the read timeouts
+/// of both connections being enforced, as they are for the channel
+/// types in this crate; see `RspConnection::set_read_timeout`.
+/// Otherwise, the proxy only reads from the stub while it waits.
+pub struct Proxy<'conn, H: ProxyHandler> {
+    debugger: RspConnection<'conn>,
+    stub: RspConnection<'conn>,
+    handler: H,
+    // A request that the debugger sent while a reply was awaited.
+    pending: Option<Vec<u8>>,
+}
+
+impl<'conn, H: ProxyHandler> Proxy<'conn, H> {
+    /// Create a new `Proxy`.  `debugger` is the connection to the
+    /// debugger, and so must have been created as a server;
+    /// `stub` is the connection to the stub, and so must have been
+    /// created as a client.  The proxy sets the read timeouts of both
+    /// connections, and makes `debugger` report control characters.
+    pub fn new(mut debugger: RspConnection<'conn>, stub: RspConnection<'conn>, handler: H)
+               -> Proxy<'conn, H> {
+        debugger.set_report_control_characters(true);
+        Proxy { debugger, stub, handler, pending: None }
+    }
+
+    /// Return a reference to the handler.
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    // Write a packet to one side, bypassing the handler.
+    fn write_packet(&mut self, direction: Direction, kind: PacketType, contents: &[u8])
+                    -> RspResult<()> {
+        let conn = match direction {
+            Direction::ToStub => &mut self.stub,
+            Direction::ToDebugger => &mut self.debugger,
+        };
+        match kind {
+            PacketType::Normal => conn.start_packet()?,
+            PacketType::Notification => conn.start_notification_packet()?,
+            _ => return conn.write_control(kind),
+        }
+        conn.write_all(contents)?;
+        conn.finish_packet()
+    }
+
+    // Pass a packet to the handler, and then act on its decision.
+    // Returns true if the packet was forwarded.
+    fn send(&mut self, direction: Direction, kind: PacketType, mut contents: Vec<u8>)
+            -> RspResult<bool> {
+        match self.handler.packet(direction, kind, &mut contents) {
+            ProxyAction::Forward => {
+                self.write_packet(direction, kind, &contents)?;
+                Ok(true)
+            }
+            ProxyAction::Drop => Ok(false),
+            ProxyAction::Reply(reply) => {
+                if direction != Direction::ToStub {
+                    return Err(RspError::InvalidArgument("only a request can be replied to"));
+                }
+                self.write_packet(Direction::ToDebugger, PacketType::Normal, &reply)?;
+                Ok(false)
+            }
+        }
+    }
+
+    // Read the stub's reply to a request, forwarding notifications to
+    // the debugger and interrupts to the stub.
+    fn wait_for_reply(&mut self) -> RspResult<Vec<u8>> {
+        loop {
+            match self.stub.read_packet() {
+                Ok((PacketType::Normal, contents)) => return Ok(contents),
+                Ok((PacketType::Notification, contents)) => {
+                    self.send(Direction::ToDebugger, PacketType::Notification, contents)?;
+                }
+                Ok(_) | Err(RspError::InvalidChecksum) => { }
+                Err(RspError::Timeout) => self.poll_debugger()?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Read whatever the debugger has sent while a reply is awaited.
+    // This is normally nothing, or an interrupt.
+    fn poll_debugger(&mut self) -> RspResult<()> {
+        if self.pending.is_some() {
+            return Ok(());
+        }
+        match self.debugger.read_packet() {
+            Ok((PacketType::Interrupt, contents)) => {
+                self.send(Direction::ToStub, PacketType::Interrupt, contents)?;
+            }
+            // A request sent early is kept until the reply is done.
+            Ok((PacketType::Normal, contents)) => self.pending = Some(contents),
+            Ok(_) | Err(RspError::InvalidChecksum) | Err(RspError::Timeout) => { }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Relay a single request from the debugger to the stub, and the
+    /// stub's reply back to the debugger.
+    pub fn relay_one(&mut self) -> RspResult<()> {
+        let request = match self.pending.take() {
+            Some(request) => request,
+            None => loop {
+                match self.debugger.read_packet() {
+                    Ok((PacketType::Normal, contents)) => break contents,
+                    // The debugger shouldn't send notifications, and a
+                    // bad checksum will be resent.  An interrupt when
+                    // nothing is running has nothing to stop.
+                    Ok(_) | Err(RspError::InvalidChecksum) => { }
+                    Err(e) => return Err(e),
+                }
+            },
+        };
+
+        let is_no_ack = request == b"QStartNoAckMode";
+        let wants_reply = request_gets_reply(&request);
+        if !self.send(Direction::ToStub, PacketType::Normal, request)? || !wants_reply {
+            return Ok(());
+        }
+
+        self.stub.set_read_timeout(Some(POLL_INTERVAL));
+        self.debugger.set_read_timeout(Some(POLL_INTERVAL));
+        let reply = self.wait_for_reply();
+        self.stub.set_read_timeout(None);
+        self.debugger.set_read_timeout(None);
+        let reply = reply?;
+
+        let accepted_no_ack = is_no_ack && reply == b"OK";
+        let forwarded = self.send(Direction::ToDebugger, PacketType::Normal, reply)?;
+        if accepted_no_ack && forwarded {
+            self.stub.disable_acking();
+            self.debugger.disable_acking();
+        }
+
+        Ok(())
+    }
+
+    /// Relay packets until the debugger closes the connection, or an
+    /// error occurs.
+    pub fn run(&mut self) -> RspResult<()> {
+        loop {
+            match self.relay_one() {
+                Ok(()) => { }
+                Err(RspError::IOError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::io::Read;
+
+    use super::{Direction, LogHandler, Proxy, ProxyAction, ProxyHandler};
+    use low::{PacketType, RspConnection, RspError};
+    use util::{frame_packet, Script};
+
+    // A reader that stands in for a socket with a read timeout: it
+    // times out once at the end of each chunk of input but the last.
+    struct Stalling {
+        chunks: VecDeque<Vec<u8>>,
+    }
+
+    impl Stalling {
+        fn new(chunks: &[&[u8]]) -> Stalling {
+            Stalling { chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect() }
+        }
+    }
+
+    impl Read for Stalling {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk = match self.chunks.front_mut() {
+                Some(chunk) => chunk,
+                None => return Ok(0),
+            };
+            if chunk.is_empty() {
+                self.chunks.pop_front();
+                if self.chunks.is_empty() {
+                    return Ok(0);
+                }
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = ::std::cmp::min(buf.len(), chunk.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            Ok(n)
+        }
+    }
+
+    // A handler that hides a feature from the debugger.
+    struct HideFeature;
+
+    impl ProxyHandler for HideFeature {
+        fn packet(&mut self, direction: Direction, _: PacketType, contents: &mut Vec<u8>)
+                  -> ProxyAction {
+            if direction == Direction::ToDebugger && contents.starts_with(b"PacketSize") {
+                *contents = b"PacketSize=100".to_vec();
+            }
+            ProxyAction::Forward
+        }
+    }
+
+    // A handler that emulates qEcho, and drops notifications.
+    struct Emulate;
+
+    impl ProxyHandler for Emulate {
+        fn packet(&mut self, direction: Direction, kind: PacketType, contents: &mut Vec<u8>)
+                  -> ProxyAction {
+            if direction == Direction::ToStub && contents.starts_with(b"qEcho:") {
+                ProxyAction::Reply(contents[6..].to_vec())
+            } else if kind == PacketType::Notification {
+                ProxyAction::Drop
+            } else {
+                ProxyAction::Forward
+            }
+        }
+    }
+
+    #[test]
+    fn synthesize() {
+        let mut from_debugger = Vec::new();
+        from_debugger.extend(frame_packet(b"qEcho:hi"));
+        from_debugger.push(b'+');
+        from_debugger.extend(frame_packet(b"?"));
+        from_debugger.push(b'+');
+
+        let mut from_stub = vec![b'+'];
+        from_stub.extend(b"%Stop:S05#00");
+        from_stub.extend(frame_packet(b"S05"));
+
+        let mut debugger_in: &[u8] = &from_debugger;
+        let mut stub_in: &[u8] = &from_stub;
+        let mut to_debugger = Vec::new();
+        let mut to_stub = Vec::new();
+        {
+            let debugger = RspConnection::new(&mut debugger_in, &mut to_debugger, false);
+            let stub = RspConnection::new(&mut stub_in, &mut to_stub, true);
+            let mut proxy = Proxy::new(debugger, stub, Emulate);
+            proxy.run().expect("run");
+        }
+
+        let mut expected = b"+".to_vec();
+        expected.extend(frame_packet(b"hi"));
+        expected.push(b'+');
+        expected.extend(frame_packet(b"S05"));
+        assert_eq!(to_debugger, expected);
+
+        let mut expected = frame_packet(b"?");
+        expected.push(b'+');
+        assert_eq!(to_stub, expected);
+    }
+
+    #[test]
+    fn relay() {
+        let mut from_debugger = Vec::new();
+        from_debugger.extend(frame_packet(b"qSupported"));
+        from_debugger.push(b'+');
+        from_debugger.extend(frame_packet(b"QStartNoAckMode"));
+        from_debugger.push(b'+');
+        from_debugger.extend(frame_packet(b"?"));
+
+        let mut from_stub = vec![b'+'];
+        from_stub.extend(frame_packet(b"PacketSize=3fff;QStartNoAckMode+"));
+        from_stub.push(b'+');
+        from_stub.extend(frame_packet(b"OK"));
+        from_stub.extend(frame_packet(b"S05"));
+
+        let mut debugger_in: &[u8] = &from_debugger;
+        let mut stub_in: &[u8] = &from_stub;
+        let mut to_debugger = Vec::new();
+        let mut to_stub = Vec::new();
+        {
+            let debugger = RspConnection::new(&mut debugger_in, &mut to_debugger, false);
+            let stub = RspConnection::new(&mut stub_in, &mut to_stub, true);
+            let mut proxy = Proxy::new(debugger, stub, HideFeature);
+            proxy.run().expect("run");
+        }
+
+        let mut expected = b"+".to_vec();
+        expected.extend(frame_packet(b"PacketSize=100"));
+        expected.push(b'+');
+        expected.extend(frame_packet(b"OK"));
+        expected.extend(frame_packet(b"S05"));
+        assert_eq!(to_debugger, expected);
+
+        let mut expected = frame_packet(b"qSupported");
+        expected.push(b'+');
+        expected.extend(frame_packet(b"QStartNoAckMode"));
+        expected.push(b'+');
+        expected.extend(frame_packet(b"?"));
+        assert_eq!(to_stub, expected);
+    }
+
+    #[test]
+    fn restart_gets_no_reply() {
+        let mut from_debugger = Vec::new();
+        from_debugger.extend(frame_packet(b"R00"));
+        from_debugger.push(b'+');
+        from_debugger.extend(frame_packet(b"?"));
+        from_debugger.push(b'+');
+
+        let mut from_stub = vec![b'+', b'+'];
+        from_stub.extend(frame_packet(b"S05"));
+
+        let mut debugger_in: &[u8] = &from_debugger;
+        let mut stub_in: &[u8] = &from_stub;
+        let mut to_debugger = Vec::new();
+        let mut to_stub = Vec::new();
+        {
+            let debugger = RspConnection::new(&mut debugger_in, &mut to_debugger, false);
+            let stub = RspConnection::new(&mut stub_in, &mut to_stub, true);
+            let mut proxy = Proxy::new(debugger, stub, HideFeature);
+            proxy.run().expect("run");
+        }
+
+        let mut expected = b"++".to_vec();
+        expected.extend(frame_packet(b"S05"));
+        assert_eq!(to_debugger, expected);
+
+        let mut expected = frame_packet(b"R00");
+        expected.extend(frame_packet(b"?"));
+        expected.push(b'+');
+        assert_eq!(to_stub, expected);
+    }
+
+    #[test]
+    fn notification_with_acks() {
+        let from_debugger = Script::new()
+            .packet(b"vCont;c")
+            .ack()
+            .packet(b"vStopped")
+            .ack()
+            .into_bytes();
+        let from_stub = Script::new()
+            .ack()
+            .notification(b"Stop:T05")
+            .packet(b"OK")
+            .ack()
+            .packet(b"OK")
+            .into_bytes();
+
+        let mut debugger_in: &[u8] = &from_debugger;
+        let mut stub_in: &[u8] = &from_stub;
+        let mut to_debugger = Vec::new();
+        let mut to_stub = Vec::new();
+        {
+            let debugger = RspConnection::new(&mut debugger_in, &mut to_debugger, false);
+            let stub = RspConnection::new(&mut stub_in, &mut to_stub, true);
+            let mut proxy = Proxy::new(debugger, stub, ());
+            proxy.run().expect("run");
+        }
+
+        // The notification is not acked, so the next request must not
+        // be mistaken for a nak.
+        let expected = Script::new()
+            .ack()
+            .notification(b"Stop:T05")
+            .packet(b"OK")
+            .ack()
+            .packet(b"OK")
+            .into_bytes();
+        assert_eq!(to_debugger, expected);
+
+        let expected = Script::new()
+            .packet(b"vCont;c")
+            .ack()
+            .packet(b"vStopped")
+            .ack()
+            .into_bytes();
+        assert_eq!(to_stub, expected);
+    }
+
+    #[test]
+    fn interrupt() {
+        let request = frame_packet(b"c");
+        let reply = frame_packet(b"S02");
+        let mut debugger_in = Stalling::new(&[&request, b"\x03+"]);
+        let mut stub_in = Stalling::new(&[b"+", b"", &reply]);
+        let mut to_debugger = Vec::new();
+        let mut to_stub = Vec::new();
+        {
+            let debugger = RspConnection::new(&mut debugger_in, &mut to_debugger, false);
+            let stub = RspConnection::new(&mut stub_in, &mut to_stub, true);
+            let mut proxy = Proxy::new(debugger, stub, ());
+            proxy.run().expect("run");
+        }
+
+        let mut expected = b"+".to_vec();
+        expected.extend(frame_packet(b"S02"));
+        assert_eq!(to_debugger, expected);
+
+        let mut expected = frame_packet(b"c");
+        expected.extend(b"\x03+");
+        assert_eq!(to_stub, expected);
+    }
+
+    // A handler that wrongly tries to reply to a reply.
+    struct ReplyToStub;
+
+    impl ProxyHandler for ReplyToStub {
+        fn packet(&mut self, direction: Direction, _: PacketType, _: &mut Vec<u8>)
+                  -> ProxyAction {
+            if direction == Direction::ToDebugger {
+                ProxyAction::Reply(b"OK".to_vec())
+            } else {
+                ProxyAction::Forward
+            }
+        }
+    }
+
+    #[test]
+    fn bad_reply() {
+        let mut debugger_in: &[u8] = &frame_packet(b"?");
+        let mut stub_in: &[u8] = &frame_packet(b"S05");
+        let mut to_debugger = Vec::new();
+        let mut to_stub = Vec::new();
+        let debugger = RspConnection::new(&mut debugger_in, &mut to_debugger, false);
+        let stub = RspConnection::new(&mut stub_in, &mut to_stub, true);
+        let mut proxy = Proxy::new(debugger, stub, ReplyToStub);
+        proxy.debugger.disable_acking();
+        proxy.stub.disable_acking();
+        match proxy.relay_one() {
+            Err(RspError::InvalidArgument(_)) => { }
+            other => panic!("expected InvalidArgument, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn log() {
+        let mut log = Vec::new();
+        {
+            let mut handler = LogHandler::new(&mut log);
+            handler.packet(Direction::ToStub, PacketType::Normal, &mut b"g".to_vec());
+            handler.packet(Direction::ToDebugger, PacketType::Notification,
+                           &mut b"Stop:S05".to_vec());
+        }
+        assert_eq!(log, b"--> $g\n<-- %Stop:S05\n".to_vec());
+    }
+}
@@ -0,0 +1,263 @@
+#![deny(missing_docs)]
+
+use low::{RspConnection, RspError, RspResult, Strictness};
+use startup::NegotiatedCapabilities;
+use stop::{parse_stop_reply_with_strictness, StopReply};
+
+/// A stub that `identify_stub` can recognize.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StubKind {
+    /// gdbserver, from GDB.
+    Gdbserver,
+    /// OpenOCD.
+    OpenOcd,
+    /// SEGGER's J-Link GDB Server.
+    JLink,
+    /// QEMU's built-in stub.
+    Qemu,
+    /// lldb-server, from LLDB.
+    LldbServer,
+    /// Apple's debugserver.
+    Debugserver,
+}
+
+// Text that identifies each stub in its version banner, as printed
+// by a monitor command or on its standard error.
+const STUB_BANNERS: &[(&[u8], StubKind)] = &[
+    (b"GNU gdbserver", StubKind::Gdbserver),
+    (b"Open On-Chip Debugger", StubKind::OpenOcd),
+    (b"J-Link", StubKind::JLink),
+    (b"QEMU", StubKind::Qemu),
+    (b"lldb-server", StubKind::LldbServer),
+    (b"debugserver", StubKind::Debugserver),
+];
+
+// Return true if |needle| appears in |haystack|.  An empty |needle|
+// matches nothing.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    !needle.is_empty() && haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Try to work out which stub is on the other end of a connection.
+/// `banner` is any identifying text that is available, such as the
+/// output of `monitor_command(conn, b"version")`, and may be empty.
+/// If the banner does not name a known stub, this falls back to
+/// features that only one stub is known to report in `qSupported`.
+/// This is a heuristic; `None` means the stub was not recognized.
+pub fn identify_stub(caps: &NegotiatedCapabilities, banner: &[u8]) -> Option<StubKind> {
+    if let Some(&(_, kind)) = STUB_BANNERS.iter().find(|&&(text, _)| contains(banner, text)) {
+        return Some(kind);
+    }
+    if let Some(compressions) = caps.values.get(&b"SupportedCompressions"[..]) {
+        // lzfse is only available on Apple platforms.
+        return Some(if contains(compressions, b"lzfse") {
+            StubKind::Debugserver
+        } else {
+            StubKind::LldbServer
+        });
+    }
+    if caps.supports(b"QThreadEvents") {
+        return Some(StubKind::Gdbserver);
+    }
+    None
+}
+
+/// Workarounds for a stub's deviations from the protocol.  The default
+/// is to work around nothing, and to parse replies leniently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// If set, the maximum packet size to use in place of the
+    /// stub's `PacketSize`, for stubs that advertise more than they
+    /// can actually accept.
+    pub packet_size: Option<usize>,
+    /// True if the stub does not run-length encode its replies, and
+    /// may send `*` as an ordinary character; see
+    /// `RspConnection::set_rle_decoding`.
+    pub literal_asterisks: bool,
+    /// How strictly to parse the stub's stop replies, which may for
+    /// example have non-standard thread-ids.
+    pub strictness: Strictness,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks { packet_size: None, literal_asterisks: false, strictness: Strictness::Lenient }
+    }
+}
+
+impl Quirks {
+    /// Return the built-in profile for `kind`.  gdbserver is the
+    /// reference implementation, so its replies are parsed strictly;
+    /// QEMU's stub rejects a packet that fills its advertised
+    /// `PacketSize`, since its buffer also holds a terminator; and
+    /// the other stubs get the defaults.
+    pub fn for_stub(kind: StubKind) -> Quirks {
+        match kind {
+            StubKind::Gdbserver => Quirks { strictness: Strictness::Strict, ..Quirks::default() },
+            StubKind::Qemu => Quirks { packet_size: Some(0xfff), ..Quirks::default() },
+            StubKind::OpenOcd | StubKind::JLink | StubKind::LldbServer
+                | StubKind::Debugserver => Quirks::default(),
+        }
+    }
+
+    /// Apply these workarounds to `conn` and to `caps`, which should
+    /// be what `startup` negotiated on it.  The strictness is a
+    /// property of parsing rather than of the connection, so it is
+    /// not applied here; use `parse_stop_reply`.
+    pub fn apply(&self, conn: &mut RspConnection, caps: &mut NegotiatedCapabilities) {
+        if let Some(size) = self.packet_size {
+            caps.packet_size = Some(size);
+        }
+        conn.set_rle_decoding(!self.literal_asterisks);
+    }
+
+    /// Parse a stop reply from the stub, with this profile's
+    /// strictness.
+    pub fn parse_stop_reply(&self, packet: &[u8]) -> RspResult<StopReply> {
+        parse_stop_reply_with_strictness(packet, self.strictness)
+    }
+}
+
+/// A set of quirk profiles, looked up by the stub they apply to.  A
+/// profile can be given to a known stub with `set`, and custom
+/// profiles can be added with `register`, for stubs that this crate
+/// does not know about.
+#[derive(Clone, Debug)]
+pub struct QuirksDatabase {
+    profiles: Vec<(StubKind, Quirks)>,
+    // Custom profiles, as (banner text, quirks), most recent first.
+    custom: Vec<(Vec<u8>, Quirks)>,
+}
+
+impl QuirksDatabase {
+    /// Make a database holding the built-in profile, from
+    /// `Quirks::for_stub`, for each stub that `identify_stub` can
+    /// recognize.
+    pub fn new() -> QuirksDatabase {
+        let profiles = STUB_BANNERS.iter().map(|&(_, kind)| (kind, Quirks::for_stub(kind)));
+        QuirksDatabase { profiles: profiles.collect(), custom: Vec::new() }
+    }
+
+    /// Make an empty database, in which every stub gets the default
+    /// `Quirks` until profiles are added.
+    pub fn empty() -> QuirksDatabase {
+        QuirksDatabase { profiles: Vec::new(), custom: Vec::new() }
+    }
+
+    /// Set the profile for `kind`, replacing any it had.
+    pub fn set(&mut self, kind: StubKind, quirks: Quirks) {
+        self.profiles.retain(|&(k, _)| k != kind);
+        self.profiles.push((kind, quirks));
+    }
+
+    /// Add a custom profile, which is used for any stub whose banner
+    /// contains `banner_text`.  Custom profiles are checked before the
+    /// ones given with `set`, most recently registered first.  An
+    /// empty `banner_text` would match every stub, and is refused
+    /// with `RspError::InvalidArgument`.
+    pub fn register(&mut self, banner_text: &[u8], quirks: Quirks) -> RspResult<()> {
+        if banner_text.is_empty() {
+            return Err(RspError::InvalidArgument("empty banner text"));
+        }
+        self.custom.insert(0, (banner_text.to_vec(), quirks));
+        Ok(())
+    }
+
+    /// Return the workarounds for the stub described by `caps` and
+    /// `banner`, as for `identify_stub`.  An unrecognized stub gets
+    /// the default `Quirks`.
+    pub fn lookup(&self, caps: &NegotiatedCapabilities, banner: &[u8]) -> Quirks {
+        if let Some((_, quirks)) = self.custom.iter().find(|&(text, _)| contains(banner, text)) {
+            return quirks.clone();
+        }
+        identify_stub(caps, banner)
+            .and_then(|kind| self.profiles.iter().find(|&&(k, _)| k == kind))
+            .map(|(_, quirks)| quirks.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for QuirksDatabase {
+    fn default() -> QuirksDatabase {
+        QuirksDatabase::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{identify_stub, Quirks, QuirksDatabase, StubKind};
+    use low::{RspConnection, Strictness};
+    use startup::NegotiatedCapabilities;
+    use util::frame_packet;
+
+    #[test]
+    fn identify() {
+        let mut caps = NegotiatedCapabilities::default();
+        assert_eq!(identify_stub(&caps, b"Open On-Chip Debugger 0.12.0\n"),
+                   Some(StubKind::OpenOcd));
+        assert_eq!(identify_stub(&caps, b""), None);
+        caps.supported.insert(b"QThreadEvents".to_vec());
+        assert_eq!(identify_stub(&caps, b""), Some(StubKind::Gdbserver));
+        assert_eq!(identify_stub(&caps, b"QEMU emulator version 8.2.0"), Some(StubKind::Qemu));
+        caps.values.insert(b"SupportedCompressions".to_vec(), b"lzfse,zlib-deflate".to_vec());
+        assert_eq!(identify_stub(&caps, b""), Some(StubKind::Debugserver));
+    }
+
+    #[test]
+    fn database() {
+        let caps = NegotiatedCapabilities { packet_size: Some(0x4000), ..Default::default() };
+        let broken = Quirks {
+            packet_size: Some(0x400),
+            literal_asterisks: true,
+            strictness: Strictness::Lenient,
+        };
+        let mut database = QuirksDatabase::empty();
+        assert_eq!(database.lookup(&caps, b"J-Link GDB Server V7.94"), Quirks::default());
+        database.set(StubKind::JLink, broken.clone());
+        assert_eq!(database.lookup(&caps, b"J-Link GDB Server V7.94"), broken);
+        database.register(b"ACME probe", Quirks::default()).unwrap();
+        assert_eq!(database.lookup(&caps, b"ACME probe with J-Link inside"), Quirks::default());
+        assert!(database.register(b"", broken.clone()).is_err());
+        assert_eq!(database.lookup(&caps, b""), Quirks::default());
+
+        let input = frame_packet(b"a*b");
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        let mut applied = caps.clone();
+        broken.apply(&mut rsp, &mut applied);
+        assert_eq!(applied.packet_size, Some(0x400));
+        assert_eq!(rsp.read_reply().unwrap(), b"a*b".to_vec());
+
+        let strict = Quirks { strictness: Strictness::Strict, ..Quirks::default() };
+        assert!(Quirks::default().parse_stop_reply(b"T0Athread:1;").is_ok());
+        assert!(strict.parse_stop_reply(b"T0Athread:1;").is_err());
+    }
+
+    #[test]
+    fn built_in() {
+        let database = QuirksDatabase::new();
+        let mut caps = NegotiatedCapabilities { packet_size: Some(0x1000), ..Default::default() };
+
+        // An unrecognized stub gets the defaults.
+        let quirks = database.lookup(&caps, b"");
+        assert!(quirks.parse_stop_reply(b"T0Athread:1;").is_ok());
+
+        // gdbserver is recognized by its features, and parsed strictly.
+        caps.supported.insert(b"QThreadEvents".to_vec());
+        let quirks = database.lookup(&caps, b"");
+        assert_eq!(quirks, Quirks::for_stub(StubKind::Gdbserver));
+        assert!(quirks.parse_stop_reply(b"T0Athread:1;").is_err());
+        assert!(quirks.parse_stop_reply(b"T0athread:1;").is_ok());
+
+        // QEMU's packet size is reduced.
+        let quirks = database.lookup(&caps, b"QEMU emulator version 8.2.0");
+        let input: Vec<u8> = Vec::new();
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        quirks.apply(&mut rsp, &mut caps);
+        assert_eq!(caps.packet_size, Some(0xfff));
+    }
+}
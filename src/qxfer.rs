@@ -0,0 +1,579 @@
+#![deny(missing_docs)]
+
+use std::io::Write;
+
+use low::{RemoteError, RspConnection, RspError, RspResult};
+use util::{check_range, parse_hex_number, unescape_binary, write_hex_number};
+
+/// A parsed `qXfer:OBJECT:read:ANNEX:OFFSET,LENGTH` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QXferRead {
+    /// The object being read, for example `features`.
+    pub object: Vec<u8>,
+    /// The annex, for example `target.xml`.  This may be empty.
+    pub annex: Vec<u8>,
+    /// The offset at which to start reading.
+    pub offset: u64,
+    /// The maximum number of bytes to return.
+    pub length: u64,
+}
+
+fn parse_qxfer_read_inner(packet: &[u8]) -> Result<QXferRead, &'static str> {
+    if !packet.starts_with(b"qXfer:") {
+        return Err("not a qXfer packet");
+    }
+    let rest = &packet[6..];
+    let colon = rest.iter().position(|&c| c == b':').ok_or("missing qXfer object")?;
+    let object = &rest[..colon];
+    let rest = &rest[colon + 1..];
+    if !rest.starts_with(b"read:") {
+        return Err("not a qXfer read");
+    }
+    let rest = &rest[5..];
+
+    // The annex is everything up to the final colon.
+    let colon = rest.iter().rposition(|&c| c == b':').ok_or("missing qXfer offset")?;
+    let annex = &rest[..colon];
+    let range = &rest[colon + 1..];
+    let comma = range.iter().position(|&c| c == b',').ok_or("missing qXfer length")?;
+    let offset = parse_hex_number(&range[..comma]).ok_or("invalid qXfer offset")?;
+    let length = parse_hex_number(&range[comma + 1..]).ok_or("invalid qXfer length")?;
+
+    Ok(QXferRead { object: object.to_vec(), annex: annex.to_vec(), offset, length })
+}
+
+/// Parse a `qXfer` read request, for use by a server.  Returns
+/// `ProtocolViolation` if the packet is malformed.
+pub fn parse_qxfer_read(packet: &[u8]) -> RspResult<QXferRead> {
+    parse_qxfer_read_inner(packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+/// A parsed `qXfer:OBJECT:write:ANNEX:OFFSET:DATA` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QXferWrite {
+    /// The object being written, for example `siginfo`.
+    pub object: Vec<u8>,
+    /// The annex.  This may be empty.
+    pub annex: Vec<u8>,
+    /// The offset at which to start writing.
+    pub offset: u64,
+    /// The data to write, with binary escapes removed.
+    pub data: Vec<u8>,
+}
+
+fn parse_qxfer_write_inner(packet: &[u8]) -> Result<QXferWrite, &'static str> {
+    if !packet.starts_with(b"qXfer:") {
+        return Err("not a qXfer packet");
+    }
+    let rest = &packet[6..];
+    let colon = rest.iter().position(|&c| c == b':').ok_or("missing qXfer object")?;
+    let object = &rest[..colon];
+    let rest = &rest[colon + 1..];
+    if !rest.starts_with(b"write:") {
+        return Err("not a qXfer write");
+    }
+    let rest = &rest[6..];
+
+    // The data is binary and may contain colons, so the annex and
+    // offset are found by searching forward.  An annex with a colon
+    // cannot be written.
+    let colon = rest.iter().position(|&c| c == b':').ok_or("missing qXfer offset")?;
+    let annex = &rest[..colon];
+    let rest = &rest[colon + 1..];
+    let colon = rest.iter().position(|&c| c == b':').ok_or("missing qXfer data")?;
+    let offset = parse_hex_number(&rest[..colon]).ok_or("invalid qXfer offset")?;
+    let data = unescape_binary(&rest[colon + 1..]).ok_or("invalid escape")?;
+
+    Ok(QXferWrite { object: object.to_vec(), annex: annex.to_vec(), offset, data })
+}
+
+/// Parse a `qXfer` write request, for use by a server.  The reply
+/// is the number of bytes written, in hex.  Returns
+/// `ProtocolViolation` if the packet is malformed.
+pub fn parse_qxfer_write(packet: &[u8]) -> RspResult<QXferWrite> {
+    parse_qxfer_write_inner(packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+// The number of bytes needed to send |byte| in binary form.
+fn escaped_size(byte: u8) -> u64 {
+    match byte {
+        b'$' | b'#' | b'}' | b'*' => 2,
+        _ => 1,
+    }
+}
+
+/// Send the reply to a `qXfer` read request, for use by a server.
+/// `data` is the entire object being transferred; this sends the
+/// portion requested by `request`.  The reply is `m` followed by
+/// data when more remains, or `l` followed by data for the final
+/// piece.  The data is escaped so that, escapes included, it is no
+/// longer than the requested length; the client will ask for any
+/// remainder in a subsequent request.
+pub fn send_qxfer_read_reply(conn: &mut RspConnection, data: &[u8], request: &QXferRead)
+                             -> RspResult<()> {
+    let start = if request.offset >= data.len() as u64 {
+        data.len()
+    } else {
+        request.offset as usize
+    };
+
+    let mut end = start;
+    let mut size = 0;
+    while end < data.len() {
+        let this_size = escaped_size(data[end]);
+        if size + this_size > request.length {
+            break;
+        }
+        size += this_size;
+        end += 1;
+    }
+
+    conn.start_packet()?;
+    conn.write_all(if end < data.len() { b"m" } else { b"l" })?;
+    conn.write_binary(&data[start..end])?;
+    conn.finish_packet()
+}
+
+/// The ways a `qXfer` read can fail, other than on the connection
+/// itself.  These are returned as `RspError::QXfer`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QXferError {
+    /// The stub does not support the object: it sent an empty reply.
+    Unsupported,
+    /// The stub rejected the request for the start of the object,
+    /// usually because the annex does not exist, or the object is not
+    /// available in the current state, as for `exec-file` with no
+    /// process.
+    AnnexNotFound,
+    /// The stub sent an error reply part way through the transfer.
+    /// The data read so far is discarded.
+    TransferError(RemoteError),
+}
+
+/// Read an entire object using `qXfer` read requests, for use by a
+/// client.  `packet_size` is the maximum packet size reported by the
+/// stub's `qSupported` reply.  Failures reported by the stub are
+/// returned as `RspError::QXfer`.  A `packet_size` too small for any
+/// data results in `RspError::InvalidArgument`.
+pub fn read_qxfer(conn: &mut RspConnection, object: &[u8], annex: &[u8], packet_size: usize)
+                  -> RspResult<Vec<u8>> {
+    if packet_size < 2 {
+        return Err(RspError::InvalidArgument("packet size too small"));
+    }
+    let mut result = Vec::new();
+    loop {
+        let mut packet = b"qXfer:".to_vec();
+        packet.extend_from_slice(object);
+        packet.extend_from_slice(b":read:");
+        packet.extend_from_slice(annex);
+        packet.push(b':');
+        write_hex_number(&mut packet, result.len() as u64);
+        packet.push(b',');
+        write_hex_number(&mut packet, (packet_size - 1) as u64);
+        let reply = conn.request(&packet)?;
+        let more = match reply.first() {
+            Some(&b'm') => true,
+            Some(&b'l') => false,
+            None if result.is_empty() => return Err(RspError::QXfer(QXferError::Unsupported)),
+            _ => {
+                let error = match RemoteError::parse(&reply) {
+                    Some(_) if result.is_empty() => QXferError::AnnexNotFound,
+                    Some(error) => QXferError::TransferError(error),
+                    None => {
+                        return Err(RspError::ProtocolViolation {
+                            packet: reply,
+                            reason: "invalid qXfer reply",
+                        });
+                    }
+                };
+                return Err(RspError::QXfer(error));
+            }
+        };
+        let data = unescape_binary(&reply[1..]).ok_or_else(|| {
+            RspError::ProtocolViolation { packet: reply.clone(), reason: "invalid escape" }
+        })?;
+        if more && data.is_empty() {
+            return Err(RspError::ProtocolViolation {
+                packet: reply,
+                reason: "empty qXfer reply",
+            });
+        }
+        result.extend_from_slice(&data);
+        if !more {
+            return Ok(result);
+        }
+    }
+}
+
+/// Read an entire object as `read_qxfer` does, but return `None` if
+/// the object is unsupported or unavailable, that is, for
+/// `QXferError::Unsupported` or `QXferError::AnnexNotFound`.  This
+/// suits optional objects such as the library list.
+pub fn read_qxfer_object(conn: &mut RspConnection, object: &[u8], annex: &[u8],
+                         packet_size: usize) -> RspResult<Option<Vec<u8>>> {
+    match read_qxfer(conn, object, annex, packet_size) {
+        Ok(data) => Ok(Some(data)),
+        Err(RspError::QXfer(QXferError::Unsupported))
+            | Err(RspError::QXfer(QXferError::AnnexNotFound)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Write `data` to an object at `offset` using `qXfer` write
+/// requests, for use by a client.  The data is split to fit
+/// `packet_size`, the maximum packet size reported by the stub's
+/// `qSupported` reply, and requests are repeated until the stub has
+/// accepted all of it, or accepts nothing more.  Returns the number
+/// of bytes written, which is less than the length of `data` if the
+/// stub stopped early.  Failures reported by the stub are returned as
+/// `RspError::QXfer`, as for `read_qxfer`.  A range that runs past the
+/// largest 64-bit offset results in `RspError::InvalidArgument`.
+pub fn qxfer_write(conn: &mut RspConnection, object: &[u8], annex: &[u8], offset: u64,
+                   data: &[u8], packet_size: usize) -> RspResult<usize> {
+    check_range(offset, data.len() as u64)?;
+    let mut written = 0;
+    while written < data.len() {
+        let mut header = b"qXfer:".to_vec();
+        header.extend_from_slice(object);
+        header.extend_from_slice(b":write:");
+        header.extend_from_slice(annex);
+        header.push(b':');
+        write_hex_number(&mut header, offset + written as u64);
+        header.push(b':');
+
+        // Leave room for at least one escaped byte.
+        if header.len() + 2 > packet_size {
+            return Err(RspError::PacketTooLarge);
+        }
+        let mut end = written;
+        let mut size = header.len() as u64;
+        while end < data.len() && size + escaped_size(data[end]) <= packet_size as u64 {
+            size += escaped_size(data[end]);
+            end += 1;
+        }
+
+        conn.start_packet()?;
+        conn.write_all(&header)?;
+        conn.write_binary(&data[written..end])?;
+        conn.finish_packet()?;
+        let reply = conn.read_reply()?;
+        if reply.is_empty() && written == 0 {
+            return Err(RspError::QXfer(QXferError::Unsupported));
+        }
+        if let Some(error) = RemoteError::parse(&reply) {
+            return Err(RspError::QXfer(if written == 0 {
+                QXferError::AnnexNotFound
+            } else {
+                QXferError::TransferError(error)
+            }));
+        }
+        match parse_hex_number(&reply) {
+            Some(0) => break,
+            Some(count) if count <= (end - written) as u64 => {
+                written += count as usize;
+            }
+            _ => {
+                return Err(RspError::ProtocolViolation {
+                    packet: reply,
+                    reason: "invalid qXfer write reply",
+                });
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// What a `qXfer` object holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QXferContent {
+    /// An XML document.
+    Xml,
+    /// Plain text, such as a file name.
+    Text,
+    /// Raw binary data, in target byte order.
+    Binary,
+}
+
+/// Whether a `qXfer` object takes an annex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnexRule {
+    /// The annex must be empty.
+    Empty,
+    /// The annex may be empty or not.
+    Optional,
+    /// The annex must not be empty.
+    Required,
+}
+
+/// A `qXfer` object documented in the GDB manual; see
+/// `QXFER_OBJECTS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QXferObject {
+    /// The name of the object, for example `auxv`.
+    pub name: &'static [u8],
+    /// What the object holds.
+    pub content: QXferContent,
+    /// Whether the object takes an annex.
+    pub annex: AnnexRule,
+    /// Whether the object can be written as well as read.
+    pub writable: bool,
+}
+
+// Shorthand for the entries in QXFER_OBJECTS.
+const fn object(name: &'static [u8], content: QXferContent, annex: AnnexRule, writable: bool)
+                -> QXferObject {
+    QXferObject { name, content, annex, writable }
+}
+
+/// The `qXfer` objects this crate knows about.  Objects that are not
+/// listed can still be read and written with `read_qxfer` and
+/// `qxfer_write`.
+pub const QXFER_OBJECTS: &[QXferObject] = &[
+    object(b"auxv", QXferContent::Binary, AnnexRule::Empty, false),
+    object(b"btrace", QXferContent::Xml, AnnexRule::Required, false),
+    object(b"btrace-conf", QXferContent::Xml, AnnexRule::Empty, false),
+    object(b"exec-file", QXferContent::Text, AnnexRule::Optional, false),
+    object(b"fdpic", QXferContent::Binary, AnnexRule::Required, false),
+    object(b"features", QXferContent::Xml, AnnexRule::Required, false),
+    object(b"libraries", QXferContent::Xml, AnnexRule::Empty, false),
+    object(b"libraries-svr4", QXferContent::Xml, AnnexRule::Optional, false),
+    object(b"memory-map", QXferContent::Xml, AnnexRule::Empty, false),
+    object(b"osdata", QXferContent::Xml, AnnexRule::Optional, false),
+    object(b"sdata", QXferContent::Binary, AnnexRule::Empty, false),
+    object(b"siginfo", QXferContent::Binary, AnnexRule::Empty, true),
+    object(b"threads", QXferContent::Xml, AnnexRule::Empty, false),
+    object(b"traceframe-info", QXferContent::Xml, AnnexRule::Empty, false),
+    object(b"uib", QXferContent::Binary, AnnexRule::Required, false),
+];
+
+/// Return the entry in `QXFER_OBJECTS` for the object `name`, if
+/// there is one.
+pub fn qxfer_object(name: &[u8]) -> Option<&'static QXferObject> {
+    QXFER_OBJECTS.iter().find(|object| object.name == name)
+}
+
+/// The contents of a `qXfer` object, as returned by
+/// `read_known_qxfer_object`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QXferData {
+    /// An XML document.
+    Xml(String),
+    /// Text.
+    Text(String),
+    /// Binary data.
+    Binary(Vec<u8>),
+}
+
+/// Read the object `name`, which must be listed in `QXFER_OBJECTS`,
+/// for use by a client.  The annex is checked against the object's
+/// rule and the contents are returned according to its kind.  An
+/// unlisted object or an unsuitable annex results in
+/// `RspError::InvalidArgument`, and XML or text that is not UTF-8 in
+/// a `ProtocolViolation`.  Otherwise this is as `read_qxfer`.
+pub fn read_known_qxfer_object(conn: &mut RspConnection, name: &[u8], annex: &[u8],
+                               packet_size: usize) -> RspResult<QXferData> {
+    let object = qxfer_object(name).ok_or(RspError::InvalidArgument("unknown qXfer object"))?;
+    match (object.annex, annex.is_empty()) {
+        (AnnexRule::Empty, false) => {
+            return Err(RspError::InvalidArgument("qXfer object takes no annex"));
+        }
+        (AnnexRule::Required, true) => {
+            return Err(RspError::InvalidArgument("qXfer object needs an annex"));
+        }
+        _ => { }
+    }
+    let data = read_qxfer(conn, name, annex, packet_size)?;
+    if object.content == QXferContent::Binary {
+        return Ok(QXferData::Binary(data));
+    }
+    let text = String::from_utf8(data).map_err(|err| {
+        RspError::ProtocolViolation { packet: err.into_bytes(), reason: "qXfer text is not UTF-8" }
+    })?;
+    Ok(match object.content {
+        QXferContent::Xml => QXferData::Xml(text),
+        _ => QXferData::Text(text),
+    })
+}
+
+/// Read the unwind info block for the code at `address` with
+/// `qXfer:uib:read`, for use by a client of an ia64 stub.
+pub fn read_uib(conn: &mut RspConnection, address: u64, packet_size: usize)
+                -> RspResult<Vec<u8>> {
+    let mut annex = Vec::new();
+    write_hex_number(&mut annex, address);
+    read_qxfer(conn, b"uib", &annex, packet_size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_qxfer_read, parse_qxfer_write, qxfer_object, qxfer_write,
+                read_known_qxfer_object, read_qxfer, read_qxfer_object, read_uib,
+                send_qxfer_read_reply, AnnexRule, QXferData, QXferError, QXferRead, QXferWrite};
+    use low::{RemoteError, RspConnection, RspError};
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_qxfer_read(b"qXfer:features:read:target.xml:0,fff").unwrap(),
+                   QXferRead {
+                       object: b"features".to_vec(),
+                       annex: b"target.xml".to_vec(),
+                       offset: 0,
+                       length: 0xfff,
+                   });
+        assert_eq!(parse_qxfer_read(b"qXfer:auxv:read::10,20").unwrap().annex, b"".to_vec());
+        assert_eq!(parse_qxfer_read(b"qXfer:auxv:read::1A,FF").unwrap().offset, 0x1a);
+        assert!(parse_qxfer_read(b"qXfer:features:write:target.xml:0:").is_err());
+        assert!(parse_qxfer_read(b"qXfer:features:read:target.xml:0").is_err());
+    }
+
+    fn reply(data: &[u8], offset: u64, length: u64) -> Vec<u8> {
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, false);
+            rsp.disable_acking();
+            let request = QXferRead {
+                object: b"features".to_vec(),
+                annex: b"target.xml".to_vec(),
+                offset,
+                length,
+            };
+            send_qxfer_read_reply(&mut rsp, data, &request).unwrap();
+        }
+        output
+    }
+
+    #[test]
+    fn chunks() {
+        assert_eq!(reply(b"abcdef", 0, 4), frame_packet(b"mabcd"));
+        assert_eq!(reply(b"abcdef", 4, 4), frame_packet(b"lef"));
+        assert_eq!(reply(b"abcdef", 6, 4), frame_packet(b"l"));
+        assert_eq!(reply(b"abcdef", 100, 4), frame_packet(b"l"));
+        // Escaping counts against the length.
+        assert_eq!(reply(b"a#b", 0, 2), frame_packet(b"ma"));
+        assert_eq!(reply(b"a#b", 1, 3), frame_packet(b"l}\x03b"));
+    }
+
+    #[test]
+    fn read_object() {
+        let mut input = frame_packet(b"mab}]");
+        input.extend(frame_packet(b"lc"));
+        input.extend(frame_packet(b"E01"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(read_qxfer_object(&mut rsp, b"exec-file", b"", 5).unwrap(),
+                       Some(b"ab}c".to_vec()));
+            assert_eq!(read_qxfer_object(&mut rsp, b"auxv", b"", 5).unwrap(), None);
+        }
+        let mut expected = frame_packet(b"qXfer:exec-file:read::0,4");
+        expected.extend(frame_packet(b"qXfer:exec-file:read::3,4"));
+        expected.extend(frame_packet(b"qXfer:auxv:read::0,4"));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn read_errors() {
+        let replies: &[&[u8]] = &[b"", b"E00", b"mab", b"E.gone", b"mab", b"", b"mab", b"x"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        let mut read = || match read_qxfer(&mut rsp, b"auxv", b"", 5) {
+            Err(RspError::QXfer(error)) => Ok(error),
+            Err(RspError::ProtocolViolation { reason, .. }) => Err(reason),
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(read(), Ok(QXferError::Unsupported));
+        assert_eq!(read(), Ok(QXferError::AnnexNotFound));
+        assert_eq!(read(),
+                   Ok(QXferError::TransferError(RemoteError::Message("gone".to_string()))));
+        assert_eq!(read(), Err("invalid qXfer reply"));
+        assert_eq!(read(), Err("invalid qXfer reply"));
+    }
+
+    #[test]
+    fn write() {
+        assert_eq!(parse_qxfer_write(b"qXfer:siginfo:write::10:a}\x03:").unwrap(),
+                   QXferWrite {
+                       object: b"siginfo".to_vec(),
+                       annex: Vec::new(),
+                       offset: 0x10,
+                       data: b"a#:".to_vec(),
+                   });
+        assert!(parse_qxfer_write(b"qXfer:siginfo:read::0,10").is_err());
+        assert!(parse_qxfer_write(b"qXfer:siginfo:write::10").is_err());
+
+        let input = frame_packets(&[b"2", b"1", b"E01", b"", b"1", b"E02", b"1", b"0"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            // The escaped byte does not fit in the first request.
+            assert_eq!(qxfer_write(&mut rsp, b"siginfo", b"", 0x10, b"ab#", 27).unwrap(), 3);
+            assert!(matches!(qxfer_write(&mut rsp, b"siginfo", b"", 0, b"x", 27),
+                             Err(RspError::QXfer(QXferError::AnnexNotFound))));
+            assert!(matches!(qxfer_write(&mut rsp, b"siginfo", b"", 0, b"x", 27),
+                             Err(RspError::QXfer(QXferError::Unsupported))));
+            match qxfer_write(&mut rsp, b"siginfo", b"", 0, b"xy", 27) {
+                Err(RspError::QXfer(QXferError::TransferError(error))) => {
+                    assert_eq!(error, RemoteError::Code(2));
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+            // The stub accepts one byte, then nothing more.
+            assert_eq!(qxfer_write(&mut rsp, b"siginfo", b"", 0, b"xy", 27).unwrap(), 1);
+            assert!(matches!(qxfer_write(&mut rsp, b"siginfo", b"", u64::MAX, b"xy", 27),
+                             Err(RspError::InvalidArgument(_))));
+        }
+        let mut expected = frame_packet(b"qXfer:siginfo:write::10:ab");
+        expected.extend(frame_packet(b"qXfer:siginfo:write::12:}\x03"));
+        expected.extend(frame_packet(b"qXfer:siginfo:write::0:x"));
+        expected.extend(frame_packet(b"qXfer:siginfo:write::0:x"));
+        for _ in 0..2 {
+            expected.extend(frame_packet(b"qXfer:siginfo:write::0:xy"));
+            expected.extend(frame_packet(b"qXfer:siginfo:write::1:y"));
+        }
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn known_objects() {
+        assert_eq!(qxfer_object(b"features").unwrap().annex, AnnexRule::Required);
+        assert!(qxfer_object(b"siginfo").unwrap().writable);
+        assert_eq!(qxfer_object(b"frobnicate"), None);
+
+        let mut input = frame_packet(b"l<target/>");
+        input.extend(frame_packet(b"l\x01\x02"));
+        input.extend(frame_packet(b"l/bin/\xff"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            assert_eq!(read_known_qxfer_object(&mut rsp, b"features", b"target.xml", 100).unwrap(),
+                       QXferData::Xml("<target/>".to_string()));
+            assert_eq!(read_uib(&mut rsp, 0x4000, 100).unwrap(), vec![1, 2]);
+            assert!(matches!(read_known_qxfer_object(&mut rsp, b"exec-file", b"", 100),
+                             Err(RspError::ProtocolViolation { .. })));
+            for &(object, annex) in &[(&b"auxv"[..], &b"x"[..]), (b"features", b""),
+                                      (b"frobnicate", b"")] {
+                assert!(matches!(read_known_qxfer_object(&mut rsp, object, annex, 100),
+                                 Err(RspError::InvalidArgument(_))));
+            }
+        }
+        let mut expected = frame_packet(b"qXfer:features:read:target.xml:0,63");
+        expected.extend(frame_packet(b"qXfer:uib:read:4000:0,63"));
+        expected.extend(frame_packet(b"qXfer:exec-file:read::0,63"));
+        assert_eq!(output, expected);
+    }
+}
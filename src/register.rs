@@ -0,0 +1,423 @@
+#![deny(missing_docs)]
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::Write;
+
+use low::{check_reply, RspConnection, RspError, RspResult};
+use stop::{StopReply, StopReplyValue};
+use tdesc::{FlagsType, Register, TargetDescription};
+use util::{decode_hex_bytes, write_hex_number};
+
+/// The byte order of the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+/// The contents of a register, as raw bytes in target byte order.
+/// Interpreting the bytes as a number requires the target's byte
+/// order, which the protocol itself does not convey.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterValue {
+    bytes: Vec<u8>,
+}
+
+impl RegisterValue {
+    /// Make a new `RegisterValue` holding `bytes`.
+    pub fn new(bytes: Vec<u8>) -> RegisterValue {
+        RegisterValue { bytes }
+    }
+
+    /// Make a zero value of the right size for `register`.
+    pub fn zeroed(register: &Register) -> RegisterValue {
+        RegisterValue { bytes: vec![0; register.bitsize().div_ceil(8) as usize] }
+    }
+
+    /// Make a `size`-byte value holding `value`.  If `size` is less
+    /// than 8, the high bits of `value` are dropped.
+    pub fn from_u64(value: u64, size: usize, endian: Endian) -> RegisterValue {
+        let mut result = RegisterValue { bytes: vec![0; size] };
+        result.set_bits(0, 8 * ::std::cmp::min(size, 8) as u32, value, endian);
+        result
+    }
+
+    /// Return the raw bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Return the width of the value in bits.
+    pub fn width(&self) -> u32 {
+        8 * self.bytes.len() as u32
+    }
+
+    // Return the index of the byte holding bit |bit|, counting from
+    // the least significant bit.
+    fn byte_index(&self, bit: u32, endian: Endian) -> usize {
+        let index = (bit / 8) as usize;
+        match endian {
+            Endian::Little => index,
+            Endian::Big => self.bytes.len() - 1 - index,
+        }
+    }
+
+    /// Return the value as a number.  Returns `None` if the value is
+    /// wider than 64 bits.
+    pub fn to_u64(&self, endian: Endian) -> Option<u64> {
+        if self.bytes.len() > 8 {
+            return None;
+        }
+        self.bits(0, self.width(), endian)
+    }
+
+    /// Return the `count` bits starting at bit `lsb`, counting from
+    /// the least significant bit.  This is useful for flags
+    /// registers.  Returns `None` if the field does not fit in the
+    /// value, or is wider than 64 bits.
+    pub fn bits(&self, lsb: u32, count: u32, endian: Endian) -> Option<u64> {
+        if count > 64 || lsb.checked_add(count)? > self.width() {
+            return None;
+        }
+        let mut result = 0;
+        for i in (0..count).rev() {
+            let bit = lsb + i;
+            let byte = self.bytes[self.byte_index(bit, endian)];
+            result = (result << 1) | u64::from((byte >> (bit % 8)) & 1);
+        }
+        Some(result)
+    }
+
+    /// Set the `count` bits starting at bit `lsb` to the low bits of
+    /// `value`.  Panics if the field does not fit in the value.
+    pub fn set_bits(&mut self, lsb: u32, count: u32, value: u64, endian: Endian) {
+        assert!(count <= 64 && lsb + count <= self.width());
+        for i in 0..count {
+            let bit = lsb + i;
+            let index = self.byte_index(bit, endian);
+            let mask = 1 << (bit % 8);
+            if (value >> i) & 1 != 0 {
+                self.bytes[index] |= mask;
+            } else {
+                self.bytes[index] &= !mask;
+            }
+        }
+    }
+
+    /// Return the field `name` of the value, where `flags` is the
+    /// register's type in the target description; see
+    /// `TargetDescription::flags_type`.  Returns `None` if there is no
+    /// such field, or it does not fit in the value.
+    pub fn field(&self, flags: &FlagsType, name: &str, endian: Endian) -> Option<u64> {
+        let field = flags.field(name)?;
+        self.bits(field.start(), field.width(), endian)
+    }
+
+    /// Set the field `name` of the value to the low bits of `value`,
+    /// where `flags` is as for `field`.  Returns
+    /// `RspError::InvalidArgument` if there is no such field, or it
+    /// does not fit in the value.
+    pub fn set_field(&mut self, flags: &FlagsType, name: &str, value: u64, endian: Endian)
+                     -> RspResult<()> {
+        let field = flags.field(name).ok_or(RspError::InvalidArgument("no such field"))?;
+        if field.width() > 64 || field.end() >= self.width() {
+            return Err(RspError::InvalidArgument("field does not fit in the register"));
+        }
+        self.set_bits(field.start(), field.width(), value, endian);
+        Ok(())
+    }
+}
+
+/// A client-side cache of the registers of a stopped thread.  The
+/// whole cache is filled with one `g` packet when a register is first
+/// read, and a register that the stub leaves out of the `g` reply is
+/// then read by itself with `p`.  Writes are sent with `P` and then
+/// kept, so that reading the register back needs no round trip.
+///
+/// The cache only holds while the thread stays stopped and selected.
+/// Call `update` with each stop reply, which also keeps the registers
+/// that the reply expedites, and `clear` whenever the inferior is
+/// resumed or another thread is selected with `Hg`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegisterCache {
+    values: BTreeMap<u32, Option<RegisterValue>>,
+    filled: bool,
+}
+
+impl RegisterCache {
+    /// Make a new, empty, `RegisterCache`.
+    pub fn new() -> RegisterCache {
+        RegisterCache::default()
+    }
+
+    /// Empty the cache, so that registers are read from the stub
+    /// again.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.filled = false;
+    }
+
+    /// Empty the cache after a stop, then fill in the registers that
+    /// `reply` expedites.
+    pub fn update(&mut self, reply: &StopReply) {
+        self.clear();
+        if let StopReply::Signal { ref values, .. } = *reply {
+            for value in values {
+                if let StopReplyValue::Register(regnum, ref bytes) = *value {
+                    if let Ok(regnum) = u32::try_from(regnum) {
+                        self.values.insert(regnum, Some(RegisterValue::new(bytes.clone())));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return register `regnum` from the cache, reading it with `conn`
+    /// if it is not there.  `tdesc` describes the layout of the `g`
+    /// reply; see `read_registers`.  Returns `None` if the stub
+    /// reports the register as unavailable.
+    pub fn read(&mut self, conn: &mut RspConnection, tdesc: &TargetDescription, regnum: u32)
+                -> RspResult<Option<&RegisterValue>> {
+        if !self.filled && !self.values.contains_key(&regnum) {
+            self.values.extend(read_registers(conn, tdesc)?);
+            self.filled = true;
+        }
+        let value = match self.values.entry(regnum) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(read_register(conn, u64::from(regnum))?),
+        };
+        Ok(value.as_ref())
+    }
+
+    /// Write `value` to register `regnum` with `write_register`, and
+    /// keep it in the cache.  If the write fails, the register is
+    /// dropped from the cache, since the stub may have changed it.
+    pub fn write(&mut self, conn: &mut RspConnection, regnum: u32, value: RegisterValue)
+                 -> RspResult<()> {
+        self.values.remove(&regnum);
+        write_register(conn, u64::from(regnum), &value)?;
+        self.values.insert(regnum, Some(value));
+        Ok(())
+    }
+}
+
+/// Read register `regnum` with the `p` packet, for use by a client.
+/// Returns `None` if the stub reports the register as unavailable.
+/// An error reply results in `RspError::Remote`, and an empty reply
+/// in a `ProtocolViolation`.
+pub fn read_register(conn: &mut RspConnection, regnum: u64)
+                     -> RspResult<Option<RegisterValue>> {
+    let mut packet = b"p".to_vec();
+    write_hex_number(&mut packet, regnum);
+    let reply = check_reply(conn.request(&packet)?)?;
+    if !reply.is_empty() && reply.iter().all(|&c| c == b'x') {
+        return Ok(None);
+    }
+    match decode_hex_bytes(&reply) {
+        Some(bytes) if !bytes.is_empty() => Ok(Some(RegisterValue::new(bytes))),
+        _ => Err(RspError::ProtocolViolation { packet: reply, reason: "register read failed" }),
+    }
+}
+
+/// Read all the registers with the `g` packet, for use by a client.
+/// The reply is split up using `tdesc`, in register number order, and
+/// each register is returned with its number.  A register is `None`
+/// if the stub reports it as unavailable.  Stubs may leave registers
+/// off the end of the reply, so the result can be shorter than
+/// `tdesc`.  Nothing here depends on the byte order; use
+/// `RegisterValue::to_u64` with the target's byte order to interpret
+/// the values.
+pub fn read_registers(conn: &mut RspConnection, tdesc: &TargetDescription)
+                      -> RspResult<Vec<(u32, Option<RegisterValue>)>> {
+    let reply = check_reply(conn.request(b"g")?)?;
+    let mut registers = tdesc.registers();
+    registers.sort_by_key(|&(regnum, _)| regnum);
+    let mut result = Vec::new();
+    let mut rest = &reply[..];
+    for (regnum, register) in registers {
+        if rest.is_empty() {
+            break;
+        }
+        let size = 2 * register.bitsize().div_ceil(8) as usize;
+        if rest.len() < size {
+            return Err(RspError::ProtocolViolation {
+                packet: reply.clone(),
+                reason: "register block ends part way through a register",
+            });
+        }
+        let (hex, tail) = rest.split_at(size);
+        rest = tail;
+        if hex.iter().all(|&c| c == b'x') {
+            result.push((regnum, None));
+            continue;
+        }
+        match decode_hex_bytes(hex) {
+            Some(bytes) => result.push((regnum, Some(RegisterValue::new(bytes)))),
+            None => {
+                return Err(RspError::ProtocolViolation {
+                    packet: reply.clone(),
+                    reason: "invalid register block",
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Write `value` to register `regnum` with the `P` packet, for use by
+/// a client.  An error reply results in `RspError::Remote`, and any
+/// other reply but `OK` in a `ProtocolViolation`.
+pub fn write_register(conn: &mut RspConnection, regnum: u64, value: &RegisterValue)
+                      -> RspResult<()> {
+    conn.start_packet()?;
+    conn.write_all(b"P")?;
+    conn.write_hex_number(regnum)?;
+    conn.write_all(b"=")?;
+    conn.write_hex(value.bytes())?;
+    conn.finish_packet()?;
+    let reply = check_reply(conn.read_reply()?)?;
+    if reply != b"OK" {
+        return Err(RspError::ProtocolViolation { packet: reply, reason: "register write failed" });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_register, read_registers, write_register, Endian, RegisterCache,
+                RegisterValue};
+    use low::RspConnection;
+    use presets::{tdesc_powerpc32, tdesc_x86_64};
+    use stop::parse_stop_reply;
+    use tdesc::{Feature, Register, TargetDescription};
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn register_value() {
+        let value = RegisterValue::new(vec![0x34, 0x12, 0, 0]);
+        assert_eq!(value.width(), 32);
+        assert_eq!(value.to_u64(Endian::Little), Some(0x1234));
+        assert_eq!(value.to_u64(Endian::Big), Some(0x3412_0000));
+        assert_eq!(RegisterValue::from_u64(0x1234, 4, Endian::Big).bytes(), &[0, 0, 0x12, 0x34]);
+        assert_eq!(RegisterValue::new(vec![0; 16]).to_u64(Endian::Little), None);
+        assert_eq!(RegisterValue::zeroed(&Register::new("eflags", 32, "i386_eflags")).width(), 32);
+
+        // Bit 9 of eflags is IF, and bits 12-13 are IOPL.
+        let mut eflags = RegisterValue::from_u64(0x246, 4, Endian::Little);
+        assert_eq!(eflags.bits(9, 1, Endian::Little), Some(1));
+        eflags.set_bits(12, 2, 3, Endian::Little);
+        assert_eq!(eflags.to_u64(Endian::Little), Some(0x3246));
+        assert_eq!(eflags.bits(30, 4, Endian::Little), None);
+
+        // The same, by name, using the type in the description.
+        let tdesc = tdesc_x86_64();
+        let flags = tdesc.flags_type("i386_eflags").unwrap();
+        assert_eq!(eflags.field(flags, "IF", Endian::Little), Some(1));
+        assert_eq!(eflags.field(flags, "CF", Endian::Little), Some(0));
+        assert_eq!(eflags.field(flags, "IOPL", Endian::Little), None);
+        eflags.set_field(flags, "CF", 1, Endian::Little).unwrap();
+        assert_eq!(eflags.to_u64(Endian::Little), Some(0x3247));
+        assert!(eflags.set_field(flags, "IOPL", 0, Endian::Little).is_err());
+        let mut narrow = RegisterValue::new(vec![0]);
+        assert!(narrow.set_field(flags, "IF", 1, Endian::Little).is_err());
+    }
+
+    #[test]
+    fn cache() {
+        let mut feature = Feature::new("test");
+        for name in &["a", "b", "c"] {
+            feature.add_register(Register::new(name, 32, "int"));
+        }
+        let mut tdesc = TargetDescription::new();
+        tdesc.add_feature(feature);
+
+        let replies: &[&[u8]] = &[b"0100000002000000", b"xxxxxxxx", b"OK", b"0900000002000000"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut cache = RegisterCache::new();
+            let read = |cache: &mut RegisterCache, rsp: &mut RspConnection, regnum| {
+                cache.read(rsp, &tdesc, regnum).unwrap()
+                    .map(|value| value.to_u64(Endian::Little).unwrap())
+            };
+
+            // An expedited register needs no round trip.
+            cache.update(&parse_stop_reply(b"T0501:78563412;").unwrap());
+            assert_eq!(read(&mut cache, &mut rsp, 1), Some(0x1234_5678));
+            assert_eq!(read(&mut cache, &mut rsp, 0), Some(1));
+            assert_eq!(read(&mut cache, &mut rsp, 1), Some(2));
+            // The g reply left this one off.
+            assert_eq!(read(&mut cache, &mut rsp, 2), None);
+            assert_eq!(read(&mut cache, &mut rsp, 2), None);
+            cache.write(&mut rsp, 0, RegisterValue::from_u64(5, 4, Endian::Little)).unwrap();
+            assert_eq!(read(&mut cache, &mut rsp, 0), Some(5));
+            cache.clear();
+            assert_eq!(read(&mut cache, &mut rsp, 0), Some(9));
+        }
+        let sent: &[&[u8]] = &[b"g", b"p2", b"P0=05000000", b"g"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn read_write() {
+        let mut input = frame_packet(b"3412000000000000");
+        input.extend(frame_packet(b"xxxxxxxx"));
+        input.extend(frame_packet(b"OK"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let value = read_register(&mut rsp, 0x10).unwrap().unwrap();
+            assert_eq!(value.to_u64(Endian::Little), Some(0x1234));
+            assert_eq!(read_register(&mut rsp, 0x11).unwrap(), None);
+            write_register(&mut rsp, 0x10, &value).unwrap();
+        }
+        let mut expected = frame_packet(b"p10");
+        expected.extend(frame_packet(b"p11"));
+        expected.extend(frame_packet(b"P10=3412000000000000"));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn big_endian() {
+        // A register block like one from qemu-system-ppc, stopped at
+        // the start of a program.  r0 is unavailable, and the stub
+        // leaves off ctr and xer.
+        let mut block = b"xxxxxxxx0ffffe30".to_vec();
+        for _ in 2..32 {
+            block.extend_from_slice(b"00000000");
+        }
+        block.extend_from_slice(b"100004c80000d032200000480ffff7d0");
+        let mut input = frame_packet(&block);
+        input.extend(frame_packet(b"0ffffe"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+
+        let registers = read_registers(&mut rsp, &tdesc_powerpc32()).unwrap();
+        assert_eq!(registers.len(), 36);
+        assert_eq!(registers[0], (0, None));
+        let value = |index: usize| {
+            let (regnum, ref value) = registers[index];
+            (regnum, value.as_ref().unwrap().to_u64(Endian::Big).unwrap())
+        };
+        assert_eq!(value(1), (1, 0x0fff_fe30));
+        assert_eq!(value(32), (64, 0x1000_04c8));
+        assert_eq!(value(33), (65, 0xd032));
+        assert_eq!(value(35), (67, 0x0fff_f7d0));
+        // The same bytes read as little-endian give a different value.
+        let (_, ref pc) = registers[32];
+        assert_eq!(pc.as_ref().unwrap().to_u64(Endian::Little), Some(0xc804_0010));
+
+        assert!(read_registers(&mut rsp, &tdesc_powerpc32()).is_err());
+    }
+}
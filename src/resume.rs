@@ -0,0 +1,609 @@
+#![deny(missing_docs)]
+
+use console::parse_inferior_output;
+use low::{check_reply, Id, PacketType, ProcessId, RspConnection, RspError, RspResult};
+use stop::{parse_stop_reply, StopReply};
+use thread::{thread_states, ThreadState};
+use util::{decode_hex, parse_hex_number};
+
+/// An action requested by a `vCont` packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResumeAction {
+    /// `c`: continue.
+    Continue,
+    /// `C sig`: continue with a signal.
+    ContinueWithSignal(u8),
+    /// `s`: single-step.
+    Step,
+    /// `S sig`: single-step with a signal.
+    StepWithSignal(u8),
+    /// `t`: stop.  This is only used in non-stop mode.
+    Stop,
+    /// `r start,end`: step while the PC is within the range
+    /// `[start, end)`.
+    RangeStep(u64, u64),
+}
+
+// Parse a single vCont action, without its thread-id.
+fn parse_action(action: &[u8]) -> Option<ResumeAction> {
+    let (&kind, rest) = action.split_first()?;
+    let signal = || {
+        if rest.len() == 2 {
+            decode_hex(rest).map(|sig| sig as u8)
+        } else {
+            None
+        }
+    };
+    match kind {
+        b'c' if rest.is_empty() => Some(ResumeAction::Continue),
+        b'C' => Some(ResumeAction::ContinueWithSignal(signal()?)),
+        b's' if rest.is_empty() => Some(ResumeAction::Step),
+        b'S' => Some(ResumeAction::StepWithSignal(signal()?)),
+        b't' if rest.is_empty() => Some(ResumeAction::Stop),
+        b'r' => {
+            let comma = rest.iter().position(|&c| c == b',')?;
+            let start = parse_hex_number(&rest[..comma])?;
+            let end = parse_hex_number(&rest[comma + 1..])?;
+            Some(ResumeAction::RangeStep(start, end))
+        }
+        _ => None,
+    }
+}
+
+// Return true if two thread-ids name exactly the same threads.
+fn same_thread(a: &ProcessId, b: &ProcessId) -> bool {
+    a == b || (a.pid == Id::Any && b.pid == Id::Any && a.tid == b.tid)
+}
+
+fn parse_vcont_inner(packet: &[u8]) -> Result<Vec<(ResumeAction, Option<ProcessId>)>,
+                                               &'static str> {
+    if !packet.starts_with(b"vCont;") {
+        return Err("not a vCont packet");
+    }
+
+    let mut result: Vec<(ResumeAction, Option<ProcessId>)> = Vec::new();
+    for item in packet[6..].split(|&c| c == b';') {
+        let (action, thread) = match item.iter().position(|&c| c == b':') {
+            Some(colon) => {
+                let thread = ProcessId::parse(&item[colon + 1..]).ok_or("invalid thread-id")?;
+                (&item[..colon], Some(thread))
+            }
+            None => (item, None),
+        };
+        let action = parse_action(action).ok_or("invalid vCont action")?;
+
+        let duplicate = result.iter().any(|&(_, other)| match (other, thread) {
+            (None, None) => true,
+            (Some(a), Some(b)) => same_thread(&a, &b),
+            _ => false,
+        });
+        if duplicate {
+            return Err("more than one action for a thread");
+        }
+
+        result.push((action, thread));
+    }
+
+    Ok(result)
+}
+
+/// Parse a `vCont` packet, for use by a server.  `packet` is the
+/// entire packet, starting with `vCont;`.  The result holds each
+/// action in the order given, along with the thread it applies to;
+/// `None` means the action applies to all threads that have no
+/// action of their own.  Per the protocol, when several actions could
+/// apply to a thread, the leftmost one is used.
+///
+/// Returns `ProtocolViolation` if the packet is malformed, or if the
+/// same thread (or the default) is given more than one action.
+pub fn parse_vcont(packet: &[u8]) -> RspResult<Vec<(ResumeAction, Option<ProcessId>)>> {
+    parse_vcont_inner(packet).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+/// Whether the stub is in all-stop or non-stop mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopMode {
+    /// All-stop mode, the default: a resumption request is answered
+    /// by a stop reply once the inferior stops.
+    AllStop,
+    /// Non-stop mode, entered with `QNonStop:1`: a resumption request
+    /// is answered by `OK` at once, and stops are reported later with
+    /// `%Stop` notifications.
+    NonStop,
+}
+
+/// The result of `resume`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResumeOutcome {
+    /// In non-stop mode, the stub accepted the request.
+    Resumed,
+    /// In all-stop mode, the inferior stopped.
+    Stopped {
+        /// The stop reply.
+        reply: StopReply,
+        /// Any output the inferior sent, in `O` packets, while it
+        /// was running.
+        output: Vec<u8>,
+    },
+}
+
+/// Send a resumption request, such as `vCont;c` or `c`, and wait for
+/// the reply appropriate to `mode`, for use by a client.  A reply
+/// that does not fit the mode, such as a stop reply in non-stop mode,
+/// results in a `ProtocolViolation`.
+pub fn resume(conn: &mut RspConnection, packet: &[u8], mode: StopMode)
+              -> RspResult<ResumeOutcome> {
+    conn.full_packet(packet)?;
+    match mode {
+        StopMode::NonStop => {
+            let reply = conn.read_reply()?;
+            if reply != b"OK" {
+                return Err(RspError::ProtocolViolation {
+                    packet: reply,
+                    reason: "expected OK after resuming in non-stop mode",
+                });
+            }
+            Ok(ResumeOutcome::Resumed)
+        }
+        StopMode::AllStop => {
+            let (reply, output) = read_stop_reply(conn)?;
+            Ok(ResumeOutcome::Stopped { reply, output })
+        }
+    }
+}
+
+// Read replies until a stop reply arrives, collecting any output.
+fn read_stop_reply(conn: &mut RspConnection) -> RspResult<(StopReply, Vec<u8>)> {
+    let mut output = Vec::new();
+    loop {
+        let reply = conn.read_reply()?;
+        if reply == b"OK" {
+            return Err(RspError::ProtocolViolation {
+                packet: reply,
+                reason: "unexpected OK after resuming in all-stop mode",
+            });
+        }
+        if reply.first() == Some(&b'O') {
+            output.extend(parse_inferior_output(&reply)?.text);
+            continue;
+        }
+        return Ok((parse_stop_reply(&reply)?, output));
+    }
+}
+
+/// Wait for the inferior to stop after an earlier resumption request,
+/// for use by a client.  This is only meaningful in all-stop mode; in
+/// non-stop mode stops arrive as notifications, so this returns
+/// `WrongMode`.
+pub fn wait_for_stop(conn: &mut RspConnection, mode: StopMode)
+                     -> RspResult<(StopReply, Vec<u8>)> {
+    if mode == StopMode::NonStop {
+        return Err(RspError::WrongMode);
+    }
+    read_stop_reply(conn)
+}
+
+// Send |packet| and check that the reply is OK.
+fn request_ok(conn: &mut RspConnection, packet: &[u8]) -> RspResult<()> {
+    let reply = check_reply(conn.request(packet)?)?;
+    if reply != b"OK" {
+        return Err(RspError::ProtocolViolation { packet: reply, reason: "expected OK" });
+    }
+    Ok(())
+}
+
+// Wait for a stop notification, taking a queued one if there is one.
+// Notifications of other kinds are dropped; the protocol defines none.
+fn wait_for_stop_notification(conn: &mut RspConnection) -> RspResult<()> {
+    loop {
+        let contents = match conn.pop_notification() {
+            Some(contents) => contents,
+            None => match conn.read_packet()? {
+                (PacketType::Notification, contents) => contents,
+                _ => continue,
+            },
+        };
+        if contents.starts_with(b"Stop:") {
+            return Ok(());
+        }
+    }
+}
+
+/// Switch the stub from `current` to `mode`, for use by a client, with
+/// the whole sequence that the switch needs rather than just the
+/// `QNonStop` packet.  The stub only allows the switch while every
+/// thread is stopped.  So, when leaving non-stop mode, this stops any
+/// threads that are running with `vCont;t`, and waits until each has
+/// reported its stop, draining the stop replies with `vStopped`,
+/// before sending `QNonStop:0`.  In all-stop mode the inferior is
+/// already stopped between requests, so entering non-stop mode is just
+/// `QNonStop:1`.  Nothing is sent if `current` is `mode`.
+///
+/// The stops that were drained are not returned; afterwards, in
+/// either mode, `?` reports why the inferior is stopped.  Queued stop
+/// notifications are consumed.  An error reply results in
+/// `RspError::Remote`, and any other reply but `OK` in a
+/// `ProtocolViolation`.
+pub fn set_execution_mode(conn: &mut RspConnection, current: StopMode, mode: StopMode)
+                          -> RspResult<()> {
+    match (current, mode) {
+        (StopMode::AllStop, StopMode::NonStop) => request_ok(conn, b"QNonStop:1"),
+        (StopMode::NonStop, StopMode::AllStop) => {
+            let mut stopping = false;
+            loop {
+                let states = thread_states(conn)?;
+                if states.iter().all(|(_, state)| *state != ThreadState::Running) {
+                    break;
+                }
+                if !stopping {
+                    request_ok(conn, b"vCont;t")?;
+                    stopping = true;
+                }
+                wait_for_stop_notification(conn)?;
+            }
+            request_ok(conn, b"QNonStop:0")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// How `WaitHandle::interrupt` asks the stub to stop the inferior.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptMethod {
+    /// Send the 0x03 byte, which every stub understands.
+    Byte,
+    /// Send a `vCtrlC` packet.  Unlike the byte, this is a packet, so
+    /// the stub acknowledges it.  It does not need extended mode.
+    VCtrlC,
+}
+
+/// A resumption that has been sent but whose stop reply has not yet
+/// arrived, returned by `resume_and_wait`.  The caller either waits
+/// for the inferior to stop with `wait`, or stops it with
+/// `interrupt`.  This is for all-stop mode only.
+pub struct WaitHandle<'a, 'conn: 'a> {
+    conn: &'a mut RspConnection<'conn>,
+    output: Vec<u8>,
+}
+
+/// Send a resumption request, such as `vCont;c` or `c`, for use by a
+/// client in all-stop mode, returning a handle with which to wait for
+/// the stop reply.
+pub fn resume_and_wait<'a, 'conn>(conn: &'a mut RspConnection<'conn>, packet: &[u8])
+                                  -> RspResult<WaitHandle<'a, 'conn>> {
+    conn.full_packet(packet)?;
+    Ok(WaitHandle { conn, output: Vec::new() })
+}
+
+impl<'a, 'conn> WaitHandle<'a, 'conn> {
+    // Read replies until a stop reply arrives, collecting any output.
+    // Unlike read_stop_reply, output read before an error is kept, so
+    // that waiting can resume.
+    fn read_stop(&mut self) -> RspResult<StopReply> {
+        loop {
+            let reply = self.conn.read_reply()?;
+            if reply.first() == Some(&b'O') && reply != b"OK" {
+                self.output.extend(parse_inferior_output(&reply)?.text);
+                continue;
+            }
+            return parse_stop_reply(&reply);
+        }
+    }
+
+    /// Wait for the inferior to stop, returning the stop reply and
+    /// any output the inferior sent meanwhile.  If the read fails, for
+    /// example because a timeout was set on the underlying stream, the
+    /// handle remains usable, so the caller can wait again or
+    /// interrupt.
+    pub fn wait(&mut self) -> RspResult<(StopReply, Vec<u8>)> {
+        let reply = self.read_stop()?;
+        Ok((reply, ::std::mem::take(&mut self.output)))
+    }
+
+    /// Interrupt the inferior with `method`, then wait for it to
+    /// stop.  The inferior may stop on its own just as the interrupt
+    /// is sent; then the stop reply already on its way is returned,
+    /// and the stub ignores the interrupt.  With `VCtrlC`, the stub's
+    /// acknowledgement may arrive before or after that stop reply,
+    /// and both are read.
+    pub fn interrupt(mut self, method: InterruptMethod) -> RspResult<(StopReply, Vec<u8>)> {
+        if method == InterruptMethod::Byte {
+            self.conn.interrupt()?;
+            return self.wait();
+        }
+
+        self.conn.full_packet(b"vCtrlC")?;
+        let mut stop = None;
+        let mut acknowledged = false;
+        while stop.is_none() || !acknowledged {
+            let reply = self.conn.read_reply()?;
+            if reply == b"OK" && !acknowledged {
+                acknowledged = true;
+            } else if reply.first() == Some(&b'O') {
+                self.output.extend(parse_inferior_output(&reply)?.text);
+            } else if reply.first() == Some(&b'E') && !acknowledged {
+                // The stub refused, presumably because the inferior
+                // had already stopped.
+                acknowledged = true;
+                if stop.is_none() {
+                    check_reply(reply)?;
+                }
+            } else {
+                stop = Some(parse_stop_reply(&reply)?);
+            }
+        }
+        Ok((stop.unwrap(), self.output))
+    }
+}
+
+/// Client-side bookkeeping of which threads are running, kept up to
+/// date from the resumption requests sent and the stop replies
+/// received.  In non-stop mode, requests such as register reads must
+/// only be sent for stopped threads, and this tells which those are
+/// without asking the stub.  Threads start out stopped.
+///
+/// As with `ThreadCache`, nothing is updated automatically: the caller
+/// passes each resumption request to `resumed`, and each stop reply,
+/// including those from `%Stop` notifications and `vStopped`, to
+/// `stopped`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunningThreads {
+    mode: StopMode,
+    // What each request or stop did, oldest first.  Each holds thread
+    // patterns in order of precedence, and whether the threads matching
+    // each are now running, or None if the request left them alone, as
+    // a vCont stop action does.  The state of a thread is that given
+    // by the first pattern matching it in the newest change that
+    // decides it.
+    changes: Vec<Vec<(ProcessId, Option<bool>)>>,
+}
+
+// The thread-id pattern that matches every thread.
+const ALL_THREADS: ProcessId = ProcessId { pid: Id::All, tid: Id::All };
+
+impl RunningThreads {
+    /// Make a new `RunningThreads` for a stub in `mode`, with every
+    /// thread stopped.
+    pub fn new(mode: StopMode) -> RunningThreads {
+        RunningThreads { mode, changes: Vec::new() }
+    }
+
+    // Record |change|, dropping older changes that it overrides.
+    fn change(&mut self, change: Vec<(ProcessId, Option<bool>)>) {
+        match change[..] {
+            [(ALL_THREADS, Some(_))] => self.changes.clear(),
+            [(pattern, Some(_))] => self.changes.retain(|older| {
+                !matches!(older[..], [(other, Some(_))] if same_thread(&other, &pattern))
+            }),
+            _ => { }
+        }
+        self.changes.push(change);
+    }
+
+    /// Update the state after sending the resumption request `packet`,
+    /// for example `vCont;s:p1.2;c`.  The legacy `c`, `C`, `s`, and `S`
+    /// packets resume every thread.  Other packets are ignored.  A
+    /// `vCont` stop action, `t`, does not stop a thread by itself; the
+    /// thread is running until its stop is reported.
+    pub fn resumed(&mut self, packet: &[u8]) -> RspResult<()> {
+        if packet.starts_with(b"vCont;") {
+            let change = parse_vcont(packet)?.into_iter().map(|(action, thread)| {
+                let running = if action == ResumeAction::Stop { None } else { Some(true) };
+                (thread.unwrap_or(ALL_THREADS), running)
+            }).collect();
+            self.change(change);
+        } else if matches!(packet.first(), Some(b'c' | b'C' | b's' | b'S')) {
+            self.change(vec![(ALL_THREADS, Some(true))]);
+        }
+        Ok(())
+    }
+
+    /// Update the state after receiving the stop reply `reply`.  In
+    /// all-stop mode, or if the reply names no thread, every thread
+    /// is stopped; in non-stop mode, only the thread that stopped is.
+    /// A process that exited, or was terminated, has all its threads
+    /// stopped.
+    pub fn stopped(&mut self, reply: &StopReply) {
+        let pattern = match *reply {
+            StopReply::Exited { process: Some(process), .. }
+                | StopReply::Terminated { process: Some(process), .. } => {
+                ProcessId { pid: process.pid, tid: Id::All }
+            }
+            _ => match reply.thread() {
+                Some(thread) if self.mode == StopMode::NonStop => thread,
+                _ => ALL_THREADS,
+            },
+        };
+        self.change(vec![(pattern, Some(false))]);
+    }
+
+    /// Return true if `thread` is running.
+    pub fn is_running(&self, thread: ProcessId) -> bool {
+        self.changes.iter().rev()
+            .filter_map(|change| change.iter().find(|(pattern, _)| pattern.matches(&thread)))
+            .find_map(|&(_, running)| running)
+            .unwrap_or(false)
+    }
+
+    /// Return `RspError::WrongMode` if `thread` is running.  A client
+    /// can call this before a request that needs a stopped thread,
+    /// such as a register read, rather than sending a request that
+    /// the stub would refuse.
+    pub fn check_stopped(&self, thread: ProcessId) -> RspResult<()> {
+        if self.is_running(thread) {
+            return Err(RspError::WrongMode);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_vcont, resume, resume_and_wait, set_execution_mode, wait_for_stop,
+                InterruptMethod, ResumeAction, ResumeOutcome, RunningThreads, StopMode};
+    use low::{Id, ProcessId, RspConnection, RspError};
+    use stop::{parse_stop_reply, StopReply};
+    use util::{frame_packet, frame_packets, Script};
+
+    #[test]
+    fn vcont() {
+        let thread = ProcessId { pid: Id::Id(0x10), tid: Id::Id(0x11) };
+        assert_eq!(parse_vcont(b"vCont;s:p10.11;c").unwrap(),
+                   vec![(ResumeAction::Step, Some(thread)), (ResumeAction::Continue, None)]);
+        assert_eq!(parse_vcont(b"vCont;r1000,1010:p10.11;C1f:p10.-1;t").unwrap(),
+                   vec![(ResumeAction::RangeStep(0x1000, 0x1010), Some(thread)),
+                        (ResumeAction::ContinueWithSignal(0x1f),
+                         Some(ProcessId { pid: Id::Id(0x10), tid: Id::All })),
+                        (ResumeAction::Stop, None)]);
+        assert_eq!(parse_vcont(b"vCont;S05:11").unwrap(),
+                   vec![(ResumeAction::StepWithSignal(5),
+                         Some(ProcessId { pid: Id::Any, tid: Id::Id(0x11) }))]);
+        assert_eq!(parse_vcont(b"vCont;C1F:pA.B").unwrap(),
+                   vec![(ResumeAction::ContinueWithSignal(0x1f),
+                         Some(ProcessId { pid: Id::Id(10), tid: Id::Id(11) }))]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_vcont(b"vCont?").is_err());
+        assert!(parse_vcont(b"vCont;").is_err());
+        assert!(parse_vcont(b"vCont;x").is_err());
+        assert!(parse_vcont(b"vCont;C5").is_err());
+        assert!(parse_vcont(b"vCont;r1000").is_err());
+        assert!(parse_vcont(b"vCont;c;s").is_err());
+        assert!(parse_vcont(b"vCont;c:p1.2;s:p1.2").is_err());
+        assert!(parse_vcont(b"vCont;c:p1.2;s:p1.-1").is_ok());
+    }
+
+    fn run(mode: StopMode, replies: &[&[u8]]) -> Result<ResumeOutcome, RspError> {
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+        resume(&mut rsp, b"vCont;c", mode)
+    }
+
+    #[test]
+    fn resume_modes() {
+        assert_eq!(run(StopMode::NonStop, &[b"OK"]).unwrap(), ResumeOutcome::Resumed);
+        assert_eq!(run(StopMode::AllStop, &[b"O6869", b"S05"]).unwrap(),
+                   ResumeOutcome::Stopped {
+                       reply: StopReply::Signal { signal: 5, values: Vec::new() },
+                       output: b"hi".to_vec(),
+                   });
+        assert!(run(StopMode::NonStop, &[b"S05"]).is_err());
+        assert!(run(StopMode::AllStop, &[b"OK"]).is_err());
+
+        let mut input: &[u8] = &[];
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        match wait_for_stop(&mut rsp, StopMode::NonStop) {
+            Err(RspError::WrongMode) => { }
+            other => panic!("expected WrongMode, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wait_handle() {
+        let replies: &[&[u8]] = &[b"S05", b"O6869", b"S02", b"O6869", b"S05", b"OK", b"OK", b"S02",
+                                  b"E.No process"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let stop = |signal| StopReply::Signal { signal, values: Vec::new() };
+            let mut handle = resume_and_wait(&mut rsp, b"c").unwrap();
+            assert_eq!(handle.wait().unwrap(), (stop(5), Vec::new()));
+
+            let handle = resume_and_wait(&mut rsp, b"c").unwrap();
+            assert_eq!(handle.interrupt(InterruptMethod::Byte).unwrap(),
+                       (stop(2), b"hi".to_vec()));
+
+            // The stop reply may come before or after the OK.  vCtrlC
+            // does not need extended mode.
+            assert!(!rsp.extended_mode());
+            let handle = resume_and_wait(&mut rsp, b"c").unwrap();
+            assert_eq!(handle.interrupt(InterruptMethod::VCtrlC).unwrap(),
+                       (stop(5), b"hi".to_vec()));
+            let handle = resume_and_wait(&mut rsp, b"c").unwrap();
+            assert_eq!(handle.interrupt(InterruptMethod::VCtrlC).unwrap(), (stop(2), Vec::new()));
+
+            let handle = resume_and_wait(&mut rsp, b"c").unwrap();
+            match handle.interrupt(InterruptMethod::VCtrlC) {
+                Err(RspError::Remote(_)) => { }
+                other => panic!("expected an error, got {:?}", other),
+            }
+        }
+        let mut expected = frame_packet(b"c");
+        expected.extend(frame_packet(b"c"));
+        expected.push(3);
+        for _ in 0..3 {
+            expected.extend(frame_packet(b"c"));
+            expected.extend(frame_packet(b"vCtrlC"));
+        }
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn running_threads() {
+        let thread = |pid, tid| ProcessId { pid: Id::Id(pid), tid: Id::Id(tid) };
+        let mut running = RunningThreads::new(StopMode::NonStop);
+        assert!(!running.is_running(thread(1, 1)));
+
+        running.resumed(b"vCont;s:p1.2;t:p1.3;c:p1.-1").unwrap();
+        assert!(running.is_running(thread(1, 1)));
+        assert!(running.is_running(thread(1, 2)));
+        // Asked to stop, but not yet reported as stopped.
+        assert!(!running.is_running(thread(1, 3)));
+        assert!(!running.is_running(thread(2, 1)));
+
+        running.stopped(&parse_stop_reply(b"T05thread:p1.2;").unwrap());
+        assert!(!running.is_running(thread(1, 2)));
+        assert!(running.is_running(thread(1, 1)));
+        assert!(matches!(running.check_stopped(thread(1, 1)), Err(RspError::WrongMode)));
+        running.check_stopped(thread(1, 2)).unwrap();
+
+        running.stopped(&parse_stop_reply(b"X09;process:1").unwrap());
+        assert!(!running.is_running(thread(1, 1)));
+        running.resumed(b"c").unwrap();
+        assert!(running.is_running(thread(2, 5)));
+
+        let mut all_stop = RunningThreads::new(StopMode::AllStop);
+        all_stop.resumed(b"vCont;c").unwrap();
+        assert!(all_stop.is_running(thread(1, 1)));
+        all_stop.stopped(&parse_stop_reply(b"T05thread:p1.2;").unwrap());
+        assert!(!all_stop.is_running(thread(1, 1)));
+        assert!(all_stop.resumed(b"vCont;x").is_err());
+    }
+
+    #[test]
+    fn execution_mode() {
+        let input = Script::new()
+            .packet(b"mp1.1,p1.2").packet(b"l").packet(b"T05thread:p1.1;").packet(b"OK")
+            .packet(b"OK")
+            .notification(b"Stop:T00thread:p1.2;")
+            .packet(b"mp1.1,p1.2").packet(b"l").packet(b"T05thread:p1.1;")
+            .packet(b"T00thread:p1.2;").packet(b"OK")
+            .packet(b"OK")
+            .packet(b"OK")
+            .into_bytes();
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            set_execution_mode(&mut rsp, StopMode::NonStop, StopMode::AllStop).unwrap();
+            set_execution_mode(&mut rsp, StopMode::AllStop, StopMode::AllStop).unwrap();
+            set_execution_mode(&mut rsp, StopMode::AllStop, StopMode::NonStop).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"qfThreadInfo", b"qsThreadInfo", b"?", b"vStopped", b"vCont;t",
+                               b"qfThreadInfo", b"qsThreadInfo", b"?", b"vStopped", b"vStopped",
+                               b"QNonStop:0", b"QNonStop:1"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
@@ -0,0 +1,624 @@
+#![deny(missing_docs)]
+
+use breakpoint::BreakpointType;
+use launch::{EnvironmentSetter, LaunchArgs};
+use low::{check_reply, ProcessId, RspConnection, RspError, RspResult};
+use memory::{read_memory, write_memory};
+use probe::{Probe, ProbeCache};
+use register::{read_register, RegisterValue};
+use resume::{resume, ResumeOutcome, StopMode};
+use startup::{startup, NegotiatedCapabilities};
+use stop::{parse_stop_reply, StopReply, StopReplyValue};
+use thread;
+use trace::set_disconnected_tracing;
+use util::{push_hex, write_hex_number};
+
+// The packet size to assume if the stub did not report one.  This is
+// the size gdb assumes.
+const DEFAULT_PACKET_SIZE: usize = 400;
+
+/// What `DebugSession::close` does with the inferior.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisposeAction {
+    /// Detach with `D`, letting the inferior run freely, as
+    /// `DebugSession::detach` does.
+    Detach,
+    /// Kill the inferior with `k`.
+    Kill,
+    /// Leave the inferior as it is.
+    Leave,
+}
+
+/// A simple, synchronous client session, for tools such as test
+/// runners and flashers that just want to start a program, stop it
+/// somewhere, and look at it.  This is a thin layer over the rest of
+/// the crate: the session runs in all-stop mode, and anything it does
+/// not cover can be done directly on the underlying connection, which
+/// `connection` returns.
+///
+/// A session that is dropped without calling `close` or `detach`
+/// makes a best-effort attempt to detach, so that the inferior is not
+/// left stopped with breakpoints inserted.  The exception is when
+/// disconnected tracing is on; see `set_disconnected_tracing`.
+pub struct DebugSession<'a, 'conn: 'a> {
+    conn: &'a mut RspConnection<'conn>,
+    capabilities: NegotiatedCapabilities,
+    probes: ProbeCache,
+    environment: EnvironmentSetter,
+    output: Vec<u8>,
+    // The breakpoints inserted with insert_breakpoint, as (type,
+    // address, kind).
+    breakpoints: Vec<(BreakpointType, u64, u64)>,
+    remove_breakpoints_on_detach: bool,
+    disconnected_tracing: bool,
+    closed: bool,
+    // The thread that the session's operations act on, and the thread
+    // that the stub has selected with Hg, where known.  These differ
+    // after read_memory_of, until an operation switches back.
+    thread: Option<ProcessId>,
+    stub_thread: Option<ProcessId>,
+}
+
+impl<'a, 'conn> DebugSession<'a, 'conn> {
+    /// Start a session on `conn`, by calling `startup` with
+    /// `features`.  The session also advertises `error-message+`, so
+    /// that failures are reported with the stub's own text where it
+    /// has any; see `RemoteError`.
+    pub fn start(conn: &'a mut RspConnection<'conn>, features: &[u8])
+                 -> RspResult<DebugSession<'a, 'conn>> {
+        DebugSession::begin(conn, features, false)
+    }
+
+    /// Start a session as for `start`, but also ask the stub to enter
+    /// extended mode, which `run` and `restart` need.  Whether the
+    /// stub agreed is recorded in `capabilities`.
+    pub fn start_extended(conn: &'a mut RspConnection<'conn>, features: &[u8])
+                          -> RspResult<DebugSession<'a, 'conn>> {
+        DebugSession::begin(conn, features, true)
+    }
+
+    // Start a session, as for start and start_extended.
+    fn begin(conn: &'a mut RspConnection<'conn>, features: &[u8], extended: bool)
+             -> RspResult<DebugSession<'a, 'conn>> {
+        let mut all_features = b"error-message+".to_vec();
+        if !features.is_empty() {
+            all_features.push(b';');
+            all_features.extend_from_slice(features);
+        }
+        let capabilities = startup(conn, &all_features, extended)?;
+        Ok(DebugSession {
+            conn,
+            probes: ProbeCache::from_capabilities(&capabilities),
+            environment: EnvironmentSetter::from_capabilities(&capabilities),
+            capabilities,
+            output: Vec::new(),
+            breakpoints: Vec::new(),
+            remove_breakpoints_on_detach: true,
+            disconnected_tracing: false,
+            closed: false,
+            thread: None,
+            stub_thread: None,
+        })
+    }
+
+    /// Return what was negotiated when the session started.
+    pub fn capabilities(&self) -> &NegotiatedCapabilities {
+        &self.capabilities
+    }
+
+    /// Set whether `detach` first removes the breakpoints inserted
+    /// with `insert_breakpoint`.  This is on by default, since a
+    /// detached inferior that hits a leftover software breakpoint will
+    /// usually die of the trap.
+    pub fn set_remove_breakpoints_on_detach(&mut self, remove: bool) {
+        self.remove_breakpoints_on_detach = remove;
+    }
+
+    /// Return the breakpoints and watchpoints that are inserted, as
+    /// (type, address, kind).
+    pub fn breakpoints(&self) -> &[(BreakpointType, u64, u64)] {
+        &self.breakpoints
+    }
+
+    /// Ask the stub to keep a trace experiment running after the
+    /// client disconnects, or not, as `set_disconnected_tracing` does.
+    /// While this is on, dropping the session leaves the inferior as
+    /// it is, as for `DisposeAction::Leave`, since detaching would end
+    /// the experiment.  On reconnecting, `trace_status` says whether
+    /// the experiment is still running.
+    pub fn set_disconnected_tracing(&mut self, enabled: bool) -> RspResult<()> {
+        set_disconnected_tracing(self.conn, enabled)?;
+        self.disconnected_tracing = enabled;
+        Ok(())
+    }
+
+    /// Return the answer to `probe`, asking the stub only if it is not
+    /// already known; see `ProbeCache`.
+    pub fn supports(&mut self, probe: &Probe) -> RspResult<bool> {
+        self.probes.probe(self.conn, probe)
+    }
+
+    /// Return the underlying connection.
+    pub fn connection(&mut self) -> &mut RspConnection<'conn> {
+        self.conn
+    }
+
+    /// Return the output the inferior has sent so far, clearing it.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        ::std::mem::take(&mut self.output)
+    }
+
+    // Send |packet| and parse the reply as a stop reply.
+    fn request_stop(&mut self, packet: &[u8]) -> RspResult<StopReply> {
+        let reply = check_reply(self.conn.request(packet)?)?;
+        let reply = parse_stop_reply(&reply)?;
+        self.stopped(&reply);
+        Ok(reply)
+    }
+
+    // Note the stop reported by |reply|.  Stubs select the thread that
+    // stopped, so the session now acts on that thread.
+    fn stopped(&mut self, reply: &StopReply) {
+        self.thread = match *reply {
+            StopReply::Signal { ref values, .. } => values.iter().find_map(|value| match *value {
+                StopReplyValue::Thread(thread) => Some(thread),
+                _ => None,
+            }),
+            _ => None,
+        };
+        self.stub_thread = self.thread;
+    }
+
+    // Send |packet| and check that the reply is "OK".
+    fn request_ok(&mut self, packet: &[u8], reason: &'static str) -> RspResult<()> {
+        let reply = check_reply(self.conn.request(packet)?)?;
+        if reply != b"OK" {
+            return Err(RspError::ProtocolViolation { packet: reply, reason });
+        }
+        Ok(())
+    }
+
+    /// Set the environment variable `name` to `value` for programs
+    /// started later with `run`.  Which packet the stub accepts is
+    /// learned the first time; see `EnvironmentSetter`.
+    pub fn set_environment(&mut self, name: &[u8], value: &[u8]) -> RspResult<()> {
+        self.environment.set(self.conn, name, value)
+    }
+
+    /// Attach to the process `pid` with `vAttach`, returning the
+    /// stop reply that reports it stopped.
+    pub fn attach(&mut self, pid: u32) -> RspResult<StopReply> {
+        let mut packet = b"vAttach;".to_vec();
+        write_hex_number(&mut packet, u64::from(pid));
+        self.request_stop(&packet)
+    }
+
+    /// Attach to the process named `name`, waiting for it to start if
+    /// there is none, returning the stop reply that reports it
+    /// stopped.  This uses `vAttachOrWait` if the stub supports it;
+    /// otherwise it uses `vAttachWait`, which only waits for a new
+    /// process.
+    pub fn attach_or_wait(&mut self, name: &[u8]) -> RspResult<StopReply> {
+        let mut packet = if self.supports(&Probe::AttachOrWait)? {
+            b"vAttachOrWait;".to_vec()
+        } else {
+            b"vAttachWait;".to_vec()
+        };
+        push_hex(&mut packet, name);
+        self.request_stop(&packet)
+    }
+
+    /// Start a new process with `vRun`, returning the stop reply
+    /// that reports it stopped at its first instruction.  Any
+    /// standard stream redirections in `args` are sent first.  This
+    /// needs extended mode, so the session must have been started
+    /// with `start_extended`; otherwise `RspError::WrongMode` is
+    /// returned and nothing is sent.
+    pub fn run(&mut self, args: &LaunchArgs) -> RspResult<StopReply> {
+        if !self.conn.extended_mode() {
+            return Err(RspError::WrongMode);
+        }
+        for packet in args.stdio_packets() {
+            self.request_ok(&packet, "stdio redirection failed")?;
+        }
+        let packet = args.vrun_packet(self.capabilities.packet_size)?;
+        self.request_stop(&packet)
+    }
+
+    /// Restart the program with `R`, returning the stop reply that
+    /// reports it stopped at its first instruction.  This needs
+    /// extended mode; without it, `RspError::WrongMode` is returned.
+    /// Breakpoints are left as they were.
+    pub fn restart(&mut self) -> RspResult<StopReply> {
+        if !self.conn.extended_mode() {
+            return Err(RspError::WrongMode);
+        }
+        // "R" has no reply; the stub then reports the new process's
+        // stop like any other.
+        self.conn.full_packet(b"R00")?;
+        self.request_stop(b"?")
+    }
+
+    // Build a Z or z packet.
+    fn breakpoint_packet(insert: bool, breakpoint_type: BreakpointType, address: u64,
+                         kind: u64) -> Vec<u8> {
+        let type_number = match breakpoint_type {
+            BreakpointType::Software => b'0',
+            BreakpointType::Hardware => b'1',
+            BreakpointType::WriteWatchpoint => b'2',
+            BreakpointType::ReadWatchpoint => b'3',
+            BreakpointType::AccessWatchpoint => b'4',
+        };
+        let mut packet = vec![if insert { b'Z' } else { b'z' }, type_number, b','];
+        write_hex_number(&mut packet, address);
+        packet.push(b',');
+        write_hex_number(&mut packet, kind);
+        packet
+    }
+
+    /// Insert a breakpoint or watchpoint at `address`.  `kind` is as
+    /// in `BreakpointRequest`.
+    pub fn insert_breakpoint(&mut self, breakpoint_type: BreakpointType, address: u64,
+                             kind: u64) -> RspResult<()> {
+        let packet = DebugSession::breakpoint_packet(true, breakpoint_type, address, kind);
+        self.request_ok(&packet, "breakpoint insertion failed")?;
+        // Insertion is idempotent, so only record the first.
+        if !self.breakpoints.contains(&(breakpoint_type, address, kind)) {
+            self.breakpoints.push((breakpoint_type, address, kind));
+        }
+        Ok(())
+    }
+
+    /// Remove a breakpoint or watchpoint inserted by
+    /// `insert_breakpoint`.
+    pub fn remove_breakpoint(&mut self, breakpoint_type: BreakpointType, address: u64,
+                             kind: u64) -> RspResult<()> {
+        let packet = DebugSession::breakpoint_packet(false, breakpoint_type, address, kind);
+        self.request_ok(&packet, "breakpoint removal failed")?;
+        self.breakpoints.retain(|&b| b != (breakpoint_type, address, kind));
+        Ok(())
+    }
+
+    // Resume with the vCont action |action|, falling back to the
+    // plain packet of the same name, and wait for the inferior to
+    // stop.
+    fn resume_and_wait(&mut self, action: &[u8]) -> RspResult<StopReply> {
+        let supported = self.probes.vcont_actions(self.conn)?.iter().any(|a| a == action);
+        let packet = if supported {
+            [&b"vCont;"[..], action].concat()
+        } else {
+            action.to_vec()
+        };
+        match resume(self.conn, &packet, StopMode::AllStop)? {
+            ResumeOutcome::Stopped { reply, output } => {
+                self.output.extend(output);
+                self.stopped(&reply);
+                Ok(reply)
+            }
+            ResumeOutcome::Resumed => unreachable!(),
+        }
+    }
+
+    /// Continue the inferior and wait for it to stop.  Any output it
+    /// sends meanwhile can be retrieved with `take_output`.
+    pub fn cont(&mut self) -> RspResult<StopReply> {
+        self.resume_and_wait(b"c")
+    }
+
+    /// Single-step the inferior and wait for it to stop.
+    pub fn step(&mut self) -> RspResult<StopReply> {
+        self.resume_and_wait(b"s")
+    }
+
+    // Select |thread| with Hg, unless the stub already has it
+    // selected.
+    fn switch_thread(&mut self, thread: ProcessId) -> RspResult<()> {
+        if self.stub_thread != Some(thread) {
+            self.stub_thread = None;
+            thread::select_thread(self.conn, thread)?;
+            self.stub_thread = Some(thread);
+        }
+        Ok(())
+    }
+
+    // Switch the stub back to the session's thread, if read_memory_of
+    // switched away from it.  If the session's thread is not known,
+    // the stub is told to use any thread, which is what it did before.
+    fn restore_thread(&mut self) -> RspResult<()> {
+        match (self.thread, self.stub_thread) {
+            (Some(thread), _) => self.switch_thread(thread),
+            (None, Some(_)) => {
+                thread::select_thread(self.conn, ProcessId::any())?;
+                self.stub_thread = None;
+                Ok(())
+            }
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Make `thread` the thread that memory and register operations
+    /// act on, selecting it with `Hg`.  A stop selects the thread
+    /// that stopped, when the stop reply names it.
+    pub fn select_thread(&mut self, thread: ProcessId) -> RspResult<()> {
+        self.switch_thread(thread)?;
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    /// Read `length` bytes of memory at `address`, as `read_memory`
+    /// does.
+    pub fn read_memory(&mut self, address: u64, length: usize) -> RspResult<Vec<u8>> {
+        self.restore_thread()?;
+        let packet_size = self.capabilities.packet_size.unwrap_or(DEFAULT_PACKET_SIZE);
+        read_memory(self.conn, address, length, packet_size)
+    }
+
+    /// Read `length` bytes of memory at `address` as seen by `thread`,
+    /// for example its thread-local storage, without changing the
+    /// thread that the session acts on.  This selects `thread` with
+    /// `Hg` if needed.  The session's own thread is selected again
+    /// before the next operation that uses it, so a run of reads from
+    /// one thread sends only one `H` packet.  If the session's thread
+    /// is not known, because no stop reply named one and
+    /// `select_thread` was not called, `Hg0` is sent instead, letting
+    /// the stub act on any thread again.
+    pub fn read_memory_of(&mut self, thread: ProcessId, address: u64, length: usize)
+                          -> RspResult<Vec<u8>> {
+        self.switch_thread(thread)?;
+        let packet_size = self.capabilities.packet_size.unwrap_or(DEFAULT_PACKET_SIZE);
+        read_memory(self.conn, address, length, packet_size)
+    }
+
+    /// Write `data` to memory at `address`, as `write_memory` does.
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) -> RspResult<()> {
+        self.restore_thread()?;
+        let packet_size = self.capabilities.packet_size.unwrap_or(DEFAULT_PACKET_SIZE);
+        write_memory(self.conn, address, data, packet_size)
+    }
+
+    /// Read register `regnum`, as `read_register` does.
+    pub fn read_register(&mut self, regnum: u64) -> RspResult<Option<RegisterValue>> {
+        self.restore_thread()?;
+        read_register(self.conn, regnum)
+    }
+
+    /// Detach from the inferior with `D`, letting it run freely.
+    /// The inserted breakpoints are removed first, unless this was
+    /// turned off with `set_remove_breakpoints_on_detach`.  Every
+    /// removal is attempted, and `D` is sent even if some fail; the
+    /// first error is then returned.  Once detached, the session does
+    /// nothing more when it is dropped.
+    pub fn detach(&mut self) -> RspResult<()> {
+        self.closed = true;
+        let mut result = Ok(());
+        if self.remove_breakpoints_on_detach {
+            for (breakpoint_type, address, kind) in self.breakpoints.clone().into_iter().rev() {
+                let removed = self.remove_breakpoint(breakpoint_type, address, kind);
+                result = result.and(removed);
+            }
+        }
+        let detached = self.request_ok(b"D", "detach failed");
+        result.and(detached)
+    }
+
+    // Dispose of the inferior according to |action|.
+    fn dispose(&mut self, action: DisposeAction) -> RspResult<()> {
+        self.closed = true;
+        match action {
+            DisposeAction::Detach => self.detach(),
+            // Stubs do not reply to "k", and may simply exit.
+            DisposeAction::Kill => self.conn.full_packet(b"k"),
+            DisposeAction::Leave => Ok(()),
+        }
+    }
+
+    /// End the session, disposing of the inferior according to
+    /// `action`.
+    pub fn close(mut self, action: DisposeAction) -> RspResult<()> {
+        self.dispose(action)
+    }
+}
+
+impl<'a, 'conn> Drop for DebugSession<'a, 'conn> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let action = if self.disconnected_tracing {
+                DisposeAction::Leave
+            } else {
+                DisposeAction::Detach
+            };
+            let _ = self.dispose(action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DebugSession, DisposeAction};
+    use breakpoint::BreakpointType;
+    use launch::LaunchArgs;
+    use low::{ProcessId, RspConnection, RspError};
+    use stop::StopReply;
+    use util::frame_packets;
+
+    #[test]
+    fn workflow() {
+        let replies: &[&[u8]] = &[b"PacketSize=8", b"OK", b"vCont;c;s", b"S05", b"OK",
+                                  b"O6869", b"S05", b"12345678", b"9a", b"OK", b"OK"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut session = DebugSession::start_extended(&mut rsp, b"").unwrap();
+            let stopped = StopReply::Signal { signal: 5, values: Vec::new() };
+            assert_eq!(session.run(&LaunchArgs::new(b"a")).unwrap(), stopped);
+            session.insert_breakpoint(BreakpointType::Software, 0x1000, 1).unwrap();
+            assert_eq!(session.cont().unwrap(), stopped);
+            assert_eq!(session.take_output(), b"hi".to_vec());
+            assert_eq!(session.read_memory(0x1000, 5).unwrap(),
+                       vec![0x12, 0x34, 0x56, 0x78, 0x9a]);
+            session.close(DisposeAction::Detach).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"qSupported:error-message+", b"!", b"vCont?", b"vRun;61",
+                               b"Z0,1000,1", b"vCont;c", b"m1000,4", b"m1004,1", b"z0,1000,1",
+                               b"D"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn dispose() {
+        let replies: &[&[u8]] = &[b"", b"", b"OK", b"OK", b"OK", b"OK", b"", b"", b"OK", b"OK",
+                                  b"", b"", b"", b"", b"OK"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            // Dropping the session removes its breakpoints and detaches.
+            {
+                let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+                session.insert_breakpoint(BreakpointType::Hardware, 0x10, 2).unwrap();
+                session.insert_breakpoint(BreakpointType::Hardware, 0x10, 2).unwrap();
+                assert_eq!(session.breakpoints(), &[(BreakpointType::Hardware, 0x10, 2)]);
+            }
+            {
+                let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+                session.insert_breakpoint(BreakpointType::Software, 0x20, 1).unwrap();
+                session.set_remove_breakpoints_on_detach(false);
+                session.detach().unwrap();
+            }
+            let session = DebugSession::start(&mut rsp, b"").unwrap();
+            session.close(DisposeAction::Kill).unwrap();
+            // "k" gets no reply, so there is none to wait for.
+            assert_eq!(rsp.drain_reply().unwrap(), None);
+            // With disconnected tracing, dropping the session does
+            // nothing, so that the trace experiment continues.
+            let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+            session.set_disconnected_tracing(true).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"qSupported:error-message+", b"vCont?", b"Z1,10,2", b"Z1,10,2",
+                               b"z1,10,2", b"D", b"qSupported:error-message+", b"vCont?",
+                               b"Z0,20,1", b"D", b"qSupported:error-message+", b"vCont?", b"k",
+                               b"qSupported:error-message+", b"vCont?", b"QTDisconnected:1"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn detach_errors() {
+        let replies: &[&[u8]] = &[b"", b"", b"OK", b"OK", b"E01", b"OK", b"OK"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+            session.insert_breakpoint(BreakpointType::Software, 0x10, 1).unwrap();
+            session.insert_breakpoint(BreakpointType::Software, 0x20, 1).unwrap();
+            // The first failure does not stop the other removal, or
+            // the detach, but is reported afterwards.
+            match session.detach() {
+                Err(RspError::Remote(_)) => { }
+                other => panic!("expected an error, got {:?}", other),
+            }
+            assert_eq!(session.breakpoints(), &[(BreakpointType::Software, 0x20, 1)]);
+        }
+        let sent: &[&[u8]] = &[b"qSupported:error-message+", b"vCont?", b"Z0,10,1", b"Z0,20,1",
+                               b"z0,20,1", b"z0,10,1", b"D"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn restart() {
+        let replies: &[&[u8]] = &[b"", b"OK", b"", b"S05"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut session = DebugSession::start_extended(&mut rsp, b"").unwrap();
+            assert!(session.capabilities().extended_mode);
+            let stopped = StopReply::Signal { signal: 5, values: Vec::new() };
+            assert_eq!(session.restart().unwrap(), stopped);
+
+            session.connection().set_extended_mode(false);
+            match session.restart() {
+                Err(RspError::WrongMode) => { }
+                other => panic!("expected WrongMode, got {:?}", other),
+            }
+            match session.run(&LaunchArgs::new(b"a")) {
+                Err(RspError::WrongMode) => { }
+                other => panic!("expected WrongMode, got {:?}", other),
+            }
+            session.close(DisposeAction::Leave).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"qSupported:error-message+", b"!", b"vCont?", b"R00", b"?"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn attach_or_wait() {
+        let replies: &[&[u8]] = &[b"", b"", b"", b"S05", b"S05", b"", b"", b"OK", b"S05"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let stopped = StopReply::Signal { signal: 5, values: Vec::new() };
+            // The probe is only sent once.
+            let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+            assert_eq!(session.attach_or_wait(b"ab").unwrap(), stopped);
+            assert_eq!(session.attach_or_wait(b"ab").unwrap(), stopped);
+            session.close(DisposeAction::Leave).unwrap();
+            let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+            assert_eq!(session.attach_or_wait(b"ab").unwrap(), stopped);
+            session.close(DisposeAction::Leave).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"qSupported:error-message+", b"vCont?", b"qVAttachOrWaitSupported",
+                               b"vAttachWait;6162", b"vAttachWait;6162",
+                               b"qSupported:error-message+", b"vCont?", b"qVAttachOrWaitSupported",
+                               b"vAttachOrWait;6162"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn thread_memory() {
+        let replies: &[&[u8]] = &[b"", b"", b"T05thread:p1.1;", b"OK", b"12", b"34", b"OK", b"56",
+                                  b"OK", b"OK", b"", b"", b"S05", b"OK", b"78", b"OK", b"9a"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+            session.attach(1).unwrap();
+            let other = ProcessId::try_new(1, Some(2)).unwrap();
+            assert_eq!(session.read_memory_of(other, 0x10, 1).unwrap(), vec![0x12]);
+            assert_eq!(session.read_memory_of(other, 0x11, 1).unwrap(), vec![0x34]);
+            // The stopped thread is selected again.
+            assert_eq!(session.read_memory(0x20, 1).unwrap(), vec![0x56]);
+            session.select_thread(other).unwrap();
+            session.select_thread(other).unwrap();
+            session.close(DisposeAction::Detach).unwrap();
+
+            // When the session's thread is not known, any thread is
+            // selected again.
+            let mut session = DebugSession::start(&mut rsp, b"").unwrap();
+            session.attach(1).unwrap();
+            assert_eq!(session.read_memory_of(other, 0x10, 1).unwrap(), vec![0x78]);
+            assert_eq!(session.read_memory(0x20, 1).unwrap(), vec![0x9a]);
+            session.close(DisposeAction::Leave).unwrap();
+        }
+        let sent: &[&[u8]] = &[b"qSupported:error-message+", b"vCont?", b"vAttach;1", b"Hgp1.2",
+                               b"m10,1", b"m11,1", b"Hgp1.1", b"m20,1", b"Hgp1.2", b"D",
+                               b"qSupported:error-message+", b"vCont?", b"vAttach;1", b"Hgp1.2",
+                               b"m10,1", b"Hg0", b"m20,1"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
@@ -0,0 +1,187 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeSet;
+
+use low::{RspError, RspResult};
+use util::{parse_hex_number, write_hex_number};
+
+/// A set of signal numbers, as used by `QPassSignals` and
+/// `QProgramSignals`.  Signal numbers are GDB's, not the host's.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SignalSet {
+    bits: [u64; 4],
+}
+
+impl SignalSet {
+    /// Make a new, empty set.
+    pub fn new() -> SignalSet {
+        SignalSet::default()
+    }
+
+    /// Add a signal to the set.
+    pub fn insert(&mut self, signal: u8) {
+        self.bits[(signal / 64) as usize] |= 1 << (signal % 64);
+    }
+
+    /// Remove a signal from the set.
+    pub fn remove(&mut self, signal: u8) {
+        self.bits[(signal / 64) as usize] &= !(1 << (signal % 64));
+    }
+
+    /// Return true if `signal` is in the set.
+    pub fn contains(&self, signal: u8) -> bool {
+        self.bits[(signal / 64) as usize] & (1 << (signal % 64)) != 0
+    }
+
+    /// Return true if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+
+    /// Return the signals in the set, in increasing order.
+    pub fn signals(&self) -> Vec<u8> {
+        (0..=255u8).filter(|&sig| self.contains(sig)).collect()
+    }
+
+    /// Return the set in the form used by `QPassSignals` and
+    /// `QProgramSignals`, that is, hex signal numbers separated by
+    /// `;`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        for sig in self.signals() {
+            if !result.is_empty() {
+                result.push(b';');
+            }
+            write_hex_number(&mut result, u64::from(sig));
+        }
+        result
+    }
+}
+
+/// The system calls that should be reported, as requested by
+/// `QCatchSyscalls`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyscallSet {
+    /// Report every system call.
+    All,
+    /// Report only the system calls with these numbers.
+    Only(BTreeSet<u64>),
+}
+
+impl SyscallSet {
+    /// Return true if `syscall` should be reported.
+    pub fn contains(&self, syscall: u64) -> bool {
+        match *self {
+            SyscallSet::All => true,
+            SyscallSet::Only(ref set) => set.contains(&syscall),
+        }
+    }
+}
+
+fn violation(packet: &[u8], reason: &'static str) -> RspError {
+    RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+}
+
+// Parse a list of hex numbers separated by ";".  An empty list is
+// allowed.
+fn parse_number_list(list: &[u8]) -> Option<Vec<u64>> {
+    if list.is_empty() {
+        return Some(Vec::new());
+    }
+    list.split(|&c| c == b';').map(parse_hex_number).collect()
+}
+
+// Parse a signal list packet with the given prefix.
+fn parse_signals(packet: &[u8], prefix: &[u8]) -> RspResult<SignalSet> {
+    if !packet.starts_with(prefix) {
+        return Err(violation(packet, "unexpected packet"));
+    }
+    let numbers = parse_number_list(&packet[prefix.len()..])
+        .ok_or_else(|| violation(packet, "invalid signal list"))?;
+    let mut result = SignalSet::new();
+    for number in numbers {
+        if number > 255 {
+            return Err(violation(packet, "signal number out of range"));
+        }
+        result.insert(number as u8);
+    }
+    Ok(result)
+}
+
+/// Parse a `QPassSignals` packet, for use by a server.  The result is
+/// the set of signals that may be delivered to the inferior without
+/// stopping to report them to the debugger.
+pub fn parse_pass_signals(packet: &[u8]) -> RspResult<SignalSet> {
+    parse_signals(packet, b"QPassSignals:")
+}
+
+/// Parse a `QProgramSignals` packet, for use by a server.  The
+/// result is the set of signals that should be passed to the
+/// inferior, rather than discarded, when it is resumed.
+pub fn parse_program_signals(packet: &[u8]) -> RspResult<SignalSet> {
+    parse_signals(packet, b"QProgramSignals:")
+}
+
+/// Parse a `QCatchSyscalls` packet, for use by a server.  Returns
+/// `None` if system call catching should be disabled; otherwise,
+/// returns the set of system calls to report.
+pub fn parse_catch_syscalls(packet: &[u8]) -> RspResult<Option<SyscallSet>> {
+    if packet == b"QCatchSyscalls:0" {
+        return Ok(None);
+    }
+    if !packet.starts_with(b"QCatchSyscalls:1") {
+        return Err(violation(packet, "unexpected packet"));
+    }
+    let rest = &packet[16..];
+    if rest.is_empty() {
+        return Ok(Some(SyscallSet::All));
+    }
+    if rest[0] != b';' {
+        return Err(violation(packet, "invalid system call list"));
+    }
+    let numbers = parse_number_list(&rest[1..])
+        .ok_or_else(|| violation(packet, "invalid system call list"))?;
+    Ok(Some(SyscallSet::Only(numbers.into_iter().collect())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_catch_syscalls, parse_pass_signals, parse_program_signals, SignalSet,
+                SyscallSet};
+
+    #[test]
+    fn signal_set() {
+        let mut set = SignalSet::new();
+        assert!(set.is_empty());
+        set.insert(14);
+        set.insert(255);
+        set.insert(0);
+        set.remove(0);
+        assert!(set.contains(14));
+        assert!(!set.contains(15));
+        assert_eq!(set.signals(), vec![14, 255]);
+        assert_eq!(set.encode(), b"e;ff".to_vec());
+    }
+
+    #[test]
+    fn parse_signals() {
+        let set = parse_pass_signals(b"QPassSignals:e;f;10").unwrap();
+        assert_eq!(set.signals(), vec![14, 15, 16]);
+        assert!(parse_pass_signals(b"QPassSignals:").unwrap().is_empty());
+        assert_eq!(parse_program_signals(b"QProgramSignals:2").unwrap().signals(), vec![2]);
+        assert!(parse_pass_signals(b"QPassSignals:e;;f").is_err());
+        assert!(parse_pass_signals(b"QPassSignals:100").is_err());
+        assert!(parse_pass_signals(b"QProgramSignals:2").is_err());
+    }
+
+    #[test]
+    fn parse_syscalls() {
+        assert_eq!(parse_catch_syscalls(b"QCatchSyscalls:0").unwrap(), None);
+        assert_eq!(parse_catch_syscalls(b"QCatchSyscalls:1").unwrap(), Some(SyscallSet::All));
+        let set = parse_catch_syscalls(b"QCatchSyscalls:1;3c;e7").unwrap().unwrap();
+        assert!(set.contains(0x3c));
+        assert!(!set.contains(0));
+        assert!(parse_catch_syscalls(b"QCatchSyscalls:2").is_err());
+        assert!(parse_catch_syscalls(b"QCatchSyscalls:1;zz").is_err());
+    }
+}
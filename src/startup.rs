@@ -0,0 +1,192 @@
+#![deny(missing_docs)]
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use low::{RspConnection, RspError, RspResult};
+use util::parse_hex_number;
+
+// The qSupported features that only take effect when both the client
+// and the stub support them.  The others describe the stub alone.
+const MUTUAL_FEATURES: &[&[u8]] = &[b"multiprocess", b"swbreak", b"hwbreak", b"qRelocInsn",
+                                    b"fork-events", b"vfork-events", b"exec-events",
+                                    b"vContSupported", b"no-resumed", b"memory-tagging",
+                                    b"error-message"];
+
+/// What a client and stub agreed on when the session started; this
+/// is returned by `startup`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NegotiatedCapabilities {
+    /// True if the connection still acks packets, that is, if
+    /// `QStartNoAckMode` was not accepted.
+    pub ack_mode: bool,
+    /// True if the stub supports non-stop mode.
+    pub non_stop: bool,
+    /// True if both the client and the stub support multiprocess
+    /// extensions.
+    pub multiprocess: bool,
+    /// True if extended mode was asked for, and the stub accepted
+    /// `!`.
+    pub extended_mode: bool,
+    /// The maximum packet size, if the stub reported one.
+    pub packet_size: Option<usize>,
+    /// The actions listed in the stub's `vCont?` reply, such as `c`
+    /// and `s`.  This is empty if the stub does not support `vCont`.
+    pub vcont_actions: Vec<Vec<u8>>,
+    /// The features that the stub marked as supported with `+`.  A
+    /// feature that only takes effect when the client supports it
+    /// too, such as `multiprocess` or `swbreak`, is only included if
+    /// the client offered it.
+    pub supported: BTreeSet<Vec<u8>>,
+    /// The features that the stub gave a value with `=`.
+    pub values: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl NegotiatedCapabilities {
+    /// Return true if the stub reported `name` as supported.
+    pub fn supports(&self, name: &[u8]) -> bool {
+        self.supported.contains(name)
+    }
+
+    /// Return true if the stub supports the `vCont` action `action`.
+    pub fn supports_vcont(&self, action: &[u8]) -> bool {
+        self.vcont_actions.iter().any(|a| a == action)
+    }
+
+    // Fill in the fields that come from a qSupported reply, |reply|,
+    // to a request in which the client offered |offered|.
+    fn parse_qsupported(&mut self, reply: &[u8], offered: &[u8]) -> Result<(), &'static str> {
+        let offered: Vec<&[u8]> = offered.split(|&c| c == b';')
+            .filter_map(|f| match f.split_last() {
+                Some((&b'+', name)) => Some(name),
+                _ => None,
+            })
+            .collect();
+        for feature in reply.split(|&c| c == b';').filter(|f| !f.is_empty()) {
+            if let Some(eq) = feature.iter().position(|&c| c == b'=') {
+                self.values.insert(feature[..eq].to_vec(), feature[eq + 1..].to_vec());
+            } else if let Some((&b'+', name)) = feature.split_last() {
+                if !MUTUAL_FEATURES.contains(&name) || offered.contains(&name) {
+                    self.supported.insert(name.to_vec());
+                }
+            }
+        }
+        if let Some(size) = self.values.get(&b"PacketSize"[..]) {
+            let size = parse_hex_number(size).ok_or("invalid PacketSize")?;
+            self.packet_size = Some(size as usize);
+        }
+        self.non_stop = self.supports(b"QNonStop");
+        self.multiprocess = self.supports(b"multiprocess");
+        Ok(())
+    }
+}
+
+/// Start a session with a stub, for use by a client.  This sends
+/// `qSupported` with the client's `features` (for example
+/// `b"multiprocess+;swbreak+"`), switches to no-ack mode if the stub
+/// allows it, enters extended mode if `extended` is true, and asks
+/// which `vCont` actions are available.  A stub that refuses
+/// extended mode is not an error; the result records whether it was
+/// entered.  If the stub reports a `PacketSize` larger than the
+/// connection's receive limit, the limit is raised to match; see
+/// `RspConnection::set_maximum_packet_size`.
+pub fn startup(conn: &mut RspConnection, features: &[u8], extended: bool)
+               -> RspResult<NegotiatedCapabilities> {
+    let mut packet = b"qSupported".to_vec();
+    if !features.is_empty() {
+        packet.push(b':');
+        packet.extend_from_slice(features);
+    }
+    let reply = conn.request(&packet)?;
+    let mut caps = NegotiatedCapabilities { ack_mode: true, ..Default::default() };
+    caps.parse_qsupported(&reply, features).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.clone(), reason }
+    })?;
+
+    // Replies may be as large as the stub's PacketSize, so make sure
+    // the connection will read them.
+    if let Some(size) = caps.packet_size {
+        if conn.maximum_packet_size().is_some_and(|max| max < size) {
+            conn.set_maximum_packet_size(Some(size));
+        }
+    }
+
+    if caps.supports(b"QStartNoAckMode") && conn.request(b"QStartNoAckMode")? == b"OK" {
+        conn.disable_acking();
+        caps.ack_mode = false;
+    }
+
+    if extended {
+        caps.extended_mode = conn.enter_extended_mode()?;
+    }
+
+    let reply = conn.request(b"vCont?")?;
+    if reply.starts_with(b"vCont;") {
+        caps.vcont_actions = reply[6..].split(|&c| c == b';')
+            .filter(|a| !a.is_empty())
+            .map(|a| a.to_vec())
+            .collect();
+    }
+
+    Ok(caps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::startup;
+    use low::RspConnection;
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn negotiate() {
+        let mut input = b"+".to_vec();
+        input.extend(frame_packet(b"PacketSize=4000;QStartNoAckMode+;multiprocess+;swbreak+;\
+                                    qXfer:auxv:read-"));
+        input.push(b'+');
+        input.extend(frame_packet(b"OK"));
+        input.extend(frame_packet(b"vCont;c;C;s;S"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let caps = {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.set_maximum_packet_size(Some(0x100));
+            let caps = startup(&mut rsp, b"multiprocess+", false).unwrap();
+            // The receive limit was raised to the stub's PacketSize.
+            assert_eq!(rsp.maximum_packet_size(), Some(0x4000));
+            caps
+        };
+
+        assert!(!caps.ack_mode);
+        assert!(!caps.non_stop);
+        assert!(caps.multiprocess);
+        // The client did not offer swbreak.
+        assert!(!caps.supports(b"swbreak"));
+        assert!(caps.supports(b"QStartNoAckMode"));
+        assert_eq!(caps.packet_size, Some(0x4000));
+        assert!(caps.supports_vcont(b"S"));
+        assert!(!caps.supports_vcont(b"t"));
+        assert!(!caps.supports(b"qXfer:auxv:read"));
+
+        let mut expected = frame_packet(b"qSupported:multiprocess+");
+        expected.push(b'+');
+        expected.extend(frame_packet(b"QStartNoAckMode"));
+        expected.push(b'+');
+        expected.extend(frame_packet(b"vCont?"));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn extended() {
+        let replies: &[&[u8]] = &[b"PacketSize=4000", b"OK", b""];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let caps = startup(&mut rsp, b"", true).unwrap();
+            assert!(caps.extended_mode);
+            assert!(rsp.extended_mode());
+        }
+        assert_eq!(output, frame_packets(&[b"qSupported", b"!", b"vCont?"]));
+    }
+}
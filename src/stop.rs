@@ -0,0 +1,774 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+
+use low::{Id, ProcessId, RspError, RspResult, Strictness};
+use register::RegisterValue;
+use tdesc::TargetDescription;
+use util;
+use util::{decode_hex, decode_hex_bytes};
+
+/// A single `n:r` pair from a `T` stop reply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReplyValue {
+    /// An expedited register.  This holds the register number and
+    /// the raw register contents, in target byte order.
+    Register(u64, Vec<u8>),
+    /// The thread that stopped.
+    Thread(ProcessId),
+    /// The core on which the stop occurred.
+    Core(u64),
+    /// A write watchpoint at the given address was hit.
+    Watch(u64),
+    /// A read watchpoint at the given address was hit.
+    ReadWatch(u64),
+    /// An access watchpoint at the given address was hit.
+    AccessWatch(u64),
+    /// The thread stopped on entry to the given system call.
+    SyscallEntry(u64),
+    /// The thread stopped on return from the given system call.
+    SyscallReturn(u64),
+    /// The set of loaded libraries changed.
+    Library,
+    /// The replay log boundary was reached.  The contents are the
+    /// raw value, normally `begin` or `end`.
+    ReplayLog(Vec<u8>),
+    /// A software breakpoint was hit.
+    SoftwareBreak,
+    /// A hardware breakpoint was hit.
+    HardwareBreak,
+    /// The process forked; this holds the id of the new child.
+    Fork(ProcessId),
+    /// The process vforked; this holds the id of the new child.
+    VFork(ProcessId),
+    /// A vforked child has exec'd or exited.
+    VForkDone,
+    /// The process exec'd.  This holds the (decoded) pathname of the
+    /// new executable.
+    Exec(Vec<u8>),
+    /// A new thread was created.  This is reported when thread
+    /// events have been requested with `QThreadEvents`.
+    Create,
+    /// The thread called `clone`; this holds the id of the new
+    /// thread.  Unlike `Create`, this is reported to the thread doing
+    /// the cloning, and the new thread is left stopped.
+    Clone(ProcessId),
+    /// A pair that this crate does not understand.  This holds the
+    /// raw name and value.
+    Unknown(Vec<u8>, Vec<u8>),
+}
+
+/// A parsed stop reply packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReply {
+    /// An `S` or `T` reply: the program received a signal.  For an
+    /// `S` reply, `values` is always empty.
+    Signal {
+        /// The signal number.
+        signal: u8,
+        /// Additional information about the stop.
+        values: Vec<StopReplyValue>,
+    },
+    /// A `W` reply: the process exited.
+    Exited {
+        /// The exit status.  This is normally only 8 bits wide, but
+        /// some stubs report wider values.
+        code: u32,
+        /// The process that exited, if the remote said.
+        process: Option<ProcessId>,
+    },
+    /// An `X` reply: the process was terminated by a signal.
+    Terminated {
+        /// The signal number.
+        signal: u8,
+        /// The process that was terminated, if the remote said.
+        process: Option<ProcessId>,
+    },
+    /// A `w` reply: a thread exited.  This is reported in non-stop
+    /// mode or when thread events have been requested.
+    ThreadExited {
+        /// The exit status.
+        code: u8,
+        /// The thread that exited.
+        thread: ProcessId,
+    },
+    /// An `N` reply: there are no resumed threads left.
+    NoResumed,
+}
+
+impl StopReply {
+    /// Return the thread that stopped, or that exited, if the reply
+    /// says.
+    pub fn thread(&self) -> Option<ProcessId> {
+        match *self {
+            StopReply::Signal { ref values, .. } => values.iter().find_map(|value| match *value {
+                StopReplyValue::Thread(thread) => Some(thread),
+                _ => None,
+            }),
+            StopReply::ThreadExited { thread, .. } => Some(thread),
+            _ => None,
+        }
+    }
+}
+
+// In strict mode, the documented grammar only allows lowercase hex
+// digits.  Return true if |buf| is acceptable.
+fn check_hex_case(buf: &[u8], strictness: Strictness) -> bool {
+    strictness == Strictness::Lenient || !buf.iter().any(|c| (b'A'..=b'F').contains(c))
+}
+
+// Split off the first two bytes of |buf| and decode them as hex.
+fn parse_hex_byte(buf: &[u8], strictness: Strictness) -> Option<(u8, &[u8])> {
+    if buf.len() < 2 || !check_hex_case(&buf[0..2], strictness) {
+        return None;
+    }
+    let value = decode_hex(&buf[0..2])?;
+    Some((value as u8, &buf[2..]))
+}
+
+// Decode a hex number, rejecting the empty string.
+fn parse_hex_number(buf: &[u8], strictness: Strictness) -> Option<u64> {
+    if !check_hex_case(buf, strictness) {
+        None
+    } else {
+        util::parse_hex_number(buf)
+    }
+}
+
+// Decode a sequence of hex digit pairs.
+fn parse_hex_bytes(buf: &[u8], strictness: Strictness) -> Option<Vec<u8>> {
+    if !check_hex_case(buf, strictness) {
+        return None;
+    }
+    decode_hex_bytes(buf)
+}
+
+// Parse a thread-id.
+fn parse_thread_id(buf: &[u8], strictness: Strictness) -> Option<ProcessId> {
+    if !check_hex_case(buf, strictness) {
+        return None;
+    }
+    ProcessId::parse(buf)
+}
+
+// Parse the body of a "W" or "X" reply: a hex number, optionally
+// followed by ";process:PID".  The manual gives the number as two
+// digits, but in lenient mode it may have any width.
+fn parse_stop_exit(buf: &[u8], strictness: Strictness) -> Option<(u32, Option<ProcessId>)> {
+    let (number, suffix) = match buf.iter().position(|&c| c == b';') {
+        Some(semi) => (&buf[..semi], Some(&buf[semi + 1..])),
+        None => (buf, None),
+    };
+    if strictness == Strictness::Strict && number.len() != 2 {
+        return None;
+    }
+
+    let value = parse_hex_number(number, strictness)?;
+    if value > u64::from(u32::MAX) {
+        return None;
+    }
+
+    let process = match suffix {
+        None => None,
+        Some(suffix) => {
+            if !suffix.starts_with(b"process:") {
+                return None;
+            }
+            let pid = parse_hex_number(&suffix[8..], strictness)?;
+            if pid == 0 || pid > u64::from(u32::MAX) {
+                return None;
+            }
+            Some(ProcessId { pid: Id::Id(pid as u32), tid: Id::All })
+        }
+    };
+
+    Some((value as u32, process))
+}
+
+// Parse a single "n:r" pair from a "T" reply.
+fn parse_stop_value(pair: &[u8], strictness: Strictness) -> Option<StopReplyValue> {
+    let colon = pair.iter().position(|&c| c == b':')?;
+    let name = &pair[..colon];
+    let value = &pair[colon + 1..];
+
+    let number = |buf| parse_hex_number(buf, strictness);
+    let thread = |buf| parse_thread_id(buf, strictness);
+
+    let result = match name {
+        b"thread" => StopReplyValue::Thread(thread(value)?),
+        b"core" => StopReplyValue::Core(number(value)?),
+        b"watch" => StopReplyValue::Watch(number(value)?),
+        b"rwatch" => StopReplyValue::ReadWatch(number(value)?),
+        b"awatch" => StopReplyValue::AccessWatch(number(value)?),
+        b"syscall_entry" => StopReplyValue::SyscallEntry(number(value)?),
+        b"syscall_return" => StopReplyValue::SyscallReturn(number(value)?),
+        b"library" => StopReplyValue::Library,
+        b"replaylog" => StopReplyValue::ReplayLog(value.to_vec()),
+        b"swbreak" => StopReplyValue::SoftwareBreak,
+        b"hwbreak" => StopReplyValue::HardwareBreak,
+        b"fork" => StopReplyValue::Fork(thread(value)?),
+        b"vfork" => StopReplyValue::VFork(thread(value)?),
+        b"vforkdone" => StopReplyValue::VForkDone,
+        b"exec" => StopReplyValue::Exec(parse_hex_bytes(value, strictness)?),
+        b"create" => StopReplyValue::Create,
+        b"clone" => StopReplyValue::Clone(thread(value)?),
+        _ => {
+            match number(name) {
+                Some(regno) => {
+                    StopReplyValue::Register(regno, parse_hex_bytes(value, strictness)?)
+                }
+                None => StopReplyValue::Unknown(name.to_vec(), value.to_vec()),
+            }
+        }
+    };
+
+    Some(result)
+}
+
+// The implementation of parse_stop_reply.  On failure this returns a
+// description of the problem.
+fn parse_stop_reply_inner(packet: &[u8], strictness: Strictness)
+                          -> Result<StopReply, &'static str> {
+    let (&kind, rest) = packet.split_first().ok_or("empty stop reply")?;
+    match kind {
+        b'S' => {
+            let (signal, rest) = parse_hex_byte(rest, strictness).ok_or("invalid signal number")?;
+            if !rest.is_empty() {
+                return Err("trailing data after signal number");
+            }
+            Ok(StopReply::Signal { signal, values: Vec::new() })
+        }
+
+        b'T' => {
+            let (signal, rest) = parse_hex_byte(rest, strictness).ok_or("invalid signal number")?;
+            let mut values = Vec::new();
+            // Each pair is terminated by ";", so the final piece is
+            // normally empty.
+            let mut pairs = rest.split(|&c| c == b';').peekable();
+            while let Some(pair) = pairs.next() {
+                let is_last = pairs.peek().is_none();
+                if pair.is_empty() {
+                    // An empty piece in the middle means there was an
+                    // extra semicolon.
+                    if strictness == Strictness::Strict && !is_last {
+                        return Err("empty stop reason");
+                    }
+                } else if strictness == Strictness::Strict && is_last {
+                    return Err("stop reason not terminated by ';'");
+                } else {
+                    values.push(parse_stop_value(pair, strictness).ok_or("invalid stop reason")?);
+                }
+            }
+            Ok(StopReply::Signal { signal, values })
+        }
+
+        b'W' => {
+            let (code, process) = parse_stop_exit(rest, strictness).ok_or("invalid exit reply")?;
+            Ok(StopReply::Exited { code, process })
+        }
+
+        b'X' => {
+            let (signal, process) = parse_stop_exit(rest, strictness)
+                .ok_or("invalid termination reply")?;
+            if signal > u32::from(u8::MAX) {
+                return Err("signal number out of range");
+            }
+            Ok(StopReply::Terminated { signal: signal as u8, process })
+        }
+
+        b'w' => {
+            let (code, rest) = parse_hex_byte(rest, strictness).ok_or("invalid exit status")?;
+            if rest.first() != Some(&b';') {
+                return Err("missing thread-id");
+            }
+            let thread = parse_thread_id(&rest[1..], strictness).ok_or("invalid thread-id")?;
+            Ok(StopReply::ThreadExited { code, thread })
+        }
+
+        b'N' if rest.is_empty() => Ok(StopReply::NoResumed),
+
+        _ => Err("not a stop reply"),
+    }
+}
+
+/// Parse a stop reply packet, as returned by `?` or by a resumption
+/// packet such as `vCont`, or as delivered in a `%Stop` notification
+/// (without the `Stop:` prefix).  `packet` is the packet contents as
+/// returned by `RspConnection::read_packet`.  Returns
+/// `ProtocolViolation` if the packet is not a valid stop reply.
+///
+/// This parses leniently; see `parse_stop_reply_with_strictness`.
+pub fn parse_stop_reply(packet: &[u8]) -> RspResult<StopReply> {
+    parse_stop_reply_with_strictness(packet, Strictness::Lenient)
+}
+
+/// Like `parse_stop_reply`, but `strictness` controls whether common
+/// deviations from the documented grammar are accepted.
+pub fn parse_stop_reply_with_strictness(packet: &[u8], strictness: Strictness)
+                                        -> RspResult<StopReply> {
+    parse_stop_reply_inner(packet, strictness).map_err(|reason| {
+        RspError::ProtocolViolation { packet: packet.to_vec(), reason }
+    })
+}
+
+/// A helper for servers that builds a `T` stop reply.  The target
+/// supplies the registers to expedite, such as the pc and sp, and
+/// the builder formats them along with the thread, core, and stop
+/// reason.
+///
+/// The `swbreak` and `hwbreak` stop reasons may only be sent to a
+/// client that advertised them in its `qSupported` request; see
+/// `set_client_features`.  Until then they are left out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StopReplyBuilder {
+    signal: u8,
+    registers: Vec<(u64, Vec<u8>)>,
+    thread: Option<ProcessId>,
+    core: Option<u64>,
+    // The stop reason, SoftwareBreak or HardwareBreak.
+    reason: Option<StopReplyValue>,
+    swbreak: bool,
+    hwbreak: bool,
+}
+
+impl StopReplyBuilder {
+    /// Start building a stop reply for `signal`.
+    pub fn new(signal: u8) -> StopReplyBuilder {
+        StopReplyBuilder {
+            signal,
+            registers: Vec::new(),
+            thread: None,
+            core: None,
+            reason: None,
+            swbreak: false,
+            hwbreak: false,
+        }
+    }
+
+    /// Say whether the client advertised `swbreak+` and `hwbreak+`.
+    pub fn set_client_features(&mut self, swbreak: bool, hwbreak: bool) {
+        self.swbreak = swbreak;
+        self.hwbreak = hwbreak;
+    }
+
+    /// Expedite register `regnum`, whose contents are `value` in
+    /// target byte order.
+    pub fn add_register(&mut self, regnum: u64, value: &[u8]) {
+        self.registers.push((regnum, value.to_vec()));
+    }
+
+    /// Set the thread that stopped.
+    pub fn set_thread(&mut self, thread: ProcessId) {
+        self.thread = Some(thread);
+    }
+
+    /// Set the core on which the thread stopped.
+    pub fn set_core(&mut self, core: u64) {
+        self.core = Some(core);
+    }
+
+    /// Report that the thread stopped at a software breakpoint.
+    pub fn set_software_break(&mut self) {
+        self.reason = Some(StopReplyValue::SoftwareBreak);
+    }
+
+    /// Report that the thread stopped at a hardware breakpoint.
+    pub fn set_hardware_break(&mut self) {
+        self.reason = Some(StopReplyValue::HardwareBreak);
+    }
+
+    /// Return the contents of the stop reply packet.
+    pub fn build(&self) -> Vec<u8> {
+        let mut result = b"T".to_vec();
+        result.extend_from_slice(&util::hex_byte(self.signal));
+        for &(regnum, ref value) in &self.registers {
+            // Like gdbserver, write at least two digits.
+            if regnum < 0x10 {
+                result.push(b'0');
+            }
+            util::write_hex_number(&mut result, regnum);
+            result.push(b':');
+            util::push_hex(&mut result, value);
+            result.push(b';');
+        }
+        if let Some(thread) = self.thread {
+            result.extend_from_slice(b"thread:");
+            util::write_thread_id(&mut result, thread);
+            result.push(b';');
+        }
+        if let Some(core) = self.core {
+            result.extend_from_slice(b"core:");
+            util::write_hex_number(&mut result, core);
+            result.push(b';');
+        }
+        match self.reason {
+            Some(StopReplyValue::SoftwareBreak) if self.swbreak => {
+                result.extend_from_slice(b"swbreak:;");
+            }
+            Some(StopReplyValue::HardwareBreak) if self.hwbreak => {
+                result.extend_from_slice(b"hwbreak:;");
+            }
+            _ => { }
+        }
+        result
+    }
+}
+
+/// Which registers a server expedites in its `T` stop replies.  A
+/// client can use these to start a backtrace without reading all the
+/// registers, so the usual choice is the pc, the stack pointer, and
+/// the frame pointer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpeditedRegisters {
+    regnums: Vec<u32>,
+}
+
+impl ExpeditedRegisters {
+    /// Expedite the registers numbered `regnums`.
+    pub fn new(regnums: Vec<u32>) -> ExpeditedRegisters {
+        ExpeditedRegisters { regnums }
+    }
+
+    /// Expedite the registers in `tdesc` whose type is `code_ptr` or
+    /// `data_ptr`.  For the presets in this crate, these are the pc
+    /// and the stack and frame pointers, along with a few others such
+    /// as the link register.
+    pub fn pointers(tdesc: &TargetDescription) -> ExpeditedRegisters {
+        let regnums = tdesc.registers().into_iter()
+            .filter(|&(_, register)| matches!(register.reg_type(), "code_ptr" | "data_ptr"))
+            .map(|(regnum, _)| regnum)
+            .collect();
+        ExpeditedRegisters { regnums }
+    }
+
+    /// Expedite the registers in `tdesc` named `names`.  Returns
+    /// `None` if any name is not in `tdesc`.
+    pub fn by_name(tdesc: &TargetDescription, names: &[&str]) -> Option<ExpeditedRegisters> {
+        let numbered = tdesc.registers();
+        let regnums = names.iter()
+            .map(|&name| numbered.iter().find(|&&(_, r)| r.name() == name).map(|&(n, _)| n))
+            .collect::<Option<Vec<_>>>()?;
+        Some(ExpeditedRegisters { regnums })
+    }
+
+    /// Return the numbers of the registers to expedite.
+    pub fn regnums(&self) -> &[u32] {
+        &self.regnums
+    }
+
+    /// Add the selected registers to `builder`.  `read` is called
+    /// with each register number, and returns its contents in target
+    /// byte order, or `None` if the register is unavailable, in which
+    /// case it is left out.
+    pub fn fill<F>(&self, builder: &mut StopReplyBuilder, mut read: F)
+        where F: FnMut(u32) -> Option<Vec<u8>>
+    {
+        for &regnum in &self.regnums {
+            if let Some(value) = read(regnum) {
+                builder.add_register(u64::from(regnum), &value);
+            }
+        }
+    }
+}
+
+/// A stop reply together with its expedited registers, named using a
+/// target description.  A client can use these registers, typically
+/// the pc, sp, and frame pointer, to start a backtrace without
+/// reading all the registers with `g`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StopEvent {
+    /// The stop reply.
+    pub reply: StopReply,
+    /// The expedited registers that the target description knows,
+    /// keyed by name.  Each value is the raw register contents, in
+    /// target byte order.
+    pub registers: BTreeMap<String, Vec<u8>>,
+}
+
+impl StopEvent {
+    /// Make a `StopEvent` from `reply`, naming its registers using
+    /// `tdesc`.  Registers whose numbers `tdesc` does not define are
+    /// left out of `registers`, but remain in `reply`.
+    pub fn new(reply: StopReply, tdesc: &TargetDescription) -> StopEvent {
+        let mut registers = BTreeMap::new();
+        if let StopReply::Signal { ref values, .. } = reply {
+            let numbered = tdesc.registers();
+            for value in values {
+                if let StopReplyValue::Register(regnum, ref bytes) = *value {
+                    let found = numbered.iter().find(|&&(n, _)| u64::from(n) == regnum);
+                    if let Some(&(_, register)) = found {
+                        registers.insert(register.name().to_string(), bytes.clone());
+                    }
+                }
+            }
+        }
+        StopEvent { reply, registers }
+    }
+
+    /// Return the raw contents of the expedited register `name`.
+    pub fn register(&self, name: &str) -> Option<&[u8]> {
+        self.registers.get(name).map(|value| &value[..])
+    }
+
+    /// Return the expedited register `name` as a `RegisterValue`, which
+    /// can be interpreted given the target's byte order.
+    pub fn register_value(&self, name: &str) -> Option<RegisterValue> {
+        self.registers.get(name).map(|value| RegisterValue::new(value.clone()))
+    }
+
+    /// Return the numbers of all the registers expedited in the stop
+    /// reply, including any that the target description does not
+    /// know.
+    pub fn expedited_regnums(&self) -> Vec<u64> {
+        match self.reply {
+            StopReply::Signal { ref values, .. } => values.iter().filter_map(|value| match *value {
+                StopReplyValue::Register(regnum, _) => Some(regnum),
+                _ => None,
+            }).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Return those of the registers `names` that were not expedited.
+    /// If this is empty, there is no need to read the registers with
+    /// `g` before using them.
+    pub fn missing_registers<'b>(&self, names: &[&'b str]) -> Vec<&'b str> {
+        names.iter().cloned().filter(|name| !self.registers.contains_key(*name)).collect()
+    }
+
+    /// Return the thread that stopped, if the reply says.
+    pub fn thread(&self) -> Option<ProcessId> {
+        self.reply.thread()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_stop_reply, parse_stop_reply_with_strictness, ExpeditedRegisters, StopEvent,
+                StopReply, StopReplyBuilder, StopReplyValue};
+    use low::{Id, ProcessId, RspError, Strictness};
+    use presets::{tdesc_powerpc32, tdesc_x86_64};
+    use register::Endian;
+
+    #[test]
+    fn signal() {
+        assert_eq!(parse_stop_reply(b"S05").unwrap(),
+                   StopReply::Signal { signal: 5, values: Vec::new() });
+        assert!(parse_stop_reply(b"S5").is_err());
+    }
+
+    #[test]
+    fn clone_and_create() {
+        let reply = parse_stop_reply(b"T05clone:p2a.2b;thread:p2a.2a;").unwrap();
+        let new_thread = ProcessId { pid: Id::Id(0x2a), tid: Id::Id(0x2b) };
+        let thread = ProcessId { pid: Id::Id(0x2a), tid: Id::Id(0x2a) };
+        assert_eq!(reply, StopReply::Signal {
+            signal: 5,
+            values: vec![StopReplyValue::Clone(new_thread),
+                         StopReplyValue::Thread(thread)],
+        });
+
+        let reply = parse_stop_reply(b"T05create:;thread:p2a.2b;").unwrap();
+        assert_eq!(reply, StopReply::Signal {
+            signal: 5,
+            values: vec![StopReplyValue::Create,
+                         StopReplyValue::Thread(new_thread)],
+        });
+
+        let reply = parse_stop_reply(b"w00;p2a.2b").unwrap();
+        assert_eq!(reply, StopReply::ThreadExited { code: 0, thread: new_thread });
+    }
+
+    #[test]
+    fn registers() {
+        let reply = parse_stop_reply(b"T0b06:0010000000000000;10:f0ff;swbreak:;").unwrap();
+        assert_eq!(reply, StopReply::Signal {
+            signal: 11,
+            values: vec![StopReplyValue::Register(6, vec![0, 0x10, 0, 0, 0, 0, 0, 0]),
+                         StopReplyValue::Register(16, vec![0xf0, 0xff]),
+                         StopReplyValue::SoftwareBreak],
+        });
+        assert!(parse_stop_reply(b"T0506:0g;").is_err());
+    }
+
+    #[test]
+    fn exit() {
+        assert_eq!(parse_stop_reply(b"W00").unwrap(),
+                   StopReply::Exited { code: 0, process: None });
+        let process = ProcessId { pid: Id::Id(0x1f), tid: Id::All };
+        assert_eq!(parse_stop_reply(b"X09;process:1f").unwrap(),
+                   StopReply::Terminated { signal: 9, process: Some(process) });
+        assert_eq!(parse_stop_reply(b"N").unwrap(), StopReply::NoResumed);
+        assert!(parse_stop_reply(b"W").is_err());
+        assert!(parse_stop_reply(b"W00;pid:1f").is_err());
+        assert!(parse_stop_reply(b"X100").is_err());
+        assert!(parse_stop_reply(b"Q").is_err());
+    }
+
+    #[test]
+    fn protocol_violation() {
+        match parse_stop_reply(b"T05thread:pq;") {
+            Err(RspError::ProtocolViolation { packet, reason }) => {
+                assert_eq!(packet, b"T05thread:pq;");
+                assert_eq!(reason, "invalid stop reason");
+            }
+            _ => panic!("expected a protocol violation"),
+        }
+    }
+
+    #[test]
+    fn exit_fixtures() {
+        // gdbserver, multiprocess.
+        let process = ProcessId { pid: Id::Id(0x6c6d), tid: Id::All };
+        assert_eq!(parse_stop_reply(b"W2a;process:6c6d").unwrap(),
+                   StopReply::Exited { code: 42, process: Some(process) });
+        assert_eq!(parse_stop_reply(b"X0b;process:6c6d").unwrap(),
+                   StopReply::Terminated { signal: 11, process: Some(process) });
+        // qemu-user.
+        assert_eq!(parse_stop_reply(b"W01").unwrap(),
+                   StopReply::Exited { code: 1, process: None });
+        assert_eq!(parse_stop_reply(b"X06").unwrap(),
+                   StopReply::Terminated { signal: 6, process: None });
+        // Stubs that report the full exit status.
+        assert_eq!(parse_stop_reply(b"W1").unwrap(),
+                   StopReply::Exited { code: 1, process: None });
+        assert_eq!(parse_stop_reply(b"Wc0000005").unwrap(),
+                   StopReply::Exited { code: 0xc000_0005, process: None });
+    }
+
+    #[test]
+    fn separator_fixtures() {
+        let no_values = StopReply::Signal { signal: 5, values: Vec::new() };
+        // Bare-metal stubs that always emit the separator.
+        assert_eq!(parse_stop_reply(b"T05;").unwrap(), no_values);
+        assert_eq!(parse_stop_reply(b"T05").unwrap(), no_values);
+        assert_eq!(parse_stop_reply(b"T05;;").unwrap(), no_values);
+
+        let thread = ProcessId { pid: Id::Any, tid: Id::Id(1) };
+        let expected = StopReply::Signal {
+            signal: 5,
+            values: vec![StopReplyValue::Thread(thread)],
+        };
+        // OpenOCD.
+        assert_eq!(parse_stop_reply(b"T05thread:00000001;").unwrap(), expected);
+        // A stub that doubles the final separator, and one that omits it.
+        assert_eq!(parse_stop_reply(b"T05thread:1;;").unwrap(), expected);
+        assert_eq!(parse_stop_reply(b"T05thread:1").unwrap(), expected);
+    }
+
+    #[test]
+    fn strictness() {
+        let lenient = |packet| parse_stop_reply_with_strictness(packet, Strictness::Lenient);
+        let strict = |packet| parse_stop_reply_with_strictness(packet, Strictness::Strict);
+
+        let expected = StopReply::Signal {
+            signal: 5,
+            values: vec![StopReplyValue::Core(0xa)],
+        };
+        assert_eq!(strict(b"T05core:a;").unwrap(), expected);
+
+        for packet in [&b"T05core:A;"[..], b"T05core:a;;", b"T05;core:a;", b"T05core:a"].iter() {
+            assert_eq!(lenient(packet).unwrap(), expected);
+            assert!(strict(packet).is_err());
+        }
+
+        assert!(strict(b"W0A").is_err());
+        assert_eq!(lenient(b"W0A").unwrap(), StopReply::Exited { code: 10, process: None });
+
+        // The exit status is two digits.
+        assert_eq!(strict(b"W0a").unwrap(), StopReply::Exited { code: 10, process: None });
+        for packet in [&b"W100"[..], b"Wa", b"X009;process:1"].iter() {
+            assert!(lenient(packet).is_ok());
+            assert!(strict(packet).is_err());
+        }
+    }
+
+    #[test]
+    fn stop_event() {
+        let reply = parse_stop_reply(b"T0506:1000000000000000;07:f0ff000000000000;\
+                                       10:3412000000000000;99:00;thread:p1.2;").unwrap();
+        let event = StopEvent::new(reply, &tdesc_x86_64());
+        assert_eq!(event.register("rip"), Some(&[0x34, 0x12, 0, 0, 0, 0, 0, 0][..]));
+        assert_eq!(event.register("rsp"), Some(&[0xf0, 0xff, 0, 0, 0, 0, 0, 0][..]));
+        assert_eq!(event.register("rbp"), Some(&[0x10, 0, 0, 0, 0, 0, 0, 0][..]));
+        assert_eq!(event.registers.len(), 3);
+        assert_eq!(event.thread(), Some(ProcessId { pid: Id::Id(1), tid: Id::Id(2) }));
+    }
+
+    #[test]
+    fn stop_event_big_endian() {
+        // A breakpoint stop from a big-endian PowerPC target, with the
+        // pc, r1, and lr expedited.
+        let reply = parse_stop_reply(b"T0540:100004c8;01:0ffffe30;43:0ffff7d0;thread:p2a.2a;")
+            .unwrap();
+        let event = StopEvent::new(reply, &tdesc_powerpc32());
+        assert_eq!(event.register("pc"), Some(&[0x10, 0, 0x04, 0xc8][..]));
+        assert_eq!(event.register_value("pc").unwrap().to_u64(Endian::Big), Some(0x1000_04c8));
+        assert_eq!(event.register_value("r1").unwrap().to_u64(Endian::Big), Some(0x0fff_fe30));
+        assert_eq!(event.register_value("lr").unwrap().to_u64(Endian::Big), Some(0x0fff_f7d0));
+        assert_eq!(event.register_value("r3"), None);
+    }
+
+    #[test]
+    fn stop_reply_builder() {
+        let mut builder = StopReplyBuilder::new(5);
+        builder.add_register(16, &[0x34, 0x12, 0, 0, 0, 0, 0, 0]);
+        builder.set_thread(ProcessId { pid: Id::Id(0x1f), tid: Id::Id(0x20) });
+        builder.set_core(2);
+        builder.set_software_break();
+        assert_eq!(builder.build(),
+                   b"T0510:3412000000000000;thread:p1f.20;core:2;".to_vec());
+
+        builder.set_client_features(true, false);
+        let packet = builder.build();
+        assert_eq!(packet, b"T0510:3412000000000000;thread:p1f.20;core:2;swbreak:;".to_vec());
+        let strict = parse_stop_reply_with_strictness(&packet, Strictness::Strict).unwrap();
+        match strict {
+            StopReply::Signal { values, .. } => {
+                assert_eq!(values.last(), Some(&StopReplyValue::SoftwareBreak));
+            }
+            _ => panic!("expected a signal stop"),
+        }
+
+        builder.set_hardware_break();
+        assert!(!builder.build().ends_with(b"hwbreak:;"));
+    }
+
+    #[test]
+    fn uppercase_hex() {
+        let thread = ProcessId { pid: Id::Id(0x2a), tid: Id::Id(0x2b) };
+        assert_eq!(parse_stop_reply(b"T0B0A:00FF;thread:p2A.2B;core:1F;").unwrap(),
+                   StopReply::Signal {
+                       signal: 11,
+                       values: vec![StopReplyValue::Register(10, vec![0, 0xff]),
+                                    StopReplyValue::Thread(thread),
+                                    StopReplyValue::Core(0x1f)],
+                   });
+        assert_eq!(parse_stop_reply(b"X1F;process:2A").unwrap(),
+                   StopReply::Terminated {
+                       signal: 0x1f,
+                       process: Some(ProcessId { pid: Id::Id(0x2a), tid: Id::All }),
+                   });
+        assert_eq!(parse_stop_reply(b"w1A;p2A.2B").unwrap(),
+                   StopReply::ThreadExited { code: 0x1a, thread });
+    }
+
+    #[test]
+    fn expedited() {
+        let tdesc = tdesc_x86_64();
+        let pointers = ExpeditedRegisters::pointers(&tdesc);
+        assert_eq!(pointers.regnums(), &[6, 7, 16]);
+        let selected = ExpeditedRegisters::by_name(&tdesc, &["rip", "rsp"]).unwrap();
+        assert_eq!(ExpeditedRegisters::by_name(&tdesc, &["pc"]), None);
+
+        // The server can't read rsp.
+        let mut builder = StopReplyBuilder::new(5);
+        selected.fill(&mut builder, |regnum| if regnum == 16 { Some(vec![1, 0]) } else { None });
+        let packet = builder.build();
+        assert_eq!(packet, b"T0510:0100;".to_vec());
+
+        let event = StopEvent::new(parse_stop_reply(&packet).unwrap(), &tdesc);
+        assert_eq!(event.expedited_regnums(), vec![0x10]);
+        assert_eq!(event.missing_registers(&["rip", "rsp", "rbp"]), vec!["rsp", "rbp"]);
+    }
+}
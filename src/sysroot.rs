@@ -0,0 +1,135 @@
+#![deny(missing_docs)]
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hostio::{RemoteFile, FILE_IO_O_RDONLY};
+use low::RspConnection;
+use qxfer::read_qxfer_object;
+use util::{library_names, local_path, to_io_error};
+
+// Copy the remote file |remote| to |local|.
+fn copy_file(conn: &mut RspConnection, remote: &[u8], local: &Path, packet_size: usize)
+             -> io::Result<()> {
+    if let Some(parent) = local.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = RemoteFile::open(conn, remote, FILE_IO_O_RDONLY, 0)?;
+    file.set_packet_size(packet_size);
+    let mut out = fs::File::create(local)?;
+    io::copy(&mut file, &mut out)?;
+    Ok(())
+}
+
+/// Mirror the remote executable and its shared libraries into the
+/// local directory `dir`, for use as a sysroot when loading symbols.
+/// This is for use by clients.  Each remote file is copied to the
+/// same path under `dir`.
+///
+/// The executable is found with `qXfer:exec-file`, and the libraries
+/// with `qXfer:libraries-svr4` or, failing that, `qXfer:libraries`;
+/// the files themselves are read with host I/O.  `packet_size` is the
+/// maximum packet size reported by the stub's `qSupported` reply.
+/// `progress` is called with the remote name of each file before it
+/// is copied.  Returns the local paths of the copied files.
+pub fn populate_sysroot(conn: &mut RspConnection, dir: &Path, packet_size: usize,
+                        progress: &mut dyn FnMut(&[u8])) -> io::Result<Vec<PathBuf>> {
+    let mut remote_files = Vec::new();
+    if let Some(exec) = read_qxfer_object(conn, b"exec-file", b"", packet_size)
+        .map_err(to_io_error)? {
+        remote_files.push(exec);
+    }
+    for object in &[&b"libraries-svr4"[..], b"libraries"] {
+        if let Some(xml) = read_qxfer_object(conn, object, b"", packet_size)
+            .map_err(to_io_error)? {
+            remote_files.extend(library_names(&xml));
+            break;
+        }
+    }
+
+    let mut result: Vec<PathBuf> = Vec::new();
+    for remote in remote_files {
+        let local = match local_path(dir, &remote) {
+            Some(local) => local,
+            None => continue,
+        };
+        if result.contains(&local) {
+            continue;
+        }
+        progress(&remote);
+        copy_file(conn, &remote, &local, packet_size)?;
+        result.push(local);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::populate_sysroot;
+    use low::RspConnection;
+    use util::{frame_packet, frame_packets, push_hex, Script};
+
+    #[test]
+    fn populate() {
+        let libraries = b"l<library-list>\
+                          <library name=\"/lib/libc.so.6\"><segment address=\"0x10\"/></library>\
+                          <library name=\"/bin/prog\"><segment address=\"0x20\"/></library>\
+                          <library name=\"/../etc/passwd\"><segment address=\"0x30\"/></library>\
+                          </library-list>";
+        let input = Script::new()
+            .packet(b"l/bin/prog")
+            // No libraries-svr4, so the plain library list is used.
+            .packet(b"")
+            .packet(libraries)
+            .packet(b"F5")
+            .packet(b"F2;ab")
+            .packet(b"F0;")
+            .packet(b"F0")
+            .packet(b"F6")
+            .packet(b"F3;x}\x03z")
+            .packet(b"F0;")
+            .packet(b"F0")
+            .into_bytes();
+
+        let root = env::temp_dir().join(format!("gdb-rsp-sysroot-{}", process::id()));
+        let mut reader: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut copied = Vec::new();
+        let result = {
+            let mut rsp = RspConnection::new(&mut reader, &mut output, true);
+            rsp.disable_acking();
+            populate_sysroot(&mut rsp, &root, 96, &mut |name| copied.push(name.to_vec()))
+                .unwrap()
+        };
+
+        // The executable is copied once, and the path escaping the
+        // sysroot is skipped.
+        let prog = root.join("bin").join("prog");
+        let libc = root.join("lib").join("libc.so.6");
+        assert_eq!(result, vec![prog.clone(), libc.clone()]);
+        assert_eq!(copied, vec![b"/bin/prog".to_vec(), b"/lib/libc.so.6".to_vec()]);
+        assert_eq!(fs::read(&prog).unwrap(), b"ab".to_vec());
+        assert_eq!(fs::read(&libc).unwrap(), b"x#z".to_vec());
+        fs::remove_dir_all(&root).unwrap();
+
+        let mut expected = frame_packets(&[b"qXfer:exec-file:read::0,5f",
+                                           b"qXfer:libraries-svr4:read::0,5f",
+                                           b"qXfer:libraries:read::0,5f"]);
+        for &(name, fd, size) in [(&b"/bin/prog"[..], 5, 2), (b"/lib/libc.so.6", 6, 3)].iter() {
+            let mut open = b"vFile:open:".to_vec();
+            push_hex(&mut open, name);
+            open.extend_from_slice(b",0,0");
+            expected.extend(frame_packet(&open));
+            expected.extend(frame_packet(format!("vFile:pread:{:x},20,0", fd).as_bytes()));
+            expected.extend(frame_packet(format!("vFile:pread:{:x},20,{:x}", fd, size)
+                                         .as_bytes()));
+            expected.extend(frame_packet(format!("vFile:close:{:x}", fd).as_bytes()));
+        }
+        assert_eq!(output, expected);
+    }
+}
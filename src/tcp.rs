@@ -0,0 +1,98 @@
+#![deny(missing_docs)]
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use low::RspConnection;
+use transport::Transport;
+
+/// An RSP channel over TCP.  Usually the debugger connects to a stub
+/// that is listening, as with gdb's `target remote host:port`; use
+/// `connect` for this.  In a reverse setup the debugger listens and
+/// the stub connects out to it, which helps when the target cannot
+/// accept incoming connections, as on some Android devices; use
+/// `accept` for this.  Either end may be a client or a server.
+///
+/// Nagle's algorithm is disabled on the socket, since the protocol
+/// consists of small packets that are each waited for.
+///
+/// `RspConnection` borrows its reader and writer, so this type owns
+/// the socket (and other handles to it) while connections are made
+/// from it using `connection`.
+pub struct TcpChannel {
+    reader: TcpStream,
+    writer: TcpStream,
+    // A third handle, used to set the read timeout while the others
+    // are lent to a connection.
+    control: TcpStream,
+}
+
+impl TcpChannel {
+    /// Make a new channel from a connected stream.
+    pub fn new(stream: TcpStream) -> io::Result<TcpChannel> {
+        stream.set_nodelay(true)?;
+        let writer = stream.try_clone()?;
+        let control = stream.try_clone()?;
+        Ok(TcpChannel { reader: stream, writer, control })
+    }
+
+    /// Connect to `addr`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpChannel> {
+        TcpChannel::new(TcpStream::connect(addr)?)
+    }
+
+    /// Wait for a connection on `listener`, returning the channel and
+    /// the address of the peer.
+    pub fn accept(listener: &TcpListener) -> io::Result<(TcpChannel, SocketAddr)> {
+        let (stream, peer) = listener.accept()?;
+        Ok((TcpChannel::new(stream)?, peer))
+    }
+
+    /// Return the underlying stream, for example to set timeouts.
+    pub fn stream(&self) -> &TcpStream {
+        &self.reader
+    }
+
+    /// Make an `RspConnection` that uses this channel.  `is_client` is
+    /// as for `RspConnection::new`.  The connection's read timeout
+    /// sets the timeout of the socket.
+    pub fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        let control = &self.control;
+        let mut conn = RspConnection::new(&mut self.reader, &mut self.writer, is_client);
+        conn.set_read_timeout_hook(move |timeout| control.set_read_timeout(timeout));
+        conn
+    }
+}
+
+impl Transport for TcpChannel {
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        TcpChannel::connection(self, is_client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::TcpChannel;
+
+    #[test]
+    fn reverse_connection() {
+        // The debugger listens, and the stub connects out to it.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stub = thread::spawn(move || {
+            let mut channel = TcpChannel::connect(addr).unwrap();
+            let mut conn = channel.connection(false);
+            assert_eq!(conn.read_packet().unwrap().1, b"?".to_vec());
+            conn.full_packet(b"S05").unwrap();
+        });
+
+        let (mut channel, _) = TcpChannel::accept(&listener).unwrap();
+        assert!(channel.stream().nodelay().unwrap());
+        let mut conn = channel.connection(true);
+        assert_eq!(conn.request(b"?").unwrap(), b"S05".to_vec());
+        stub.join().unwrap();
+    }
+}
@@ -0,0 +1,741 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use low::{Id, ProcessId, RspConnection, RspResult};
+use qxfer::read_qxfer_object;
+use stop::{StopReply, StopReplyValue};
+use thread::with_thread;
+use util::{unescape_xml, xml_attribute};
+
+// Escape |text| for use in XML character data or attribute values.
+fn escape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// A register in a target description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    name: String,
+    bitsize: u32,
+    reg_type: String,
+    group: Option<String>,
+    regnum: Option<u32>,
+}
+
+impl Register {
+    /// Make a new register.  `name` is the register's name,
+    /// `bitsize` its size in bits, and `reg_type` its type, for
+    /// example `int`, `code_ptr`, `data_ptr`, or `ieee_double`.
+    pub fn new(name: &str, bitsize: u32, reg_type: &str) -> Register {
+        Register {
+            name: name.to_string(),
+            bitsize,
+            reg_type: reg_type.to_string(),
+            group: None,
+            regnum: None,
+        }
+    }
+
+    /// Set the register group, for example `general` or `float`.
+    pub fn set_group(&mut self, group: &str) {
+        self.group = Some(group.to_string());
+    }
+
+    /// Set the register number.  By default registers are numbered
+    /// consecutively, in the order they appear in the description.
+    pub fn set_regnum(&mut self, regnum: u32) {
+        self.regnum = Some(regnum);
+    }
+
+    /// Return the register's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the register's size in bits.
+    pub fn bitsize(&self) -> u32 {
+        self.bitsize
+    }
+
+    /// Return the register's type.
+    pub fn reg_type(&self) -> &str {
+        &self.reg_type
+    }
+
+    /// Return the register's group, if one was set.
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Return the register's explicit number, if one was set.
+    pub fn regnum(&self) -> Option<u32> {
+        self.regnum
+    }
+}
+
+/// A named bit field of a `FlagsType`, holding bits `start` through
+/// `end` inclusive, counting from the least significant bit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    name: String,
+    start: u32,
+    end: u32,
+}
+
+impl Field {
+    /// Make a new field.  Panics if `end` is less than `start`.
+    pub fn new(name: &str, start: u32, end: u32) -> Field {
+        assert!(start <= end);
+        Field { name: name.to_string(), start, end }
+    }
+
+    /// Return the field's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the field's lowest bit.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Return the field's highest bit.
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Return the field's width in bits.
+    pub fn width(&self) -> u32 {
+        self.end - self.start + 1
+    }
+}
+
+/// A `<flags>` type in a target description, which divides a
+/// register into named bit fields, such as `i386_eflags`.  A register
+/// has this type when its `reg_type` is the type's id; see
+/// `TargetDescription::flags_type`, and `RegisterValue::field` for
+/// reading the fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlagsType {
+    id: String,
+    size: u32,
+    fields: Vec<Field>,
+}
+
+impl FlagsType {
+    /// Make a new flags type with no fields.  `size` is its size in
+    /// bytes.
+    pub fn new(id: &str, size: u32) -> FlagsType {
+        FlagsType { id: id.to_string(), size, fields: Vec::new() }
+    }
+
+    /// Add a field to this type.
+    pub fn add_field(&mut self, field: Field) {
+        self.fields.push(field);
+    }
+
+    /// Return the type's id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Return the type's size in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Return the fields, in the order they were added.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Return the field named `name`, if there is one.
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// A feature in a target description: a named group of registers,
+/// such as `org.gnu.gdb.i386.core`, and the types they use.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Feature {
+    name: String,
+    flags: Vec<FlagsType>,
+    registers: Vec<Register>,
+}
+
+impl Feature {
+    /// Make a new, empty feature.
+    pub fn new(name: &str) -> Feature {
+        Feature { name: name.to_string(), flags: Vec::new(), registers: Vec::new() }
+    }
+
+    /// Add a flags type to this feature, for use by its registers.
+    pub fn add_flags(&mut self, flags: FlagsType) {
+        self.flags.push(flags);
+    }
+
+    /// Add a register to this feature.
+    pub fn add_register(&mut self, register: Register) {
+        self.registers.push(register);
+    }
+
+    /// Return the feature's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the flags types in this feature.
+    pub fn flags(&self) -> &[FlagsType] {
+        &self.flags
+    }
+
+    /// Return the registers in this feature.
+    pub fn registers(&self) -> &[Register] {
+        &self.registers
+    }
+}
+
+/// A target description, for use by a server.  This can be turned
+/// into the `target.xml` document served by `qXfer:features:read`;
+/// see `send_qxfer_read_reply`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TargetDescription {
+    architecture: Option<String>,
+    osabi: Option<String>,
+    features: Vec<Feature>,
+}
+
+impl TargetDescription {
+    /// Make a new, empty target description.
+    pub fn new() -> TargetDescription {
+        TargetDescription::default()
+    }
+
+    /// Set the architecture, for example `i386:x86-64`.
+    pub fn set_architecture(&mut self, architecture: &str) {
+        self.architecture = Some(architecture.to_string());
+    }
+
+    /// Set the OS ABI, for example `GNU/Linux`.
+    pub fn set_osabi(&mut self, osabi: &str) {
+        self.osabi = Some(osabi.to_string());
+    }
+
+    /// Add a feature.
+    pub fn add_feature(&mut self, feature: Feature) {
+        self.features.push(feature);
+    }
+
+    /// Return the architecture, if one was set.
+    pub fn architecture(&self) -> Option<&str> {
+        self.architecture.as_deref()
+    }
+
+    /// Return the OS ABI, if one was set.
+    pub fn osabi(&self) -> Option<&str> {
+        self.osabi.as_deref()
+    }
+
+    /// Return the architecture as an `Arch`, if one was set.
+    pub fn arch(&self) -> Option<Arch> {
+        self.architecture.as_ref().map(|name| Arch::from_name(name))
+    }
+
+    /// Return the OS ABI as an `OsAbi`, if one was set.
+    pub fn os_abi(&self) -> Option<OsAbi> {
+        self.osabi.as_ref().map(|name| OsAbi::from_name(name))
+    }
+
+    /// Return the features.
+    pub fn features(&self) -> &[Feature] {
+        &self.features
+    }
+
+    /// Return all the registers, in order, along with their register
+    /// numbers.  As in GDB, registers are numbered consecutively,
+    /// except that a register with an explicit number resets the
+    /// count.
+    pub fn registers(&self) -> Vec<(u32, &Register)> {
+        let mut result = Vec::new();
+        let mut next = 0;
+        for feature in &self.features {
+            for reg in &feature.registers {
+                let regnum = reg.regnum.unwrap_or(next);
+                result.push((regnum, reg));
+                next = regnum + 1;
+            }
+        }
+        result
+    }
+
+    /// Return the flags type with the id `id`, from any feature.
+    /// Passing a register's `reg_type` finds the fields of a flags
+    /// register.
+    pub fn flags_type(&self, id: &str) -> Option<&FlagsType> {
+        self.features.iter().flat_map(|feature| &feature.flags).find(|flags| flags.id == id)
+    }
+
+    /// Return the target description as an XML document, suitable for
+    /// serving as `target.xml`.
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\"?>\n");
+        xml.push_str("<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n");
+        xml.push_str("<target version=\"1.0\">\n");
+        if let Some(ref arch) = self.architecture {
+            let _ = writeln!(xml, "  <architecture>{}</architecture>", escape_xml(arch));
+        }
+        if let Some(ref osabi) = self.osabi {
+            let _ = writeln!(xml, "  <osabi>{}</osabi>", escape_xml(osabi));
+        }
+        for feature in &self.features {
+            let _ = writeln!(xml, "  <feature name=\"{}\">", escape_xml(&feature.name));
+            for flags in &feature.flags {
+                let _ = writeln!(xml, "    <flags id=\"{}\" size=\"{}\">", escape_xml(&flags.id),
+                                 flags.size);
+                for field in &flags.fields {
+                    let _ = writeln!(xml, "      <field name=\"{}\" start=\"{}\" end=\"{}\"/>",
+                                     escape_xml(&field.name), field.start, field.end);
+                }
+                xml.push_str("    </flags>\n");
+            }
+            for reg in &feature.registers {
+                let _ = write!(xml, "    <reg name=\"{}\" bitsize=\"{}\" type=\"{}\"",
+                               escape_xml(&reg.name), reg.bitsize, escape_xml(&reg.reg_type));
+                if let Some(regnum) = reg.regnum {
+                    let _ = write!(xml, " regnum=\"{}\"", regnum);
+                }
+                if let Some(ref group) = reg.group {
+                    let _ = write!(xml, " group=\"{}\"", escape_xml(group));
+                }
+                xml.push_str("/>\n");
+            }
+            xml.push_str("  </feature>\n");
+        }
+        xml.push_str("</target>\n");
+        xml
+    }
+}
+
+/// A processor architecture, as named by the `<architecture>` element
+/// of a target description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Arch {
+    /// 32-bit x86, `i386`.
+    X86,
+    /// 64-bit x86, `i386:x86-64`.
+    X86_64,
+    /// 32-bit Arm, `arm` or a variant such as `armv7`.
+    Arm,
+    /// 64-bit Arm, `aarch64`.
+    AArch64,
+    /// 32-bit RISC-V, `riscv:rv32`.
+    RiscV32,
+    /// 64-bit RISC-V, `riscv:rv64`.
+    RiscV64,
+    /// 32-bit MIPS, `mips` or a 32-bit variant such as `mips:isa32`.
+    Mips,
+    /// 64-bit MIPS, a 64-bit variant such as `mips:isa64`.
+    Mips64,
+    /// 32-bit PowerPC, `powerpc:common`.
+    PowerPc,
+    /// 64-bit PowerPC, `powerpc:common64`.
+    PowerPc64,
+    /// Any other architecture, holding its name.  This includes plain
+    /// `riscv`, which does not say how wide the registers are.
+    Other(String),
+}
+
+impl Arch {
+    /// Return the `Arch` for the BFD architecture name `name`.
+    pub fn from_name(name: &str) -> Arch {
+        match name {
+            "i386" | "i386:intel" => Arch::X86,
+            "i386:x86-64" | "i386:x86-64:intel" => Arch::X86_64,
+            "aarch64" => Arch::AArch64,
+            "riscv:rv32" => Arch::RiscV32,
+            "riscv:rv64" => Arch::RiscV64,
+            "powerpc:common" => Arch::PowerPc,
+            "powerpc:common64" => Arch::PowerPc64,
+            _ if name.starts_with("arm") => Arch::Arm,
+            _ if name == "mips" || name.starts_with("mips:") => {
+                if name.contains("64") { Arch::Mips64 } else { Arch::Mips }
+            }
+            _ => Arch::Other(name.to_string()),
+        }
+    }
+
+    // The architecture part of a target triple.
+    fn triple_arch(&self) -> Option<&'static str> {
+        Some(match *self {
+            Arch::X86 => "i686",
+            Arch::X86_64 => "x86_64",
+            Arch::Arm => "arm",
+            Arch::AArch64 => "aarch64",
+            Arch::RiscV32 => "riscv32",
+            Arch::RiscV64 => "riscv64",
+            Arch::Mips => "mips",
+            Arch::Mips64 => "mips64",
+            Arch::PowerPc => "powerpc",
+            Arch::PowerPc64 => "powerpc64",
+            Arch::Other(_) => return None,
+        })
+    }
+
+    /// Return the target triple for this architecture running under
+    /// `osabi`, for example `aarch64-unknown-linux-gnu`, as used to
+    /// select a disassembler or ABI.  Returns `None` for an `Other`
+    /// architecture.  An OS ABI that is missing or not understood
+    /// gives an `unknown` OS.
+    pub fn triple(&self, osabi: Option<&OsAbi>) -> Option<String> {
+        let arch = self.triple_arch()?;
+        let os = match osabi {
+            Some(&OsAbi::Linux) if *self == Arch::Arm => "unknown-linux-gnueabi",
+            Some(&OsAbi::Linux) => "unknown-linux-gnu",
+            Some(&OsAbi::FreeBsd) => "unknown-freebsd",
+            Some(&OsAbi::NetBsd) => "unknown-netbsd",
+            Some(&OsAbi::OpenBsd) => "unknown-openbsd",
+            Some(&OsAbi::Windows) => "pc-windows-gnu",
+            Some(&OsAbi::Darwin) => "apple-darwin",
+            Some(&OsAbi::None) => "unknown-none-elf",
+            Some(&OsAbi::Other(_)) | None => "unknown-unknown",
+        };
+        Some(format!("{}-{}", arch, os))
+    }
+}
+
+/// An OS ABI, as named by the `<osabi>` element of a target
+/// description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OsAbi {
+    /// `GNU/Linux`.
+    Linux,
+    /// `FreeBSD`.
+    FreeBsd,
+    /// `NetBSD`.
+    NetBsd,
+    /// `OpenBSD`.
+    OpenBsd,
+    /// `Windows` or `Cygwin`.
+    Windows,
+    /// `Darwin`.
+    Darwin,
+    /// `none`, for bare-metal targets.
+    None,
+    /// Any other OS ABI, holding its name.
+    Other(String),
+}
+
+impl OsAbi {
+    /// Return the `OsAbi` for the name `name`, as gdb spells it.
+    pub fn from_name(name: &str) -> OsAbi {
+        match name {
+            "GNU/Linux" => OsAbi::Linux,
+            "FreeBSD" => OsAbi::FreeBsd,
+            "NetBSD" => OsAbi::NetBsd,
+            "OpenBSD" => OsAbi::OpenBsd,
+            "Windows" | "Cygwin" => OsAbi::Windows,
+            "Darwin" => OsAbi::Darwin,
+            "none" => OsAbi::None,
+            _ => OsAbi::Other(name.to_string()),
+        }
+    }
+}
+
+// Return the text of the first <|name|> element in |xml|, unescaped
+// and with surrounding whitespace removed.
+fn element_text(xml: &[u8], name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.windows(open.len()).position(|w| w == open.as_bytes())? + open.len();
+    let rest = &xml[start..];
+    let end = rest.windows(close.len()).position(|w| w == close.as_bytes())?;
+    let text = String::from_utf8_lossy(&unescape_xml(&rest[..end])).into_owned();
+    Some(text.trim().to_string())
+}
+
+/// Return the architecture named by a `target.xml` document, as
+/// fetched by a client.  Returns `None` if it has no
+/// `<architecture>` element.  Descriptions that pull in other
+/// documents with `xi:include` may name it in one of those instead.
+pub fn parse_architecture(xml: &[u8]) -> Option<Arch> {
+    element_text(xml, "architecture").map(|name| Arch::from_name(&name))
+}
+
+/// Return the OS ABI named by a `target.xml` document, as fetched by
+/// a client.  Returns `None` if it has no `<osabi>` element.
+pub fn parse_osabi(xml: &[u8]) -> Option<OsAbi> {
+    element_text(xml, "osabi").map(|name| OsAbi::from_name(&name))
+}
+
+/// Return the `<flags>` types defined in a `target.xml` document, as
+/// fetched by a client, so that the fields of flags registers can be
+/// found; see `RegisterValue::field`.  A field without an `end` is a
+/// single bit.  Malformed types and fields are skipped, as are
+/// `<struct>` types.  As with `parse_architecture`, types defined in
+/// documents pulled in with `xi:include` are not found.
+pub fn parse_flags_types(xml: &[u8]) -> Vec<FlagsType> {
+    let number = |tag: &[u8], name: &[u8]| {
+        xml_attribute(tag, name).and_then(|value| {
+            ::std::str::from_utf8(&value).ok().and_then(|value| value.parse::<u32>().ok())
+        })
+    };
+    let mut result = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.windows(7).position(|w| w == b"<flags ") {
+        rest = &rest[start + 7..];
+        let end = rest.iter().position(|&c| c == b'>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        let close = rest.windows(8).position(|w| w == b"</flags>").unwrap_or(0);
+        let mut body = &rest[..close];
+        let (id, size) = match (xml_attribute(tag, b"id"), number(tag, b"size")) {
+            (Some(id), Some(size)) => (String::from_utf8_lossy(&id).into_owned(), size),
+            _ => continue,
+        };
+        let mut flags = FlagsType::new(&id, size);
+        while let Some(start) = body.windows(7).position(|w| w == b"<field ") {
+            body = &body[start + 7..];
+            let end = body.iter().position(|&c| c == b'>').unwrap_or(body.len());
+            let tag = &body[..end];
+            body = &body[end..];
+            let (name, start) = match (xml_attribute(tag, b"name"), number(tag, b"start")) {
+                (Some(name), Some(start)) => (String::from_utf8_lossy(&name).into_owned(), start),
+                _ => continue,
+            };
+            let end = number(tag, b"end").unwrap_or(start);
+            if end >= start {
+                flags.add_field(Field::new(&name, start, end));
+            }
+        }
+        result.push(flags);
+    }
+    result
+}
+
+/// Client-side tracking of the target description of each inferior.
+/// Inferiors need not share an architecture: for example, a 64-bit
+/// process may exec a 32-bit program.  The description is fetched
+/// when first needed, and fetched again after an exec.
+///
+/// The descriptions are kept as the raw `target.xml` text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InferiorDescriptions {
+    descriptions: BTreeMap<Id, Vec<u8>>,
+    packet_size: usize,
+}
+
+impl InferiorDescriptions {
+    /// Make a new, empty, `InferiorDescriptions`.  `packet_size` is
+    /// the maximum packet size reported by the stub's `qSupported`
+    /// reply.
+    pub fn new(packet_size: usize) -> InferiorDescriptions {
+        InferiorDescriptions { descriptions: BTreeMap::new(), packet_size }
+    }
+
+    /// Update from a stop reply.  An exec discards the description of
+    /// the process that did it, or of all processes if the reply does
+    /// not say which thread stopped; an exit discards the description
+    /// of the process that exited.
+    pub fn update(&mut self, reply: &StopReply) {
+        match *reply {
+            StopReply::Signal { ref values, .. } => {
+                let exec = values.iter().any(|value| matches!(*value, StopReplyValue::Exec(_)));
+                if !exec {
+                    return;
+                }
+                let thread = values.iter().find_map(|value| match *value {
+                    StopReplyValue::Thread(thread) => Some(thread),
+                    _ => None,
+                });
+                match thread {
+                    Some(ProcessId { pid: Id::Id(pid), .. }) => {
+                        self.descriptions.remove(&Id::Id(pid));
+                    }
+                    _ => self.descriptions.clear(),
+                }
+            }
+            StopReply::Exited { process: Some(process), .. }
+                | StopReply::Terminated { process: Some(process), .. } => {
+                self.descriptions.remove(&process.pid);
+            }
+            _ => { }
+        }
+    }
+
+    /// Return the target description of the process that `thread`
+    /// belongs to, fetching it first if needed.  Fetching selects
+    /// `thread` with `Hg`, since the stub describes the current
+    /// thread's process, and then restores the stub's selection; see
+    /// `with_thread`.  Returns `None` if the stub does not supply
+    /// target descriptions.
+    pub fn description(&mut self, conn: &mut RspConnection, thread: ProcessId)
+                       -> RspResult<Option<&[u8]>> {
+        if !self.descriptions.contains_key(&thread.pid) {
+            let packet_size = self.packet_size;
+            let xml = with_thread(conn, thread, |conn| {
+                read_qxfer_object(conn, b"features", b"target.xml", packet_size)
+            })?;
+            match xml {
+                Some(xml) => self.descriptions.insert(thread.pid, xml),
+                None => return Ok(None),
+            };
+        }
+        Ok(self.descriptions.get(&thread.pid).map(|xml| &xml[..]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_architecture, parse_flags_types, parse_osabi, Arch, Feature, Field, FlagsType,
+                InferiorDescriptions, OsAbi, Register, TargetDescription};
+    use low::{Id, ProcessId, RspConnection};
+    use stop::parse_stop_reply;
+    use util::frame_packets;
+
+    #[test]
+    fn registers() {
+        let mut feature = Feature::new("test");
+        feature.add_register(Register::new("a", 32, "int"));
+        let mut b = Register::new("b", 32, "int");
+        b.set_regnum(10);
+        feature.add_register(b);
+        feature.add_register(Register::new("c", 64, "int"));
+        let mut tdesc = TargetDescription::new();
+        tdesc.add_feature(feature);
+
+        let numbers: Vec<_> = tdesc.registers().iter()
+            .map(|&(regnum, reg)| (regnum, reg.name().to_string()))
+            .collect();
+        assert_eq!(numbers, vec![(0, "a".to_string()), (10, "b".to_string()),
+                                 (11, "c".to_string())]);
+    }
+
+    #[test]
+    fn to_xml() {
+        let mut core = Feature::new("org.gnu.gdb.riscv.cpu");
+        core.add_register(Register::new("zero", 32, "int"));
+        let mut pc = Register::new("pc", 32, "code_ptr");
+        pc.set_regnum(32);
+        pc.set_group("general");
+        core.add_register(pc);
+        let mut flags = FlagsType::new("fflags", 1);
+        flags.add_field(Field::new("NX", 0, 0));
+        flags.add_field(Field::new("rm", 5, 7));
+        core.add_flags(flags);
+
+        let mut tdesc = TargetDescription::new();
+        tdesc.set_architecture("riscv:rv32");
+        tdesc.set_osabi("<none>");
+        tdesc.add_feature(core);
+
+        assert_eq!(tdesc.to_xml(), "<?xml version=\"1.0\"?>
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">
+<target version=\"1.0\">
+  <architecture>riscv:rv32</architecture>
+  <osabi>&lt;none&gt;</osabi>
+  <feature name=\"org.gnu.gdb.riscv.cpu\">
+    <flags id=\"fflags\" size=\"1\">
+      <field name=\"NX\" start=\"0\" end=\"0\"/>
+      <field name=\"rm\" start=\"5\" end=\"7\"/>
+    </flags>
+    <reg name=\"zero\" bitsize=\"32\" type=\"int\"/>
+    <reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"32\" group=\"general\"/>
+  </feature>
+</target>
+");
+    }
+
+    #[test]
+    fn flags() {
+        let xml = b"<target><feature name=\"test\">\
+                    <flags id=\"fflags\" size=\"1\"><field name=\"NX\" start=\"0\" end=\"0\"/>\
+                    <field name=\"rm\" start=\"5\" end=\"7\"/></flags>\
+                    <flags id=\"odd\" size=\"4\"><field name=\"A&amp;B\" start=\"3\"/>\
+                    <field name=\"C\"/><field name=\"D\" start=\"4\" end=\"2\"/></flags>\
+                    <flags size=\"4\"></flags>\
+                    <reg name=\"fflags\" bitsize=\"8\" type=\"fflags\"/></feature></target>";
+        let types = parse_flags_types(xml);
+        assert_eq!(types.len(), 2);
+        let fflags = &types[0];
+        assert_eq!((fflags.id(), fflags.size()), ("fflags", 1));
+        assert_eq!(fflags.fields(), &[Field::new("NX", 0, 0), Field::new("rm", 5, 7)]);
+        assert_eq!(fflags.field("rm").unwrap().width(), 3);
+        // Fields with no start, or that end before they start, are
+        // skipped.
+        assert_eq!(types[1].fields(), &[Field::new("A&B", 3, 3)]);
+
+        let mut feature = Feature::new("test");
+        feature.add_flags(types[0].clone());
+        let mut tdesc = TargetDescription::new();
+        tdesc.add_feature(feature);
+        assert_eq!(tdesc.flags_type("fflags"), Some(&types[0]));
+        assert_eq!(tdesc.flags_type("int"), None);
+    }
+
+    #[test]
+    fn inferior_descriptions() {
+        let input = frame_packets(&[b"QCp1.2", b"OK", b"l<target>64</target>", b"OK", b"",
+                                    b"OK", b"l<target>32</target>", b"OK"]);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let thread = ProcessId { pid: Id::Id(1), tid: Id::Id(1) };
+            let mut descriptions = InferiorDescriptions::new(0x100);
+            assert_eq!(descriptions.description(&mut rsp, thread).unwrap(),
+                       Some(&b"<target>64</target>"[..]));
+            // Cached.
+            assert!(descriptions.description(&mut rsp, thread).unwrap().is_some());
+
+            descriptions.update(&parse_stop_reply(b"T05exec:2f62696e;thread:p1.1;").unwrap());
+            assert_eq!(descriptions.description(&mut rsp, thread).unwrap(),
+                       Some(&b"<target>32</target>"[..]));
+        }
+        let sent: &[&[u8]] = &[b"qC", b"Hgp1.1", b"qXfer:features:read:target.xml:0,ff", b"Hgp1.2",
+                               b"qC", b"Hgp1.1", b"qXfer:features:read:target.xml:0,ff", b"Hg0"];
+        assert_eq!(output, frame_packets(sent));
+    }
+
+    #[test]
+    fn arch() {
+        let xml = b"<?xml version=\"1.0\"?>\n<target version=\"1.0\">\n\
+                    <architecture> aarch64 </architecture>\n<osabi>GNU/Linux</osabi>\n</target>";
+        let arch = parse_architecture(xml).unwrap();
+        let osabi = parse_osabi(xml);
+        assert_eq!(arch, Arch::AArch64);
+        assert_eq!(osabi, Some(OsAbi::Linux));
+        assert_eq!(arch.triple(osabi.as_ref()), Some("aarch64-unknown-linux-gnu".to_string()));
+        assert_eq!(parse_osabi(b"<target/>"), None);
+
+        let mut tdesc = TargetDescription::new();
+        tdesc.set_architecture("riscv:rv32");
+        tdesc.set_osabi("none");
+        let xml = tdesc.to_xml();
+        assert_eq!(parse_architecture(xml.as_bytes()), Some(Arch::RiscV32));
+        assert_eq!(tdesc.arch().unwrap().triple(tdesc.os_abi().as_ref()),
+                   Some("riscv32-unknown-none-elf".to_string()));
+
+        assert_eq!(Arch::from_name("i386:x86-64").triple(None),
+                   Some("x86_64-unknown-unknown".to_string()));
+        assert_eq!(Arch::from_name("armv7"), Arch::Arm);
+        assert_eq!(Arch::from_name("mips:isa64r2"), Arch::Mips64);
+        assert_eq!(Arch::from_name("riscv"), Arch::Other("riscv".to_string()));
+        assert_eq!(Arch::from_name("riscv").triple(None), None);
+    }
+}
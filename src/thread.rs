@@ -0,0 +1,419 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeSet;
+
+use low::{check_reply, Id, ProcessId, RspConnection, RspError, RspResult};
+use stop::{parse_stop_reply, StopReply, StopReplyValue};
+use util::write_thread_id;
+
+/// Which operations an `H` packet selects a thread for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadOperation {
+    /// `Hg`: general operations, such as reading registers or memory.
+    General,
+    /// `Hc`: the legacy `c` and `s` resumption packets.
+    Continue,
+}
+
+/// Parse an `H` packet, for use by a server.  Returns the operation
+/// and the selected thread.  A thread id of `0` (any thread) is
+/// returned as `Id::Any`, and `-1` (all threads) as `Id::All`.
+pub fn parse_set_thread(packet: &[u8]) -> RspResult<(ThreadOperation, ProcessId)> {
+    let violation = |reason| RspError::ProtocolViolation { packet: packet.to_vec(), reason };
+    let operation = match packet.get(0..2) {
+        Some(b"Hg") => ThreadOperation::General,
+        Some(b"Hc") => ThreadOperation::Continue,
+        _ => return Err(violation("not an H packet")),
+    };
+    let thread = ProcessId::parse(&packet[2..]).ok_or_else(|| violation("invalid thread-id"))?;
+    Ok((operation, thread))
+}
+
+/// The threads currently selected by `H` packets, for use by a
+/// server.  Both selections start out as "any thread".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThreadSelection {
+    /// The thread selected by `Hg`.
+    pub general: ProcessId,
+    /// The thread selected by `Hc`.
+    pub resume: ProcessId,
+}
+
+impl Default for ThreadSelection {
+    fn default() -> ThreadSelection {
+        let any = ProcessId { pid: Id::Any, tid: Id::Any };
+        ThreadSelection { general: any, resume: any }
+    }
+}
+
+impl ThreadSelection {
+    /// Make a new `ThreadSelection`.
+    pub fn new() -> ThreadSelection {
+        ThreadSelection::default()
+    }
+
+    /// Update the selection from an `H` packet.  On success the
+    /// server should reply `OK`.
+    pub fn handle_packet(&mut self, packet: &[u8]) -> RspResult<()> {
+        let (operation, thread) = parse_set_thread(packet)?;
+        self.select(operation, thread);
+        Ok(())
+    }
+
+    /// Select `thread` for `operation`.
+    pub fn select(&mut self, operation: ThreadOperation, thread: ProcessId) {
+        match operation {
+            ThreadOperation::General => self.general = thread,
+            ThreadOperation::Continue => self.resume = thread,
+        }
+    }
+
+    /// Return the thread selected for `operation`.
+    pub fn selected(&self, operation: ThreadOperation) -> ProcessId {
+        match operation {
+            ThreadOperation::General => self.general,
+            ThreadOperation::Continue => self.resume,
+        }
+    }
+}
+
+fn parse_thread_info_inner(reply: &[u8]) -> Result<(Vec<ProcessId>, bool), &'static str> {
+    let (&kind, rest) = reply.split_first().ok_or("empty thread info reply")?;
+    let more = match kind {
+        b'm' => true,
+        b'l' => false,
+        _ => return Err("expected m or l"),
+    };
+    let threads = rest.split(|&c| c == b',')
+        .filter(|id| !id.is_empty())
+        .map(|id| ProcessId::parse(id).ok_or("invalid thread-id"))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((threads, more))
+}
+
+/// Parse a reply to `qfThreadInfo` or `qsThreadInfo`, for use by a
+/// client.  Returns the threads listed, and whether more may follow.
+pub fn parse_thread_info(reply: &[u8]) -> RspResult<(Vec<ProcessId>, bool)> {
+    parse_thread_info_inner(reply).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.to_vec(), reason }
+    })
+}
+
+/// A client-side cache of the inferior's threads.  The cache is
+/// filled from `qfThreadInfo` when it is stale, and is otherwise kept
+/// up to date from stop replies, so that the thread list can be
+/// queried without a round trip after every stop.
+///
+/// Thread creation is only reported when `QThreadEvents` is enabled.
+/// Without it, callers should call `mark_stale` whenever the thread
+/// list may have changed, for example after each stop.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadCache {
+    threads: BTreeSet<ProcessId>,
+    stale: bool,
+}
+
+impl Default for ThreadCache {
+    fn default() -> ThreadCache {
+        ThreadCache { threads: BTreeSet::new(), stale: true }
+    }
+}
+
+impl ThreadCache {
+    /// Make a new, stale, `ThreadCache`.
+    pub fn new() -> ThreadCache {
+        ThreadCache::default()
+    }
+
+    /// Return true if the cache must be refreshed before use.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Mark the cache as stale, so that it is refreshed on next use.
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// Return the cached threads, without refreshing them.
+    pub fn cached(&self) -> &BTreeSet<ProcessId> {
+        &self.threads
+    }
+
+    /// Return the threads, first refreshing the cache using `conn` if
+    /// it is stale.
+    pub fn threads(&mut self, conn: &mut RspConnection) -> RspResult<&BTreeSet<ProcessId>> {
+        if self.stale {
+            self.refresh(conn)?;
+        }
+        Ok(&self.threads)
+    }
+
+    /// Refill the cache using `qfThreadInfo` and `qsThreadInfo`.
+    pub fn refresh(&mut self, conn: &mut RspConnection) -> RspResult<()> {
+        let mut threads = BTreeSet::new();
+        let mut packet: &[u8] = b"qfThreadInfo";
+        loop {
+            let (list, more) = parse_thread_info(&conn.request(packet)?)?;
+            threads.extend(list);
+            if !more {
+                break;
+            }
+            packet = b"qsThreadInfo";
+        }
+        self.threads = threads;
+        self.stale = false;
+        Ok(())
+    }
+
+    /// Update the cache from a stop reply.
+    pub fn update(&mut self, reply: &StopReply) {
+        match *reply {
+            StopReply::Signal { ref values, .. } => {
+                for value in values {
+                    match *value {
+                        // The reporting thread, which for a "create"
+                        // stop is the new thread.
+                        StopReplyValue::Thread(thread) | StopReplyValue::Clone(thread) => {
+                            self.threads.insert(thread);
+                        }
+                        StopReplyValue::Fork(_) | StopReplyValue::VFork(_)
+                            | StopReplyValue::Exec(_) => self.stale = true,
+                        _ => { }
+                    }
+                }
+            }
+            StopReply::ThreadExited { thread, .. } => {
+                self.threads.retain(|t| !thread.matches(t));
+            }
+            StopReply::Exited { process, .. } | StopReply::Terminated { process, .. } => {
+                match process {
+                    Some(process) => self.threads.retain(|t| t.pid != process.pid),
+                    None => self.threads.clear(),
+                }
+            }
+            StopReply::NoResumed => { }
+        }
+    }
+}
+
+/// The state of a thread in non-stop mode, as reported by
+/// `thread_states`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ThreadState {
+    /// The thread is running.
+    Running,
+    /// The thread is stopped.  This holds the stop reply that says
+    /// why.
+    Stopped(StopReply),
+}
+
+/// Find the state of every thread, for use by a client in non-stop
+/// mode, for example to fill in a thread list after connecting.  This
+/// lists the threads with `qfThreadInfo`, then sends `?` and drains
+/// the rest of the stop replies with `vStopped`.  Threads with a stop
+/// reply are stopped and the rest are running.
+///
+/// Threads are returned in the order the stub listed them; a thread
+/// that stopped but was not listed, such as one that has just exited,
+/// comes after those.  Stop replies that name no thread are left out.
+/// Sending `?` makes the stub report every stopped thread again, so
+/// stop notifications that are still queued on `conn` may repeat
+/// what this returns.
+pub fn thread_states(conn: &mut RspConnection) -> RspResult<Vec<(ProcessId, ThreadState)>> {
+    let mut states = Vec::new();
+    let mut packet: &[u8] = b"qfThreadInfo";
+    loop {
+        let (list, more) = parse_thread_info(&conn.request(packet)?)?;
+        states.extend(list.into_iter().map(|thread| (thread, ThreadState::Running)));
+        if !more {
+            break;
+        }
+        packet = b"qsThreadInfo";
+    }
+
+    let mut reply = conn.request(b"?")?;
+    while reply != b"OK" {
+        let stop = parse_stop_reply(&reply)?;
+        if let Some(thread) = stop.thread() {
+            match states.iter_mut().find(|(t, _)| *t == thread) {
+                // Keep the first reply, should a stub send two.
+                Some(&mut (_, ref mut state)) => if *state == ThreadState::Running {
+                    *state = ThreadState::Stopped(stop);
+                },
+                None => states.push((thread, ThreadState::Stopped(stop))),
+            }
+        }
+        reply = conn.request(b"vStopped")?;
+    }
+    Ok(states)
+}
+
+/// Select `thread` for memory and register operations with `Hg`, for
+/// use by a client.  An error reply results in `RspError::Remote`.
+pub fn select_thread(conn: &mut RspConnection, thread: ProcessId) -> RspResult<()> {
+    let mut packet = b"Hg".to_vec();
+    write_thread_id(&mut packet, thread);
+    let reply = check_reply(conn.request(&packet)?)?;
+    if reply != b"OK" {
+        return Err(RspError::ProtocolViolation {
+            packet: reply,
+            reason: "could not select thread",
+        });
+    }
+    Ok(())
+}
+
+/// Ask the stub which thread is selected, with `qC`, for use by a
+/// client.  Returns `None` if the stub does not support `qC`.
+pub fn current_thread(conn: &mut RspConnection) -> RspResult<Option<ProcessId>> {
+    let reply = check_reply(conn.request(b"qC")?)?;
+    if reply.is_empty() {
+        return Ok(None);
+    }
+    match if reply.starts_with(b"QC") { ProcessId::parse(&reply[2..]) } else { None } {
+        Some(thread) => Ok(Some(thread)),
+        None => Err(RspError::ProtocolViolation {
+            packet: reply,
+            reason: "invalid qC reply",
+        }),
+    }
+}
+
+/// Run `f` with `thread` selected by `Hg`, for use by a client, then
+/// select the thread that `qC` reported beforehand.  If the stub does
+/// not support `qC`, it is told to use any thread afterward, as it
+/// does before a thread is first selected.  The thread is restored
+/// even if `f` fails; an error from `f` takes precedence over one from
+/// restoring it.
+pub fn with_thread<'conn, T, F>(conn: &mut RspConnection<'conn>, thread: ProcessId, f: F)
+                                -> RspResult<T>
+    where F: FnOnce(&mut RspConnection<'conn>) -> RspResult<T>
+{
+    let previous = current_thread(conn)?;
+    if previous == Some(thread) {
+        return f(conn);
+    }
+    select_thread(conn, thread)?;
+    let result = f(conn);
+    let restored = select_thread(conn, previous.unwrap_or_else(ProcessId::any));
+    let value = result?;
+    restored?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_set_thread, parse_thread_info, thread_states, with_thread, ThreadCache,
+                ThreadOperation, ThreadSelection, ThreadState};
+    use low::{check_reply, Id, ProcessId, RspConnection, RspError};
+    use stop::parse_stop_reply;
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn set_thread() {
+        assert_eq!(parse_set_thread(b"Hgp10.11").unwrap(),
+                   (ThreadOperation::General,
+                    ProcessId { pid: Id::Id(0x10), tid: Id::Id(0x11) }));
+        assert_eq!(parse_set_thread(b"Hc-1").unwrap(),
+                   (ThreadOperation::Continue, ProcessId { pid: Id::Any, tid: Id::All }));
+        assert_eq!(parse_set_thread(b"Hg0").unwrap(),
+                   (ThreadOperation::General, ProcessId { pid: Id::Any, tid: Id::Any }));
+        assert!(parse_set_thread(b"Hx1").is_err());
+        assert!(parse_set_thread(b"Hg").is_err());
+    }
+
+    #[test]
+    fn selection() {
+        let mut selection = ThreadSelection::new();
+        selection.handle_packet(b"Hgp1.2").unwrap();
+        selection.handle_packet(b"Hcp1.-1").unwrap();
+        assert!(selection.handle_packet(b"Hq").is_err());
+        assert_eq!(selection.selected(ThreadOperation::General),
+                   ProcessId { pid: Id::Id(1), tid: Id::Id(2) });
+        assert_eq!(selection.selected(ThreadOperation::Continue),
+                   ProcessId { pid: Id::Id(1), tid: Id::All });
+    }
+
+    #[test]
+    fn thread_info() {
+        let thread = |pid, tid| ProcessId { pid: Id::Id(pid), tid: Id::Id(tid) };
+        assert_eq!(parse_thread_info(b"mp1.1,p1.2").unwrap(),
+                   (vec![thread(1, 1), thread(1, 2)], true));
+        assert_eq!(parse_thread_info(b"l").unwrap(), (vec![], false));
+        assert_eq!(parse_thread_info(b"lpA.1F").unwrap(), (vec![thread(10, 0x1f)], false));
+        assert!(parse_thread_info(b"mp1.x").is_err());
+        assert!(parse_thread_info(b"").is_err());
+    }
+
+    #[test]
+    fn thread_cache() {
+        let thread = |pid, tid| ProcessId { pid: Id::Id(pid), tid: Id::Id(tid) };
+        let mut input = frame_packet(b"mp1.1,p1.2");
+        input.extend(frame_packet(b"l"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+
+        let mut cache = ThreadCache::new();
+        assert_eq!(cache.threads(&mut rsp).unwrap().len(), 2);
+        assert!(!cache.is_stale());
+
+        cache.update(&parse_stop_reply(b"T05create:;thread:p1.3;").unwrap());
+        cache.update(&parse_stop_reply(b"w00;p1.1").unwrap());
+        let expected = vec![thread(1, 2), thread(1, 3)];
+        assert_eq!(cache.threads(&mut rsp).unwrap().iter().cloned().collect::<Vec<_>>(),
+                   expected);
+
+        cache.update(&parse_stop_reply(b"W00;process:1").unwrap());
+        assert!(cache.cached().is_empty());
+        cache.update(&parse_stop_reply(b"T05fork:p2.2;thread:p1.2;").unwrap());
+        assert!(cache.is_stale());
+    }
+
+    #[test]
+    fn states() {
+        let replies: &[&[u8]] = &[b"mp1.1,p1.2", b"l", b"T05thread:p1.2;", b"w00;p1.3", b"OK"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let states = {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            thread_states(&mut rsp).unwrap()
+        };
+        let sent: &[&[u8]] = &[b"qfThreadInfo", b"qsThreadInfo", b"?", b"vStopped", b"vStopped"];
+        assert_eq!(output, frame_packets(sent));
+
+        let thread = |tid| ProcessId { pid: Id::Id(1), tid: Id::Id(tid) };
+        assert_eq!(states, vec![
+            (thread(1), ThreadState::Running),
+            (thread(2), ThreadState::Stopped(parse_stop_reply(b"T05thread:p1.2;").unwrap())),
+            (thread(3), ThreadState::Stopped(parse_stop_reply(b"w00;p1.3").unwrap())),
+        ]);
+    }
+
+    #[test]
+    fn select_and_restore() {
+        let replies: &[&[u8]] = &[b"QCp1.1", b"OK", b"E01", b"OK", b"QCp1.2", b"1234"];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let thread = ProcessId { pid: Id::Id(1), tid: Id::Id(2) };
+            // The thread is restored even if the operation fails.
+            match with_thread(&mut rsp, thread, |conn| check_reply(conn.request(b"m0,2")?)) {
+                Err(RspError::Remote(_)) => { }
+                result => panic!("unexpected result {:?}", result),
+            }
+            // Nothing is sent when the thread is already selected.
+            assert_eq!(with_thread(&mut rsp, thread, |conn| conn.request(b"m0,2")).unwrap(),
+                       b"1234");
+        }
+        let sent: &[&[u8]] = &[b"qC", b"Hgp1.2", b"m0,2", b"Hgp1.1", b"qC", b"m0,2"];
+        assert_eq!(output, frame_packets(sent));
+    }
+}
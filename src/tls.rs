@@ -0,0 +1,142 @@
+#![deny(missing_docs)]
+
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::pki_types::pem::PemObject;
+
+use low::RspConnection;
+use transport::Transport;
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// One direction of a TLS stream.  RspConnection wants separate
+// reader and writer objects, but a TLS session can't be split, so
+// both halves share it.
+struct TlsHalf(Arc<Mutex<TlsStream>>);
+
+impl TlsHalf {
+    fn lock(&self) -> MutexGuard<'_, TlsStream> {
+        // A panic while holding the lock can't leave the stream in a
+        // state that is worse than any other I/O error.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Read for TlsHalf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl Write for TlsHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+fn to_io_error(err: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Build a TLS client configuration that trusts the certificate
+/// authorities found in `pem`, which holds one or more PEM-encoded
+/// certificates.  For more control, for example to present a client
+/// certificate, build a `rustls::ClientConfig` directly.
+pub fn tls_client_config(pem: &[u8]) -> io::Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(pem) {
+        let cert = cert.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        roots.add(cert).map_err(to_io_error)?;
+    }
+    if roots.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no certificates found"));
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(to_io_error)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// An RSP channel tunnelled over TLS, for remote setups that put the
+/// stub behind a TLS endpoint.  This is only available when the `tls`
+/// feature is enabled.  The protocol itself is unchanged; only the
+/// transport differs.
+///
+/// Since the TLS session is shared by both directions, the halves of
+/// a split connection (see `RspConnection::split`) cannot write while
+/// a read is blocked.
+pub struct TlsChannel {
+    reader: TlsHalf,
+    writer: TlsHalf,
+}
+
+impl TlsChannel {
+    /// Connect to `addr` over TCP and start a TLS session with it.
+    /// `server_name` is the name checked against the server's
+    /// certificate, and `config` is the TLS configuration, for example
+    /// from `tls_client_config`.
+    pub fn connect<A: ToSocketAddrs>(addr: A, server_name: &str, config: Arc<ClientConfig>)
+                                     -> io::Result<TlsChannel> {
+        let name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let session = ClientConnection::new(config, name).map_err(to_io_error)?;
+        let mut stream = StreamOwned::new(session, TcpStream::connect(addr)?);
+        // Complete the handshake now, so that certificate errors are
+        // reported here rather than on the first packet.
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        let shared = Arc::new(Mutex::new(stream));
+        Ok(TlsChannel { reader: TlsHalf(shared.clone()), writer: TlsHalf(shared) })
+    }
+
+    /// Make an `RspConnection` that uses this channel.  `is_client` is
+    /// as for `RspConnection::new`.
+    pub fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        RspConnection::new(&mut self.reader, &mut self.writer, is_client)
+    }
+}
+
+impl Transport for TlsChannel {
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        TlsChannel::connection(self, is_client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::tls_client_config;
+
+    const CA: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIBjDCCATGgAwIBAgIUIa7l0EPdP711wGbOjr3Q/MolKWAwCgYIKoZIzj0EAwIw
+GjEYMBYGA1UEAwwPZ2RiLXJzcCB0ZXN0IENBMCAXDTI2MTAxNzAzMjM1MFoYDzIx
+MjYwOTIzMDMyMzUwWjAaMRgwFgYDVQQDDA9nZGItcnNwIHRlc3QgQ0EwWTATBgcq
+hkjOPQIBBggqhkjOPQMBBwNCAAQH3PQpIdOHphPi0eyN33hO8bpbV3IDiq79t1vi
+K7E+rJq+82WwUYZO444qQy4zunRpF6T/riFOxbh4qY1HTGY6o1MwUTAdBgNVHQ4E
+FgQUBl5Mhs3hQJD5YRacSLHtVLJ1OqAwHwYDVR0jBBgwFoAUBl5Mhs3hQJD5YRac
+SLHtVLJ1OqAwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNJADBGAiEAssyF
+HuIh5xKntKzl8L5dMrzcnW53dlWb/BKb3ympg4ACIQDxz7OU82XJlagjGcKrS5q3
+UiVRmb98vOuteKM0PxA/Zg==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn client_config() {
+        assert!(tls_client_config(CA).is_ok());
+        assert!(tls_client_config(b"").is_err());
+        assert!(tls_client_config(b"not a certificate").is_err());
+    }
+}
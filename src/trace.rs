@@ -0,0 +1,417 @@
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+
+use low::{check_reply, RspConnection, RspError, RspResult};
+use util::{decode_hex_bytes, parse_hex_number};
+
+/// The kind of a tracepoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TracepointKind {
+    /// An ordinary tracepoint, implemented with a trap.
+    Normal,
+    /// A fast tracepoint, implemented with a jump.  This holds the
+    /// length of the instruction that the jump replaced.
+    Fast(u64),
+    /// A static tracepoint, at a marker compiled into the program.
+    Static,
+}
+
+/// Part of the source form of a tracepoint, as the user wrote it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TracepointSource {
+    /// What this is: `at` for the location, `cond` for the condition,
+    /// or `cmd` for one line of the actions.
+    pub source_type: Vec<u8>,
+    /// The text.
+    pub text: Vec<u8>,
+}
+
+/// A tracepoint defined on the target, as uploaded with `qTfP` and
+/// `qTsP`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadedTracepoint {
+    /// The tracepoint number.
+    pub number: u64,
+    /// The address.
+    pub address: u64,
+    /// Whether the tracepoint is enabled.
+    pub enabled: bool,
+    /// The number of steps to collect data for after hitting the
+    /// tracepoint.
+    pub step_count: u64,
+    /// The number of hits after which tracing stops, or 0 for no
+    /// limit.
+    pub pass_count: u64,
+    /// The kind of tracepoint.
+    pub kind: TracepointKind,
+    /// The condition, as agent expression bytecode, if there is one.
+    pub condition: Option<Vec<u8>>,
+    /// The actions, in the form sent with `QTDP`.
+    pub actions: Vec<Vec<u8>>,
+    /// The actions performed while stepping.
+    pub step_actions: Vec<Vec<u8>>,
+    /// The source form, if the client that created the tracepoint
+    /// sent it with `QTDPsrc`.
+    pub sources: Vec<TracepointSource>,
+}
+
+// Split |text| at the first colon, returning the parts before and
+// after it.
+fn split_colon(text: &[u8]) -> Result<(&[u8], &[u8]), &'static str> {
+    let colon = text.iter().position(|&c| c == b':').ok_or("missing ':'")?;
+    Ok((&text[..colon], &text[colon + 1..]))
+}
+
+// Parse the optional fields of a "T" piece, after the pass count,
+// into |tracepoint|.
+fn parse_tracepoint_options(tracepoint: &mut UploadedTracepoint, options: &[u8])
+                            -> Result<(), &'static str> {
+    let mut rest = options;
+    while let Some((&c, tail)) = rest.split_first() {
+        match c {
+            b'F' | b'S' => {
+                let end = tail.iter().position(|&c| c == b':').unwrap_or(tail.len());
+                tracepoint.kind = if c == b'S' {
+                    TracepointKind::Static
+                } else {
+                    TracepointKind::Fast(parse_hex_number(&tail[..end]).ok_or("invalid length")?)
+                };
+                rest = &tail[end..];
+            }
+            b'X' => {
+                let comma = tail.iter().position(|&c| c == b',').ok_or("missing ','")?;
+                let length = parse_hex_number(&tail[..comma]).ok_or("invalid length")? as usize;
+                let bytecode = &tail[comma + 1..];
+                let hex_length = length.checked_mul(2).filter(|&n| n <= bytecode.len())
+                    .ok_or("condition too short")?;
+                tracepoint.condition = Some(decode_hex_bytes(&bytecode[..hex_length])
+                                            .ok_or("invalid condition")?);
+                rest = &bytecode[hex_length..];
+            }
+            b':' => rest = tail,
+            _ => return Err("unknown tracepoint option"),
+        }
+    }
+    Ok(())
+}
+
+// Apply one piece of a tracepoint upload to |tracepoints|.  Pieces
+// other than "T" add to a tracepoint defined by an earlier "T" piece.
+fn apply_tracepoint_piece(tracepoints: &mut Vec<UploadedTracepoint>, piece: &[u8])
+                          -> Result<(), &'static str> {
+    let (&kind, rest) = piece.split_first().ok_or("empty tracepoint piece")?;
+    let (number, rest) = split_colon(rest)?;
+    let number = parse_hex_number(number).ok_or("invalid tracepoint number")?;
+    let (address, rest) = split_colon(rest)?;
+    let address = parse_hex_number(address).ok_or("invalid tracepoint address")?;
+
+    if kind == b'T' {
+        let (enabled, rest) = split_colon(rest)?;
+        let enabled = match enabled {
+            b"E" => true,
+            b"D" => false,
+            _ => return Err("invalid enabled flag"),
+        };
+        let (step_count, rest) = split_colon(rest)?;
+        let step_count = parse_hex_number(step_count).ok_or("invalid step count")?;
+        let end = rest.iter().position(|&c| c == b':').unwrap_or(rest.len());
+        let pass_count = parse_hex_number(&rest[..end]).ok_or("invalid pass count")?;
+        let mut tracepoint = UploadedTracepoint {
+            number,
+            address,
+            enabled,
+            step_count,
+            pass_count,
+            kind: TracepointKind::Normal,
+            condition: None,
+            actions: Vec::new(),
+            step_actions: Vec::new(),
+            sources: Vec::new(),
+        };
+        parse_tracepoint_options(&mut tracepoint, &rest[end..])?;
+        tracepoints.push(tracepoint);
+        return Ok(());
+    }
+
+    let tracepoint = tracepoints.iter_mut()
+        .find(|tracepoint| tracepoint.number == number && tracepoint.address == address)
+        .ok_or("piece for unknown tracepoint")?;
+    match kind {
+        b'A' => tracepoint.actions.push(rest.to_vec()),
+        b'S' => tracepoint.step_actions.push(rest.to_vec()),
+        b'Z' => {
+            // The sequence number and length are implied by the order
+            // of the pieces and the text itself.
+            let (source_type, rest) = split_colon(rest)?;
+            let (_, rest) = split_colon(rest)?;
+            let (_, text) = split_colon(rest)?;
+            tracepoint.sources.push(TracepointSource {
+                source_type: source_type.to_vec(),
+                text: decode_hex_bytes(text).ok_or("invalid source text")?,
+            });
+        }
+        _ => return Err("unknown tracepoint piece"),
+    }
+    Ok(())
+}
+
+// Send |first|, then |next| until the reply is "l", passing each
+// other reply to |handle|.  Returns false if the stub does not
+// support |first|.
+fn upload<F>(conn: &mut RspConnection, first: &[u8], next: &[u8], mut handle: F)
+             -> RspResult<bool>
+    where F: FnMut(&[u8]) -> Result<(), &'static str>
+{
+    let mut reply = check_reply(conn.request(first)?)?;
+    if reply.is_empty() {
+        return Ok(false);
+    }
+    while reply != b"l" {
+        if let Err(reason) = handle(&reply) {
+            return Err(RspError::ProtocolViolation { packet: reply, reason });
+        }
+        reply = check_reply(conn.request(next)?)?;
+    }
+    Ok(true)
+}
+
+/// Download the tracepoints defined on the target with `qTfP` and
+/// `qTsP`, for use by a client that reconnects to a target during a
+/// trace run.  Returns an empty list if the stub does not support
+/// tracepoints.
+pub fn upload_tracepoints(conn: &mut RspConnection) -> RspResult<Vec<UploadedTracepoint>> {
+    let mut tracepoints = Vec::new();
+    upload(conn, b"qTfP", b"qTsP", |piece| apply_tracepoint_piece(&mut tracepoints, piece))?;
+    Ok(tracepoints)
+}
+
+/// A trace state variable, as uploaded with `qTfV` and `qTsV`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStateVariable {
+    /// The variable number.
+    pub number: u64,
+    /// The initial value.
+    pub initial_value: i64,
+    /// Whether the variable is built into the stub, such as
+    /// `trace_timestamp`.
+    pub builtin: bool,
+    /// The name, without the leading `$`.
+    pub name: Vec<u8>,
+}
+
+fn parse_trace_state_variable_inner(reply: &[u8]) -> Result<TraceStateVariable, &'static str> {
+    let (number, rest) = split_colon(reply)?;
+    let (initial_value, rest) = split_colon(rest)?;
+    let (builtin, name) = split_colon(rest)?;
+    Ok(TraceStateVariable {
+        number: parse_hex_number(number).ok_or("invalid variable number")?,
+        // The value is sent as its 64-bit two's complement.
+        initial_value: parse_hex_number(initial_value).ok_or("invalid initial value")? as i64,
+        builtin: parse_hex_number(builtin).ok_or("invalid builtin flag")? != 0,
+        name: decode_hex_bytes(name).ok_or("invalid name")?,
+    })
+}
+
+/// Parse one reply to `qTfV` or `qTsV`, for use by a client.
+pub fn parse_trace_state_variable(reply: &[u8]) -> RspResult<TraceStateVariable> {
+    parse_trace_state_variable_inner(reply).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.to_vec(), reason }
+    })
+}
+
+/// Download the trace state variables defined on the target with
+/// `qTfV` and `qTsV`.  Returns an empty list if the stub does not
+/// support tracepoints.
+pub fn upload_trace_state_variables(conn: &mut RspConnection)
+                                    -> RspResult<Vec<TraceStateVariable>> {
+    let mut variables = Vec::new();
+    upload(conn, b"qTfV", b"qTsV", |reply| {
+        variables.push(parse_trace_state_variable_inner(reply)?);
+        Ok(())
+    })?;
+    Ok(variables)
+}
+
+/// Ask the stub, with `QTDisconnected`, to keep a trace experiment
+/// running if the client disconnects, or not.  A client that wants
+/// this should then end its session without detaching; see
+/// `DebugSession::set_disconnected_tracing`.
+pub fn set_disconnected_tracing(conn: &mut RspConnection, enabled: bool) -> RspResult<()> {
+    let packet: &[u8] = if enabled { b"QTDisconnected:1" } else { b"QTDisconnected:0" };
+    let reply = check_reply(conn.request(packet)?)?;
+    if reply != b"OK" {
+        return Err(RspError::ProtocolViolation {
+            packet: reply,
+            reason: "QTDisconnected failed",
+        });
+    }
+    Ok(())
+}
+
+/// The state of the trace experiment, as reported by `qTStatus`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceStatus {
+    /// Whether a trace experiment is running.
+    pub running: bool,
+    /// Why the last experiment stopped, if it did: the name of the
+    /// reason, such as `tstop`, `tfull`, or `tdisconnected`, and its
+    /// raw value.
+    pub stop_reason: Option<(Vec<u8>, Vec<u8>)>,
+    /// The number of trace frames collected, if reported.
+    pub frames: Option<u64>,
+    /// Whether the experiment continues if the client disconnects.
+    pub disconnected_tracing: bool,
+    /// Whether the trace buffer is circular.
+    pub circular: bool,
+    /// Any other fields, by name, with their raw values.
+    pub other: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+// The stop reasons that qTStatus may report.
+const STOP_REASONS: &[&[u8]] = &[b"tnotrun", b"tstop", b"tfull", b"tdisconnected",
+                                 b"tpasscount", b"terror", b"tunknown"];
+
+fn parse_trace_status_inner(reply: &[u8]) -> Result<TraceStatus, &'static str> {
+    let mut fields = reply.split(|&c| c == b';');
+    let running = match fields.next() {
+        Some(b"T1") => true,
+        Some(b"T0") => false,
+        _ => return Err("invalid running flag"),
+    };
+    let mut status = TraceStatus { running, ..TraceStatus::default() };
+    for field in fields.filter(|field| !field.is_empty()) {
+        let (name, value) = split_colon(field)?;
+        match name {
+            b"tframes" => status.frames = Some(parse_hex_number(value).ok_or("invalid tframes")?),
+            b"disconn" => status.disconnected_tracing = value != b"0",
+            b"circular" => status.circular = value != b"0",
+            _ if STOP_REASONS.contains(&name) => {
+                status.stop_reason = Some((name.to_vec(), value.to_vec()));
+            }
+            _ => {
+                status.other.insert(name.to_vec(), value.to_vec());
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Parse the reply to `qTStatus`, for use by a client.
+pub fn parse_trace_status(reply: &[u8]) -> RspResult<TraceStatus> {
+    let reply = check_reply(reply.to_vec())?;
+    parse_trace_status_inner(&reply).map_err(|reason| {
+        RspError::ProtocolViolation { packet: reply.clone(), reason }
+    })
+}
+
+/// Ask for the state of the trace experiment with `qTStatus`, for
+/// example to see whether one kept running while the client was
+/// disconnected.  Returns `None` if the stub does not support
+/// tracepoints.
+pub fn trace_status(conn: &mut RspConnection) -> RspResult<Option<TraceStatus>> {
+    let reply = conn.request(b"qTStatus")?;
+    if reply.is_empty() {
+        return Ok(None);
+    }
+    parse_trace_status(&reply).map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_trace_state_variable, parse_trace_status, set_disconnected_tracing,
+                trace_status, upload_trace_state_variables, upload_tracepoints,
+                TraceStateVariable, TracepointKind, TracepointSource};
+    use low::RspConnection;
+    use util::{frame_packet, frame_packets};
+
+    #[test]
+    fn tracepoints() {
+        let replies: &[&[u8]] = &[b"T1:401000:E:0:0:X3,220000", b"A1:401000:R03",
+                                  b"S1:401000:M2000,4", b"Z1:401000:at:0:6:2a3430313030",
+                                  b"T2:402000:D:1:a:F5", b"l", b""];
+        let input = frame_packets(replies);
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        let mut rsp = RspConnection::new(&mut input, &mut output, true);
+        rsp.disable_acking();
+
+        let tracepoints = upload_tracepoints(&mut rsp).unwrap();
+        assert_eq!(tracepoints.len(), 2);
+        assert_eq!(tracepoints[0].address, 0x401000);
+        assert!(tracepoints[0].enabled);
+        assert_eq!(tracepoints[0].condition, Some(vec![0x22, 0, 0]));
+        assert_eq!(tracepoints[0].actions, vec![b"R03".to_vec()]);
+        assert_eq!(tracepoints[0].step_actions, vec![b"M2000,4".to_vec()]);
+        assert_eq!(tracepoints[0].sources,
+                   vec![TracepointSource { source_type: b"at".to_vec(),
+                                           text: b"*40100".to_vec() }]);
+        assert_eq!(tracepoints[1].kind, TracepointKind::Fast(5));
+        assert_eq!((tracepoints[1].enabled, tracepoints[1].step_count, tracepoints[1].pass_count),
+                   (false, 1, 10));
+
+        // Not supported.
+        assert_eq!(upload_tracepoints(&mut rsp).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn state_variables() {
+        assert_eq!(parse_trace_state_variable(b"1:ffffffffffffffff:0:6d79766172").unwrap(),
+                   TraceStateVariable {
+                       number: 1,
+                       initial_value: -1,
+                       builtin: false,
+                       name: b"myvar".to_vec(),
+                   });
+        assert!(parse_trace_state_variable(b"1:0:0").is_err());
+
+        let mut input = frame_packet(b"1:0:1:74726163655f74696d657374616d70");
+        input.extend(frame_packet(b"l"));
+        input.extend(frame_packet(b"E01"));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            let variables = upload_trace_state_variables(&mut rsp).unwrap();
+            assert_eq!(variables.len(), 1);
+            assert!(variables[0].builtin);
+            assert_eq!(variables[0].name, b"trace_timestamp".to_vec());
+            assert!(upload_trace_state_variables(&mut rsp).is_err());
+        }
+        let mut expected = frame_packet(b"qTfV");
+        expected.extend(frame_packet(b"qTsV"));
+        expected.extend(frame_packet(b"qTfV"));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn status() {
+        let status = parse_trace_status(b"T0;tstop::0;tframes:10;tcreated:12;disconn:1;circular:0")
+            .unwrap();
+        assert!(!status.running);
+        assert_eq!(status.stop_reason, Some((b"tstop".to_vec(), b":0".to_vec())));
+        assert_eq!(status.frames, Some(0x10));
+        assert!(status.disconnected_tracing && !status.circular);
+        assert_eq!(status.other.get(&b"tcreated"[..]), Some(&b"12".to_vec()));
+        assert!(parse_trace_status(b"T2").is_err());
+
+        let mut input = frame_packet(b"OK");
+        input.extend(frame_packet(b"T1;tnotrun:0;disconn:1"));
+        input.extend(frame_packet(b""));
+        let mut input: &[u8] = &input;
+        let mut output = Vec::new();
+        {
+            let mut rsp = RspConnection::new(&mut input, &mut output, true);
+            rsp.disable_acking();
+            set_disconnected_tracing(&mut rsp, true).unwrap();
+            let status = trace_status(&mut rsp).unwrap().unwrap();
+            assert!(status.running && status.disconnected_tracing);
+            assert_eq!(trace_status(&mut rsp).unwrap(), None);
+        }
+        let mut expected = frame_packet(b"QTDisconnected:1");
+        expected.extend(frame_packet(b"qTStatus"));
+        expected.extend(frame_packet(b"qTStatus"));
+        assert_eq!(output, expected);
+    }
+}
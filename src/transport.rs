@@ -0,0 +1,233 @@
+#![deny(missing_docs)]
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use low::RspConnection;
+use tcp::TcpChannel;
+
+/// A channel over which RSP connections can be made.  Each of the
+/// channel types in this crate implements this, so that code can be
+/// written without caring how the other side is reached.
+pub trait Transport {
+    /// Make an `RspConnection` that uses this channel.  `is_client` is
+    /// as for `RspConnection::new`.
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_>;
+}
+
+/// Connect to a stub listening on `addr`, for example
+/// `"localhost:1234"`, as gdb's `target remote` does.  The returned
+/// channel has Nagle's algorithm disabled; see `TcpChannel`.
+pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpChannel> {
+    TcpChannel::connect(addr)
+}
+
+/// Listen on `addr` and wait for a single connection, as gdbserver
+/// does, or as a debugger does in a reverse setup.  Returns the
+/// channel and the address of the peer.  To accept more than one
+/// connection, or to learn the port before waiting, bind a
+/// `TcpListener` directly and use `TcpChannel::accept`.
+pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<(TcpChannel, SocketAddr)> {
+    TcpChannel::accept(&TcpListener::bind(addr)?)
+}
+
+// One direction of an in-memory pipe.
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    // True once the writing end has been dropped.
+    closed: bool,
+    // How long a read may wait, as set by the reading end's
+    // connection.
+    read_timeout: Option<Duration>,
+}
+
+#[derive(Default)]
+struct SharedBuffer {
+    buffer: Mutex<Buffer>,
+    ready: Condvar,
+}
+
+impl SharedBuffer {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// The reading end of one direction of an in-memory pipe.  Reads block
+// until data arrives, or until the read timeout runs out, and return
+// end of file once the writer is gone.
+struct MemoryReader(Arc<SharedBuffer>);
+
+impl Read for MemoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.0.lock();
+        let deadline = buffer.read_timeout.map(|timeout| Instant::now() + timeout);
+        while buffer.data.is_empty() && !buffer.closed {
+            buffer = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::from(io::ErrorKind::TimedOut));
+                    }
+                    self.0.ready.wait_timeout(buffer, deadline - now)
+                        .map(|(guard, _)| guard)
+                        .unwrap_or_else(|e| e.into_inner().0)
+                }
+                None => self.0.ready.wait(buffer).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+        let count = ::std::cmp::min(buf.len(), buffer.data.len());
+        for (dest, src) in buf.iter_mut().zip(buffer.data.drain(..count)) {
+            *dest = src;
+        }
+        Ok(count)
+    }
+}
+
+// The writing end of one direction of an in-memory pipe.
+struct MemoryWriter(Arc<SharedBuffer>);
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().data.extend(buf);
+        self.0.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        self.0.lock().closed = true;
+        self.0.ready.notify_all();
+    }
+}
+
+/// One end of an in-memory pipe made by `pipe`.  Reads block until
+/// the other end writes, or until the connection's read timeout runs
+/// out; once the other end is dropped, reads report end of file.
+pub struct MemoryChannel {
+    reader: MemoryReader,
+    writer: MemoryWriter,
+}
+
+impl Transport for MemoryChannel {
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        let shared = self.reader.0.clone();
+        let mut conn = RspConnection::new(&mut self.reader, &mut self.writer, is_client);
+        conn.set_read_timeout_hook(move |timeout| {
+            shared.lock().read_timeout = timeout;
+            Ok(())
+        });
+        conn
+    }
+}
+
+/// Make two in-memory channels that are connected to each other, so
+/// that a client and a server can be tested against each other
+/// without sockets.  Since reads block, the two ends are normally
+/// used from different threads.
+pub fn pipe() -> (MemoryChannel, MemoryChannel) {
+    let forward = Arc::new(SharedBuffer::default());
+    let backward = Arc::new(SharedBuffer::default());
+    let first = MemoryChannel {
+        reader: MemoryReader(backward.clone()),
+        writer: MemoryWriter(forward.clone()),
+    };
+    let second = MemoryChannel { reader: MemoryReader(forward), writer: MemoryWriter(backward) };
+    (first, second)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use std::io;
+
+    use super::{connect, pipe, Transport};
+    use low::RspError;
+    use tcp::TcpChannel;
+
+    // Send a request over any transport.
+    fn query<T: Transport>(transport: &mut T) -> Vec<u8> {
+        transport.connection(true).request(b"?").unwrap()
+    }
+
+    #[test]
+    fn generic() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stub = thread::spawn(move || {
+            let (mut channel, _) = TcpChannel::accept(&listener).unwrap();
+            let mut conn = Transport::connection(&mut channel, false);
+            assert_eq!(conn.read_packet().unwrap().1, b"?".to_vec());
+            conn.full_packet(b"S05").unwrap();
+        });
+
+        let mut channel = connect(addr).unwrap();
+        assert!(channel.stream().nodelay().unwrap());
+        assert_eq!(query(&mut channel), b"S05".to_vec());
+        stub.join().unwrap();
+    }
+
+    #[test]
+    fn memory_pipe() {
+        let (mut client, mut server) = pipe();
+        let stub = thread::spawn(move || {
+            let mut conn = server.connection(false);
+            assert_eq!(conn.read_packet().unwrap().1, b"?".to_vec());
+            conn.full_packet(b"S05").unwrap();
+        });
+
+        {
+            let mut conn = client.connection(true);
+            assert_eq!(conn.request(b"?").unwrap(), b"S05".to_vec());
+        }
+        stub.join().unwrap();
+        // The server is gone, so reading reaches end of file.
+        let result = client.connection(true).read_packet();
+        match result {
+            Err(RspError::IOError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => { }
+            other => panic!("expected end of file, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let (mut client, mut server) = pipe();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stub = thread::spawn(move || TcpChannel::accept(&listener).unwrap());
+        let mut channel = connect(addr).unwrap();
+        // Keep the stub's end open, but never answer.
+        let _stub = stub.join().unwrap();
+
+        for conn in &mut [client.connection(true), channel.connection(true)] {
+            conn.set_read_timeout(Some(Duration::from_millis(10)));
+            match conn.request(b"?") {
+                Err(RspError::Timeout) => { }
+                other => panic!("expected a timeout, got {:?}", other),
+            }
+        }
+
+        let mut conn = server.connection(false);
+        conn.set_deadline(Some(Instant::now()));
+        match conn.read_packet() {
+            Err(RspError::Timeout) => { }
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        // With no deadline, the client's request is still there.
+        conn.set_deadline(None);
+        assert_eq!(conn.read_packet().unwrap().1, b"?".to_vec());
+    }
+}
@@ -0,0 +1,96 @@
+#![deny(missing_docs)]
+
+use std::io;
+use std::os::unix::io::OwnedFd;
+use std::os::unix::net::UnixStream;
+
+use low::RspConnection;
+use transport::Transport;
+
+/// An RSP channel over a Unix-domain stream socket.  This is useful
+/// for tools that fork a debuggee-plus-stub child and talk to it over
+/// an inherited descriptor, rather than over TCP.
+///
+/// `RspConnection` borrows its reader and writer, so this type owns
+/// the socket (and other handles to it) while connections are made
+/// from it using `connection`.
+pub struct UnixChannel {
+    reader: UnixStream,
+    writer: UnixStream,
+    // A third handle, used to set the read timeout while the others
+    // are lent to a connection.
+    control: UnixStream,
+}
+
+impl UnixChannel {
+    /// Make a new channel from a connected stream.
+    pub fn new(stream: UnixStream) -> io::Result<UnixChannel> {
+        let writer = stream.try_clone()?;
+        let control = stream.try_clone()?;
+        Ok(UnixChannel { reader: stream, writer, control })
+    }
+
+    /// Make a new channel from an already-connected socket
+    /// descriptor, for example one inherited from a parent process.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<UnixChannel> {
+        UnixChannel::new(UnixStream::from(fd))
+    }
+
+    /// Make a pair of channels that are connected to each other, using
+    /// `socketpair`.  Typically one end is kept and the other is
+    /// passed to a child process.
+    pub fn pair() -> io::Result<(UnixChannel, UnixChannel)> {
+        let (a, b) = UnixStream::pair()?;
+        Ok((UnixChannel::new(a)?, UnixChannel::new(b)?))
+    }
+
+    /// Return the underlying stream, for example to set timeouts or to
+    /// pass its descriptor to a child.
+    pub fn stream(&self) -> &UnixStream {
+        &self.reader
+    }
+
+    /// Make an `RspConnection` that uses this channel.  `is_client` is
+    /// as for `RspConnection::new`.  The connection's read timeout
+    /// sets the timeout of the socket.
+    pub fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        let control = &self.control;
+        let mut conn = RspConnection::new(&mut self.reader, &mut self.writer, is_client);
+        conn.set_read_timeout_hook(move |timeout| control.set_read_timeout(timeout));
+        conn
+    }
+}
+
+impl Transport for UnixChannel {
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        UnixChannel::connection(self, is_client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::UnixChannel;
+    use low::PacketType;
+
+    #[test]
+    fn pair() {
+        let (mut client, mut server) = UnixChannel::pair().unwrap();
+
+        let stub = thread::spawn(move || {
+            let mut conn = server.connection(false);
+            let (kind, contents) = conn.read_packet().unwrap();
+            assert_eq!(kind, PacketType::Normal);
+            assert_eq!(contents, b"?".to_vec());
+            conn.full_packet(b"S05").unwrap();
+        });
+
+        let mut conn = client.connection(true);
+        conn.full_packet(b"?").unwrap();
+        let (kind, contents) = conn.read_packet().unwrap();
+        assert_eq!(kind, PacketType::Normal);
+        assert_eq!(contents, b"S05".to_vec());
+        stub.join().unwrap();
+    }
+}
@@ -1,26 +1,412 @@
 #![deny(missing_docs)]
 
-/// Decode a hex sequence.
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use low::{Id, ProcessId, RspError};
+
+// The lowercase hex digits, indexed by value.
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+// A marker in HEX_VALUES for a byte that is not a hex digit.
+const NOT_HEX: u8 = 0xff;
+
+// The value of each hex digit, indexed by character, or NOT_HEX.
+// Looking the value up is much cheaper than matching on ranges, which
+// matters for large memory transfers.
+const HEX_VALUES: [u8; 256] = {
+    let mut table = [NOT_HEX; 256];
+    let mut i = 0;
+    while i < 16 {
+        table[HEX_DIGITS[i] as usize] = i as u8;
+        table[HEX_DIGITS[i].to_ascii_uppercase() as usize] = i as u8;
+        i += 1;
+    }
+    table
+};
+
+/// Return the two lowercase hex digits for |byte|.
+pub fn hex_byte(byte: u8) -> [u8; 2] {
+    [HEX_DIGITS[(byte >> 4) as usize], HEX_DIGITS[(byte & 0xf) as usize]]
+}
+
+/// Decode a hex sequence.  Returns `None` if the sequence contains a
+/// non-hex character or if the value does not fit in a `u64`.
 pub fn decode_hex(seq: &[u8]) -> Option<u64> {
-    let mut result = 0;
-    for c in seq {
-        let digit = match *c {
-            b'0'...b'9' => c - b'0',
-            b'a'...b'f' => c - b'a' + 10,
-            b'A'...b'F' => c - b'A' + 10,
-            _ => { return None; },
-        };
-        result = result * 16 + digit as u64;
+    let mut result: u64 = 0;
+    for &c in seq {
+        let digit = HEX_VALUES[c as usize];
+        if digit == NOT_HEX {
+            return None;
+        }
+        // Reject values that don't fit, rather than overflowing.
+        result = result.checked_mul(16)? + digit as u64;
+    }
+    Some(result)
+}
+
+/// Decode a hex number, rejecting the empty string.
+pub fn parse_hex_number(seq: &[u8]) -> Option<u64> {
+    if seq.is_empty() {
+        None
+    } else {
+        decode_hex(seq)
+    }
+}
+
+/// Decode a hex number that may be preceded by `-`, as used by the
+/// File-I/O protocol.
+pub fn parse_signed_hex(text: &[u8]) -> Option<i64> {
+    match text.split_first() {
+        Some((&b'-', rest)) => parse_hex_number(rest).map(|v| (v as i64).wrapping_neg()),
+        _ => parse_hex_number(text).map(|v| v as i64),
+    }
+}
+
+/// Decode a sequence of hex digit pairs into the bytes they
+/// represent.
+pub fn decode_hex_bytes(seq: &[u8]) -> Option<Vec<u8>> {
+    if !seq.len().is_multiple_of(2) {
+        return None;
+    }
+    seq.chunks(2).map(|pair| {
+        let high = HEX_VALUES[pair[0] as usize];
+        let low = HEX_VALUES[pair[1] as usize];
+        if high == NOT_HEX || low == NOT_HEX {
+            None
+        } else {
+            Some((high << 4) | low)
+        }
+    }).collect()
+}
+
+/// Append the hex encoding of |data| to |out|, two digits per byte.
+pub fn push_hex(out: &mut Vec<u8>, data: &[u8]) {
+    out.reserve(2 * data.len());
+    for &byte in data {
+        out.extend_from_slice(&hex_byte(byte));
+    }
+}
+
+/// Append |value| to |out| in hex, the usual form for numbers in the
+/// protocol: lowercase, with no leading zeros.
+pub fn write_hex_number(out: &mut Vec<u8>, value: u64) {
+    let mut digits = [0; 16];
+    let mut start = digits.len();
+    let mut value = value;
+    loop {
+        start -= 1;
+        digits[start] = HEX_DIGITS[(value & 0xf) as usize];
+        value >>= 4;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&digits[start..]);
+}
+
+/// Append |value| to |out| in decimal, as used by a few packets such
+/// as `A`.
+pub fn write_decimal(out: &mut Vec<u8>, value: u64) {
+    let mut digits = [0; 20];
+    let mut start = digits.len();
+    let mut value = value;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&digits[start..]);
+}
+
+// Append the id |id| to |out|.
+fn write_id(out: &mut Vec<u8>, id: Id) {
+    match id {
+        Id::Id(value) => write_hex_number(out, u64::from(value)),
+        Id::All => out.extend_from_slice(b"-1"),
+        Id::Any => out.push(b'0'),
+    }
+}
+
+/// Append the "thread-id" |thread| to |out|, in the form that
+/// `ProcessId` is displayed in.
+pub fn write_thread_id(out: &mut Vec<u8>, thread: ProcessId) {
+    if thread.pid != Id::Any {
+        out.push(b'p');
+        write_id(out, thread.pid);
+        out.push(b'.');
+    }
+    write_id(out, thread.tid);
+}
+
+/// Undo the binary escaping used by packets such as `X`, in which
+/// `}` means that the next byte is xored with 0x20.  Returns `None`
+/// if the sequence ends with a lone `}`.
+pub fn unescape_binary(seq: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(seq.len());
+    let mut iter = seq.iter();
+    while let Some(&c) = iter.next() {
+        if c == b'}' {
+            result.push(iter.next()? ^ 0x20);
+        } else {
+            result.push(c);
+        }
     }
     Some(result)
 }
 
+/// Return true if the request |contents| gets a reply.  "k" and "R"
+/// do not.
+pub fn request_gets_reply(contents: &[u8]) -> bool {
+    match contents.split_first() {
+        Some((&b'k', rest)) => !rest.is_empty(),
+        Some((&b'R', rest)) => !rest.iter().all(u8::is_ascii_hexdigit),
+        _ => true,
+    }
+}
+
+/// Return the local path corresponding to the remote path |remote|
+/// under |dir|, or None if |remote| would escape |dir|.  Absolute
+/// remote paths are taken to be relative to |dir|.
+pub fn local_path(dir: &Path, remote: &[u8]) -> Option<PathBuf> {
+    let remote = String::from_utf8_lossy(remote).into_owned();
+    let mut result = dir.to_path_buf();
+    let mut any = false;
+    for component in Path::new(&remote).components() {
+        match component {
+            Component::Normal(name) => {
+                result.push(name);
+                any = true;
+            }
+            Component::RootDir | Component::CurDir => { }
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    if any { Some(result) } else { None }
+}
+
+/// Undo the escaping of an XML attribute value.
+pub fn unescape_xml(text: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((&c, tail)) = rest.split_first() {
+        rest = tail;
+        if c != b'&' {
+            result.push(c);
+            continue;
+        }
+        let entities: &[(&[u8], u8)] = &[(b"amp;", b'&'), (b"lt;", b'<'), (b"gt;", b'>'),
+                                         (b"quot;", b'"'), (b"apos;", b'\'')];
+        match entities.iter().find(|&&(name, _)| rest.starts_with(name)) {
+            Some(&(name, value)) => {
+                result.push(value);
+                rest = &rest[name.len()..];
+            }
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+/// Return the value of the attribute |name| in |tag|, the text of an
+/// XML start tag after the element name, unescaped.
+pub fn xml_attribute(tag: &[u8], name: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    while pos + name.len() + 2 <= tag.len() {
+        let rest = &tag[pos..];
+        let at_start = pos == 0 || tag[pos - 1].is_ascii_whitespace();
+        if at_start && rest.starts_with(name) && rest[name.len()..].starts_with(b"=\"") {
+            let value = &rest[name.len() + 2..];
+            let quote = value.iter().position(|&c| c == b'"')?;
+            return Some(unescape_xml(&value[..quote]));
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// Return each <library> element in a library list, in either the
+/// "libraries" or the "libraries-svr4" format, as a pair of the start
+/// tag's attribute text and the text that follows it up to the next
+/// library.  The latter holds the segments or sections of a library in
+/// the "libraries" format.
+pub fn library_elements(xml: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut result = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.windows(9).position(|w| w == b"<library ") {
+        rest = &rest[start + 9..];
+        let end = rest.iter().position(|&c| c == b'>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        let next = rest.windows(9).position(|w| w == b"<library ").unwrap_or(rest.len());
+        result.push((tag, &rest[..next]));
+    }
+    result
+}
+
+/// Return the "name" attribute of each <library> element in a
+/// library list, in either the "libraries" or the "libraries-svr4"
+/// format.
+pub fn library_names(xml: &[u8]) -> Vec<Vec<u8>> {
+    library_elements(xml).into_iter().filter_map(|(tag, _)| xml_attribute(tag, b"name"))
+        .collect()
+}
+
+/// Check that the range of |length| bytes at |start| does not wrap
+/// around the 64-bit address space, returning InvalidArgument if it
+/// does.  A range may end at the very top of the space.
+pub fn check_range(start: u64, length: u64) -> Result<(), RspError> {
+    if length > 0 && start.checked_add(length - 1).is_none() {
+        return Err(RspError::InvalidArgument("range wraps around the address space"));
+    }
+    Ok(())
+}
+
+/// Convert an RspError into an io::Error, for use in the std::io
+/// trait impls.
+pub fn to_io_error(err: RspError) -> io::Error {
+    match err {
+        RspError::IOError(e) => e,
+        other => io::Error::other(format!("{:?}", other)),
+    }
+}
+
+/// Frame |contents| as a complete packet, as the remote would send
+/// it.  This is only used by tests.
+#[cfg(test)]
+pub fn frame_packet(contents: &[u8]) -> Vec<u8> {
+    let checksum = contents.iter().fold(0u8, |sum, &c| sum.wrapping_add(c));
+    let mut result = vec![b'$'];
+    result.extend_from_slice(contents);
+    result.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+    result
+}
+
+/// Frame each of |packets| in turn, as the remote would send them.
+/// This is only used by tests.
+#[cfg(test)]
+pub fn frame_packets(packets: &[&[u8]]) -> Vec<u8> {
+    packets.iter().flat_map(|packet| frame_packet(packet)).collect()
+}
+
+/// The bytes that a scripted remote sends, for tests that need to
+/// control exactly how packets, acks, and notifications interleave.
+#[cfg(test)]
+pub struct Script {
+    bytes: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Script {
+    /// Start an empty script.
+    pub fn new() -> Script {
+        Script { bytes: Vec::new() }
+    }
+
+    /// Send an ack.
+    pub fn ack(mut self) -> Script {
+        self.bytes.push(b'+');
+        self
+    }
+
+    /// Send a packet.
+    pub fn packet(mut self, contents: &[u8]) -> Script {
+        self.bytes.extend(frame_packet(contents));
+        self
+    }
+
+    /// Send a notification.
+    pub fn notification(mut self, contents: &[u8]) -> Script {
+        let mut packet = frame_packet(contents);
+        packet[0] = b'%';
+        self.bytes.extend(packet);
+        self
+    }
+
+    /// Return the bytes of the script.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::path::Path;
+
     #[test]
     fn decode_hex() {
         assert_eq!(super::decode_hex(b"000a").unwrap(), 10);
         assert_eq!(super::decode_hex(b"f01").unwrap(), 3841);
         assert_eq!(super::decode_hex(b"hi"), None);
+        assert_eq!(super::decode_hex(b"ffffffffffffffff").unwrap(), u64::MAX);
+        assert_eq!(super::decode_hex(b"10000000000000000"), None);
+    }
+
+    #[test]
+    fn encode_hex() {
+        assert_eq!(super::hex_byte(0x0f), *b"0f");
+        assert_eq!(super::hex_byte(0xa0), *b"a0");
+        let mut out = b"x".to_vec();
+        super::push_hex(&mut out, &[0, 0x7f, 0xff]);
+        assert_eq!(out, b"x007fff".to_vec());
+        let all: Vec<u8> = (0..=255).collect();
+        let mut out = Vec::new();
+        super::push_hex(&mut out, &all);
+        assert_eq!(super::decode_hex_bytes(&out).unwrap(), all);
+    }
+
+    #[test]
+    fn decode_hex_bytes() {
+        assert_eq!(super::decode_hex_bytes(b"0aFf").unwrap(), vec![10, 255]);
+        assert_eq!(super::decode_hex_bytes(b"abc"), None);
+        assert_eq!(super::decode_hex_bytes(b"zz"), None);
+    }
+
+    #[test]
+    fn unescape_binary() {
+        assert_eq!(super::unescape_binary(b"a}\x03b}]").unwrap(), b"a#b}".to_vec());
+        assert_eq!(super::unescape_binary(b"a}"), None);
+    }
+
+    #[test]
+    fn parse_signed_hex() {
+        assert_eq!(super::parse_signed_hex(b"1f"), Some(0x1f));
+        assert_eq!(super::parse_signed_hex(b"-1"), Some(-1));
+        assert_eq!(super::parse_signed_hex(b"-"), None);
+    }
+
+    #[test]
+    fn local_path() {
+        let dir = Path::new("sysroot");
+        assert_eq!(super::local_path(dir, b"/lib/libc.so.6"),
+                   Some(dir.join("lib").join("libc.so.6")));
+        assert_eq!(super::local_path(dir, b"./a.out"), Some(dir.join("a.out")));
+        assert_eq!(super::local_path(dir, b"/lib/../../etc/passwd"), None);
+        assert_eq!(super::local_path(dir, b"/"), None);
+    }
+
+    #[test]
+    fn library_names() {
+        let svr4 = b"<library-list-svr4 version=\"1.0\">\
+                     <library name=\"/lib/libc.so.6\" lm=\"0x1\" l_addr=\"0x2\" l_ld=\"0x3\"/>\
+                     <library name=\"/opt/a&amp;b.so\" lm=\"0x4\" l_addr=\"0x5\" l_ld=\"0x6\"/>\
+                     </library-list-svr4>";
+        assert_eq!(super::library_names(svr4),
+                   vec![b"/lib/libc.so.6".to_vec(), b"/opt/a&b.so".to_vec()]);
+
+        let plain = b"<library-list><library name=\"/lib/libm.so\"><segment address=\"0x10\"/>\
+                      </library></library-list>";
+        assert_eq!(super::library_names(plain), vec![b"/lib/libm.so".to_vec()]);
+    }
+
+    #[test]
+    fn xml_attribute() {
+        let tag = b"name=\"a&lt;b\" l_addr=\"0x10\" addr=\"0x20\"/";
+        assert_eq!(super::xml_attribute(tag, b"name"), Some(b"a<b".to_vec()));
+        assert_eq!(super::xml_attribute(tag, b"addr"), Some(b"0x20".to_vec()));
+        assert_eq!(super::xml_attribute(tag, b"lm"), None);
     }
 }
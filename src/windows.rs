@@ -0,0 +1,57 @@
+#![deny(missing_docs)]
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use low::RspConnection;
+use transport::Transport;
+
+/// An RSP channel over a Windows named pipe.  Several Windows JTAG
+/// tools expose RSP this way rather than over TCP.
+///
+/// This connects to an existing pipe, as a client of the pipe (the
+/// RSP role is chosen separately, in `connection`).  Creating the
+/// pipe itself is left to the stub.
+pub struct NamedPipeChannel {
+    reader: File,
+    writer: File,
+}
+
+/// Return the full path of the named pipe `name`, that is,
+/// `\\.\pipe\NAME`.
+pub fn named_pipe_path(name: &str) -> PathBuf {
+    PathBuf::from(format!(r"\\.\pipe\{}", name))
+}
+
+impl NamedPipeChannel {
+    /// Connect to the named pipe at `path`, which is usually made with
+    /// `named_pipe_path`.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<NamedPipeChannel> {
+        let reader = OpenOptions::new().read(true).write(true).open(path)?;
+        let writer = reader.try_clone()?;
+        Ok(NamedPipeChannel { reader, writer })
+    }
+
+    /// Make an `RspConnection` that uses this channel.  `is_client` is
+    /// as for `RspConnection::new`.
+    pub fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        RspConnection::new(&mut self.reader, &mut self.writer, is_client)
+    }
+}
+
+impl Transport for NamedPipeChannel {
+    fn connection(&mut self, is_client: bool) -> RspConnection<'_> {
+        NamedPipeChannel::connection(self, is_client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::named_pipe_path;
+
+    #[test]
+    fn pipe_path() {
+        assert_eq!(named_pipe_path("jtag0").to_str(), Some(r"\\.\pipe\jtag0"));
+    }
+}